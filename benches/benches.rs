@@ -1,84 +1,117 @@
 //! Demosaicing algorithm benchmarks.
+//!
+//! Run with `cargo bench`.  The large images take a while, so pass a
+//! filter to run a subset, e.g. `cargo bench -- algorithms/Linear`.
 
-#![cfg_attr(feature = "bench", feature(test))]
+extern crate bayer;
+#[macro_use]
+extern crate criterion;
 
-#[cfg(all(feature = "bench", test))]
-mod bench {
-    extern crate bayer;
-    extern crate test;
+use criterion::{BenchmarkId,Criterion,Throughput};
+use bayer::*;
 
-    use std::io::Cursor;
-    use self::bayer::*;
+/// The image sizes, from a thumbnail up to a 45 megapixel sensor.
+const SIZES: [(&str, usize, usize); 4] = [
+    ("128x128", 128, 128),
+    ("1080p", 1920, 1080),
+    ("4K", 3840, 2160),
+    ("45MP", 8192, 5464),
+];
 
-    const IMG_W: usize = 128;
-    const IMG_H: usize = 128;
-    const SRC_U8: [u8; IMG_W * IMG_H] = [0u8; IMG_W * IMG_H];
-    const SRC_U16: [u8; 2 * IMG_W * IMG_H] = [0u8; 2 * IMG_W * IMG_H];
+/// The depths, with the number of bytes per RGB pixel.
+const DEPTHS: [(&str, BayerDepth, RasterDepth, usize); 2] = [
+    ("u8", BayerDepth::Depth8, RasterDepth::Depth8, 3),
+    ("u16", BayerDepth::Depth16LE, RasterDepth::Depth16, 6),
+];
 
-    static mut BUF_U8: [u8; 3 * IMG_W * IMG_H] = [0u8; 3 * IMG_W * IMG_H];
-    static mut BUF_U16: [u8; 6 * IMG_W * IMG_H] = [0u8; 6 * IMG_W * IMG_H];
+/// A Bayer image with some variation, so that no algorithm can take
+/// shortcuts on constant data.
+fn source(w: usize, h: usize, depth: BayerDepth) -> Vec<u8> {
+    let len = w * h * depth.bytes_per_sample();
+    (0..len).map(|i| (i * 7 + i / w * 13) as u8).collect()
+}
 
-    #[bench]
-    fn bench_none_u8(b: &mut test::Bencher) {
-        let mut dst = unsafe{ RasterMut::new(
-                IMG_W, IMG_H, RasterDepth::Depth8, &mut BUF_U8) };
-        b.iter(|| run_demosaic(&mut Cursor::new(&SRC_U8[..]),
-                BayerDepth::Depth8, CFA::RGGB, Demosaic::None, &mut dst));
-    }
+fn demosaic(src: &[u8], depth: BayerDepth, raster_depth: RasterDepth,
+        cfa: CFA, alg: Demosaic, options: &DemosaicOptions,
+        w: usize, h: usize, buf: &mut [u8]) {
+    let mut dst = RasterMut::new(w, h, raster_depth, buf);
+    demosaic_from(&mut &src[..], depth, cfa, alg, options, &mut dst).unwrap();
+}
 
-    #[bench]
-    fn bench_none_u16(b: &mut test::Bencher) {
-        let mut dst = unsafe{ RasterMut::new(
-                IMG_W, IMG_H, RasterDepth::Depth16, &mut BUF_U16) };
-        b.iter(|| run_demosaic(&mut Cursor::new(&SRC_U16[..]),
-                BayerDepth::Depth16LE, CFA::RGGB, Demosaic::None, &mut dst));
-    }
+/// Every algorithm at every depth and size.
+fn bench_algorithms(c: &mut Criterion) {
+    let mut group = c.benchmark_group("algorithms");
+    let options = DemosaicOptions::default();
 
-    #[bench]
-    fn bench_nearest_neighbour_u8(b: &mut test::Bencher) {
-        let mut dst = unsafe{ RasterMut::new(
-                IMG_W, IMG_H, RasterDepth::Depth8, &mut BUF_U8) };
-        b.iter(|| run_demosaic(&mut Cursor::new(&SRC_U8[..]),
-                BayerDepth::Depth8, CFA::RGGB, Demosaic::NearestNeighbour, &mut dst));
-    }
+    for &(size, w, h) in SIZES.iter() {
+        if w * h > 1920 * 1080 {
+            group.sample_size(10);
+        }
+        group.throughput(Throughput::Elements((w * h) as u64));
 
-    #[bench]
-    fn bench_nearest_neighbour_u16(b: &mut test::Bencher) {
-        let mut dst = unsafe{ RasterMut::new(
-                IMG_W, IMG_H, RasterDepth::Depth16, &mut BUF_U16) };
-        b.iter(|| run_demosaic(&mut Cursor::new(&SRC_U16[..]),
-                BayerDepth::Depth16LE, CFA::RGGB, Demosaic::NearestNeighbour, &mut dst));
-    }
+        for &(depth_name, depth, raster_depth, bytes_per_pixel) in DEPTHS.iter() {
+            let src = source(w, h, depth);
+            let mut buf = vec![0; bytes_per_pixel * w * h];
 
-    #[bench]
-    fn bench_linear_u8(b: &mut test::Bencher) {
-        let mut dst = unsafe{ RasterMut::new(
-                IMG_W, IMG_H, RasterDepth::Depth8, &mut BUF_U8) };
-        b.iter(|| run_demosaic(&mut Cursor::new(&SRC_U8[..]),
-                BayerDepth::Depth8, CFA::RGGB, Demosaic::Linear, &mut dst));
+            for &alg in Demosaic::all() {
+                let id = BenchmarkId::new(format!("{:?}/{}", alg, depth_name), size);
+                group.bench_function(id, |b| b.iter(|| {
+                    demosaic(&src, depth, raster_depth, CFA::RGGB, alg, &options, w, h, &mut buf)
+                }));
+            }
+        }
     }
 
-    #[bench]
-    fn bench_linear_u16(b: &mut test::Bencher) {
-        let mut dst = unsafe{ RasterMut::new(
-                IMG_W, IMG_H, RasterDepth::Depth16, &mut BUF_U16) };
-        b.iter(|| run_demosaic(&mut Cursor::new(&SRC_U16[..]),
-                BayerDepth::Depth16LE, CFA::RGGB, Demosaic::Linear, &mut dst));
-    }
+    group.finish();
+}
 
-    #[bench]
-    fn bench_cubic_u8(b: &mut test::Bencher) {
-        let mut dst = unsafe{ RasterMut::new(
-                IMG_W, IMG_H, RasterDepth::Depth8, &mut BUF_U8) };
-        b.iter(|| run_demosaic(&mut Cursor::new(&SRC_U8[..]),
-                BayerDepth::Depth8, CFA::RGGB, Demosaic::Cubic, &mut dst));
+/// Each CFA pattern, which changes the order of the kernels in a row.
+fn bench_cfa(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cfa");
+    let (w, h) = (1920, 1080);
+    let options = DemosaicOptions::default();
+    let src = source(w, h, BayerDepth::Depth8);
+    let mut buf = vec![0; 3 * w * h];
+    group.throughput(Throughput::Elements((w * h) as u64));
+
+    for &cfa in [CFA::BGGR, CFA::GBRG, CFA::GRBG, CFA::RGGB].iter() {
+        for &alg in [Demosaic::Linear, Demosaic::Cubic].iter() {
+            let id = BenchmarkId::new(format!("{:?}", alg), format!("{:?}", cfa));
+            group.bench_function(id, |b| b.iter(|| {
+                demosaic(&src, BayerDepth::Depth8, RasterDepth::Depth8, cfa, alg, &options,
+                        w, h, &mut buf)
+            }));
+        }
     }
 
-    #[bench]
-    fn bench_cubic_u16(b: &mut test::Bencher) {
-        let mut dst = unsafe{ RasterMut::new(
-                IMG_W, IMG_H, RasterDepth::Depth16, &mut BUF_U16) };
-        b.iter(|| run_demosaic(&mut Cursor::new(&SRC_U16[..]),
-                BayerDepth::Depth16LE, CFA::RGGB, Demosaic::Cubic, &mut dst));
+    group.finish();
+}
+
+/// Sequential against parallel processing of the rows.
+fn bench_parallel(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parallel");
+    let (w, h) = (3840, 2160);
+    let src = source(w, h, BayerDepth::Depth16LE);
+    let mut buf = vec![0; 6 * w * h];
+    group.sample_size(10);
+    group.throughput(Throughput::Elements((w * h) as u64));
+
+    for &parallel in [false, true].iter() {
+        let mut options = DemosaicOptions::default();
+        options.parallel = parallel;
+
+        for &alg in [Demosaic::Linear, Demosaic::Cubic].iter() {
+            let id = BenchmarkId::new(format!("{:?}", alg),
+                    if parallel { "parallel" } else { "sequential" });
+            group.bench_function(id, |b| b.iter(|| {
+                demosaic(&src, BayerDepth::Depth16LE, RasterDepth::Depth16, CFA::RGGB, alg,
+                        &options, w, h, &mut buf)
+            }));
+        }
     }
+
+    group.finish();
 }
+
+criterion_group!(benches, bench_algorithms, bench_cfa, bench_parallel);
+criterion_main!(benches);
@@ -5,7 +5,7 @@ extern crate sdl2;
 
 use std::cmp::min;
 use std::env;
-use std::fs::File;
+use std::fs::{self,File};
 use std::path::Path;
 use std::slice;
 use bayer::*;
@@ -20,6 +20,21 @@ enum ImgDepth {
     Depth12LE,
     Depth16BE,
     Depth16LE,
+    Raw10,
+    Raw12,
+}
+
+/// The adjustments applied when displaying the image.
+#[derive(Copy,Clone,Debug,PartialEq)]
+struct Display {
+    wb: WhiteBalance,
+    gamma: f64,
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Display { wb: WhiteBalance::default(), gamma: 1.0 }
+    }
 }
 
 fn main() {
@@ -38,9 +53,11 @@ fn main() {
     let mut idx = 0;
     let mut cfa = CFA::BGGR;
     let mut alg = Demosaic::Linear;
+    let mut display = Display::default();
     let mut old_idx = 1;
     let mut old_cfa = CFA::RGGB;
     let mut old_alg = Demosaic::None;
+    let mut old_display = display;
 
     // Initialise SDL window.
     let sdl = sdl2::init().unwrap();
@@ -64,7 +81,8 @@ fn main() {
     let mut buf = vec![0; bayer_w * bayer_h * bytes_per_pixel];
 
     read_file(&Path::new(&files[0]), bayer_w, bayer_h, depth, cfa, alg,
-            &mut buf, &mut texture);
+            &mut buf);
+    render_to_texture(&mut texture, bayer_w, bayer_h, depth, display, &buf);
 
     let mut redraw = true;
     'mainloop: loop {
@@ -83,7 +101,22 @@ fn main() {
                 Event::KeyDown { keycode: Some(Keycode::Num0), .. } => { alg = Demosaic::None; },
                 Event::KeyDown { keycode: Some(Keycode::Num1), .. } => { alg = Demosaic::NearestNeighbour; },
                 Event::KeyDown { keycode: Some(Keycode::Num2), .. } => { alg = Demosaic::Linear; },
-                Event::KeyDown { keycode: Some(Keycode::Num3), .. } => { alg = Demosaic::Cubic; },
+                Event::KeyDown { keycode: Some(Keycode::Num3), .. } => { alg = Demosaic::MalvarHeCutler; },
+                Event::KeyDown { keycode: Some(Keycode::Num4), .. } => { alg = Demosaic::Cubic; },
+                Event::KeyDown { keycode: Some(Keycode::Num5), .. } => { alg = Demosaic::PPG; },
+                Event::KeyDown { keycode: Some(Keycode::Num6), .. } => { alg = Demosaic::LinearMedian; },
+
+                Event::KeyDown { keycode: Some(Keycode::Q), .. } => { display.wb.r = display.wb.r * 1.05; },
+                Event::KeyDown { keycode: Some(Keycode::A), .. } => { display.wb.r = display.wb.r / 1.05; },
+                Event::KeyDown { keycode: Some(Keycode::W), .. } => { display.wb.g = display.wb.g * 1.05; },
+                Event::KeyDown { keycode: Some(Keycode::S), .. } => { display.wb.g = display.wb.g / 1.05; },
+                Event::KeyDown { keycode: Some(Keycode::E), .. } => { display.wb.b = display.wb.b * 1.05; },
+                Event::KeyDown { keycode: Some(Keycode::D), .. } => { display.wb.b = display.wb.b / 1.05; },
+                Event::KeyDown { keycode: Some(Keycode::Up), .. } => { display.gamma = display.gamma + 0.1; },
+                Event::KeyDown { keycode: Some(Keycode::Down), .. } => {
+                    display.gamma = (display.gamma - 0.1).max(0.1);
+                },
+                Event::KeyDown { keycode: Some(Keycode::Backspace), .. } => { display = Display::default(); },
 
                 Event::KeyDown { keycode: Some(Keycode::Space), .. }
                 | Event::KeyDown { keycode: Some(Keycode::Right), .. } => {
@@ -101,7 +134,7 @@ fn main() {
                 _ => (),
             }
 
-            if idx != old_idx || cfa != old_cfa || alg != old_alg {
+            if idx != old_idx || cfa != old_cfa || alg != old_alg || display != old_display {
                 redraw = true;
             }
         } else {
@@ -109,7 +142,9 @@ fn main() {
         }
 
         if redraw {
-            if idx != old_idx || cfa != old_cfa || alg != old_alg {
+            if idx != old_idx || cfa != old_cfa || alg != old_alg || display != old_display {
+                let reload = idx != old_idx || cfa != old_cfa || alg != old_alg;
+
                 if old_idx != idx {
                     old_idx = idx;
                     println!("{}", files[idx]);
@@ -122,9 +157,16 @@ fn main() {
                     old_alg = alg;
                     print_alg(alg);
                 }
+                if old_display != display {
+                    old_display = display;
+                    print_display(display);
+                }
 
-                read_file(&Path::new(&files[idx]), bayer_w, bayer_h, depth, cfa, alg,
-                        &mut buf, &mut texture);
+                if reload {
+                    read_file(&Path::new(&files[idx]), bayer_w, bayer_h, depth, cfa, alg,
+                            &mut buf);
+                }
+                render_to_texture(&mut texture, bayer_w, bayer_h, depth, display, &buf);
             }
 
             present_to_screen(&mut canvas, &texture);
@@ -135,7 +177,7 @@ fn main() {
 fn usage() {
     println!("usage: ShowBayer <width> <height> <depth> <filename> [filenames ...]");
     println!();
-    println!("  depth     8, 12BE, 12LE, 16BE, 16LE");
+    println!("  depth     8, 12BE, 12LE, 16BE, 16LE, RAW10, RAW12");
     println!();
     println!("  <ESC>     Quit.");
     println!("  <left>    Go to previous image.");
@@ -143,7 +185,14 @@ fn usage() {
     println!("  <space>   Go to next image.");
     println!();
     println!("  F1-F4     Change CFA pattern: BGGR, GBRG, GRBG, RGGB");
-    println!("  0-3       Change demosaicing algorithm");
+    println!("  0-6       Change demosaicing algorithm");
+    println!();
+    println!("  Q/A       Increase/decrease red gain");
+    println!("  W/S       Increase/decrease green gain");
+    println!("  E/D       Increase/decrease blue gain");
+    println!("  <up>      Increase display gamma");
+    println!("  <down>    Decrease display gamma");
+    println!("  <bksp>    Reset gains and gamma");
     println!();
 }
 
@@ -159,6 +208,10 @@ fn parse_depth(s: &String) -> ImgDepth {
         ImgDepth::Depth16BE
     } else if s == "16LE" {
         ImgDepth::Depth16LE
+    } else if s == "RAW10" {
+        ImgDepth::Raw10
+    } else if s == "RAW12" {
+        ImgDepth::Raw12
     } else {
         panic!("invalid depth");
     }
@@ -171,6 +224,8 @@ fn bayer_depth(depth: ImgDepth) -> BayerDepth {
         ImgDepth::Depth12LE => BayerDepth::Depth16LE,
         ImgDepth::Depth16BE => BayerDepth::Depth16BE,
         ImgDepth::Depth16LE => BayerDepth::Depth16LE,
+        ImgDepth::Raw10     => BayerDepth::Depth16LE,
+        ImgDepth::Raw12     => BayerDepth::Depth16LE,
     }
 }
 
@@ -181,16 +236,36 @@ fn raster_depth(depth: ImgDepth) -> RasterDepth {
         ImgDepth::Depth12LE => RasterDepth::Depth16,
         ImgDepth::Depth16BE => RasterDepth::Depth16,
         ImgDepth::Depth16LE => RasterDepth::Depth16,
+        ImgDepth::Raw10     => RasterDepth::Depth16,
+        ImgDepth::Raw12     => RasterDepth::Depth16,
     }
 }
 
-fn bytes_per_pixel(depth: RasterDepth) -> usize {
+fn packed_format(depth: ImgDepth) -> Option<PackedFormat> {
+    match depth {
+        ImgDepth::Raw10 => Some(PackedFormat::Raw10),
+        ImgDepth::Raw12 => Some(PackedFormat::Raw12),
+        _ => None,
+    }
+}
+
+/// The number of significant bits in each sample.
+fn sample_bits(depth: ImgDepth) -> u32 {
     match depth {
-        RasterDepth::Depth8 => 3,
-        RasterDepth::Depth16 => 6,
+        ImgDepth::Depth8    => 8,
+        ImgDepth::Depth12BE => 12,
+        ImgDepth::Depth12LE => 12,
+        ImgDepth::Depth16BE => 16,
+        ImgDepth::Depth16LE => 16,
+        ImgDepth::Raw10     => 10,
+        ImgDepth::Raw12     => 12,
     }
 }
 
+fn bytes_per_pixel(depth: RasterDepth) -> usize {
+    depth.bytes_per_pixel()
+}
+
 fn print_cfa(cfa: CFA) {
     let s = match cfa {
         CFA::BGGR => "BGGR",
@@ -202,44 +277,61 @@ fn print_cfa(cfa: CFA) {
 }
 
 fn print_alg(alg: Demosaic) {
-    let s = match alg {
-        Demosaic::None => "none",
-        Demosaic::NearestNeighbour => "nearest neighbour",
-        Demosaic::Linear => "linear",
-        Demosaic::Cubic => "cubic",
-    };
-    println!("Demosaic: {}", s);
+    println!("Demosaic: {}", alg.info().name);
+}
+
+fn print_display(display: Display) {
+    println!("Gains: {:.2} {:.2} {:.2}, gamma: {:.1}",
+            display.wb.r, display.wb.g, display.wb.b, display.gamma);
 }
 
 fn read_file(
         path: &Path, bayer_w: usize, bayer_h: usize,
         depth: ImgDepth, cfa: CFA, alg: Demosaic,
-        buf: &mut [u8], texture: &mut sdl2::render::Texture) {
-    let maybe_file = File::open(path);
-    match maybe_file {
-        Ok(mut f) => {
-            let result = run_demosaic(&mut f, bayer_depth(depth), cfa, alg,
-                    &mut RasterMut::new(bayer_w, bayer_h, raster_depth(depth), buf));
-            match result {
-                Ok(_) => (),
-                Err(e) => {
-                    println!("Error occurred - {}", e);
-                    return;
-                },
-            }
+        buf: &mut [u8]) {
+    let mut dst = RasterMut::new(bayer_w, bayer_h, raster_depth(depth), buf);
+
+    let result = match packed_format(depth) {
+        Some(format) => {
+            // Unpack into 16-bit little-endian samples first.
+            fs::read(path).map_err(BayerError::from)
+                .and_then(|packed| unpack(&packed, format, bayer_w * bayer_h))
+                .and_then(|samples| {
+                    let mut raw = Vec::with_capacity(2 * samples.len());
+                    for v in samples {
+                        raw.extend_from_slice(&v.to_le_bytes());
+                    }
+                    run_demosaic(&mut &raw[..], bayer_depth(depth), cfa, alg, &mut dst)
+                })
         },
-        Err(e) => {
-            println!("Error occurred - {}", e);
-            return;
-        }
+        None => {
+            File::open(path).map_err(BayerError::from)
+                .and_then(|mut f| run_demosaic(&mut f, bayer_depth(depth), cfa, alg, &mut dst))
+        },
+    };
+
+    if let Err(e) = result {
+        println!("Error occurred - {}", e);
     }
+}
+
+/// Build the table mapping samples of each channel to display values.
+fn display_lut(depth: ImgDepth, display: Display) -> Vec<Vec<u8>> {
+    let max = ((1u32 << sample_bits(depth)) - 1) as f64;
 
-    render_to_texture(texture, bayer_w, bayer_h, depth, &buf);
+    display.wb.gains().iter().map(|&gain| {
+        (0..=(max as usize)).map(|v| {
+            let x = (v as f64 * gain / max).min(1.0);
+            (x.powf(1.0 / display.gamma) * 255.0 + 0.5) as u8
+        }).collect()
+    }).collect()
 }
 
 fn render_to_texture(
         texture: &mut sdl2::render::Texture,
-        w: usize, h: usize, depth: ImgDepth, buf: &[u8]) {
+        w: usize, h: usize, depth: ImgDepth, display: Display, buf: &[u8]) {
+    let lut = display_lut(depth, display);
+
     match raster_depth(depth) {
         RasterDepth::Depth8 => {
             texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
@@ -248,14 +340,14 @@ fn render_to_texture(
                     let dst_offset = pitch * y;
 
                     for i in 0..3 * w {
-                        buffer[dst_offset + i] = buf[src_offset + i];
+                        let v = buf[src_offset + i] as usize;
+                        buffer[dst_offset + i] = lut[i % 3][v];
                     }
                 }
             }).unwrap();
         },
 
         RasterDepth::Depth16 => {
-            let shr = if depth == ImgDepth::Depth12BE || depth == ImgDepth::Depth12LE { 4 } else { 8 };
             let buf = unsafe {
                 slice::from_raw_parts(buf.as_ptr() as *const u16, buf.len() / 2)
             };
@@ -266,14 +358,16 @@ fn render_to_texture(
                     let dst_offset = pitch * y;
 
                     for i in 0..3 * w {
-                        // shr = 8 for u16 to u8, or
-                        // shr = 4 for u12 to u8.
-                        let v = buf[src_offset + i] >> shr;
-                        buffer[dst_offset + i] = min(v, 255) as u8;
+                        // Samples beyond the nominal depth are clipped.
+                        let lut = &lut[i % 3];
+                        let v = min(buf[src_offset + i] as usize, lut.len() - 1);
+                        buffer[dst_offset + i] = lut[v];
                     }
                 }
             }).unwrap();
         },
+
+        RasterDepth::Depth32F => unreachable!(),
     }
 }
 
@@ -6,25 +6,31 @@ extern crate sdl2;
 
 use std::env;
 use std::fs::File;
-use std::io::Write;
 use std::path::{Path,PathBuf};
+use std::str::FromStr;
 use sdl2::image::LoadSurface;
 use sdl2::surface::Surface;
 
+/// The settings given on the command line.
+struct Settings {
+    cfa: bayer::CFA,
+    depth: bayer::BayerDepth,
+    bits: u32,
+    options: bayer::MosaicOptions,
+}
+
 fn main() {
     let args: Vec<String> = env::args().skip(1).collect();
 
     usage();
-    if args.len() < 2 {
+    let (settings, files) = parse_args(&args);
+    if files.is_empty() {
         return;
     }
 
     sdl2::image::init(sdl2::image::INIT_JPG | sdl2::image::INIT_PNG | sdl2::image::INIT_TIF)
         .unwrap();
 
-    let cfa = parse_cfa(&args[0]);
-    let files = &args[1..];
-
     for f in files {
         let src = Path::new(f);
         if !src.exists() {
@@ -50,26 +56,89 @@ fn main() {
                 continue;
             }
 
-            write_mosaic_pal(&dst, &buf, &pal, w, h, cfa);
+            write_mosaic_pal(&dst, &buf, &pal, w, h, &settings);
             continue;
         }
 
         if let Ok(surface) = Surface::from_file(&src) {
             let w = surface.width() as usize;
             let h = surface.height() as usize;
-            surface.with_lock(|s| write_mosaic_rgba(&dst, s, w, h, cfa));
+            surface.with_lock(|s| write_mosaic_rgba(&dst, s, w, h, &settings));
             continue;
         }
     }
 }
 
 fn usage() {
-    println!("usage: WriteBayer <cfa> <filename> [filenames ...]");
+    println!("usage: WriteBayer [options] <cfa> <filename> [filenames ...]");
+    println!();
+    println!("  cfa           BGGR, GBRG, GRBG, RGGB");
     println!();
-    println!("  cfa       BGGR, GBRG, GRBG, RGGB");
+    println!("  --depth <d>   8, 16BE, 16LE, 10P, 12P, 12DNG, 14P (default 8)");
+    println!("  --bits <n>    Significant bits of 16-bit output (default 16)");
+    println!("  --black <v>   Black level added to each sample");
+    println!("  --gain <g>    Electrons per sample value, for shot noise");
+    println!("  --read <s>    Standard deviation of the read noise");
+    println!("  --seed <n>    Seed for the noise (default 0)");
     println!();
 }
 
+fn parse_args(args: &[String]) -> (Settings, &[String]) {
+    let mut settings = Settings {
+        cfa: bayer::CFA::RGGB,
+        depth: bayer::BayerDepth::Depth8,
+        bits: 16,
+        options: bayer::MosaicOptions::default(),
+    };
+    let mut noise = bayer::Noise { gain: 0.0, read: 0.0, seed: 0 };
+    let mut noisy = false;
+
+    let mut i = 0;
+    while i + 1 < args.len() && args[i].starts_with("--") {
+        let value = &args[i + 1];
+        match args[i].as_str() {
+            "--depth" => settings.depth = parse_depth(value),
+            "--bits" => settings.bits = parse_value(value),
+            "--black" => settings.options.black_level = parse_value(value),
+            "--gain" => { noise.gain = parse_value(value); noisy = true; },
+            "--read" => { noise.read = parse_value(value); noisy = true; },
+            "--seed" => noise.seed = parse_value(value),
+            _ => panic!("invalid option"),
+        }
+        i = i + 2;
+    }
+
+    if settings.bits < 8 || settings.bits > 16 {
+        panic!("invalid bits");
+    }
+    if noisy {
+        settings.options.noise = Some(noise);
+    }
+    if i >= args.len() {
+        return (settings, &args[i..]);
+    }
+
+    settings.cfa = parse_cfa(&args[i]);
+    (settings, &args[(i + 1)..])
+}
+
+fn parse_value<T: FromStr>(s: &str) -> T {
+    s.parse().ok().expect("invalid value")
+}
+
+fn parse_depth(s: &str) -> bayer::BayerDepth {
+    match s.to_uppercase().as_str() {
+        "8" => bayer::BayerDepth::Depth8,
+        "16BE" => bayer::BayerDepth::Depth16BE,
+        "16LE" => bayer::BayerDepth::Depth16LE,
+        "10P" => bayer::BayerDepth::Depth10Packed,
+        "12P" => bayer::BayerDepth::Depth12Packed,
+        "12DNG" => bayer::BayerDepth::Depth12PackedDng,
+        "14P" => bayer::BayerDepth::Depth14Packed,
+        _ => panic!("invalid depth"),
+    }
+}
+
 fn parse_cfa(s: &String) -> bayer::CFA {
     let s = s.to_uppercase();
     if s == "BGGR" {
@@ -86,63 +155,52 @@ fn parse_cfa(s: &String) -> bayer::CFA {
 }
 
 fn write_mosaic_rgba(dst: &PathBuf,
-        s: &[u8], w: usize, h: usize, cfa: bayer::CFA) {
-    let mut v = Vec::with_capacity(w * h);
-    let mut cfa_y = cfa;
-
-    for y in 0..h {
-        let mut cfa_x = cfa_y;
-
-        for x in 0..w {
-            let c = match cfa_x {
-                bayer::CFA::BGGR =>
-                    s[4 * w * y + 4 * x + 2],
-                bayer::CFA::GBRG | bayer::CFA::GRBG =>
-                    s[4 * w * y + 4 * x + 1],
-                bayer::CFA::RGGB =>
-                    s[4 * w * y + 4 * x + 0],
-            };
-
-            v.push(c);
-            cfa_x = cfa_x.next_x();
-        }
-
-        cfa_y = cfa_y.next_y();
+        s: &[u8], w: usize, h: usize, settings: &Settings) {
+    let mut rgb = Vec::with_capacity(3 * w * h);
+    for px in s[0..(4 * w * h)].chunks(4) {
+        rgb.extend_from_slice(&px[0..3]);
     }
 
-    if let Ok(mut fp) = File::create(&dst) {
-        println!("writing {} [{}x{}]", dst.display(), w, h);
-        let _ = fp.write_all(&v[..]);
-    }
+    write_mosaic(dst, &rgb, w, h, settings);
 }
 
 fn write_mosaic_pal(dst: &PathBuf,
-        buf: &[u8], pal: &[u8], w: usize, h: usize, cfa: bayer::CFA) {
-    let mut v = Vec::with_capacity(w * h);
-    let mut cfa_y = cfa;
-
-    for y in 0..h {
-        let mut cfa_x = cfa_y;
-
-        for x in 0..w {
-            let c = match cfa_x {
-                bayer::CFA::BGGR =>
-                    pal[3 * buf[w * y + x] as usize + 2],
-                bayer::CFA::GBRG | bayer::CFA::GRBG =>
-                    pal[3 * buf[w * y + x] as usize + 1],
-                bayer::CFA::RGGB =>
-                    pal[3 * buf[w * y + x] as usize + 0],
-            };
-
-            v.push(c);
-            cfa_x = cfa_x.next_x();
-        }
-
-        cfa_y = cfa_y.next_y();
+        buf: &[u8], pal: &[u8], w: usize, h: usize, settings: &Settings) {
+    let mut rgb = Vec::with_capacity(3 * w * h);
+    for &c in buf[0..(w * h)].iter() {
+        let c = c as usize;
+        rgb.extend_from_slice(&pal[(3 * c)..(3 * c + 3)]);
     }
 
+    write_mosaic(dst, &rgb, w, h, settings);
+}
+
+fn write_mosaic(dst: &PathBuf,
+        rgb: &[u8], w: usize, h: usize, settings: &Settings) {
+    // Widen 8-bit sources to the significant bits of 16-bit output.
+    let max = (1u32 << settings.bits) - 1;
+    let rgb16: Vec<u8> = match settings.depth {
+        bayer::BayerDepth::Depth8 => Vec::new(),
+        bayer::BayerDepth::Depth16BE | bayer::BayerDepth::Depth16LE
+            | bayer::BayerDepth::Depth10Packed | bayer::BayerDepth::Depth12Packed
+            | bayer::BayerDepth::Depth12PackedDng | bayer::BayerDepth::Depth14Packed => {
+            rgb.iter()
+                .flat_map(|&v| ((v as u32 * max / 255) as u16).to_ne_bytes().to_vec())
+                .collect()
+        },
+    };
+
+    let src = match settings.depth {
+        bayer::BayerDepth::Depth8 => bayer::Raster::new(w, h, bayer::RasterDepth::Depth8, rgb),
+        bayer::BayerDepth::Depth16BE | bayer::BayerDepth::Depth16LE
+            | bayer::BayerDepth::Depth10Packed | bayer::BayerDepth::Depth12Packed
+            | bayer::BayerDepth::Depth12PackedDng | bayer::BayerDepth::Depth14Packed =>
+            bayer::Raster::new(w, h, bayer::RasterDepth::Depth16, &rgb16),
+    };
+
     if let Ok(mut fp) = File::create(&dst) {
         println!("writing {} [{}x{}]", dst.display(), w, h);
-        let _ = fp.write_all(&v[..]);
+        let _ = bayer::mosaic_with_options(&src, settings.cfa, settings.depth,
+                &settings.options, &mut fp);
     }
 }
@@ -0,0 +1,75 @@
+//! Feed arbitrary raw data and geometries through the readers and
+//! the demosaicing entry points.
+
+#![no_main]
+
+use bayer::*;
+use libfuzzer_sys::arbitrary::{self,Arbitrary};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Arbitrary,Debug)]
+struct Input {
+    w: u8,
+    h: u8,
+    depth: u8,
+    cfa: u8,
+    alg: u8,
+    border: u8,
+    truncation: u8,
+    parallel: bool,
+    strip_rows: u8,
+    chunk: u8,
+    data: Vec<u8>,
+}
+
+fuzz_target!(|input: Input| {
+    let (w, h) = (input.w as usize, input.h as usize);
+    let depth = match input.depth % 7 {
+        0 => BayerDepth::Depth8,
+        1 => BayerDepth::Depth16BE,
+        2 => BayerDepth::Depth10Packed,
+        3 => BayerDepth::Depth12Packed,
+        4 => BayerDepth::Depth12PackedDng,
+        5 => BayerDepth::Depth14Packed,
+        _ => BayerDepth::Depth16LE,
+    };
+    let raster_depth = match depth {
+        BayerDepth::Depth8 => RasterDepth::Depth8,
+        _ => RasterDepth::Depth16,
+    };
+    let cfa = [CFA::BGGR, CFA::GBRG, CFA::GRBG, CFA::RGGB][(input.cfa % 4) as usize];
+    let alg = Demosaic::all()[input.alg as usize % Demosaic::all().len()];
+
+    let mut options = DemosaicOptions::default();
+    options.border = [None, Some(Border::None), Some(Border::Replicate), Some(Border::Mirror)]
+        [(input.border % 4) as usize];
+    options.truncation = match input.truncation % 3 {
+        0 => Truncation::Error,
+        1 => Truncation::Replicate,
+        _ => Truncation::Constant(input.truncation as u16),
+    };
+    options.parallel = input.parallel;
+
+    let demosaicer = Demosaicer::new()
+        .depth(depth).cfa(cfa).algorithm(alg).options(options.clone())
+        .build();
+
+    let bytes_per_pixel = if raster_depth == RasterDepth::Depth8 { 3 } else { 6 };
+    let mut buf = vec![0u8; bytes_per_pixel * w * h];
+    if let Ok(mut dst) = RasterMut::try_new(w, h, raster_depth, &mut buf) {
+        let _ = demosaic_with_options(&mut &input.data[..], depth, cfa, alg, &options, &mut dst);
+        let _ = preview(&mut &input.data[..], depth, cfa, w, h, &mut dst);
+    }
+
+    let _ = demosaicer.run_strips(&mut &input.data[..], w, h, input.strip_rows as usize,
+            |_, _| Ok(()));
+
+    if let Ok(mut stream) = StreamingDemosaicer::new(&demosaicer, w, h) {
+        let chunk = input.chunk as usize + 1;
+        for data in input.data.chunks(chunk) {
+            if stream.push(data, |_, _| ()).is_err() {
+                break;
+            }
+        }
+    }
+});
@@ -0,0 +1,84 @@
+//! Call the C API with arbitrary raster geometries and parameters.
+
+#![no_main]
+
+use std::ffi::c_void;
+use std::ptr;
+
+use bayer::ffi::*;
+use libfuzzer_sys::arbitrary::{self,Arbitrary};
+use libfuzzer_sys::fuzz_target;
+
+extern "C" {
+    fn malloc(size: usize) -> *mut c_void;
+    fn free(ptr: *mut c_void);
+}
+
+#[derive(Arbitrary,Debug)]
+struct Input {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+    stride: u32,
+    raster_depth: u8,
+    buf_len: u16,
+    depth: u8,
+    be: u8,
+    cfa: u8,
+    alg: u8,
+    owned: bool,
+    allocator: bool,
+    data: Vec<u8>,
+}
+
+fuzz_target!(|input: Input| {
+    let mut buf = vec![0u8; input.buf_len as usize];
+    let raster_depth = if input.raster_depth % 2 == 0 { 8 } else { 16 };
+    let depth = [8, 10, 12, 14, 16][(input.depth % 5) as usize];
+
+    unsafe {
+        if input.allocator {
+            // Both callbacks must be given.
+            assert_ne!(bayerrs_set_allocator(Some(malloc), None), 0);
+            assert_eq!(bayerrs_set_allocator(Some(malloc), Some(free)), 0);
+        }
+
+        let dst = if input.owned {
+            // Keep the owned buffers small.
+            let (w, h) = ((input.w % 256) as usize, (input.h % 256) as usize);
+            let dst = bayerrs_raster_mut_alloc_owned(w, h, raster_depth);
+            if !dst.is_null() {
+                let mut len = 0;
+                assert!(!bayerrs_raster_mut_buffer(dst, &mut len).is_null());
+                assert_eq!(len, 3 * (raster_depth as usize / 8) * w * h);
+            }
+            dst
+        } else {
+            bayerrs_raster_mut_alloc(
+                    input.x as usize, input.y as usize, input.w as usize, input.h as usize,
+                    input.stride as usize, raster_depth, buf.as_mut_ptr(), buf.len())
+        };
+
+        if !dst.is_null() {
+            let run = match input.alg % 7 {
+                0 => bayerrs_demosaic_none,
+                1 => bayerrs_demosaic_nearest_neighbour,
+                2 => bayerrs_demosaic_linear,
+                3 => bayerrs_demosaic_linear_median,
+                4 => bayerrs_demosaic_malvar_he_cutler,
+                5 => bayerrs_demosaic_ppg,
+                _ => bayerrs_demosaic_cubic,
+            };
+            let _ = run(input.data.as_ptr(), input.data.len(),
+                    depth, input.be as u32, input.cfa as u32 % 5, dst);
+
+            bayerrs_raster_mut_free(dst);
+        }
+        assert!(bayerrs_raster_mut_buffer(ptr::null_mut(), ptr::null_mut()).is_null());
+
+        if input.allocator {
+            assert_eq!(bayerrs_set_allocator(None, None), 0);
+        }
+    }
+});
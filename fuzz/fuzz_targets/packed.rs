@@ -0,0 +1,26 @@
+//! Feed arbitrary bytes into the packed-format decoders, and check
+//! that packing the decoded samples reproduces them.
+
+#![no_main]
+
+use bayer::*;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (u8, u16, Vec<u8>)| {
+    let (format, count, data) = input;
+    let format = match format % 4 {
+        0 => PackedFormat::Raw10,
+        1 => PackedFormat::Raw12,
+        2 => PackedFormat::Raw14,
+        _ => PackedFormat::Dng12,
+    };
+
+    if let Ok(samples) = unpack(&data, format, count as usize) {
+        assert_eq!(samples.len(), count as usize);
+        assert!(samples.iter().all(|&v| v >> format.bits() == 0));
+
+        let packed = pack(&samples, format);
+        assert_eq!(Some(packed.len()), format.packed_len(samples.len()));
+        assert_eq!(unpack(&packed, format, samples.len()).ok(), Some(samples));
+    }
+});
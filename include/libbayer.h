@@ -10,6 +10,17 @@ enum {
     BAYERRS_ERROR = 1,
     BAYERRS_WRONG_RESOLUTION = 2,
     BAYERRS_WRONG_DEPTH = 3,
+    BAYERRS_INPUT_TOO_SHORT = 4,
+    BAYERRS_TRAILING_DATA = 5,
+    BAYERRS_DIMENSION_MISMATCH = 6,
+    BAYERRS_IO_ERROR = 7,
+    BAYERRS_CANCELLED = 8,
+    BAYERRS_OUTPUT_TOO_SHORT = 9,
+    BAYERRS_OUT_OF_BOUNDS = 10,
+    BAYERRS_INVALID_DEFECT_MAP = 11,
+    BAYERRS_TRUNCATED = 12,
+    BAYERRS_DEADLINE_EXCEEDED = 13,
+    BAYERRS_INVALID_ARGUMENT = 14,
 };
 
 #define CFA_BGGR 0
@@ -39,12 +50,40 @@ bayerrs_demosaic_linear(
         unsigned int depth, unsigned int big_endian, unsigned int cfa,
         struct CRasterMut *dst);
 
+extern unsigned int
+bayerrs_demosaic_linear_median(
+        const unsigned char *src, size_t src_len,
+        unsigned int depth, unsigned int big_endian, unsigned int cfa,
+        struct CRasterMut *dst);
+
+extern unsigned int
+bayerrs_demosaic_malvar_he_cutler(
+        const unsigned char *src, size_t src_len,
+        unsigned int depth, unsigned int big_endian, unsigned int cfa,
+        struct CRasterMut *dst);
+
 extern unsigned int
 bayerrs_demosaic_cubic(
         const unsigned char *src, size_t src_len,
         unsigned int depth, unsigned int big_endian, unsigned int cfa,
         struct CRasterMut *dst);
 
+extern unsigned int
+bayerrs_demosaic_ppg(
+        const unsigned char *src, size_t src_len,
+        unsigned int depth, unsigned int big_endian, unsigned int cfa,
+        struct CRasterMut *dst);
+
+/*--------------------------------------------------------------*/
+/* Allocator                                                    */
+/*--------------------------------------------------------------*/
+
+typedef void *(*bayerrs_malloc_fn)(size_t size);
+typedef void (*bayerrs_free_fn)(void *ptr);
+
+extern unsigned int
+bayerrs_set_allocator(bayerrs_malloc_fn malloc_fn, bayerrs_free_fn free_fn);
+
 /*--------------------------------------------------------------*/
 /* Raster                                                       */
 /*--------------------------------------------------------------*/
@@ -54,6 +93,12 @@ bayerrs_raster_mut_alloc(
         size_t x, size_t y, size_t w, size_t h, size_t stride, unsigned int depth,
         unsigned char *buf, size_t buf_len);
 
+extern struct CRasterMut *
+bayerrs_raster_mut_alloc_owned(size_t w, size_t h, unsigned int depth);
+
+extern unsigned char *
+bayerrs_raster_mut_buffer(struct CRasterMut *raster, size_t *len);
+
 extern void
 bayerrs_raster_mut_free(struct CRasterMut *raster);
 
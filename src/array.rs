@@ -0,0 +1,301 @@
+//! Demosaicing from ndarray views.
+//!
+//! The views may have arbitrary strides, e.g. a transposed or sliced
+//! view of a larger array, so data that already lives in an ndarray
+//! does not need to be copied into a contiguous buffer first.
+
+use std::cmp;
+use std::io::{self,Read};
+use std::mem;
+use std::slice;
+use ndarray::ArrayView2;
+
+use ::{BayerDepth,BayerError,BayerResult,CFA,Demosaic,DemosaicOptions,RasterMut};
+use raster::RasterSample;
+
+/// Reader that produces the elements of a two-dimensional array in
+/// row-major order, regardless of the memory layout of the array.
+/// Contiguous rows are copied whole.
+struct ArrayReader<'a, T: 'a> {
+    src: ArrayView2<'a, T>,
+    /// The number of bytes read so far.
+    pos: usize,
+}
+
+impl<'a, T: RasterSample> ArrayReader<'a, T> {
+    fn new(src: ArrayView2<'a, T>) -> Self {
+        ArrayReader { src, pos: 0 }
+    }
+}
+
+impl<'a, T: RasterSample> Read for ArrayReader<'a, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (h, w) = self.src.dim();
+        let bps = mem::size_of::<T>();
+        let row_len = bps * w;
+        let mut n = 0;
+
+        while n < buf.len() && self.pos < row_len * h {
+            let (y, offset) = (self.pos / row_len, self.pos % row_len);
+            let len = cmp::min(buf.len() - n, row_len - offset);
+            let row = self.src.row(y);
+            let dst = &mut buf[n..(n + len)];
+
+            match row.as_slice() {
+                Some(samples) => dst.copy_from_slice(&T::as_bytes(samples)[offset..(offset + len)]),
+                None => {
+                    for (i, e) in dst.iter_mut().enumerate() {
+                        let b = offset + i;
+                        *e = T::as_bytes(slice::from_ref(&row[b / bps]))[b % bps];
+                    }
+                },
+            }
+
+            n = n + len;
+            self.pos = self.pos + len;
+        }
+
+        Ok(n)
+    }
+}
+
+/// Check that the array of shape (height, width) matches the raster,
+/// which has the transformed dimensions if there is an output
+/// transform.
+fn check_dim((h, w): (usize, usize), options: &DemosaicOptions, dst: &RasterMut)
+        -> BayerResult<()> {
+    let dim = options.transform.map_or((dst.w, dst.h), |t| t.dimensions(dst.w, dst.h));
+    if (w, h) == dim {
+        Ok(())
+    } else {
+        Err(BayerError::DimensionMismatch {
+            width: w, height: h, raster_width: dst.w, raster_height: dst.h,
+        })
+    }
+}
+
+/// Run the demosaicing algorithm on an 8-bit Bayer image stored in
+/// an ndarray view of shape (height, width).
+///
+/// # Example
+///
+/// ```
+/// extern crate bayer;
+/// extern crate ndarray;
+///
+/// let width: usize = 320;
+/// let height: usize = 200;
+/// let img = ndarray::Array2::<u8>::zeros((height, width));
+/// let mut buf = vec![0; 3 * width * height];
+///
+/// let mut dst = bayer::RasterMut::new(
+///         width, height, bayer::RasterDepth::Depth8,
+///         &mut buf);
+/// bayer::run_demosaic_array_u8(img.view(),
+///         bayer::CFA::RGGB,
+///         bayer::Demosaic::Linear,
+///         &mut dst).unwrap();
+/// ```
+pub fn run_demosaic_array_u8(src: ArrayView2<u8>,
+        cfa: CFA, alg: Demosaic, dst: &mut RasterMut)
+        -> BayerResult<()> {
+    demosaic_array_u8_with_options(src, cfa, alg, &DemosaicOptions::default(), dst)
+}
+
+/// Run the demosaicing algorithm on an 8-bit Bayer image stored in
+/// an ndarray view, with additional options.
+pub fn demosaic_array_u8_with_options(src: ArrayView2<u8>,
+        cfa: CFA, alg: Demosaic, options: &DemosaicOptions, dst: &mut RasterMut)
+        -> BayerResult<()> {
+    check_dim(src.dim(), options, dst)?;
+
+    ::demosaic_with_options(&mut ArrayReader::new(src),
+            BayerDepth::Depth8, cfa, alg, options, dst)
+}
+
+/// Run the demosaicing algorithm on a 16-bit Bayer image stored in
+/// an ndarray view of shape (height, width).
+///
+/// The samples are in host byte order.
+pub fn run_demosaic_array_u16(src: ArrayView2<u16>,
+        cfa: CFA, alg: Demosaic, dst: &mut RasterMut)
+        -> BayerResult<()> {
+    demosaic_array_u16_with_options(src, cfa, alg, &DemosaicOptions::default(), dst)
+}
+
+/// Run the demosaicing algorithm on a 16-bit Bayer image stored in
+/// an ndarray view, with additional options, e.g. source_bits for
+/// 12-bit data stored in 16-bit elements.
+///
+/// # Example
+///
+/// ```
+/// extern crate bayer;
+/// extern crate ndarray;
+///
+/// let img = ndarray::Array2::<u16>::from_elem((4, 4), 0x0FFF);
+/// let mut buf = vec![0; 6 * 4 * 4];
+/// let mut options = bayer::DemosaicOptions::default();
+/// options.source_bits = 12;
+///
+/// bayer::demosaic_array_u16_with_options(img.view(),
+///         bayer::CFA::RGGB,
+///         bayer::Demosaic::Linear,
+///         &options,
+///         &mut bayer::RasterMut::new(4, 4, bayer::RasterDepth::Depth16, &mut buf))
+///         .unwrap();
+/// assert_eq!(&buf[0..2], &[0xFF, 0xFF]);
+/// ```
+pub fn demosaic_array_u16_with_options(src: ArrayView2<u16>,
+        cfa: CFA, alg: Demosaic, options: &DemosaicOptions, dst: &mut RasterMut)
+        -> BayerResult<()> {
+    check_dim(src.dim(), options, dst)?;
+
+    ::demosaic_with_options(&mut ArrayReader::new(src), BayerDepth::native16(),
+            cfa, alg, options, dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor,Read};
+    use ndarray::{Array2,s};
+    use ::{BayerDepth,CFA,Demosaic,DemosaicOptions,RasterDepth,RasterMut,Transform,demosaic_with_options,run_demosaic};
+    use super::{ArrayReader,demosaic_array_u8_with_options,demosaic_array_u16_with_options,run_demosaic_array_u8,run_demosaic_array_u16};
+
+    #[test]
+    fn test_strided_u8() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+
+        const IMG_W: usize = 4;
+        const IMG_H: usize = 4;
+        let mut expected = [0u8; 3 * IMG_W * IMG_H];
+        let mut buf = [0u8; 3 * IMG_W * IMG_H];
+
+        let res = run_demosaic(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::RGGB, Demosaic::Linear,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut expected));
+        assert!(res.is_ok());
+
+        // Store the image transposed, then view it transposed back.
+        let arr = Array2::from_shape_fn((IMG_W, IMG_H), |(x, y)| src[IMG_W * y + x]);
+        let res = run_demosaic_array_u8(arr.t(), CFA::RGGB, Demosaic::Linear,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+        assert_eq!(&buf[..], &expected[..]);
+    }
+
+    #[test]
+    fn test_sliced_u16() {
+        const IMG_W: usize = 4;
+        const IMG_H: usize = 4;
+        let arr = Array2::from_shape_fn((2 * IMG_H, 2 * IMG_W), |(y, x)| (1000 * y + x) as u16);
+        let view = arr.slice(s![..;2, 1..;2]);
+
+        let mut src = Vec::new();
+        for v in view.iter() {
+            src.push((v >> 8) as u8);
+            src.push(*v as u8);
+        }
+
+        let mut expected = [0u8; 6 * IMG_W * IMG_H];
+        let mut buf = [0u8; 6 * IMG_W * IMG_H];
+
+        let res = run_demosaic(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth16BE, CFA::GRBG, Demosaic::Cubic,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth16, &mut expected));
+        assert!(res.is_ok());
+
+        let res = run_demosaic_array_u16(view, CFA::GRBG, Demosaic::Cubic,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth16, &mut buf));
+        assert!(res.is_ok());
+        assert_eq!(&buf[..], &expected[..]);
+    }
+
+    #[test]
+    fn test_source_bits() {
+        const IMG_W: usize = 4;
+        const IMG_H: usize = 4;
+        let arr = Array2::from_shape_fn((IMG_H, IMG_W), |(y, x)| (1000 * y + 300 * x) as u16);
+        let mut options = DemosaicOptions::default();
+        options.source_bits = 12;
+
+        let mut src = Vec::new();
+        for v in arr.iter() {
+            src.extend_from_slice(&v.to_be_bytes());
+        }
+
+        let mut expected = [0u8; 6 * IMG_W * IMG_H];
+        let mut buf = [0u8; 6 * IMG_W * IMG_H];
+
+        let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth16BE, CFA::GRBG, Demosaic::Cubic, &options,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth16, &mut expected));
+        assert!(res.is_ok());
+
+        let res = demosaic_array_u16_with_options(arr.view(), CFA::GRBG, Demosaic::Cubic, &options,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth16, &mut buf));
+        assert!(res.is_ok());
+        assert_eq!(&buf[..], &expected[..]);
+    }
+
+    #[test]
+    fn test_reader() {
+        let arr = Array2::from_shape_fn((3, 10), |(y, x)| (100 * y + x) as u16);
+        let strided = arr.slice(s![.., ..;2]);
+        let contiguous = strided.to_owned();
+        let expected: Vec<u8> = strided.iter().flat_map(|v| v.to_ne_bytes().to_vec()).collect();
+
+        // Contiguous and strided rows, read in odd-sized pieces.
+        for view in [contiguous.view(), strided].iter() {
+            let mut r = ArrayReader::new(view.clone());
+            let mut out = Vec::new();
+            let mut piece = [0u8; 7];
+            loop {
+                let n = r.read(&mut piece).unwrap();
+                if n == 0 {
+                    break;
+                }
+                out.extend_from_slice(&piece[..n]);
+            }
+            assert_eq!(out, expected);
+        }
+    }
+
+    #[test]
+    fn test_transform() {
+        const IMG_W: usize = 6;
+        const IMG_H: usize = 4;
+        let arr = Array2::from_shape_fn((IMG_H, IMG_W), |(y, x)| (37 * y + 11 * x) as u8);
+        let src: Vec<u8> = arr.iter().cloned().collect();
+        let mut options = DemosaicOptions::default();
+        options.transform = Some(Transform::Rotate90);
+
+        let mut expected = [0u8; 3 * IMG_W * IMG_H];
+        let mut buf = [0u8; 3 * IMG_W * IMG_H];
+
+        let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::RGGB, Demosaic::Linear, &options,
+                &mut RasterMut::new(IMG_H, IMG_W, RasterDepth::Depth8, &mut expected));
+        assert!(res.is_ok());
+
+        let res = demosaic_array_u8_with_options(arr.view(), CFA::RGGB, Demosaic::Linear, &options,
+                &mut RasterMut::new(IMG_H, IMG_W, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+        assert_eq!(&buf[..], &expected[..]);
+    }
+
+    #[test]
+    fn test_wrong_shape() {
+        let arr = Array2::<u8>::zeros((4, 6));
+        let mut buf = [0u8; 3 * 6 * 4];
+
+        let res = run_demosaic_array_u8(arr.view(), CFA::RGGB, Demosaic::None,
+                &mut RasterMut::new(4, 6, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_err());
+    }
+}
@@ -1,9 +1,15 @@
 //! Bayer image definitions.
 
 use std::io::Read;
-use byteorder::{BigEndian,LittleEndian,ReadBytesExt};
+use std::marker::PhantomData;
+use byteorder::{BigEndian,ByteOrder,LittleEndian,ReadBytesExt};
 
-use ::BayerResult;
+use ::{BayerError,BayerResult,PackedFormat};
+use errcode::OVERFLOW;
+use raster::RasterSample;
+
+/// The largest number of samples in a packed group.
+const MAX_GROUP: usize = 4;
 
 /// The 2x2 colour filter array (CFA) pattern.
 ///
@@ -23,26 +29,272 @@ pub enum CFA {
 /// Note that many cameras only capture 12-bits per pixel, but still
 /// store the data as 16-bits per pixel.  These should be treated as
 /// 16-bits per pixel for the purposes of this library.
+///
+/// Packed depths store the samples of the whole image as one packed
+/// stream, so the width of the image must be a multiple of the number
+/// of samples in each packed group.
 #[derive(Clone,Copy,Debug,Eq,PartialEq)]
 pub enum BayerDepth {
     Depth8,
     Depth16BE,
     Depth16LE,
+    /// MIPI CSI-2 RAW10, see `PackedFormat::Raw10`.
+    Depth10Packed,
+    /// MIPI CSI-2 RAW12, see `PackedFormat::Raw12`.
+    Depth12Packed,
+    /// 12-bit DNG packing, see `PackedFormat::Dng12`.
+    Depth12PackedDng,
+    /// MIPI CSI-2 RAW14, see `PackedFormat::Raw14`.
+    Depth14Packed,
+}
+
+/// Check that an in-memory Bayer image of len bytes contains exactly
+/// the data for a w x h image of the given depth.
+///
+/// # Example
+///
+/// ```
+/// let img = vec![0; 2 * 320 * 200 + 16];
+///
+/// match bayer::check_input_len(img.len(), bayer::BayerDepth::Depth16LE, 320, 200) {
+///     Err(bayer::BayerError::TrailingData { extra }) => assert_eq!(extra, 16),
+///     _ => unreachable!(),
+/// }
+/// ```
+pub fn check_input_len(len: usize, depth: BayerDepth, w: usize, h: usize)
+        -> BayerResult<()> {
+    // An image that does not fit in memory is always too short.
+    let expected = depth.byte_len(w.saturating_mul(h)).unwrap_or(usize::MAX);
+
+    if len < expected {
+        Err(BayerError::InputTooShort { expected, got: len })
+    } else if len > expected {
+        Err(BayerError::TrailingData { extra: len - expected })
+    } else {
+        Ok(())
+    }
+}
+
+/// Check that an output buffer of len bytes can hold a w x h image of
+/// the given depth.
+pub(crate) fn check_output_len(len: usize, depth: BayerDepth, w: usize, h: usize)
+        -> BayerResult<()> {
+    let expected = depth.byte_len(w.saturating_mul(h)).unwrap_or(usize::MAX);
+
+    if len < expected {
+        Err(BayerError::OutputTooShort { expected, got: len })
+    } else {
+        Ok(())
+    }
+}
+
+/// Read sample i of an in-memory Bayer image.
+pub(crate) fn get_sample(buf: &[u8], depth: BayerDepth, i: usize) -> u32 {
+    match depth {
+        BayerDepth::Depth8 => buf[i] as u32,
+        BayerDepth::Depth16BE => BigEndian::read_u16(&buf[(2 * i)..]) as u32,
+        BayerDepth::Depth16LE => LittleEndian::read_u16(&buf[(2 * i)..]) as u32,
+        BayerDepth::Depth10Packed => get_packed(buf, PackedFormat::Raw10, i),
+        BayerDepth::Depth12Packed => get_packed(buf, PackedFormat::Raw12, i),
+        BayerDepth::Depth12PackedDng => get_packed(buf, PackedFormat::Dng12, i),
+        BayerDepth::Depth14Packed => get_packed(buf, PackedFormat::Raw14, i),
+    }
+}
+
+/// Write sample i of an in-memory Bayer image.
+pub(crate) fn put_sample(buf: &mut [u8], depth: BayerDepth, i: usize, v: u32) {
+    match depth {
+        BayerDepth::Depth8 => buf[i] = v as u8,
+        BayerDepth::Depth16BE => BigEndian::write_u16(&mut buf[(2 * i)..], v as u16),
+        BayerDepth::Depth16LE => LittleEndian::write_u16(&mut buf[(2 * i)..], v as u16),
+        BayerDepth::Depth10Packed => put_packed(buf, PackedFormat::Raw10, i, v),
+        BayerDepth::Depth12Packed => put_packed(buf, PackedFormat::Raw12, i, v),
+        BayerDepth::Depth12PackedDng => put_packed(buf, PackedFormat::Dng12, i, v),
+        BayerDepth::Depth14Packed => put_packed(buf, PackedFormat::Raw14, i, v),
+    }
+}
+
+/// Read sample i of packed data.
+fn get_packed(buf: &[u8], format: PackedFormat, i: usize) -> u32 {
+    let (samples, bytes) = format.group();
+    let mut group = [0u16; MAX_GROUP];
+    format.unpack_group(&buf[(i / samples * bytes)..], &mut group);
+    group[i % samples] as u32
+}
+
+/// Write sample i of packed data, leaving the other samples of its
+/// group unchanged.
+fn put_packed(buf: &mut [u8], format: PackedFormat, i: usize, v: u32) {
+    let (samples, bytes) = format.group();
+    let group_bytes = &mut buf[(i / samples * bytes)..];
+    let mut group = [0u16; MAX_GROUP];
+    format.unpack_group(group_bytes, &mut group);
+    group[i % samples] = v as u16;
+    format.pack_group(&group, group_bytes);
+}
+
+/// Check that the samples of the given depth are not packed, for
+/// functions that address each sample by its byte offset.
+pub(crate) fn check_unpacked(depth: BayerDepth) -> BayerResult<()> {
+    match depth.packed() {
+        Some(_) => Err(BayerError::InvalidArgument {
+            reason: "packed samples must be unpacked first",
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Check that a w pixel wide image of the given depth can be packed.
+pub(crate) fn check_packing(depth: BayerDepth, w: usize) -> BayerResult<()> {
+    match depth.packed() {
+        Some(format) if w % format.group().0 != 0 => Err(BayerError::InvalidArgument {
+            reason: "the width must be a multiple of the packed group size",
+        }),
+        _ => Ok(()),
+    }
 }
 
-/// Trait for reading 8-bpp Bayer lines.
-pub trait BayerRead8 {
-    fn read_line(&self, r: &mut Read, dst: &mut [u8]) -> BayerResult<()>;
+/// Trait for reading Bayer lines of samples of type T.
+pub trait BayerRead<T> {
+    /// Read a line of raw data into dst, including the border pixels
+    /// on either side of the raw data.
+    fn read_line(&self, r: &mut Read, dst: &mut [T]) -> BayerResult<()>;
 }
 
-/// Trait for reading 16-bpp Bayer lines, big-endian or little-endian.
-pub trait BayerRead16 {
-    fn read_line(&self, r: &mut Read, dst: &mut [u16]) -> BayerResult<()>;
+/// The encoding of the raw samples in the byte stream.
+pub trait RawFormat {
+    /// The type of the decoded samples.
+    type Sample: Copy + Default;
+
+    /// Read the exact number of bytes required to fill buf.
+    fn read_exact<R: Read + ?Sized>(r: &mut R, buf: &mut [Self::Sample])
+            -> BayerResult<()>;
+}
+
+/// 8-bit raw samples.
+pub struct Raw8;
+
+/// 16-bit big-endian raw samples.
+pub struct Raw16BE;
+
+/// 16-bit little-endian raw samples.
+pub struct Raw16LE;
+
+/// 8-bit raw samples, decoded into 16-bit samples.
+pub struct Raw8Wide;
+
+/// MIPI CSI-2 RAW10 packed samples, decoded into 16-bit samples.
+pub struct Raw10;
+
+/// MIPI CSI-2 RAW12 packed samples, decoded into 16-bit samples.
+pub struct Raw12;
+
+/// DNG 12-bit packed samples, decoded into 16-bit samples.
+pub struct Dng12;
+
+/// MIPI CSI-2 RAW14 packed samples, decoded into 16-bit samples.
+pub struct Raw14;
+
+impl RawFormat for Raw8 {
+    type Sample = u8;
+
+    fn read_exact<R: Read + ?Sized>(r: &mut R, buf: &mut [u8])
+            -> BayerResult<()> {
+        read_exact_u8(r, buf)
+    }
+}
+
+impl RawFormat for Raw8Wide {
+    type Sample = u16;
+
+    fn read_exact<R: Read + ?Sized>(r: &mut R, buf: &mut [u16])
+            -> BayerResult<()> {
+        for i in 0..buf.len() {
+            buf[i] = r.read_u8()? as u16;
+        }
+        Ok(())
+    }
+}
+
+impl RawFormat for Raw16BE {
+    type Sample = u16;
+
+    fn read_exact<R: Read + ?Sized>(r: &mut R, buf: &mut [u16])
+            -> BayerResult<()> {
+        read_exact_u16be(r, buf)
+    }
+}
+
+impl RawFormat for Raw16LE {
+    type Sample = u16;
+
+    fn read_exact<R: Read + ?Sized>(r: &mut R, buf: &mut [u16])
+            -> BayerResult<()> {
+        read_exact_u16le(r, buf)
+    }
+}
+
+impl RawFormat for Raw10 {
+    type Sample = u16;
+
+    fn read_exact<R: Read + ?Sized>(r: &mut R, buf: &mut [u16])
+            -> BayerResult<()> {
+        read_exact_raw10(r, buf)
+    }
+}
+
+impl RawFormat for Raw12 {
+    type Sample = u16;
+
+    fn read_exact<R: Read + ?Sized>(r: &mut R, buf: &mut [u16])
+            -> BayerResult<()> {
+        read_exact_raw12(r, buf)
+    }
+}
+
+impl RawFormat for Dng12 {
+    type Sample = u16;
+
+    fn read_exact<R: Read + ?Sized>(r: &mut R, buf: &mut [u16])
+            -> BayerResult<()> {
+        read_exact_dng12(r, buf)
+    }
+}
+
+impl RawFormat for Raw14 {
+    type Sample = u16;
+
+    fn read_exact<R: Read + ?Sized>(r: &mut R, buf: &mut [u16])
+            -> BayerResult<()> {
+        read_exact_raw14(r, buf)
+    }
+}
+
+/// Raw samples of format F, decoded into 32-bit samples, leaving
+/// headroom above the largest sample.
+pub(crate) struct Raw32<F>(PhantomData<F>);
+
+impl<F: RawFormat> RawFormat for Raw32<F> where F::Sample: RasterSample {
+    type Sample = u32;
+
+    fn read_exact<R: Read + ?Sized>(r: &mut R, buf: &mut [u32])
+            -> BayerResult<()> {
+        // A multiple of the number of samples in each packed group.
+        let mut samples = [F::Sample::default(); 64];
+        for chunk in buf.chunks_mut(samples.len()) {
+            let samples = &mut samples[0..chunk.len()];
+            F::read_exact(r, samples)?;
+            for (d, &s) in chunk.iter_mut().zip(samples.iter()) {
+                *d = s.to_u32();
+            }
+        }
+        Ok(())
+    }
 }
 
 /// Read the exact number of bytes required to fill buf.
 /// For u8 source data.
-pub fn read_exact_u8(r: &mut Read, buf: &mut [u8])
+pub fn read_exact_u8<R: Read + ?Sized>(r: &mut R, buf: &mut [u8])
         -> BayerResult<()> {
     r.read_exact(buf)?;
     Ok(())
@@ -50,7 +302,7 @@ pub fn read_exact_u8(r: &mut Read, buf: &mut [u8])
 
 /// Read the exact number of bytes required to fill buf.
 /// For u16 big-endian source data.
-pub fn read_exact_u16be(r: &mut Read, buf: &mut [u16])
+pub fn read_exact_u16be<R: Read + ?Sized>(r: &mut R, buf: &mut [u16])
         -> BayerResult<()> {
     for i in 0..buf.len() {
         buf[i] = r.read_u16::<BigEndian>()?;
@@ -60,7 +312,7 @@ pub fn read_exact_u16be(r: &mut Read, buf: &mut [u16])
 
 /// Read the exact number of bytes required to fill buf.
 /// For u16 little-endian source data.
-pub fn read_exact_u16le(r: &mut Read, buf: &mut [u16])
+pub fn read_exact_u16le<R: Read + ?Sized>(r: &mut R, buf: &mut [u16])
         -> BayerResult<()> {
     for i in 0..buf.len() {
         buf[i] = r.read_u16::<LittleEndian>()?;
@@ -68,6 +320,161 @@ pub fn read_exact_u16le(r: &mut Read, buf: &mut [u16])
     Ok(())
 }
 
+/// Read the exact number of bytes required to fill buf.
+/// For packed source data; an incomplete group at the end of buf is
+/// read in full and the extra samples are discarded.
+pub fn read_exact_packed<R: Read + ?Sized>(r: &mut R, format: PackedFormat,
+        buf: &mut [u16])
+        -> BayerResult<()> {
+    let (samples, bytes) = format.group();
+    let mut packed = [0u8; 60 * MAX_GROUP];
+    let mut group = [0u16; MAX_GROUP];
+
+    for chunk in buf.chunks_mut(packed.len() / bytes * samples) {
+        let len = format.packed_len(chunk.len()).ok_or(OVERFLOW)?;
+        r.read_exact(&mut packed[0..len])?;
+
+        for (s, d) in packed[0..len].chunks(bytes).zip(chunk.chunks_mut(samples)) {
+            format.unpack_group(s, &mut group);
+            d.copy_from_slice(&group[0..d.len()]);
+        }
+    }
+    Ok(())
+}
+
+/// Read the exact number of bytes required to fill buf.
+/// For MIPI RAW10 packed source data.
+pub fn read_exact_raw10<R: Read + ?Sized>(r: &mut R, buf: &mut [u16])
+        -> BayerResult<()> {
+    read_exact_packed(r, PackedFormat::Raw10, buf)
+}
+
+/// Read the exact number of bytes required to fill buf.
+/// For MIPI RAW12 packed source data.
+pub fn read_exact_raw12<R: Read + ?Sized>(r: &mut R, buf: &mut [u16])
+        -> BayerResult<()> {
+    read_exact_packed(r, PackedFormat::Raw12, buf)
+}
+
+/// Read the exact number of bytes required to fill buf.
+/// For MIPI RAW14 packed source data.
+pub fn read_exact_raw14<R: Read + ?Sized>(r: &mut R, buf: &mut [u16])
+        -> BayerResult<()> {
+    read_exact_packed(r, PackedFormat::Raw14, buf)
+}
+
+/// Read the exact number of bytes required to fill buf.
+/// For DNG 12-bit packed source data.
+pub fn read_exact_dng12<R: Read + ?Sized>(r: &mut R, buf: &mut [u16])
+        -> BayerResult<()> {
+    read_exact_packed(r, PackedFormat::Dng12, buf)
+}
+
+impl BayerDepth {
+    /// The depth of samples with the given number of significant bits,
+    /// each stored in the smallest whole number of bytes.  The byte
+    /// order only matters for more than 8 bits.
+    ///
+    /// Packed 10-bit, 12-bit and 14-bit samples use the MIPI CSI-2
+    /// layouts;
+    /// use `Depth12PackedDng` directly for DNG packing.  Other packed
+    /// samples, apart from 8-bit and 16-bit samples which need
+    /// no packing, have no depth and are rejected; unpack them first,
+    /// see `PackedFormat`.  Samples of 9 to 16 bits should be scaled
+    /// with `DemosaicOptions::source_bits`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let depth = bayer::BayerDepth::from_bits(12, true, false).unwrap();
+    /// assert_eq!(depth, bayer::BayerDepth::Depth16LE);
+    ///
+    /// let depth = bayer::BayerDepth::from_bits(10, true, true).unwrap();
+    /// assert_eq!(depth, bayer::BayerDepth::Depth10Packed);
+    ///
+    /// assert!(bayer::BayerDepth::from_bits(11, true, true).is_err());
+    /// ```
+    pub fn from_bits(bits: u8, little_endian: bool, packed: bool)
+            -> BayerResult<BayerDepth> {
+        if packed && bits == 10 {
+            return Ok(BayerDepth::Depth10Packed);
+        } else if packed && bits == 12 {
+            return Ok(BayerDepth::Depth12Packed);
+        } else if packed && bits == 14 {
+            return Ok(BayerDepth::Depth14Packed);
+        } else if packed && bits != 8 && bits != 16 {
+            return Err(BayerError::InvalidArgument {
+                reason: "packed samples must be unpacked first",
+            });
+        }
+
+        match bits {
+            1..=8 => Ok(BayerDepth::Depth8),
+            9..=16 if little_endian => Ok(BayerDepth::Depth16LE),
+            9..=16 => Ok(BayerDepth::Depth16BE),
+            _ => Err(BayerError::InvalidArgument {
+                reason: "samples must have between 1 and 16 bits",
+            }),
+        }
+    }
+
+    /// The depth of 16-bit samples in host byte order, e.g. for images
+    /// that are already held in a `&[u16]`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let depth = bayer::BayerDepth::native16();
+    /// assert_eq!(depth.byte_len(4), Some(8));
+    /// ```
+    pub fn native16() -> BayerDepth {
+        if cfg!(target_endian = "big") {
+            BayerDepth::Depth16BE
+        } else {
+            BayerDepth::Depth16LE
+        }
+    }
+
+    /// The number of bytes used to store each raw sample.  Packed
+    /// samples are unpacked into 16-bit samples, so use `byte_len` for
+    /// the size of the raw data.
+    pub fn bytes_per_sample(self) -> usize {
+        match self {
+            BayerDepth::Depth8 => 1,
+            BayerDepth::Depth16BE | BayerDepth::Depth16LE
+                | BayerDepth::Depth10Packed | BayerDepth::Depth12Packed
+                | BayerDepth::Depth12PackedDng | BayerDepth::Depth14Packed => 2,
+        }
+    }
+
+    /// The number of bytes used to store count raw samples, or None on
+    /// overflow.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// assert_eq!(bayer::BayerDepth::Depth16LE.byte_len(8), Some(16));
+    /// assert_eq!(bayer::BayerDepth::Depth10Packed.byte_len(8), Some(10));
+    /// ```
+    pub fn byte_len(self, count: usize) -> Option<usize> {
+        match self.packed() {
+            Some(format) => format.packed_len(count),
+            None => count.checked_mul(self.bytes_per_sample()),
+        }
+    }
+
+    /// The packing of the raw samples, if any.
+    pub(crate) fn packed(self) -> Option<PackedFormat> {
+        match self {
+            BayerDepth::Depth10Packed => Some(PackedFormat::Raw10),
+            BayerDepth::Depth12Packed => Some(PackedFormat::Raw12),
+            BayerDepth::Depth12PackedDng => Some(PackedFormat::Dng12),
+            BayerDepth::Depth14Packed => Some(PackedFormat::Raw14),
+            _ => None,
+        }
+    }
+}
+
 impl CFA {
     /// The 2x2 pixel block obtained when moving right 1 column.
     pub fn next_x(self) -> Self {
@@ -88,4 +495,130 @@ impl CFA {
             CFA::RGGB => CFA::GBRG,
         }
     }
+
+    /// The 2x2 pixel block starting at pixel (x, y).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// assert_eq!(bayer::CFA::RGGB.at(1, 0), bayer::CFA::GRBG);
+    /// assert_eq!(bayer::CFA::RGGB.at(3, 5), bayer::CFA::BGGR);
+    /// ```
+    pub fn at(self, x: usize, y: usize) -> Self {
+        let cfa = if x % 2 == 0 { self } else { self.next_x() };
+        if y % 2 == 0 { cfa } else { cfa.next_y() }
+    }
+
+    /// The pattern of an image cropped from an image with this
+    /// pattern, with the top-left pixel at (x, y).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// assert_eq!(bayer::CFA::RGGB.with_offset(1, 0), bayer::CFA::GRBG);
+    /// assert_eq!(bayer::CFA::RGGB.with_offset(4, 3), bayer::CFA::GBRG);
+    /// ```
+    pub fn with_offset(self, x: usize, y: usize) -> Self {
+        self.at(x, y)
+    }
+
+    /// The channel (0 = red, 1 = green, 2 = blue) of the top-left
+    /// pixel of the 2x2 block.
+    pub(crate) fn channel(self) -> usize {
+        match self {
+            CFA::BGGR => 2,
+            CFA::GBRG | CFA::GRBG => 1,
+            CFA::RGGB => 0,
+        }
+    }
+
+    /// The 2x2 block with the given top-left and top-right channels.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the channels do not form a Bayer pattern.  This is an
+    /// internal invariant: the channels of two horizontally adjacent
+    /// pixels of any Bayer pattern always form one.
+    pub(crate) fn from_channels(c0: usize, c1: usize) -> Self {
+        match (c0, c1) {
+            (2, 1) => CFA::BGGR,
+            (1, 2) => CFA::GBRG,
+            (1, 0) => CFA::GRBG,
+            (0, 1) => CFA::RGGB,
+            _ => panic!("invalid cfa"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{BayerDepth,BayerError,PackedFormat,pack};
+    use super::{check_input_len,get_sample,put_sample,read_exact_packed};
+
+    #[test]
+    fn test_from_bits() {
+        assert_eq!(BayerDepth::from_bits(8, false, false).ok(), Some(BayerDepth::Depth8));
+        assert_eq!(BayerDepth::from_bits(8, true, true).ok(), Some(BayerDepth::Depth8));
+        assert_eq!(BayerDepth::from_bits(10, false, false).ok(), Some(BayerDepth::Depth16BE));
+        assert_eq!(BayerDepth::from_bits(14, true, false).ok(), Some(BayerDepth::Depth16LE));
+        assert_eq!(BayerDepth::from_bits(16, true, true).ok(), Some(BayerDepth::Depth16LE));
+
+        assert_eq!(BayerDepth::from_bits(10, false, true).ok(), Some(BayerDepth::Depth10Packed));
+
+        assert_eq!(BayerDepth::from_bits(12, true, true).ok(), Some(BayerDepth::Depth12Packed));
+
+        assert_eq!(BayerDepth::from_bits(14, true, true).ok(), Some(BayerDepth::Depth14Packed));
+
+        for &(bits, packed) in [(0, false), (17, false), (11, true)].iter() {
+            match BayerDepth::from_bits(bits, true, packed) {
+                Err(BayerError::InvalidArgument { .. }) => (),
+                _ => panic!(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_check_input_len() {
+        assert!(check_input_len(12, BayerDepth::Depth8, 4, 3).is_ok());
+        assert!(check_input_len(24, BayerDepth::Depth16BE, 4, 3).is_ok());
+
+        match check_input_len(20, BayerDepth::Depth16LE, 4, 3) {
+            Err(BayerError::InputTooShort { expected: 24, got: 20 }) => (),
+            _ => panic!(),
+        }
+
+        match check_input_len(13, BayerDepth::Depth8, 4, 3) {
+            Err(BayerError::TrailingData { extra: 1 }) => (),
+            _ => panic!(),
+        }
+
+        match check_input_len(0, BayerDepth::Depth8, ::std::usize::MAX, 2) {
+            Err(BayerError::InputTooShort { expected: ::std::usize::MAX, got: 0 }) => (),
+            _ => panic!(),
+        }
+
+        assert!(check_input_len(15, BayerDepth::Depth10Packed, 4, 3).is_ok());
+    }
+
+    #[test]
+    fn test_packed_samples() {
+        let src: Vec<u16> = (0..7).map(|i| i * 150).collect();
+        let mut packed = pack(&src, PackedFormat::Raw10);
+
+        // A short read discards the padding of the last group.
+        let mut buf = [0u16; 7];
+        assert!(read_exact_packed(&mut &packed[..], PackedFormat::Raw10, &mut buf).is_ok());
+        assert_eq!(&buf[..], &src[..]);
+        assert!(read_exact_packed(&mut &packed[1..], PackedFormat::Raw10, &mut buf).is_err());
+
+        put_sample(&mut packed, BayerDepth::Depth10Packed, 5, 1023);
+        assert_eq!(get_sample(&packed, BayerDepth::Depth10Packed, 4), 600);
+        assert_eq!(get_sample(&packed, BayerDepth::Depth10Packed, 5), 1023);
+        assert_eq!(get_sample(&packed, BayerDepth::Depth10Packed, 6), 900);
+
+        let mut packed = pack(&[0xABC, 0x123], PackedFormat::Dng12);
+        put_sample(&mut packed, BayerDepth::Depth12PackedDng, 0, 0x456);
+        assert_eq!(packed, [0x45, 0x61, 0x23]);
+        assert_eq!(get_sample(&packed, BayerDepth::Depth12Packed, 1), 0x612);
+    }
 }
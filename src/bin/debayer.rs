@@ -0,0 +1,231 @@
+//! Debayer - convert raw Bayer files to PPM or PNG images.
+
+extern crate bayer;
+
+use std::env;
+use std::fs::File;
+use std::io::{BufReader,BufWriter,Write};
+use std::process;
+use bayer::*;
+
+/// The settings given on the command line.
+struct Args {
+    width: usize,
+    height: usize,
+    depth: BayerDepth,
+    cfa: CFA,
+    alg: Demosaic,
+    options: DemosaicOptions,
+    input: String,
+    output: String,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let args = match parse_args(&args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("debayer: {}", e);
+            eprintln!();
+            usage();
+            process::exit(2);
+        },
+    };
+
+    if let Err(e) = convert(&args) {
+        eprintln!("debayer: {}", e);
+        process::exit(1);
+    }
+}
+
+fn usage() {
+    eprintln!("usage: debayer [options] <width> <height> <depth> <input.raw> <output>");
+    eprintln!();
+    eprintln!("  depth                 8, 16BE, 16LE, 10P, 12P, 12DNG, 14P");
+    eprintln!("  output                PPM file, or PNG file if built with the image feature");
+    eprintln!();
+    eprintln!("  -c, --cfa <cfa>       BGGR, GBRG, GRBG, RGGB (default RGGB)");
+    eprintln!("  -a, --alg <alg>       none, nearest, linear, median, malvar, cubic, ppg");
+    eprintln!("                        (default linear)");
+    eprintln!("  -b, --border <mode>   none, replicate, mirror (default per algorithm)");
+    eprintln!("  -r, --rotate <deg>    90, 180, 270");
+    eprintln!("  --bits <n>            Significant bits of 16-bit samples (default 16)");
+    eprintln!("  --wb <preset>         daylight, cloudy, tungsten, flash");
+    eprintln!("  --fallback            Use a cheaper algorithm for small images");
+    eprintln!("  --tolerant            Fill in the rows of truncated files");
+}
+
+fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut cfa = CFA::RGGB;
+    let mut alg = Demosaic::Linear;
+    let mut options = DemosaicOptions::default();
+    let mut positional = Vec::new();
+
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        let value = || args.get(i + 1).ok_or(format!("missing value for {}", arg));
+
+        match arg {
+            "-c" | "--cfa" => { cfa = parse_cfa(value()?)?; i = i + 1; },
+            "-a" | "--alg" => { alg = parse_alg(value()?)?; i = i + 1; },
+            "-b" | "--border" => { options.border = Some(parse_border(value()?)?); i = i + 1; },
+            "-r" | "--rotate" => { options.transform = Some(parse_rotation(value()?)?); i = i + 1; },
+            "--bits" => { options.source_bits = parse_bits(value()?)?; i = i + 1; },
+            "--wb" => { options.white_balance = Some(parse_wb(value()?)?); i = i + 1; },
+            "--fallback" => { options.fallback = true; },
+            "--tolerant" => { options.truncation = Truncation::Replicate; },
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                return Err(format!("unknown option {}", arg));
+            },
+            _ => positional.push(arg),
+        }
+
+        i = i + 1;
+    }
+
+    if positional.len() != 5 {
+        return Err("wrong number of arguments".to_string());
+    }
+
+    Ok(Args {
+        width: parse_dim(positional[0])?,
+        height: parse_dim(positional[1])?,
+        depth: parse_depth(positional[2])?,
+        cfa, alg, options,
+        input: positional[3].to_string(),
+        output: positional[4].to_string(),
+    })
+}
+
+fn parse_dim(s: &str) -> Result<usize, String> {
+    s.parse::<usize>().map_err(|_| format!("invalid dimension {}", s))
+}
+
+fn parse_bits(s: &str) -> Result<u8, String> {
+    match s.parse::<u8>() {
+        Ok(b) if b >= 1 && b <= 16 => Ok(b),
+        _ => Err(format!("invalid bits {}", s)),
+    }
+}
+
+fn parse_wb(s: &str) -> Result<WhiteBalance, String> {
+    WhiteBalancePreset::from_name(s)
+        .map(WhiteBalance::from)
+        .ok_or_else(|| format!("invalid white balance {}", s))
+}
+
+fn parse_depth(s: &str) -> Result<BayerDepth, String> {
+    match s.to_uppercase().as_str() {
+        "8" => Ok(BayerDepth::Depth8),
+        "16BE" => Ok(BayerDepth::Depth16BE),
+        "16LE" => Ok(BayerDepth::Depth16LE),
+        "10P" => Ok(BayerDepth::Depth10Packed),
+        "12P" => Ok(BayerDepth::Depth12Packed),
+        "12DNG" => Ok(BayerDepth::Depth12PackedDng),
+        "14P" => Ok(BayerDepth::Depth14Packed),
+        _ => Err(format!("invalid depth {}", s)),
+    }
+}
+
+fn parse_cfa(s: &str) -> Result<CFA, String> {
+    match s.to_uppercase().as_str() {
+        "BGGR" => Ok(CFA::BGGR),
+        "GBRG" => Ok(CFA::GBRG),
+        "GRBG" => Ok(CFA::GRBG),
+        "RGGB" => Ok(CFA::RGGB),
+        _ => Err(format!("invalid CFA {}", s)),
+    }
+}
+
+fn parse_alg(s: &str) -> Result<Demosaic, String> {
+    match s.to_lowercase().as_str() {
+        "none" => Ok(Demosaic::None),
+        "nearest" | "nearestneighbour" => Ok(Demosaic::NearestNeighbour),
+        "linear" => Ok(Demosaic::Linear),
+        "median" | "linearmedian" => Ok(Demosaic::LinearMedian),
+        "malvar" | "malvarhecutler" => Ok(Demosaic::MalvarHeCutler),
+        "cubic" => Ok(Demosaic::Cubic),
+        "ppg" => Ok(Demosaic::PPG),
+        _ => Err(format!("invalid algorithm {}", s)),
+    }
+}
+
+fn parse_border(s: &str) -> Result<Border, String> {
+    match s.to_lowercase().as_str() {
+        "none" => Ok(Border::None),
+        "replicate" => Ok(Border::Replicate),
+        "mirror" => Ok(Border::Mirror),
+        _ => Err(format!("invalid border {}", s)),
+    }
+}
+
+fn parse_rotation(s: &str) -> Result<Transform, String> {
+    match s {
+        "90" => Ok(Transform::Rotate90),
+        "180" => Ok(Transform::Rotate180),
+        "270" => Ok(Transform::Rotate270),
+        _ => Err(format!("invalid rotation {}", s)),
+    }
+}
+
+fn convert(args: &Args) -> Result<(), String> {
+    let raster_depth = match args.depth {
+        BayerDepth::Depth8 => RasterDepth::Depth8,
+        BayerDepth::Depth16BE | BayerDepth::Depth16LE
+            | BayerDepth::Depth10Packed | BayerDepth::Depth12Packed
+            | BayerDepth::Depth12PackedDng | BayerDepth::Depth14Packed => RasterDepth::Depth16,
+    };
+    let bytes_per_pixel = raster_depth.bytes_per_pixel();
+
+    let (w, h) = match args.options.transform {
+        Some(t) => t.dimensions(args.width, args.height),
+        None => (args.width, args.height),
+    };
+
+    let file = File::open(&args.input)
+        .map_err(|e| format!("{}: {}", args.input, e))?;
+
+    let demosaicer = Demosaicer::new()
+        .depth(args.depth)
+        .cfa(args.cfa)
+        .algorithm(args.alg)
+        .options(args.options.clone())
+        .build();
+
+    let mut buf = vec![0; bytes_per_pixel * w * h];
+    let mut dst = RasterMut::new(w, h, raster_depth, &mut buf);
+    match demosaicer.run_from(&mut BufReader::new(file), &mut dst) {
+        Ok(()) => (),
+        Err(e @ BayerError::Truncated { .. }) => eprintln!("debayer: {}: {}", args.input, e),
+        Err(e) => return Err(format!("{}: {}", args.input, e)),
+    }
+
+    let out = File::create(&args.output)
+        .map_err(|e| format!("{}: {}", args.output, e))?;
+    let mut out = BufWriter::new(out);
+    write_image(&args.output, &dst.as_raster(), &mut out)
+        .and_then(|_| out.flush().map_err(BayerError::from))
+        .map_err(|e| format!("{}: {}", args.output, e))
+}
+
+#[cfg(feature = "image")]
+fn write_image<W: Write>(path: &str, src: &Raster, w: &mut W) -> BayerResult<()> {
+    if path.to_lowercase().ends_with(".png") {
+        write_png(src, w)
+    } else {
+        write_ppm(src, w)
+    }
+}
+
+#[cfg(not(feature = "image"))]
+fn write_image<W: Write>(path: &str, src: &Raster, w: &mut W) -> BayerResult<()> {
+    if path.to_lowercase().ends_with(".png") {
+        let e = std::io::Error::new(std::io::ErrorKind::Other,
+                "PNG output requires the image feature");
+        Err(e.into())
+    } else {
+        write_ppm(src, w)
+    }
+}
@@ -0,0 +1,144 @@
+//! RawConv - convert raw Bayer dumps between sample formats.
+
+extern crate bayer;
+
+use std::env;
+use std::fs;
+use std::process;
+use bayer::*;
+
+/// A raw sample format.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+enum Format {
+    Depth8,
+    Depth16BE,
+    Depth16LE,
+    Packed(PackedFormat),
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let mut bits = 16;
+    let mut positional = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--bits" => {
+                bits = match args.get(i + 1).and_then(|s| s.parse::<u32>().ok()) {
+                    Some(b) if b >= 1 && b <= 16 => b,
+                    _ => fail_usage("invalid value for --bits"),
+                };
+                i = i + 1;
+            },
+            arg if arg.starts_with('-') => fail_usage(&format!("unknown option {}", arg)),
+            arg => positional.push(arg.to_string()),
+        }
+        i = i + 1;
+    }
+
+    if positional.len() != 4 {
+        fail_usage("wrong number of arguments");
+    }
+
+    let from = parse_format(&positional[0]).unwrap_or_else(|e| fail_usage(&e));
+    let to = parse_format(&positional[1]).unwrap_or_else(|e| fail_usage(&e));
+
+    let src = fs::read(&positional[2]).unwrap_or_else(|e| fail(&format!("{}: {}", positional[2], e)));
+    let samples = decode(&src, from).unwrap_or_else(|e| fail(&format!("{}: {}", positional[2], e)));
+    let samples = rescale(samples, format_bits(from, bits), format_bits(to, bits));
+
+    let dst = encode(&samples, to);
+    fs::write(&positional[3], &dst).unwrap_or_else(|e| fail(&format!("{}: {}", positional[3], e)));
+}
+
+fn usage() {
+    eprintln!("usage: rawconv [options] <from> <to> <input.raw> <output.raw>");
+    eprintln!();
+    eprintln!("  from, to        8, 16BE, 16LE, RAW10, RAW12, RAW14, DNG12");
+    eprintln!();
+    eprintln!("  --bits <n>      Significant bits of the 16-bit samples (default 16)");
+    eprintln!();
+    eprintln!("Samples are shifted to convert between bit depths, e.g. from");
+    eprintln!("RAW10 to 16-bit with --bits 12, each sample is multiplied by 4.");
+}
+
+fn fail_usage(msg: &str) -> ! {
+    eprintln!("rawconv: {}", msg);
+    eprintln!();
+    usage();
+    process::exit(2);
+}
+
+fn fail(msg: &str) -> ! {
+    eprintln!("rawconv: {}", msg);
+    process::exit(1);
+}
+
+fn parse_format(s: &str) -> Result<Format, String> {
+    match s.to_uppercase().as_str() {
+        "8" => Ok(Format::Depth8),
+        "16BE" => Ok(Format::Depth16BE),
+        "16LE" => Ok(Format::Depth16LE),
+        "RAW10" => Ok(Format::Packed(PackedFormat::Raw10)),
+        "RAW12" => Ok(Format::Packed(PackedFormat::Raw12)),
+        "RAW14" => Ok(Format::Packed(PackedFormat::Raw14)),
+        "DNG12" => Ok(Format::Packed(PackedFormat::Dng12)),
+        _ => Err(format!("invalid format {}", s)),
+    }
+}
+
+/// The number of significant bits in the samples of the format.
+fn format_bits(format: Format, bits16: u32) -> u32 {
+    match format {
+        Format::Depth8 => 8,
+        Format::Depth16BE | Format::Depth16LE => bits16,
+        Format::Packed(p) => p.bits(),
+    }
+}
+
+fn decode(src: &[u8], format: Format) -> BayerResult<Vec<u16>> {
+    match format {
+        Format::Depth8 => Ok(src.iter().map(|&v| v as u16).collect()),
+        Format::Depth16BE | Format::Depth16LE => {
+            if src.len() % 2 != 0 {
+                return Err(BayerError::TrailingData { extra: 1 });
+            }
+
+            Ok(src.chunks(2).map(|s| {
+                if format == Format::Depth16BE {
+                    u16::from_be_bytes([s[0], s[1]])
+                } else {
+                    u16::from_le_bytes([s[0], s[1]])
+                }
+            }).collect())
+        },
+        Format::Packed(p) => {
+            // Any padding in the last group is decoded as samples.
+            let group_bytes = p.packed_len(1).unwrap();
+            let group_samples = 8 * group_bytes / p.bits() as usize;
+            if src.len() % group_bytes != 0 {
+                return Err(BayerError::TrailingData { extra: src.len() % group_bytes });
+            }
+
+            unpack(src, p, src.len() / group_bytes * group_samples)
+        },
+    }
+}
+
+fn rescale(samples: Vec<u16>, from_bits: u32, to_bits: u32) -> Vec<u16> {
+    if from_bits > to_bits {
+        samples.into_iter().map(|v| v >> (from_bits - to_bits)).collect()
+    } else {
+        samples.into_iter().map(|v| v << (to_bits - from_bits)).collect()
+    }
+}
+
+fn encode(samples: &[u16], format: Format) -> Vec<u8> {
+    match format {
+        Format::Depth8 => samples.iter().map(|&v| v as u8).collect(),
+        Format::Depth16BE => samples.iter().flat_map(|v| v.to_be_bytes().to_vec()).collect(),
+        Format::Depth16LE => samples.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect(),
+        Format::Packed(p) => pack(samples, p),
+    }
+}
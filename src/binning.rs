@@ -0,0 +1,99 @@
+//! Binning Bayer images.
+
+use ::{BayerDepth,BayerResult};
+use bayer::{check_input_len,check_output_len,get_sample,put_sample};
+
+/// Halve the resolution of a w x h Bayer image by averaging each 2x2
+/// neighbourhood of same-coloured pixels, like hardware binning.
+///
+/// The binned image has the same depth and CFA pattern as the source.
+/// Returns the dimensions of the binned image, (w / 2, h / 2).
+///
+/// # Example
+///
+/// ```
+/// let src = [
+///     10,20,30,40,
+///     50,60,70,80,
+///     10,20,30,40,
+///     50,60,70,80 ];
+/// let mut dst = [0u8; 4];
+///
+/// let (w, h) = bayer::bin2x2(&src, bayer::BayerDepth::Depth8, 4, 4,
+///         &mut dst).unwrap();
+/// assert_eq!((w, h), (2, 2));
+/// assert_eq!(dst, [20,30, 60,70]);
+/// ```
+pub fn bin2x2(src: &[u8], depth: BayerDepth, w: usize, h: usize,
+        dst: &mut [u8])
+        -> BayerResult<(usize, usize)> {
+    check_input_len(src.len(), depth, w, h)?;
+
+    let (dst_w, dst_h) = (w / 2, h / 2);
+    check_output_len(dst.len(), depth, dst_w, dst_h)?;
+
+    for y in 0..dst_h {
+        let sy = 4 * (y / 2) + y % 2;
+
+        for x in 0..dst_w {
+            let sx = 4 * (x / 2) + x % 2;
+            let mut sum = 0;
+            let mut n = 0;
+
+            // Near the right and bottom edges, some of the pixels may
+            // lie beyond the image.
+            for &(dx, dy) in [(0, 0), (2, 0), (0, 2), (2, 2)].iter() {
+                if sx + dx < w && sy + dy < h {
+                    sum = sum + get_sample(src, depth, w * (sy + dy) + sx + dx);
+                    n = n + 1;
+                }
+            }
+
+            put_sample(dst, depth, dst_w * y + x, (sum + n / 2) / n);
+        }
+    }
+
+    Ok((dst_w, dst_h))
+}
+
+#[cfg(test)]
+mod tests {
+    use ::BayerDepth;
+    use super::bin2x2;
+
+    #[test]
+    fn test_even() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+
+        let expected = [
+            127,107,
+            151,166 ];
+
+        let mut dst = [0u8; 4];
+        let res = bin2x2(&src, BayerDepth::Depth8, 4, 4, &mut dst);
+        assert_eq!(res.unwrap(), (2, 2));
+        assert_eq!(dst, expected);
+    }
+
+    #[test]
+    fn test_odd_u16() {
+        let src = [
+            0x01,0x00, 0x02,0x00, 0x03,0x00,
+            0x04,0x00, 0x05,0x00, 0x06,0x00,
+            0x07,0x00, 0x08,0x00, 0xFF,0xFF ];
+
+        // (1 + 3 + 7 + 65535) / 4, rounded.
+        let expected = [
+            0x03,0x40 ];
+
+        let mut dst = [0u8; 2];
+        let res = bin2x2(&src, BayerDepth::Depth16LE, 3, 3, &mut dst);
+        assert_eq!(res.unwrap(), (1, 1));
+        assert_eq!(dst, expected);
+    }
+}
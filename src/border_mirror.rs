@@ -9,21 +9,44 @@
 //! ```
 
 use std::io::Read;
+use std::marker::PhantomData;
 
-use ::BayerResult;
+use ::{BayerError,BayerResult};
 use bayer::*;
+use errcode::OVERFLOW;
 
-/// Tuple structs (x1, x2, x3) designating the different sub-regions
-/// of the output lines.
+// Tuple struct (x1, x2, x3) designating the different sub-regions
+// of the output lines.
+//
+//    0 .. x1 => left border
+//   x1 .. x2 => raw data
+//   x2 .. x3 => right border
+
+/// Reader for Bayer rows that mirrors the pixels on the border.
 ///
-/// ```text
-///    0 .. x1 => left border
-///   x1 .. x2 => raw data
-///   x2 .. x3 => right border
-/// ```
-pub struct BorderMirror8(usize, usize, usize);
-pub struct BorderMirror16BE(usize, usize, usize);
-pub struct BorderMirror16LE(usize, usize, usize);
+/// The type parameter is the raw format of the samples, e.g. Raw8.
+pub struct BorderMirror<F>(usize, usize, usize, PhantomData<F>);
+
+/// Reader for 8-bit Bayer rows that mirrors the pixels on the border.
+pub type BorderMirror8 = BorderMirror<Raw8>;
+
+/// Reader for 16-bit big-endian Bayer rows that mirrors the pixels on the border.
+pub type BorderMirror16BE = BorderMirror<Raw16BE>;
+
+/// Reader for 16-bit little-endian Bayer rows that mirrors the pixels on the border.
+pub type BorderMirror16LE = BorderMirror<Raw16LE>;
+
+/// Reader for MIPI RAW10 packed Bayer rows that mirrors the pixels on the border.
+pub type BorderMirror10Packed = BorderMirror<Raw10>;
+
+/// Reader for MIPI RAW12 packed Bayer rows that mirrors the pixels on the border.
+pub type BorderMirror12Packed = BorderMirror<Raw12>;
+
+/// Reader for DNG 12-bit packed Bayer rows that mirrors the pixels on the border.
+pub type BorderMirror12PackedDng = BorderMirror<Dng12>;
+
+/// Reader for MIPI RAW14 packed Bayer rows that mirrors the pixels on the border.
+pub type BorderMirror14Packed = BorderMirror<Raw14>;
 
 macro_rules! fill_row {
     ($dst:ident, $x1:expr, $x2:expr, $x3:expr) => {{
@@ -50,74 +73,71 @@ macro_rules! fill_row {
     }}
 }
 
-impl BorderMirror8 {
+impl<F> BorderMirror<F> {
+    /// Create a reader for rows of width raw pixels, producing rows
+    /// with padding pixels on either side.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the width is not greater than the padding, or if the
+    /// rows overflow the address space.  See `try_new`.
     pub fn new(width: usize, padding: usize) -> Self {
-        let x1 = padding;
-        let x2 = x1.checked_add(width).expect("overflow");
-        let x3 = x2.checked_add(padding).expect("overflow");
-        assert!(width > padding);
-
-        BorderMirror8(x1, x2, x3)
+        match Self::try_new(width, padding) {
+            Ok(rdr) => rdr,
+            Err(e) => panic!("{}", e),
+        }
     }
-}
 
-impl BayerRead8 for BorderMirror8 {
-    fn read_line(&self, r: &mut Read, dst: &mut [u8])
-            -> BayerResult<()> {
-        let BorderMirror8(x1, x2, x3) = *self;
-        read_exact_u8(r, &mut dst[x1..x2])?;
-        fill_row!(dst, x1, x2, x3);
-        Ok(())
-    }
-}
+    /// Create a reader for rows of width raw pixels, producing rows
+    /// with padding pixels on either side, returning an error instead
+    /// of panicking if the width is too small.
+    pub fn try_new(width: usize, padding: usize) -> BayerResult<Self> {
+        if width <= padding {
+            return Err(BayerError::InvalidArgument { reason: "the width must be greater than the padding" });
+        }
 
-impl BorderMirror16BE {
-    pub fn new(width: usize, padding: usize) -> Self {
         let x1 = padding;
-        let x2 = x1.checked_add(width).expect("overflow");
-        let x3 = x2.checked_add(padding).expect("overflow");
-        assert!(width > padding);
-
-        BorderMirror16BE(x1, x2, x3)
+        let x2 = x1.checked_add(width).ok_or(OVERFLOW)?;
+        let x3 = x2.checked_add(padding).ok_or(OVERFLOW)?;
+        Ok(BorderMirror(x1, x2, x3, PhantomData))
     }
 }
 
-impl BayerRead16 for BorderMirror16BE {
-    fn read_line(&self, r: &mut Read, dst: &mut [u16])
+impl<F: RawFormat> BorderMirror<F> {
+    /// Read a line from any reader, without dynamic dispatch.
+    pub(crate) fn read_line_from<R: Read + ?Sized>(&self,
+            r: &mut R, dst: &mut [F::Sample])
             -> BayerResult<()> {
-        let BorderMirror16BE(x1, x2, x3) = *self;
-        read_exact_u16be(r, &mut dst[x1..x2])?;
-        fill_row!(dst, x1, x2, x3);
-        Ok(())
+        self.read_line_with(r, dst, |_| ())
     }
-}
 
-impl BorderMirror16LE {
-    pub fn new(width: usize, padding: usize) -> Self {
-        let x1 = padding;
-        let x2 = x1.checked_add(width).expect("overflow");
-        let x3 = x2.checked_add(padding).expect("overflow");
-        assert!(width > padding);
-
-        BorderMirror16LE(x1, x2, x3)
+    /// Read a line, passing the raw samples to f before the border
+    /// is filled in.
+    pub(crate) fn read_line_with<R, G>(&self,
+            r: &mut R, dst: &mut [F::Sample], f: G)
+            -> BayerResult<()>
+            where R: Read + ?Sized, G: FnOnce(&mut [F::Sample]) {
+        let BorderMirror(x1, x2, x3, _) = *self;
+        F::read_exact(r, &mut dst[x1..x2])?;
+        f(&mut dst[x1..x2]);
+        fill_row!(dst, x1, x2, x3);
+        Ok(())
     }
 }
 
-impl BayerRead16 for BorderMirror16LE {
-    fn read_line(&self, r: &mut Read, dst: &mut [u16])
+impl<F: RawFormat> BayerRead<F::Sample> for BorderMirror<F> {
+    fn read_line(&self, r: &mut Read, dst: &mut [F::Sample])
             -> BayerResult<()> {
-        let BorderMirror16LE(x1, x2, x3) = *self;
-        read_exact_u16le(r, &mut dst[x1..x2])?;
-        fill_row!(dst, x1, x2, x3);
-        Ok(())
+        self.read_line_from(r, dst)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::io::Cursor;
-    use bayer::BayerRead8;
-    use super::BorderMirror8;
+    use bayer::BayerRead;
+    use ::BayerError;
+    use super::{BorderMirror8,BorderMirror16LE};
 
     #[test]
     fn test_mirror_even() {
@@ -154,4 +174,33 @@ mod tests {
         assert!(res.is_ok());
         assert_eq!(&buf[..], &expected[..]);
     }
+
+    #[test]
+    fn test_mirror_u16le() {
+        let src = [
+            1,0, 2,0, 3,1, 4,1, 5,2, ];
+
+        let expected = [
+            0x104, 0x103,0x002,
+            /*-------------*/ 0x001,0x002, 0x103,0x104, 0x205,
+            /*------------------------------------*/ 0x104, 0x103,0x002 ];
+
+        let rdr = BorderMirror16LE::new(5, 3);
+        let mut buf = [0u16; 3 + 5 + 3];
+
+        let res = rdr.read_line(&mut Cursor::new(&src[..]), &mut buf);
+        assert!(res.is_ok());
+        assert_eq!(&buf[..], &expected[..]);
+    }
+
+    #[test]
+    fn test_try_new() {
+        assert!(BorderMirror8::try_new(3, 2).is_ok());
+        for &(width, padding) in [(2, 2), (0, 0), (::std::usize::MAX, 1)].iter() {
+            match BorderMirror8::try_new(width, padding) {
+                Err(BayerError::InvalidArgument { .. }) => (),
+                _ => panic!(),
+            }
+        }
+    }
 }
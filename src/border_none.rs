@@ -1,49 +1,139 @@
 //! Bayer reader without any additional border logic.
+//!
+//! If the raw data is given by the values shown below, this reader
+//! will produce the following row, where the border is filled with
+//! zeros.
+//!
+//! ```text
+//!   0 0 0 0 | r0 g0 r1 g1 r2 g2 ... rl gl rm gm rn gn | 0 0 0 0
+//! ```
 
 use std::io::Read;
+use std::marker::PhantomData;
 
 use ::BayerResult;
 use bayer::*;
+use errcode::OVERFLOW;
 
-pub struct BorderNone8;
-pub struct BorderNone16BE;
-pub struct BorderNone16LE;
+// Tuple struct (x1, x2, x3) designating the different sub-regions
+// of the output lines.
+//
+//    0 .. x1 => left border
+//   x1 .. x2 => raw data
+//   x2 .. x3 => right border
 
-impl BorderNone8 {
-    pub fn new() -> Self {
-        BorderNone8
-    }
+/// Reader for Bayer rows that fills the border with zeros.
+///
+/// The type parameter is the raw format of the samples, e.g. Raw8.
+pub struct BorderNone<F>(usize, usize, usize, PhantomData<F>);
+
+/// Reader for 8-bit Bayer rows that fills the border with zeros.
+pub type BorderNone8 = BorderNone<Raw8>;
+
+/// Reader for 16-bit big-endian Bayer rows that fills the border with zeros.
+pub type BorderNone16BE = BorderNone<Raw16BE>;
+
+/// Reader for 16-bit little-endian Bayer rows that fills the border with zeros.
+pub type BorderNone16LE = BorderNone<Raw16LE>;
+
+/// Reader for MIPI RAW10 packed Bayer rows that fills the border with zeros.
+pub type BorderNone10Packed = BorderNone<Raw10>;
+
+/// Reader for MIPI RAW12 packed Bayer rows that fills the border with zeros.
+pub type BorderNone12Packed = BorderNone<Raw12>;
+
+/// Reader for DNG 12-bit packed Bayer rows that fills the border with zeros.
+pub type BorderNone12PackedDng = BorderNone<Dng12>;
+
+/// Reader for MIPI RAW14 packed Bayer rows that fills the border with zeros.
+pub type BorderNone14Packed = BorderNone<Raw14>;
+
+macro_rules! fill_row {
+    ($dst:ident, $x1:expr, $x2:expr, $x3:expr) => {{
+        for e in $dst[0..$x1].iter_mut() {
+            *e = Default::default();
+        }
+        for e in $dst[$x2..$x3].iter_mut() {
+            *e = Default::default();
+        }
+    }}
 }
 
-impl BayerRead8 for BorderNone8 {
-    fn read_line(&self, r: &mut Read, dst: &mut [u8])
-            -> BayerResult<()> {
-        read_exact_u8(r, dst)
+impl<F> BorderNone<F> {
+    /// Create a reader for rows of width raw pixels, producing rows
+    /// with padding pixels on either side.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the rows overflow the address space.  See `try_new`.
+    pub fn new(width: usize, padding: usize) -> Self {
+        match Self::try_new(width, padding) {
+            Ok(rdr) => rdr,
+            Err(e) => panic!("{}", e),
+        }
     }
-}
 
-impl BorderNone16BE {
-    pub fn new() -> Self {
-        BorderNone16BE
+    /// Create a reader for rows of width raw pixels, producing rows
+    /// with padding pixels on either side, returning an error instead
+    /// of panicking if the rows overflow the address space.
+    pub fn try_new(width: usize, padding: usize) -> BayerResult<Self> {
+        let x1 = padding;
+        let x2 = x1.checked_add(width).ok_or(OVERFLOW)?;
+        let x3 = x2.checked_add(padding).ok_or(OVERFLOW)?;
+        Ok(BorderNone(x1, x2, x3, PhantomData))
     }
 }
 
-impl BayerRead16 for BorderNone16BE {
-    fn read_line(&self, r: &mut Read, dst: &mut [u16])
+impl<F: RawFormat> BorderNone<F> {
+    /// Read a line from any reader, without dynamic dispatch.
+    pub(crate) fn read_line_from<R: Read + ?Sized>(&self,
+            r: &mut R, dst: &mut [F::Sample])
             -> BayerResult<()> {
-        read_exact_u16be(r, dst)
+        self.read_line_with(r, dst, |_| ())
     }
-}
 
-impl BorderNone16LE {
-    pub fn new() -> Self {
-        BorderNone16LE
+    /// Read a line, passing the raw samples to f before the border
+    /// is filled in.
+    pub(crate) fn read_line_with<R, G>(&self,
+            r: &mut R, dst: &mut [F::Sample], f: G)
+            -> BayerResult<()>
+            where R: Read + ?Sized, G: FnOnce(&mut [F::Sample]) {
+        let BorderNone(x1, x2, x3, _) = *self;
+        F::read_exact(r, &mut dst[x1..x2])?;
+        f(&mut dst[x1..x2]);
+        fill_row!(dst, x1, x2, x3);
+        Ok(())
     }
 }
 
-impl BayerRead16 for BorderNone16LE {
-    fn read_line(&self, r: &mut Read, dst: &mut [u16])
+impl<F: RawFormat> BayerRead<F::Sample> for BorderNone<F> {
+    fn read_line(&self, r: &mut Read, dst: &mut [F::Sample])
             -> BayerResult<()> {
-        read_exact_u16le(r, dst)
+        self.read_line_from(r, dst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use bayer::BayerRead;
+    use super::BorderNone8;
+
+    #[test]
+    fn test_none() {
+        let src = [
+            1,2, 3,4, 5, ];
+
+        let expected = [
+            0, 0,0,
+            /*---*/ 1,2, 3,4, 5,
+            /*---------------*/ 0, 0,0 ];
+
+        let rdr = BorderNone8::new(5, 3);
+        let mut buf = [0xFFu8; 3 + 5 + 3];
+
+        let res = rdr.read_line(&mut Cursor::new(&src[..]), &mut buf);
+        assert!(res.is_ok());
+        assert_eq!(&buf[..], &expected[..]);
     }
 }
@@ -9,21 +9,44 @@
 //! ```
 
 use std::io::Read;
+use std::marker::PhantomData;
 
-use ::BayerResult;
+use ::{BayerError,BayerResult};
 use bayer::*;
+use errcode::OVERFLOW;
 
-/// Tuple structs (x1, x2, x3) designating the different sub-regions
-/// of the output lines.
+// Tuple struct (x1, x2, x3) designating the different sub-regions
+// of the output lines.
+//
+//    0 .. x1 => left border
+//   x1 .. x2 => raw data
+//   x2 .. x3 => right border
+
+/// Reader for Bayer rows that replicates the 2x2 block on the border.
 ///
-/// ```text
-///    0 .. x1 => left border
-///   x1 .. x2 => raw data
-///   x2 .. x3 => right border
-/// ```
-pub struct BorderReplicate8(usize, usize, usize);
-pub struct BorderReplicate16BE(usize, usize, usize);
-pub struct BorderReplicate16LE(usize, usize, usize);
+/// The type parameter is the raw format of the samples, e.g. Raw8.
+pub struct BorderReplicate<F>(usize, usize, usize, PhantomData<F>);
+
+/// Reader for 8-bit Bayer rows that replicates the 2x2 block on the border.
+pub type BorderReplicate8 = BorderReplicate<Raw8>;
+
+/// Reader for 16-bit big-endian Bayer rows that replicates the 2x2 block on the border.
+pub type BorderReplicate16BE = BorderReplicate<Raw16BE>;
+
+/// Reader for 16-bit little-endian Bayer rows that replicates the 2x2 block on the border.
+pub type BorderReplicate16LE = BorderReplicate<Raw16LE>;
+
+/// Reader for MIPI RAW10 packed Bayer rows that replicates the 2x2 block on the border.
+pub type BorderReplicate10Packed = BorderReplicate<Raw10>;
+
+/// Reader for MIPI RAW12 packed Bayer rows that replicates the 2x2 block on the border.
+pub type BorderReplicate12Packed = BorderReplicate<Raw12>;
+
+/// Reader for DNG 12-bit packed Bayer rows that replicates the 2x2 block on the border.
+pub type BorderReplicate12PackedDng = BorderReplicate<Dng12>;
+
+/// Reader for MIPI RAW14 packed Bayer rows that replicates the 2x2 block on the border.
+pub type BorderReplicate14Packed = BorderReplicate<Raw14>;
 
 macro_rules! fill_row {
     ($dst:ident, $x1:expr, $x2:expr, $x3:expr) => {{
@@ -58,73 +81,70 @@ macro_rules! fill_row {
     }}
 }
 
-impl BorderReplicate8 {
+impl<F> BorderReplicate<F> {
+    /// Create a reader for rows of width raw pixels, producing rows
+    /// with padding pixels on either side.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the width is less than 2, or if the rows overflow the
+    /// address space.  See `try_new`.
     pub fn new(width: usize, padding: usize) -> Self {
-        let x1 = padding;
-        let x2 = x1.checked_add(width).expect("overflow");
-        let x3 = x2.checked_add(padding).expect("overflow");
-        assert!(width >= 2);
-
-        BorderReplicate8(x1, x2, x3)
+        match Self::try_new(width, padding) {
+            Ok(rdr) => rdr,
+            Err(e) => panic!("{}", e),
+        }
     }
-}
 
-impl BayerRead8 for BorderReplicate8 {
-    fn read_line(&self, r: &mut Read, dst: &mut [u8])
-            -> BayerResult<()> {
-        let BorderReplicate8(x1, x2, x3) = *self;
-        read_exact_u8(r, &mut dst[x1..x2])?;
-        fill_row!(dst, x1, x2, x3);
-        Ok(())
-    }
-}
+    /// Create a reader for rows of width raw pixels, producing rows
+    /// with padding pixels on either side, returning an error instead
+    /// of panicking if the width is too small.
+    pub fn try_new(width: usize, padding: usize) -> BayerResult<Self> {
+        if width < 2 {
+            return Err(BayerError::InvalidArgument { reason: "the width must be at least 2" });
+        }
 
-impl BorderReplicate16BE {
-    pub fn new(width: usize, padding: usize) -> Self {
         let x1 = padding;
-        let x2 = x1.checked_add(width).expect("overflow");
-        let x3 = x2.checked_add(padding).expect("overflow");
-        assert!(width >= 2);
-
-        BorderReplicate16BE(x1, x2, x3)
+        let x2 = x1.checked_add(width).ok_or(OVERFLOW)?;
+        let x3 = x2.checked_add(padding).ok_or(OVERFLOW)?;
+        Ok(BorderReplicate(x1, x2, x3, PhantomData))
     }
 }
 
-impl BayerRead16 for BorderReplicate16BE {
-    fn read_line(&self, r: &mut Read, dst: &mut [u16])
+impl<F: RawFormat> BorderReplicate<F> {
+    /// Read a line from any reader, without dynamic dispatch.
+    pub(crate) fn read_line_from<R: Read + ?Sized>(&self,
+            r: &mut R, dst: &mut [F::Sample])
             -> BayerResult<()> {
-        let BorderReplicate16BE(x1, x2, x3) = *self;
-        read_exact_u16be(r, &mut dst[x1..x2])?;
-        fill_row!(dst, x1, x2, x3);
-        Ok(())
+        self.read_line_with(r, dst, |_| ())
     }
-}
-
-impl BorderReplicate16LE {
-    pub fn new(width: usize, padding: usize) -> Self {
-        let x1 = padding;
-        let x2 = x1.checked_add(width).expect("overflow");
-        let x3 = x2.checked_add(padding).expect("overflow");
-        assert!(width >= 2);
 
-        BorderReplicate16LE(x1, x2, x3)
+    /// Read a line, passing the raw samples to f before the border
+    /// is filled in.
+    pub(crate) fn read_line_with<R, G>(&self,
+            r: &mut R, dst: &mut [F::Sample], f: G)
+            -> BayerResult<()>
+            where R: Read + ?Sized, G: FnOnce(&mut [F::Sample]) {
+        let BorderReplicate(x1, x2, x3, _) = *self;
+        F::read_exact(r, &mut dst[x1..x2])?;
+        f(&mut dst[x1..x2]);
+        fill_row!(dst, x1, x2, x3);
+        Ok(())
     }
 }
 
-impl BayerRead16 for BorderReplicate16LE {
-    fn read_line(&self, r: &mut Read, dst: &mut [u16])
+impl<F: RawFormat> BayerRead<F::Sample> for BorderReplicate<F> {
+    fn read_line(&self, r: &mut Read, dst: &mut [F::Sample])
             -> BayerResult<()> {
-        let BorderReplicate16LE(x1, x2, x3) = *self;
-        read_exact_u16le(r, &mut dst[x1..x2])?;
-        fill_row!(dst, x1, x2, x3);
-        Ok(())
+        self.read_line_from(r, dst)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::io::Cursor;
-    use bayer::BayerRead8;
+    use ::BayerError;
+    use bayer::BayerRead;
     use super::BorderReplicate8;
 
     #[test]
@@ -162,4 +182,15 @@ mod tests {
         assert!(res.is_ok());
         assert_eq!(&buf[..], &expected[..]);
     }
+
+    #[test]
+    fn test_try_new() {
+        assert!(BorderReplicate8::try_new(2, 3).is_ok());
+        for &(width, padding) in [(1, 3), (::std::usize::MAX, 1)].iter() {
+            match BorderReplicate8::try_new(width, padding) {
+                Err(BayerError::InvalidArgument { .. }) => (),
+                _ => panic!(),
+            }
+        }
+    }
 }
@@ -0,0 +1,299 @@
+//! Demosaicing of images with clear pixels.
+//!
+//! RCCB, RCCC and RGBW sensors replace some of the colour filters of
+//! the Bayer pattern with clear (or white) filters, which pass all
+//! colours and so gather more light.  The interpolated channels are
+//! written to the output as:
+//!
+//! ```text
+//!   RCCB:  red, clear, blue
+//!   RCCC:  red, clear, clear
+//!   RGBW:  red, green, blue
+//! ```
+//!
+//! The white samples of RGBW images do not contribute to the output.
+
+use std::io::Read;
+
+use ::{BayerDepth,BayerError,BayerResult,CfaPattern,Colour,Demosaic,DemosaicOptions,Fill,RasterMut};
+use demosaic::calibration::Calibration;
+use demosaic::{RowFormat,check_conversion};
+use pattern::read_samples;
+
+/// The neighbours of a pixel, nearest first.
+const NEIGHBOURS: [(isize, isize); 8] = [
+    (1, 0), (0, 1), (-1, 0), (0, -1),
+    (1, 1), (-1, 1), (1, -1), (-1, -1) ];
+
+/// The calibrated samples of an image with a 2x2 pattern other than
+/// the Bayer pattern.
+pub(crate) struct Mosaic<'a> {
+    pub(crate) w: usize,
+    pub(crate) h: usize,
+    pattern: &'a CfaPattern,
+    samples: Vec<u32>,
+    pub(crate) pedestal: u32,
+    /// The value of the missing channels without interpolation.
+    fill: Fill,
+    /// The format of the raster rows.
+    pub(crate) format: RowFormat,
+}
+
+impl<'a> Mosaic<'a> {
+    /// Read a mosaic with the dimensions of dst, checking that it can
+    /// be demosaiced into dst by alg.
+    ///
+    /// Only `Demosaic::None`, `Demosaic::NearestNeighbour` and
+    /// `Demosaic::Linear` are supported.
+    pub(crate) fn read<R: Read>(r: &mut R,
+            depth: BayerDepth, pattern: &'a CfaPattern, alg: Demosaic,
+            options: &DemosaicOptions, dst: &RasterMut)
+            -> BayerResult<Self> {
+        match alg {
+            Demosaic::None | Demosaic::NearestNeighbour | Demosaic::Linear => (),
+            _ => return Err(BayerError::InvalidArgument {
+                reason: "the pattern only supports nearest neighbour and linear interpolation",
+            }),
+        }
+
+        check_conversion(depth, dst.depth, options)?;
+        if options.transform.is_some() {
+            return Err(BayerError::InvalidArgument {
+                reason: "the pattern cannot be transformed",
+            });
+        }
+
+        let (w, h) = (dst.w, dst.h);
+        if w < 2 || h < 2 {
+            return Err(BayerError::WrongResolution {
+                width: w, height: h, min_width: 2, min_height: 2,
+            });
+        }
+
+        // Without interpolation, clamping the calibrated samples to
+        // zero loses nothing.
+        let calibration = Calibration::for_raster(options, depth, dst.depth, None, w, h)?;
+        let calibration = if alg == Demosaic::None { calibration.unsigned() } else { calibration };
+        let samples = read_samples(r, depth, &calibration, w, h)?;
+        Ok(Mosaic {
+            w, h, pattern, samples,
+            pedestal: calibration.pedestal() as u32,
+            fill: options.fill,
+            format: RowFormat::for_image(options, depth, dst),
+        })
+    }
+
+    /// The value of the given colour at pixel (x, y), offset by the
+    /// pedestal.
+    pub(crate) fn value(&self, alg: Demosaic, x: usize, y: usize, colour: Colour) -> u32 {
+        if self.pattern.colour_at(x, y) == colour {
+            return self.samples[self.w * y + x];
+        }
+
+        let mut neighbours = NEIGHBOURS.iter().filter_map(|&(dx, dy)| {
+            let (u, v) = (x as isize + dx, y as isize + dy);
+            if u < 0 || v < 0 || u >= self.w as isize || v >= self.h as isize {
+                return None;
+            }
+
+            let (u, v) = (u as usize, v as usize);
+            if self.pattern.colour_at(u, v) == colour {
+                Some(self.samples[self.w * v + u])
+            } else {
+                None
+            }
+        });
+
+        match alg {
+            Demosaic::None => match self.fill {
+                Fill::Zero => self.pedestal,
+                Fill::MidGray => self.pedestal + (self.format.max() + 1) / 2,
+                Fill::Replicate => self.samples[self.w * y + x],
+            },
+            Demosaic::NearestNeighbour => neighbours.next().unwrap_or(0),
+            _ => {
+                let (sum, n) = neighbours.fold((0, 0), |(sum, n), v| (sum + v, n + 1));
+                if n > 0 { (sum + n / 2) / n } else { 0 }
+            },
+        }
+    }
+}
+
+fn interpolate(m: &Mosaic, alg: Demosaic, outputs: [Colour; 3], dst: &mut RasterMut) {
+    let mut row = vec![0u32; 3 * m.w];
+    for y in 0..m.h {
+        for (x, px) in row.chunks_mut(3).enumerate() {
+            for (e, &colour) in px.iter_mut().zip(outputs.iter()) {
+                *e = m.value(alg, x, y, colour).saturating_sub(m.pedestal);
+            }
+        }
+        m.format.put_row(y, &row, dst);
+    }
+}
+
+/// Run the demosaicing algorithm on an RCCB, RCCC or RGBW image.
+pub(crate) fn demosaic_clear<R: Read>(r: &mut R,
+        depth: BayerDepth, pattern: &CfaPattern, alg: Demosaic,
+        options: &DemosaicOptions, dst: &mut RasterMut)
+        -> BayerResult<()> {
+    let outputs = match *pattern {
+        CfaPattern::Rccb(_) => [Colour::Red, Colour::Clear, Colour::Blue],
+        CfaPattern::Rccc(_) => [Colour::Red, Colour::Clear, Colour::Clear],
+        CfaPattern::Rgbw(_) => [Colour::Red, Colour::Green, Colour::Blue],
+        _ => return Err(BayerError::InvalidArgument {
+            reason: "the pattern has no clear pixels",
+        }),
+    };
+
+    let mosaic = Mosaic::read(r, depth, pattern, alg, options, dst)?;
+    interpolate(&mosaic, alg, outputs, dst);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{BayerDepth,BayerError,CFA,CfaPattern,Demosaic,DemosaicOptions,Fill,RasterDepth,RasterMut,demosaic_pattern};
+
+    fn run(src: &[u8], pattern: CfaPattern, alg: Demosaic, buf: &mut [u8])
+            -> Result<(), BayerError> {
+        demosaic_pattern(&mut &src[..], BayerDepth::Depth8, &pattern, alg,
+                &DemosaicOptions::default(),
+                &mut RasterMut::new(4, 4, RasterDepth::Depth8, buf))
+    }
+
+    #[test]
+    fn test_rccb() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+
+        let mut buf = [0u8; 3 * 4 * 4];
+        let res = run(&src, CfaPattern::Rccb(CFA::RGGB), Demosaic::Linear, &mut buf);
+        assert!(res.is_ok());
+        assert_eq!(&buf[0..3], &[229,150, 51]);
+        assert_eq!(&buf[15..18], &[127,172, 51]);
+
+        let res = run(&src, CfaPattern::Rccb(CFA::RGGB), Demosaic::NearestNeighbour, &mut buf);
+        assert!(res.is_ok());
+        assert_eq!(&buf[0..3], &[229, 67, 51]);
+        assert_eq!(&buf[15..18], &[ 15,229, 51]);
+    }
+
+    #[test]
+    fn test_rccc() {
+        let src = [
+            100,200,100,200,
+            200,200,200,200,
+            100,200,100,200,
+            200,200,200,200 ];
+
+        let mut buf = [0u8; 3 * 4 * 4];
+        let res = run(&src, CfaPattern::Rccc(CFA::RGGB), Demosaic::Linear, &mut buf);
+        assert!(res.is_ok());
+        assert!(buf.chunks(3).all(|px| px == [100, 200, 200]));
+    }
+
+    #[test]
+    fn test_rgbw() {
+        // The white samples are ignored.
+        let src = [
+             10, 20, 10, 20,
+            255, 30,255, 30,
+             10, 20, 10, 20,
+            255, 30,255, 30 ];
+
+        let mut buf = [0u8; 3 * 4 * 4];
+        let res = run(&src, CfaPattern::Rgbw(CFA::RGGB), Demosaic::Linear, &mut buf);
+        assert!(res.is_ok());
+        assert!(buf.chunks(3).all(|px| px == [10, 20, 30]));
+    }
+
+    #[test]
+    fn test_rgbw_discards_white() {
+        let dark = [
+             10, 20, 10, 20,
+              0, 30,  0, 30,
+             10, 20, 10, 20,
+              0, 30,  0, 30 ];
+        let varied = [
+             10, 20, 10, 20,
+            255, 30, 17, 30,
+             10, 20, 10, 20,
+             90, 30,200, 30 ];
+
+        for &alg in &[Demosaic::None, Demosaic::NearestNeighbour, Demosaic::Linear] {
+            let mut expected = [0u8; 3 * 4 * 4];
+            let mut buf = [0u8; 3 * 4 * 4];
+            assert!(run(&dark, CfaPattern::Rgbw(CFA::RGGB), alg, &mut expected).is_ok());
+            assert!(run(&varied, CfaPattern::Rgbw(CFA::RGGB), alg, &mut buf).is_ok());
+            assert_eq!(&buf[..], &expected[..]);
+        }
+    }
+
+    #[test]
+    fn test_depth() {
+        // 8-bit images are widened for 16-bit rasters.
+        let src = [
+            100,200,100,200,
+            200,200,200,200,
+            100,200,100,200,
+            200,200,200,200 ];
+
+        let mut buf = [0u8; 6 * 4 * 4];
+        let res = demosaic_pattern(&mut &src[..], BayerDepth::Depth8, &CfaPattern::Rccc(CFA::RGGB),
+                Demosaic::Linear, &DemosaicOptions::default(),
+                &mut RasterMut::new(4, 4, RasterDepth::Depth16, &mut buf));
+        assert!(res.is_ok());
+
+        let mut dst = RasterMut::new(4, 4, RasterDepth::Depth16, &mut buf);
+        for y in 0..4 {
+            assert!(dst.borrow_row_u16_mut(y).chunks(3).all(|px| px == [100 << 8, 200 << 8, 200 << 8]));
+        }
+    }
+
+    #[test]
+    fn test_fill() {
+        let src = [
+            100,200,100,200,
+            200, 50,200, 50,
+            100,200,100,200,
+            200, 50,200, 50 ];
+
+        let mut buf = [0u8; 3 * 4 * 4];
+        let mut options = DemosaicOptions::default();
+        for &(fill, expected) in [
+                (Fill::Zero, [[100, 0, 0], [0, 200, 0], [0, 0, 50]]),
+                (Fill::MidGray, [[100, 128, 128], [128, 200, 128], [128, 128, 50]]),
+                (Fill::Replicate, [[100, 100, 100], [200, 200, 200], [50, 50, 50]]) ].iter() {
+            options.fill = fill;
+            let res = demosaic_pattern(&mut &src[..], BayerDepth::Depth8, &CfaPattern::Rccb(CFA::RGGB),
+                    Demosaic::None, &options, &mut RasterMut::new(4, 4, RasterDepth::Depth8, &mut buf));
+            assert!(res.is_ok());
+            assert_eq!(&buf[0..3], &expected[0]);
+            assert_eq!(&buf[3..6], &expected[1]);
+            assert_eq!(&buf[15..18], &expected[2]);
+        }
+
+        // Mid-gray is half of the 16-bit range in 16-bit rasters.
+        let mut buf = [0u8; 6 * 4 * 4];
+        options.fill = Fill::MidGray;
+        let res = demosaic_pattern(&mut &src[..], BayerDepth::Depth8, &CfaPattern::Rccb(CFA::RGGB),
+                Demosaic::None, &options, &mut RasterMut::new(4, 4, RasterDepth::Depth16, &mut buf));
+        assert!(res.is_ok());
+        let mut dst = RasterMut::new(4, 4, RasterDepth::Depth16, &mut buf);
+        assert_eq!(&dst.borrow_row_u16_mut(0)[0..3], &[100 << 8, 0x8000, 0x8000]);
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let src = [0u8; 16];
+        let mut buf = [0u8; 3 * 4 * 4];
+        match run(&src, CfaPattern::Rccb(CFA::RGGB), Demosaic::Cubic, &mut buf) {
+            Err(BayerError::InvalidArgument { .. }) => (),
+            _ => panic!(),
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Comparison of demosaicing algorithms.
+
+use ::{BayerDepth,BayerResult,CFA,Demosaic,DemosaicOptions,RasterDepth,RasterMut};
+use errcode::FLOAT_RASTER;
+use raster::RasterSample;
+
+/// Summary of the differences between two demosaiced images.
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct DiffStats {
+    /// The largest absolute difference of any channel.
+    pub max: u32,
+    /// The mean absolute difference over all channels.
+    pub mean: f64,
+    /// The root mean square difference over all channels.
+    pub rmse: f64,
+    /// The number of pixels where any channel differs.
+    pub differing: u64,
+}
+
+/// Accumulated differences.
+#[derive(Default)]
+struct Accumulator {
+    max: u32,
+    sum: u64,
+    sum_sq: u64,
+    differing: u64,
+}
+
+impl Accumulator {
+    fn add(&mut self, diff: [u32; 3]) {
+        for &d in diff.iter() {
+            self.max = if d > self.max { d } else { self.max };
+            self.sum = self.sum + d as u64;
+            self.sum_sq = self.sum_sq + d as u64 * d as u64;
+        }
+
+        if diff != [0; 3] {
+            self.differing = self.differing + 1;
+        }
+    }
+}
+
+/// Replace the colour samples of dst with the absolute difference from
+/// those of other, which has the same layout, accumulating the
+/// differences.
+macro_rules! diff_rows {
+    ($acc:ident, $dst:ident, $other:ident, $T:ty) => {{
+        let channels = $dst.layout.channels();
+        for y in 0..$dst.h {
+            let o = <$T>::from_bytes_mut($other.borrow_pixels_mut(y));
+            let d = <$T>::from_bytes_mut($dst.borrow_pixels_mut(y));
+
+            for (pd, po) in d.chunks_mut(channels).zip(o.chunks(channels)) {
+                let mut diff = [0u32; 3];
+                for c in 0..3 {
+                    let (u, v) = (pd[c] as u32, po[c] as u32);
+                    diff[c] = if u > v { u - v } else { v - u };
+                    pd[c] = diff[c] as $T;
+                }
+                $acc.add(diff);
+            }
+        }
+    }}
+}
+
+/// Run two demosaicing algorithms on the same Bayer image, and write
+/// the per-channel absolute difference of the results to dst.
+///
+/// # Example
+///
+/// ```
+/// let width: usize = 320;
+/// let height: usize = 200;
+/// let img = vec![0; width * height];
+/// let mut buf = vec![0; 3 * width * height];
+///
+/// let mut dst = bayer::RasterMut::new(
+///         width, height, bayer::RasterDepth::Depth8,
+///         &mut buf);
+/// let stats = bayer::compare_algorithms(&img,
+///         bayer::BayerDepth::Depth8,
+///         bayer::CFA::RGGB,
+///         bayer::Demosaic::Linear,
+///         bayer::Demosaic::Cubic,
+///         &mut dst).unwrap();
+/// assert_eq!(stats.max, 0);
+/// ```
+pub fn compare_algorithms(src: &[u8],
+        depth: BayerDepth, cfa: CFA, a: Demosaic, b: Demosaic,
+        dst: &mut RasterMut)
+        -> BayerResult<DiffStats> {
+    let options = DemosaicOptions::default();
+    let (w, h) = (dst.w, dst.h);
+    if dst.depth == RasterDepth::Depth32F {
+        return Err(FLOAT_RASTER);
+    }
+
+    ::demosaic_from(&mut &src[..], depth, cfa, a, &options, dst)?;
+
+    let bytes_per_pixel = dst.depth.bytes_per_sample() * dst.layout.channels();
+    let mut buf = vec![0u8; bytes_per_pixel * w * h];
+    let mut other = RasterMut::try_with_layout(0, 0, w, h, bytes_per_pixel * w,
+            dst.depth, dst.layout, &mut buf)?;
+    ::demosaic_from(&mut &src[..], depth, cfa, b, &options, &mut other)?;
+
+    let mut acc = Accumulator::default();
+    match dst.depth {
+        RasterDepth::Depth8 => diff_rows!(acc, dst, other, u8),
+        RasterDepth::Depth16 => diff_rows!(acc, dst, other, u16),
+        RasterDepth::Depth32F => unreachable!(),
+    }
+
+    let n = (3 * w * h) as f64;
+    Ok(DiffStats {
+        max: acc.max,
+        mean: acc.sum as f64 / n,
+        rmse: (acc.sum_sq as f64 / n).sqrt(),
+        differing: acc.differing,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{BayerDepth,CFA,Demosaic,RasterDepth,RasterLayout,RasterMut};
+    use super::compare_algorithms;
+
+    #[test]
+    fn test_compare() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+
+        const IMG_W: usize = 4;
+        const IMG_H: usize = 4;
+        let mut a = [0u8; 3 * IMG_W * IMG_H];
+        let mut b = [0u8; 3 * IMG_W * IMG_H];
+        let mut buf = [0u8; 3 * IMG_W * IMG_H];
+
+        ::demosaic_from(&mut &src[..], BayerDepth::Depth8, CFA::RGGB,
+                Demosaic::NearestNeighbour, &Default::default(),
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut a)).unwrap();
+        ::demosaic_from(&mut &src[..], BayerDepth::Depth8, CFA::RGGB,
+                Demosaic::Linear, &Default::default(),
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut b)).unwrap();
+
+        let stats = compare_algorithms(&src, BayerDepth::Depth8, CFA::RGGB,
+                Demosaic::NearestNeighbour, Demosaic::Linear,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf)).unwrap();
+
+        let diff: Vec<u8> = a.iter().zip(b.iter())
+                .map(|(&u, &v)| if u > v { u - v } else { v - u })
+                .collect();
+        assert_eq!(&buf[..], &diff[..]);
+        assert_eq!(stats.max, *diff.iter().max().unwrap() as u32);
+        assert!(stats.differing > 0 && stats.differing <= (IMG_W * IMG_H) as u64);
+        assert!(stats.rmse >= stats.mean);
+
+        // The differences are written in the layout of the raster.
+        let mut buf = [0u8; 4 * IMG_W * IMG_H];
+        let bgra = compare_algorithms(&src, BayerDepth::Depth8, CFA::RGGB,
+                Demosaic::NearestNeighbour, Demosaic::Linear,
+                &mut RasterMut::try_with_layout(0, 0, IMG_W, IMG_H, 4 * IMG_W, RasterDepth::Depth8,
+                        RasterLayout::Bgra { alpha: 0xFFFF }, &mut buf).unwrap()).unwrap();
+        assert_eq!(bgra, stats);
+        for (px, diff) in buf.chunks(4).zip(diff.chunks(3)) {
+            assert_eq!(px, [diff[2], diff[1], diff[0], 0xFF]);
+        }
+    }
+
+    #[test]
+    fn test_compare_same() {
+        let src = [0x1234u16; 16];
+        let mut bytes = [0u8; 32];
+        for (i, v) in src.iter().enumerate() {
+            bytes[2 * i..2 * i + 2].copy_from_slice(&v.to_le_bytes());
+        }
+        let mut buf = [0xFFu8; 6 * 16];
+
+        let stats = compare_algorithms(&bytes, BayerDepth::Depth16LE, CFA::BGGR,
+                Demosaic::Cubic, Demosaic::Cubic,
+                &mut RasterMut::new(4, 4, RasterDepth::Depth16, &mut buf)).unwrap();
+        assert_eq!(stats, Default::default());
+        assert!(buf.iter().all(|&v| v == 0));
+    }
+}
@@ -0,0 +1,102 @@
+//! Demosaicing of images with complementary colour filters.
+//!
+//! CYGM sensors use cyan, yellow and magenta filters, which pass
+//! twice as much light as the primaries, along with green.  RGBE
+//! sensors replace one of the greens of the Bayer pattern with an
+//! emerald filter.  The four channels are interpolated, then
+//! converted to RGB assuming ideal filters:
+//!
+//! ```text
+//!   CYGM:  R = (Y + M - C) / 2
+//!          G = (2G + C + Y - M) / 4
+//!          B = (C + M - Y) / 2
+//!
+//!   RGBE:  R = R,  G = (G + E) / 2,  B = B
+//! ```
+
+use std::cmp;
+use std::io::Read;
+
+use ::{BayerDepth,BayerError,BayerResult,CfaPattern,Colour,Demosaic,DemosaicOptions,RasterMut};
+use clear::Mosaic;
+
+/// Convert the interpolated channels of pixel (x, y) to RGB.
+fn convert(m: &Mosaic, pattern: &CfaPattern, alg: Demosaic, x: usize, y: usize) -> [i32; 3] {
+    let v = |c: Colour| m.value(alg, x, y, c) as i32 - m.pedestal as i32;
+    match *pattern {
+        CfaPattern::Cygm(_) => {
+            let (c, ye, g, mg) = (v(Colour::Cyan), v(Colour::Yellow), v(Colour::Green), v(Colour::Magenta));
+            [(ye + mg - c) / 2, (2 * g + c + ye - mg) / 4, (c + mg - ye) / 2]
+        },
+        _ => [v(Colour::Red), (v(Colour::Green) + v(Colour::Emerald) + 1) / 2, v(Colour::Blue)],
+    }
+}
+
+fn interpolate(m: &Mosaic, pattern: &CfaPattern, alg: Demosaic, dst: &mut RasterMut) {
+    let mut row = vec![0u32; 3 * m.w];
+    for y in 0..m.h {
+        for (x, px) in row.chunks_mut(3).enumerate() {
+            let rgb = convert(m, pattern, alg, x, y);
+            for (e, &v) in px.iter_mut().zip(rgb.iter()) {
+                *e = cmp::max(0, v) as u32;
+            }
+        }
+        m.format.put_row(y, &row, dst);
+    }
+}
+
+/// Run the demosaicing algorithm on a CYGM or RGBE image.
+pub(crate) fn demosaic_complementary<R: Read>(r: &mut R,
+        depth: BayerDepth, pattern: &CfaPattern, alg: Demosaic,
+        options: &DemosaicOptions, dst: &mut RasterMut)
+        -> BayerResult<()> {
+    match *pattern {
+        CfaPattern::Cygm(_) | CfaPattern::Rgbe(_) => (),
+        _ => return Err(BayerError::InvalidArgument {
+            reason: "the pattern has no complementary colours",
+        }),
+    }
+
+    let mosaic = Mosaic::read(r, depth, pattern, alg, options, dst)?;
+    interpolate(&mosaic, pattern, alg, dst);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{BayerDepth,CfaPattern,Demosaic,DemosaicOptions,RasterDepth,RasterMut,demosaic_pattern};
+
+    fn run(src: &[u8], pattern: CfaPattern) -> Vec<u8> {
+        let mut buf = vec![0u8; 3 * 4 * 4];
+        let res = demosaic_pattern(&mut &src[..], BayerDepth::Depth8, &pattern,
+                Demosaic::Linear, &DemosaicOptions::default(),
+                &mut RasterMut::new(4, 4, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+        buf
+    }
+
+    #[test]
+    fn test_cygm() {
+        // An orange of R = 150, G = 80, B = 20.
+        let src = [
+            100,230,100,230,
+             80,170, 80,170,
+            100,230,100,230,
+             80,170, 80,170 ];
+
+        let buf = run(&src, CfaPattern::cygm());
+        assert!(buf.chunks(3).all(|px| px == [150, 80, 20]));
+    }
+
+    #[test]
+    fn test_rgbe() {
+        let src = [
+            200,100,200,100,
+             20,110, 20,110,
+            200,100,200,100,
+             20,110, 20,110 ];
+
+        let buf = run(&src, CfaPattern::rgbe());
+        assert!(buf.chunks(3).all(|px| px == [200, 105, 20]));
+    }
+}
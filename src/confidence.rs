@@ -0,0 +1,162 @@
+//! Per-pixel interpolation confidence.
+//!
+//! Interpolation artefacts, such as zippering and false colour, occur
+//! where the image changes quickly in every direction, so that no
+//! direction is safe to interpolate along.  The confidence of each
+//! pixel is the smaller of the horizontal and vertical changes of the
+//! green channel, inverted, so flat regions and clean edges score high
+//! while fine texture scores low.  Denoisers and sharpeners can use
+//! the map to avoid amplifying the artefacts.
+
+use std::cmp;
+use std::io::Read;
+
+use ::{BayerDepth,BayerError,BayerResult,CFA,Demosaic,DemosaicOptions,Raster,RasterDepth,RasterMut};
+use errcode::FLOAT_RASTER;
+
+/// Compute the interpolation confidence of each pixel of a demosaiced
+/// image, writing w * h values into dst in row-major order.
+///
+/// A confidence of 255 means the green channel is flat in at least
+/// one direction, and 0 means it swings over the full range in both
+/// directions.  16-bit rasters are scaled to the same range.
+///
+/// # Example
+///
+/// ```
+/// let rgb = [100u8; 3 * 4 * 4];
+/// let mut confidence = [0u8; 4 * 4];
+///
+/// bayer::confidence_map(&bayer::Raster::new(4, 4, bayer::RasterDepth::Depth8, &rgb),
+///         &mut confidence).unwrap();
+/// assert!(confidence.iter().all(|&c| c == 255));
+/// ```
+pub fn confidence_map(src: &Raster, dst: &mut [u8]) -> BayerResult<()> {
+    let (w, h) = (src.w, src.h);
+    if dst.len() < w * h {
+        return Err(BayerError::OutputTooShort { expected: w * h, got: dst.len() });
+    }
+
+    let shift = match src.depth {
+        RasterDepth::Depth8 => 0,
+        RasterDepth::Depth16 => 8,
+        RasterDepth::Depth32F => return Err(FLOAT_RASTER),
+    };
+
+    let mut rgb = Vec::with_capacity(3 * w);
+    let rows: Vec<Vec<u32>> = (0..h)
+        .map(|y| {
+            src.read_rgb_row(y, &mut rgb);
+            rgb.chunks(3).map(|px| px[1] as u32).collect()
+        })
+        .collect();
+
+    // The largest step from the pixel to either neighbour.
+    let change = |a: u32, c: u32, b: u32| {
+        let diff = |u: u32, v: u32| if u > v { u - v } else { v - u };
+        cmp::max(diff(a, c), diff(c, b))
+    };
+
+    for y in 0..h {
+        let (up, down) = (&rows[y.saturating_sub(1)], &rows[cmp::min(y + 1, h - 1)]);
+        let row = &rows[y];
+        for x in 0..w {
+            let (left, right) = (row[x.saturating_sub(1)], row[cmp::min(x + 1, w - 1)]);
+            let horizontal = change(left, row[x], right);
+            let vertical = change(up[x], row[x], down[x]);
+            let gradient = cmp::min(horizontal, vertical) >> shift;
+            dst[w * y + x] = 255 - cmp::min(gradient, 255) as u8;
+        }
+    }
+
+    Ok(())
+}
+
+/// Run the demosaicing algorithm on the Bayer image, and record the
+/// interpolation confidence of each pixel in confidence.
+///
+/// See `confidence_map`.
+pub fn demosaic_with_confidence<R: Read>(r: &mut R,
+        depth: BayerDepth, cfa: CFA, alg: Demosaic,
+        options: &DemosaicOptions, dst: &mut RasterMut, confidence: &mut [u8])
+        -> BayerResult<()> {
+    let expected = dst.w * dst.h;
+    if confidence.len() < expected {
+        return Err(BayerError::OutputTooShort { expected, got: confidence.len() });
+    }
+
+    ::demosaic_from(r, depth, cfa, alg, options, dst)?;
+    confidence_map(&dst.as_raster(), confidence)
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{BayerDepth,BayerError,CFA,Demosaic,DemosaicOptions,Raster,RasterDepth,RasterLayout,RasterMut,run_demosaic};
+    use super::{confidence_map,demosaic_with_confidence};
+
+    #[test]
+    fn test_confidence() {
+        // Vertical stripes are flat vertically, a checkerboard is not
+        // flat in either direction.
+        let mut stripes = [0u8; 3 * 4 * 4];
+        let mut checkerboard = [0u8; 3 * 4 * 4];
+        for i in 0..16 {
+            let (x, y) = (i % 4, i / 4);
+            stripes[3 * i + 1] = if x % 2 == 0 { 200 } else { 0 };
+            checkerboard[3 * i + 1] = if (x + y) % 2 == 0 { 200 } else { 0 };
+        }
+
+        let mut dst = [0u8; 4 * 4];
+        let res = confidence_map(&Raster::new(4, 4, RasterDepth::Depth8, &stripes), &mut dst);
+        assert!(res.is_ok());
+        assert!(dst.iter().all(|&c| c == 255));
+
+        let res = confidence_map(&Raster::new(4, 4, RasterDepth::Depth8, &checkerboard), &mut dst);
+        assert!(res.is_ok());
+        assert_eq!(dst[4 * 1 + 1], 55);
+        assert_eq!(dst[4 * 2 + 2], 55);
+
+        let res = confidence_map(&Raster::new(4, 4, RasterDepth::Depth8, &stripes), &mut dst[..15]);
+        match res {
+            Err(BayerError::OutputTooShort { expected: 16, got: 15 }) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_demosaic_with_confidence() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+
+        let mut expected = [0u8; 3 * 4 * 4];
+        let res = run_demosaic(&mut &src[..], BayerDepth::Depth8, CFA::RGGB, Demosaic::Linear,
+                &mut RasterMut::new(4, 4, RasterDepth::Depth8, &mut expected));
+        assert!(res.is_ok());
+        let mut expected_confidence = [0u8; 4 * 4];
+        let res = confidence_map(&Raster::new(4, 4, RasterDepth::Depth8, &expected),
+                &mut expected_confidence);
+        assert!(res.is_ok());
+
+        let mut buf = [0u8; 3 * 4 * 4];
+        let mut confidence = [0u8; 4 * 4];
+        let res = demosaic_with_confidence(&mut &src[..], BayerDepth::Depth8, CFA::RGGB,
+                Demosaic::Linear, &DemosaicOptions::default(),
+                &mut RasterMut::new(4, 4, RasterDepth::Depth8, &mut buf), &mut confidence);
+        assert!(res.is_ok());
+        assert_eq!(&buf[..], &expected[..]);
+        assert_eq!(confidence, expected_confidence);
+
+        let mut buf = [0u8; 3 * 4 * 4];
+        let mut confidence = [0u8; 4 * 4];
+        let res = demosaic_with_confidence(&mut &src[..], BayerDepth::Depth8, CFA::RGGB,
+                Demosaic::Linear, &DemosaicOptions::default(),
+                &mut RasterMut::try_with_layout(0, 0, 4, 4, 3 * 4, RasterDepth::Depth8,
+                        RasterLayout::Bgr, &mut buf).unwrap(), &mut confidence);
+        assert!(res.is_ok());
+        assert_eq!(confidence, expected_confidence);
+    }
+}
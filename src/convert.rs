@@ -0,0 +1,105 @@
+//! Conversions between raw formats.
+
+use std::io::{ErrorKind,Read,Write};
+
+use ::{BayerError,BayerResult};
+
+/// The size of the chunks used by the streaming conversions.
+const CHUNK_LEN: usize = 64 * 1024;
+
+/// Read until buf is full or the end of the stream, returning the
+/// number of bytes read.
+fn read_chunk(r: &mut Read, buf: &mut [u8]) -> BayerResult<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match r.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(len) => n = n + len,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => return Err(BayerError::Io(e)),
+        }
+    }
+
+    Ok(n)
+}
+
+/// Convert a stream of 16-bit samples from big-endian to
+/// little-endian, or vice versa, returning the number of samples
+/// converted.
+///
+/// The stream is processed in chunks, so files of any size can be
+/// converted.  Returns BayerError::TrailingData if the stream ends
+/// with half a sample.
+///
+/// # Example
+///
+/// ```
+/// let be = [0x01,0x02, 0x03,0x04];
+/// let mut le = Vec::new();
+///
+/// let n = bayer::swap_endianness(&mut &be[..], &mut le).unwrap();
+/// assert_eq!(n, 2);
+/// assert_eq!(le, [0x02,0x01, 0x04,0x03]);
+/// ```
+pub fn swap_endianness(r: &mut Read, w: &mut Write) -> BayerResult<u64> {
+    let mut buf = vec![0u8; CHUNK_LEN];
+    let mut count = 0;
+
+    loop {
+        let n = read_chunk(r, &mut buf)?;
+        if n % 2 != 0 {
+            return Err(BayerError::TrailingData { extra: 1 });
+        }
+
+        for pair in buf[0..n].chunks_mut(2) {
+            pair.swap(0, 1);
+        }
+
+        w.write_all(&buf[0..n])?;
+        count = count + (n / 2) as u64;
+
+        if n < buf.len() {
+            return Ok(count);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self,Read};
+    use ::BayerError;
+    use super::{CHUNK_LEN,swap_endianness};
+
+    /// Reader returning at most 3 bytes at a time.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = [3, buf.len(), self.0.len()].iter().cloned().min().unwrap();
+            buf[0..n].copy_from_slice(&self.0[0..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_chunks() {
+        let src: Vec<u8> = (0..(CHUNK_LEN + 6)).map(|i| i as u8).collect();
+        let mut dst = Vec::new();
+
+        let n = swap_endianness(&mut Trickle(&src), &mut dst).unwrap();
+        assert_eq!(n as usize, src.len() / 2);
+        for (a, b) in src.chunks(2).zip(dst.chunks(2)) {
+            assert_eq!((a[0], a[1]), (b[1], b[0]));
+        }
+    }
+
+    #[test]
+    fn test_odd() {
+        let src = [1, 2, 3];
+        match swap_endianness(&mut &src[..], &mut Vec::new()) {
+            Err(BayerError::TrailingData { extra: 1 }) => (),
+            _ => panic!(),
+        }
+    }
+}
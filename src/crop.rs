@@ -0,0 +1,175 @@
+//! Cropping Bayer images.
+
+use ::{BayerDepth,BayerError,BayerResult,CFA};
+use bayer::{check_input_len,check_output_len,check_unpacked};
+
+/// A rectangle within an image, in pixels.
+#[derive(Clone,Copy,Debug,Default,Eq,PartialEq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub w: usize,
+    pub h: usize,
+}
+
+impl Rect {
+    /// Create a w x h rectangle with the top-left pixel at (x, y).
+    pub fn new(x: usize, y: usize, w: usize, h: usize) -> Self {
+        Rect { x, y, w, h }
+    }
+
+    /// Check that the rectangle lies within a width x height image.
+    pub fn check_bounds(&self, width: usize, height: usize)
+            -> BayerResult<()> {
+        let fits = self.x.checked_add(self.w).map_or(false, |x1| x1 <= width)
+            && self.y.checked_add(self.h).map_or(false, |y1| y1 <= height);
+
+        if fits {
+            Ok(())
+        } else {
+            Err(BayerError::OutOfBounds { rect: *self, width, height })
+        }
+    }
+
+    /// The rectangle with its offsets rounded down to even values,
+    /// which preserves the CFA pattern.
+    pub fn align_even(&self) -> Self {
+        Rect { x: self.x & !1, y: self.y & !1, w: self.w, h: self.h }
+    }
+}
+
+/// Extract a rectangle from a w x h Bayer image.
+///
+/// If even is true, the offsets of the rectangle are first rounded
+/// down to even values, so that the cropped image has the same CFA
+/// pattern as the source.  Returns the rectangle that was extracted
+/// and the CFA pattern of the cropped image.
+///
+/// # Example
+///
+/// ```
+/// let src = [
+///     1, 2, 3, 4,
+///     5, 6, 7, 8,
+///     9,10,11,12 ];
+/// let mut dst = [0u8; 4];
+///
+/// let (rect, cfa) = bayer::crop(&src, bayer::BayerDepth::Depth8, 4, 3,
+///         bayer::CFA::RGGB, bayer::Rect::new(1, 1, 2, 2), false,
+///         &mut dst).unwrap();
+/// assert_eq!(dst, [6,7, 10,11]);
+/// assert_eq!(cfa, bayer::CFA::BGGR);
+/// ```
+pub fn crop(src: &[u8], depth: BayerDepth, w: usize, h: usize,
+        cfa: CFA, rect: Rect, even: bool, dst: &mut [u8])
+        -> BayerResult<(Rect, CFA)> {
+    check_unpacked(depth)?;
+    check_input_len(src.len(), depth, w, h)?;
+    rect.check_bounds(w, h)?;
+
+    let rect = if even { rect.align_even() } else { rect };
+    check_output_len(dst.len(), depth, rect.w, rect.h)?;
+
+    let bps = depth.bytes_per_sample();
+    let row_len = bps * rect.w;
+    for y in 0..rect.h {
+        let i = bps * (w * (rect.y + y) + rect.x);
+        let j = row_len * y;
+        dst[j..(j + row_len)].copy_from_slice(&src[i..(i + row_len)]);
+    }
+
+    Ok((rect, cfa.at(rect.x, rect.y)))
+}
+
+/// Convert a w x h Bayer image from one CFA pattern to another, by
+/// dropping the first column and/or the first row.
+///
+/// Any Bayer pattern can be reached this way, at the cost of at most
+/// one column and one row.  Returns the dimensions of the converted
+/// image.
+///
+/// # Example
+///
+/// ```
+/// let src = [
+///     1,2,3,
+///     4,5,6 ];
+/// let mut dst = [0u8; 4];
+///
+/// let (w, h) = bayer::reorder_cfa(&src, bayer::BayerDepth::Depth8, 3, 2,
+///         bayer::CFA::GRBG, bayer::CFA::RGGB, &mut dst).unwrap();
+/// assert_eq!((w, h), (2, 2));
+/// assert_eq!(dst, [2,3, 5,6]);
+/// ```
+pub fn reorder_cfa(src: &[u8], depth: BayerDepth, w: usize, h: usize,
+        from: CFA, to: CFA, dst: &mut [u8])
+        -> BayerResult<(usize, usize)> {
+    // Each of the four patterns starts at one pixel of the 2x2 block
+    // of any other, so the search always succeeds.
+    let &(dx, dy) = [(0, 0), (1, 0), (0, 1), (1, 1)].iter()
+        .find(|&&(dx, dy)| from.at(dx, dy) == to)
+        .expect("unreachable CFA pattern");
+
+    let rect = Rect::new(dx, dy, w.saturating_sub(dx), h.saturating_sub(dy));
+    crop(src, depth, w, h, from, rect, false, dst)?;
+    Ok((rect.w, rect.h))
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{BayerDepth,BayerError,CFA};
+    use super::{Rect,crop,reorder_cfa};
+
+    #[test]
+    fn test_even() {
+        let src = [
+            0x00,0x01, 0x02,0x03, 0x04,0x05,
+            0x10,0x11, 0x12,0x13, 0x14,0x15,
+            0x20,0x21, 0x22,0x23, 0x24,0x25 ];
+        let mut dst = [0u8; 8];
+
+        let res = crop(&src, BayerDepth::Depth16LE, 3, 3,
+                CFA::GBRG, Rect::new(1, 1, 2, 2), true, &mut dst);
+        assert_eq!(res.unwrap(), (Rect::new(0, 0, 2, 2), CFA::GBRG));
+        assert_eq!(dst, [0x00,0x01, 0x02,0x03, 0x10,0x11, 0x12,0x13]);
+    }
+
+    #[test]
+    fn test_out_of_bounds() {
+        let src = [0u8; 16];
+        let mut dst = [0u8; 16];
+
+        match crop(&src, BayerDepth::Depth8, 4, 4,
+                CFA::RGGB, Rect::new(3, 0, 2, 2), false, &mut dst) {
+            Err(BayerError::OutOfBounds { width: 4, height: 4, .. }) => (),
+            _ => panic!(),
+        }
+
+        match crop(&src, BayerDepth::Depth8, 4, 4,
+                CFA::RGGB, Rect::new(1, 1, ::std::usize::MAX, 2), false, &mut dst) {
+            Err(BayerError::OutOfBounds { .. }) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_reorder() {
+        let src = [
+            1,2,3,
+            4,5,6,
+            7,8,9 ];
+
+        let cases = [
+            (CFA::BGGR, (3, 3), [1,2,3, 4,5,6, 7,8,9]),
+            (CFA::GBRG, (2, 3), [2,3, 5,6, 8,9, 0,0,0]),
+            (CFA::GRBG, (3, 2), [4,5,6, 7,8,9, 0,0,0]),
+            (CFA::RGGB, (2, 2), [5,6, 8,9, 0,0,0,0,0]) ];
+
+        for &(to, dim, expected) in cases.iter() {
+            let mut dst = [0u8; 9];
+            let res = reorder_cfa(&src, BayerDepth::Depth8, 3, 3, CFA::BGGR, to, &mut dst);
+            assert_eq!(res.unwrap(), dim);
+            assert_eq!(dst, expected);
+        }
+    }
+}
@@ -0,0 +1,311 @@
+//! Defective pixel maps and correction.
+//!
+//! A defect map lists the defective pixels, rows, and columns of a
+//! sensor.  The text format has one entry per line:
+//!
+//! ```text
+//!   # Comments start with a hash.
+//!   pixel 12 34
+//!   row 100
+//!   column 200
+//! ```
+//!
+//! Lines holding only "x y" are read as pixels, so the plain lists of
+//! coordinates shipped with many cameras can be imported directly.
+
+use std::cmp;
+use std::collections::BTreeSet;
+use std::io::{BufRead,BufReader,Read,Write};
+
+use ::{BayerDepth,BayerError,BayerResult,Demosaic};
+use bayer::{check_input_len,get_sample,put_sample};
+
+/// A map of the defective pixels, rows, and columns of a sensor.
+#[derive(Clone,Debug,Default,Eq,PartialEq)]
+pub struct DefectMap {
+    pixels: BTreeSet<(usize, usize)>,
+    rows: BTreeSet<usize>,
+    columns: BTreeSet<usize>,
+}
+
+impl DefectMap {
+    /// Create an empty defect map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mark the pixel (x, y) as defective.
+    pub fn add_pixel(&mut self, x: usize, y: usize) {
+        self.pixels.insert((x, y));
+    }
+
+    /// Mark row y as defective.
+    pub fn add_row(&mut self, y: usize) {
+        self.rows.insert(y);
+    }
+
+    /// Mark column x as defective.
+    pub fn add_column(&mut self, x: usize) {
+        self.columns.insert(x);
+    }
+
+    /// The defective pixels, in row-major order.
+    pub fn pixels(&self) -> Vec<(usize, usize)> {
+        let mut pixels: Vec<_> = self.pixels.iter().cloned().collect();
+        pixels.sort_by_key(|&(x, y)| (y, x));
+        pixels
+    }
+
+    /// The defective rows.
+    pub fn rows(&self) -> Vec<usize> {
+        self.rows.iter().cloned().collect()
+    }
+
+    /// The defective columns.
+    pub fn columns(&self) -> Vec<usize> {
+        self.columns.iter().cloned().collect()
+    }
+
+    /// Check whether the pixel (x, y) is defective.
+    pub fn is_defective(&self, x: usize, y: usize) -> bool {
+        self.rows.contains(&y)
+            || self.columns.contains(&x)
+            || self.pixels.contains(&(x, y))
+    }
+
+    /// Read a defect map in the text format.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let text = "# hot pixels\n12 34\nrow 7\n";
+    /// let map = bayer::DefectMap::read(&mut text.as_bytes()).unwrap();
+    /// assert_eq!(map.pixels(), [(12, 34)]);
+    /// assert_eq!(map.rows(), [7]);
+    /// ```
+    pub fn read(r: &mut Read) -> BayerResult<Self> {
+        let mut map = DefectMap::new();
+
+        for (i, line) in BufReader::new(r).lines().enumerate() {
+            let line = line?;
+            let line = line.split('#').next().unwrap_or("");
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let invalid = || BayerError::InvalidDefectMap { line: i + 1 };
+
+            let (kind, args) = match words.first() {
+                None => continue,
+                Some(w) if w.parse::<usize>().is_ok() => ("pixel", &words[..]),
+                Some(w) => (*w, &words[1..]),
+            };
+
+            let nums = args.iter()
+                .map(|a| a.parse::<usize>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| invalid())?;
+
+            match (kind, &nums[..]) {
+                ("pixel", &[x, y]) => map.add_pixel(x, y),
+                ("row", &[y]) => map.add_row(y),
+                ("column", &[x]) => map.add_column(x),
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(map)
+    }
+
+    /// Write the defect map in the text format.
+    pub fn write(&self, w: &mut Write) -> BayerResult<()> {
+        writeln!(w, "# bayer defect map")?;
+        for y in self.rows.iter() {
+            writeln!(w, "row {}", y)?;
+        }
+        for x in self.columns.iter() {
+            writeln!(w, "column {}", x)?;
+        }
+        for (x, y) in self.pixels() {
+            writeln!(w, "pixel {} {}", x, y)?;
+        }
+        Ok(())
+    }
+
+    /// Correct the defective pixels of an in-memory w x h Bayer image.
+    ///
+    /// Each defective pixel is replaced by the mean of the nearest
+    /// non-defective pixels of the same colour, two pixels away
+    /// horizontally and vertically.  Pixels without any such
+    /// neighbours are left unchanged.  Returns the number of pixels
+    /// corrected.
+    pub fn correct(&self, raw: &mut [u8], depth: BayerDepth, w: usize, h: usize)
+            -> BayerResult<usize> {
+        check_input_len(raw.len(), depth, w, h)?;
+
+        let mut count = 0;
+        for y in 0..h {
+            for x in 0..w {
+                if !self.is_defective(x, y) {
+                    continue;
+                }
+
+                let mut sum = 0;
+                let mut n = 0;
+                let neighbours = [
+                    (x.wrapping_sub(2), y), (x + 2, y),
+                    (x, y.wrapping_sub(2)), (x, y + 2) ];
+
+                for &(nx, ny) in neighbours.iter() {
+                    if nx < w && ny < h && !self.is_defective(nx, ny) {
+                        sum = sum + get_sample(raw, depth, w * ny + nx);
+                        n = n + 1;
+                    }
+                }
+
+                if n > 0 {
+                    put_sample(raw, depth, w * y + x, (sum + n / 2) / n);
+                    count = count + 1;
+                }
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Mark the RGB pixels of a w x h image that were interpolated
+    /// from defective pixels, so that they can be excluded from
+    /// measurements.
+    ///
+    /// dst receives w * h values in row-major order: 255 where the
+    /// neighbourhood read by the algorithm includes a defective
+    /// pixel, and 0 elsewhere.  To also mark the rows that were filled
+    /// in after BayerError::Truncated, add them to the map first.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut map = bayer::DefectMap::new();
+    /// map.add_pixel(2, 2);
+    ///
+    /// let mut mask = [0u8; 5 * 5];
+    /// map.output_mask(bayer::Demosaic::Linear, 5, 5, &mut mask).unwrap();
+    /// assert_eq!(mask.iter().filter(|&&m| m == 255).count(), 9);
+    /// ```
+    pub fn output_mask(&self, alg: Demosaic, w: usize, h: usize, dst: &mut [u8])
+            -> BayerResult<()> {
+        let len = w.checked_mul(h).unwrap_or(::std::usize::MAX);
+        if dst.len() < len {
+            return Err(BayerError::OutputTooShort { expected: len, got: dst.len() });
+        }
+
+        // The kernels read at most padding pixels away, and the border
+        // handling only reflects pixels from within that distance.
+        let p = alg.padding();
+        let span = |c: usize, n: usize| c.saturating_sub(p)..cmp::min(c.saturating_add(p + 1), n);
+
+        for d in dst[..len].iter_mut() {
+            *d = 0;
+        }
+        for &y in self.rows.iter() {
+            for yy in span(y, h) {
+                for d in dst[(w * yy)..(w * yy + w)].iter_mut() {
+                    *d = 255;
+                }
+            }
+        }
+        for &x in self.columns.iter() {
+            for xx in span(x, w) {
+                for yy in 0..h {
+                    dst[w * yy + xx] = 255;
+                }
+            }
+        }
+        for &(x, y) in self.pixels.iter() {
+            for yy in span(y, h) {
+                for xx in span(x, w) {
+                    dst[w * yy + xx] = 255;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{BayerDepth,BayerError,Demosaic};
+    use super::DefectMap;
+
+    #[test]
+    fn test_round_trip() {
+        let mut map = DefectMap::new();
+        map.add_pixel(3, 1);
+        map.add_pixel(1, 3);
+        map.add_row(2);
+        map.add_column(5);
+
+        let mut text = Vec::new();
+        assert!(map.write(&mut text).is_ok());
+        assert_eq!(DefectMap::read(&mut &text[..]).unwrap(), map);
+        assert_eq!(map.pixels(), [(3, 1), (1, 3)]);
+    }
+
+    #[test]
+    fn test_invalid() {
+        match DefectMap::read(&mut "1 2\nrow\n".as_bytes()) {
+            Err(BayerError::InvalidDefectMap { line: 2 }) => (),
+            _ => panic!(),
+        }
+
+        assert!(DefectMap::read(&mut "pixel 1 x\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_correct() {
+        let mut raw = [
+            10,20,10,20,10,
+            30,40,30,40,30,
+            10,20,99,20,10,
+            30,40,30,40,30,
+            10,20,10,20,10 ];
+
+        let mut map = DefectMap::new();
+        map.add_pixel(2, 2);
+        map.add_column(4);
+
+        let res = map.correct(&mut raw, BayerDepth::Depth8, 5, 5);
+        assert_eq!(res.unwrap(), 5);
+        assert_eq!(raw[12], 10);
+        assert_eq!(&raw[4..10], &[10, 30,40,30,40,30]);
+
+        // All of the neighbours of (4, 2) are defective.
+        assert_eq!(raw[14], 10);
+    }
+
+    #[test]
+    fn test_output_mask() {
+        let mut map = DefectMap::new();
+        map.add_pixel(0, 0);
+        map.add_row(6);
+        map.add_column(8);
+
+        let expected = [
+            1,1,0,0,0,0,0,1,
+            1,1,0,0,0,0,0,1,
+            0,0,0,0,0,0,0,1,
+            0,0,0,0,0,0,0,1,
+            0,0,0,0,0,0,0,1,
+            1,1,1,1,1,1,1,1 ];
+
+        let mut mask = [0u8; 8 * 6];
+        let res = map.output_mask(Demosaic::Linear, 8, 6, &mut mask);
+        assert!(res.is_ok());
+        let mask: Vec<u8> = mask.iter().map(|&m| m / 255).collect();
+        assert_eq!(&mask[..], &expected[..]);
+
+        let res = map.output_mask(Demosaic::None, 8, 6, &mut [0u8; 47]);
+        match res {
+            Err(BayerError::OutputTooShort { expected: 48, got: 47 }) => (),
+            _ => panic!(),
+        }
+    }
+}
@@ -0,0 +1,459 @@
+//! Calibration of the Bayer rows as they are read.
+//!
+//! The samples are first scaled up from the significant bits of the
+//! source, or widened from 8 to 16 bits, then the dark frame is
+//! subtracted.  Black and white levels replace the scaling from the
+//! significant bits, and are applied before any widening.  Finally,
+//! the samples are white balanced.
+//!
+//! The calibrated samples are offset by a pedestal, so that samples
+//! below zero survive the unsigned interpolation kernels.  The
+//! pedestal is removed again from each interpolated row.  The offset
+//! samples may exceed the range of the image depth, so they are held
+//! in 32-bit samples, and are only clamped when they are written out.
+
+use std::cmp;
+use std::sync::Arc;
+
+use ::{BayerDepth,BayerError,BayerResult,CFA,DemosaicOptions,Intermediate,Levels,RasterDepth,Saturation,Scaling};
+use demosaic::saturation;
+use raster::RasterSample;
+
+/// The scaling of the raw samples.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+enum Scale {
+    Identity,
+    /// Scale samples with the given maximum up to the full 16-bit
+    /// range, clamping them to the maximum first.
+    Full(u16),
+    /// Shift 8-bit samples left.
+    Shift(u8),
+    /// Shift 8-bit samples left, filling the low bits with copies of
+    /// the high bits.
+    Replicate(u8),
+}
+
+impl Scale {
+    fn apply(self, v: u32) -> u32 {
+        match self {
+            Scale::Identity => v,
+            Scale::Full(max) => {
+                let max = max as u32;
+                let v = cmp::min(v, max);
+                (v * 0xFFFF + max / 2) / max
+            },
+            Scale::Shift(n) => cmp::min(v, 0xFF) << n,
+            Scale::Replicate(n) => {
+                let v = cmp::min(v, 0xFF);
+                (v << n) | (v >> (8 - n))
+            },
+        }
+    }
+}
+
+/// The black and white levels, stretching the samples to the given
+/// maximum.
+#[derive(Clone,Copy,Debug)]
+struct Stretch {
+    levels: Levels,
+    max: u16,
+}
+
+impl Stretch {
+    fn apply<T: RasterSample>(self, y: usize, row: &mut [T]) {
+        let white = self.levels.white as u32;
+        let max = self.max as u32;
+        let black = &self.levels.black[(2 * (y % 2))..(2 * (y % 2) + 2)];
+        for (x, e) in row.iter_mut().enumerate() {
+            let b = black[x % 2] as u32;
+            let v = cmp::min(e.to_u32(), white).saturating_sub(b);
+            let range = white - b;
+            *e = T::saturating_from((v * max + range / 2) / range);
+        }
+    }
+}
+
+/// The calibration of one image.
+#[derive(Clone,Debug)]
+pub(crate) struct Calibration {
+    width: usize,
+    stretch: Option<Stretch>,
+    scale: Scale,
+    dark_frame: Option<Arc<Vec<u16>>>,
+    /// The white balance multipliers of the top-left 2x2 block of
+    /// photosites, in row-major order.
+    balance: Option<[f64; 4]>,
+    pedestal: u16,
+    saturation: Saturation,
+}
+
+impl Calibration {
+    /// The calibration of a w x h image, with the given pattern if it
+    /// is a Bayer image.
+    pub(crate) fn new(options: &DemosaicOptions, depth: BayerDepth, cfa: Option<CFA>,
+            w: usize, h: usize)
+            -> BayerResult<Self> {
+        if options.source_bits < 1 || options.source_bits > 16 {
+            return Err(BayerError::InvalidArgument {
+                reason: "source bits must be between 1 and 16",
+            });
+        }
+
+        let bits = match depth.packed() {
+            Some(format) => cmp::min(options.source_bits as u32, format.bits()),
+            None => options.source_bits as u32,
+        };
+
+        let scale = match depth {
+            BayerDepth::Depth8 => Scale::Identity,
+            _ if options.levels.is_some() => Scale::Identity,
+            _ if bits < 16 => Scale::Full((0xFFFFu32 >> (16 - bits)) as u16),
+            _ => Scale::Identity,
+        };
+        let max = if depth == BayerDepth::Depth8 { 0xFF } else { 0xFFFF };
+
+        Self::with_scale(options, max, scale, cfa, w, h)
+    }
+
+    /// The calibration of a w x h 8-bit Bayer image that is widened
+    /// to 16 bits.
+    pub(crate) fn widened(options: &DemosaicOptions, cfa: Option<CFA>, w: usize, h: usize)
+            -> BayerResult<Self> {
+        if options.widen_shift > 8 {
+            return Err(BayerError::InvalidArgument {
+                reason: "the widening shift must be at most 8",
+            });
+        }
+
+        let scale = if options.widen_replicate {
+            Scale::Replicate(options.widen_shift)
+        } else {
+            Scale::Shift(options.widen_shift)
+        };
+        Self::with_scale(options, 0xFF, scale, cfa, w, h)
+    }
+
+    /// The calibration of a w x h image demosaiced into a raster of
+    /// the given depth.  8-bit images are widened for 16-bit rasters.
+    pub(crate) fn for_raster(options: &DemosaicOptions, depth: BayerDepth,
+            raster: RasterDepth, cfa: Option<CFA>, w: usize, h: usize)
+            -> BayerResult<Self> {
+        if depth == BayerDepth::Depth8 && raster == RasterDepth::Depth16 {
+            Self::widened(options, cfa, w, h)
+        } else {
+            Self::new(options, depth, cfa, w, h)
+        }
+    }
+
+    /// The calibration with the given scale, where max is the largest
+    /// raw sample value.
+    fn with_scale(options: &DemosaicOptions, max: u16, scale: Scale, cfa: Option<CFA>,
+            w: usize, h: usize)
+            -> BayerResult<Self> {
+        let stretch = match options.levels {
+            Some(levels) => {
+                if levels.black.iter().any(|&b| b >= levels.white) {
+                    return Err(BayerError::InvalidArgument {
+                        reason: "the white level must be above the black levels",
+                    });
+                }
+                if options.dark_frame.is_some() {
+                    return Err(BayerError::InvalidArgument {
+                        reason: "the levels cannot be combined with a dark frame",
+                    });
+                }
+                Some(Stretch { levels, max })
+            },
+            None => None,
+        };
+
+        let balance = match (options.white_balance, cfa) {
+            (Some(wb), Some(cfa)) => {
+                let gains = wb.gains();
+                if gains.iter().any(|&g| !(g >= 0.0 && g.is_finite())) {
+                    return Err(BayerError::InvalidArgument {
+                        reason: "the white balance multipliers must be finite and not negative",
+                    });
+                }
+                let gain = |x, y| gains[cfa.at(x, y).channel()];
+                Some([gain(0, 0), gain(1, 0), gain(0, 1), gain(1, 1)])
+            },
+            (Some(_), None) => return Err(BayerError::InvalidArgument {
+                reason: "only Bayer images can be white balanced before interpolation",
+            }),
+            (None, _) => None,
+        };
+
+        let mut pedestal = 0;
+        if let Some(ref dark) = options.dark_frame {
+            if Some(dark.len()) != w.checked_mul(h) {
+                return Err(BayerError::InvalidArgument {
+                    reason: "the dark frame does not match the image",
+                });
+            }
+            if options.intermediate == Intermediate::Signed {
+                pedestal = scale.apply(dark.iter().cloned().max().unwrap_or(0) as u32) as u16;
+            }
+        }
+
+        Ok(Calibration {
+            width: w,
+            stretch,
+            scale,
+            dark_frame: options.dark_frame.clone(),
+            balance,
+            pedestal,
+            saturation: options.saturation,
+        })
+    }
+
+    /// The saturation handling.  The white level is offset by the
+    /// pedestal in the calibrated samples.
+    pub(crate) fn saturation(&self) -> Saturation {
+        self.saturation
+    }
+
+    /// The scaling of the raw samples, for the processing report.
+    pub(crate) fn scaling(&self) -> Scaling {
+        match self.scale {
+            Scale::Identity => Scaling::Identity,
+            Scale::Full(_) => Scaling::SourceBits,
+            Scale::Shift(shift) => Scaling::Widen { shift, replicate: false },
+            Scale::Replicate(shift) => Scaling::Widen { shift, replicate: true },
+        }
+    }
+
+    /// The offset added to the calibrated samples.
+    pub(crate) fn pedestal(&self) -> u16 {
+        self.pedestal
+    }
+
+    /// The calibration without a pedestal, clamping the calibrated
+    /// samples to zero.
+    pub(crate) fn unsigned(self) -> Self {
+        Calibration { pedestal: 0, ..self }
+    }
+
+    /// Calibrate the raw samples of row y.
+    pub(crate) fn apply<T: RasterSample>(&self, y: usize, row: &mut [T]) {
+        if let Some(stretch) = self.stretch {
+            stretch.apply(y, row);
+        }
+
+        let scale = self.scale;
+        if scale != Scale::Identity {
+            for e in row.iter_mut() {
+                *e = T::saturating_from(scale.apply(e.to_u32()));
+            }
+        }
+
+        if let Some(ref dark) = self.dark_frame {
+            let start = self.width * y;
+            let dark = &dark[start..(start + self.width)];
+            for (e, &d) in row.iter_mut().zip(dark.iter()) {
+                let d = scale.apply(d as u32);
+                let v = e.to_u32() as i32 - d as i32 + self.pedestal as i32;
+                *e = T::saturating_from(if v < 0 { 0 } else { v as u32 });
+            }
+        }
+
+        if let Some(ref balance) = self.balance {
+            // The pedestal is not balanced.
+            let pedestal = self.pedestal as f64;
+            let gains = &balance[(2 * (y % 2))..(2 * (y % 2) + 2)];
+            for (x, e) in row.iter_mut().enumerate() {
+                let v = (e.to_u32() as f64 - pedestal) * gains[x % 2] + pedestal + 0.5;
+                *e = T::saturating_from(v.max(0.0).min(u32::max_value() as f64) as u32);
+            }
+        }
+
+        saturation::clip_row(self.saturation, self.pedestal, row);
+    }
+}
+
+/// Remove the pedestal from an interpolated row.
+pub(crate) fn remove_pedestal<T: RasterSample>(pedestal: u16, dst: &mut [T]) {
+    if pedestal > 0 {
+        for e in dst.iter_mut() {
+            *e = T::saturating_from(e.to_u32().saturating_sub(pedestal as u32));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use ::{BayerDepth,BayerError,CFA,Demosaic,DemosaicOptions,Intermediate,Levels,RasterDepth,RasterMut,WhiteBalance,apply_white_balance,demosaic_from};
+
+    fn run(src: &[u8], dark: &[u16], intermediate: Intermediate) -> Vec<u8> {
+        let mut options = DemosaicOptions::default();
+        options.dark_frame = Some(Arc::new(dark.to_vec()));
+        options.intermediate = intermediate;
+
+        let mut buf = vec![0u8; 3 * 4 * 4];
+        let res = demosaic_from(&mut &src[..], BayerDepth::Depth8, CFA::RGGB,
+                Demosaic::Linear, &options,
+                &mut RasterMut::new(4, 4, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+        buf
+    }
+
+    #[test]
+    fn test_dark_frame() {
+        // A dark exposure whose green noise straddles the dark frame.
+        let src = [
+            12,14,12,14,
+            10,12,10,12,
+            12,14,12,14,
+            10,12,10,12 ];
+        let dark = [12u16; 16];
+
+        // Clamping first turns the noise into a bias.
+        let unsigned = run(&src, &dark, Intermediate::Unsigned);
+        assert_eq!(&unsigned[30..33], &[0, 1, 0]);
+
+        // The negative samples cancel the positive ones.
+        let signed = run(&src, &dark, Intermediate::Signed);
+        assert_eq!(&signed[30..33], &[0, 0, 0]);
+        assert_eq!(&signed[33..36], &[0, 2, 0]);
+    }
+
+    #[test]
+    fn test_dark_frame_highlights() {
+        // The pedestal does not clip the highlights.
+        let src = [250u8; 16];
+        let mut dark = [0u16; 16];
+        dark[0] = 12;
+
+        let signed = run(&src, &dark, Intermediate::Signed);
+        assert_eq!(&signed[30..33], &[250, 250, 250]);
+        assert_eq!(&signed[30..33], &run(&src, &dark, Intermediate::Unsigned)[30..33]);
+    }
+
+    #[test]
+    fn test_source_bits() {
+        // 12-bit samples in little-endian 16-bit words, one out of range.
+        let src = [
+            0x00,0x00, 0xFF,0x0F, 0x00,0x08, 0x00,0x10 ];
+
+        let mut options = DemosaicOptions::default();
+        options.source_bits = 12;
+
+        let mut buf = [0u8; 6 * 2 * 2];
+        let mut dst = RasterMut::new(2, 2, RasterDepth::Depth16, &mut buf);
+        let res = demosaic_from(&mut &src[..], BayerDepth::Depth16LE, CFA::RGGB,
+                Demosaic::None, &options, &mut dst);
+        assert!(res.is_ok());
+        assert_eq!(dst.borrow_row_u16_mut(0), &[0,0,0, 0,0xFFFF,0]);
+        assert_eq!(dst.borrow_row_u16_mut(1), &[0,0x8008,0, 0,0,0xFFFF]);
+
+        options.source_bits = 17;
+        let res = demosaic_from(&mut &src[..], BayerDepth::Depth16LE, CFA::RGGB,
+                Demosaic::None, &options, &mut dst);
+        match res {
+            Err(BayerError::InvalidArgument { .. }) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_levels() {
+        // 10-bit samples in little-endian 16-bit words, one above the
+        // white level.
+        let src = [
+            0x40,0x00, 0xFF,0x03, 0x1D,0x02, 0xD0,0x07 ];
+
+        let mut options = DemosaicOptions::default();
+        options.source_bits = 12;
+        options.levels = Some(Levels { black: [64, 60, 60, 50], white: 1023 });
+
+        let mut buf = [0u8; 6 * 2 * 2];
+        let mut dst = RasterMut::new(2, 2, RasterDepth::Depth16, &mut buf);
+        let res = demosaic_from(&mut &src[..], BayerDepth::Depth16LE, CFA::RGGB,
+                Demosaic::None, &options, &mut dst);
+        assert!(res.is_ok());
+        assert_eq!(dst.borrow_row_u16_mut(0), &[0,0,0, 0,0xFFFF,0]);
+        assert_eq!(dst.borrow_row_u16_mut(1), &[0,0x7FDD,0, 0,0,0xFFFF]);
+
+        // 8-bit samples are stretched before they are widened.
+        options.levels = Some(Levels { black: [16; 4], white: 235 });
+        let res = demosaic_from(&mut &[16u8, 126, 126, 240][..], BayerDepth::Depth8, CFA::RGGB,
+                Demosaic::None, &options, &mut dst);
+        assert!(res.is_ok());
+        assert_eq!(dst.borrow_row_u16_mut(0), &[0,0,0, 0,0x8000,0]);
+        assert_eq!(dst.borrow_row_u16_mut(1), &[0,0x8000,0, 0,0,0xFF00]);
+
+        options.levels = Some(Levels { black: [16, 16, 16, 235], white: 235 });
+        let res = demosaic_from(&mut &[0u8; 4][..], BayerDepth::Depth8, CFA::RGGB,
+                Demosaic::None, &options, &mut dst);
+        match res {
+            Err(BayerError::InvalidArgument { .. }) => (),
+            _ => panic!(),
+        }
+
+        options.levels = Some(Levels { black: [16; 4], white: 235 });
+        options.dark_frame = Some(Arc::new(vec![0; 4]));
+        let res = demosaic_from(&mut &[0u8; 4][..], BayerDepth::Depth8, CFA::RGGB,
+                Demosaic::None, &options, &mut dst);
+        match res {
+            Err(BayerError::InvalidArgument { .. }) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_white_balance() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+        let wb = WhiteBalance::new(1.5, 1.0, 0.75);
+
+        // Balancing as the rows are read is the same as balancing the
+        // raw image beforehand.
+        let mut balanced = src;
+        assert!(apply_white_balance(&mut balanced, BayerDepth::Depth8, 4, 4,
+                CFA::GBRG, wb, 255).is_ok());
+
+        let mut expected = [0u8; 3 * 4 * 4];
+        let res = demosaic_from(&mut &balanced[..], BayerDepth::Depth8, CFA::GBRG,
+                Demosaic::Cubic, &DemosaicOptions::default(),
+                &mut RasterMut::new(4, 4, RasterDepth::Depth8, &mut expected));
+        assert!(res.is_ok());
+
+        let mut options = DemosaicOptions::default();
+        options.white_balance = Some(wb);
+        let mut buf = [0u8; 3 * 4 * 4];
+        let res = demosaic_from(&mut &src[..], BayerDepth::Depth8, CFA::GBRG,
+                Demosaic::Cubic, &options,
+                &mut RasterMut::new(4, 4, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+        assert_eq!(&buf[..], &expected[..]);
+
+        options.white_balance = Some(WhiteBalance::new(-1.0, 1.0, 1.0));
+        let res = demosaic_from(&mut &src[..], BayerDepth::Depth8, CFA::GBRG,
+                Demosaic::Cubic, &options,
+                &mut RasterMut::new(4, 4, RasterDepth::Depth8, &mut buf));
+        match res {
+            Err(BayerError::InvalidArgument { .. }) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_dark_frame_size() {
+        let mut options = DemosaicOptions::default();
+        options.dark_frame = Some(Arc::new(vec![0; 15]));
+
+        let mut buf = [0u8; 3 * 4 * 4];
+        let res = demosaic_from(&mut &[0u8; 16][..], BayerDepth::Depth8, CFA::RGGB,
+                Demosaic::Linear, &options,
+                &mut RasterMut::new(4, 4, RasterDepth::Depth8, &mut buf));
+        match res {
+            Err(BayerError::InvalidArgument { .. }) => (),
+            _ => panic!(),
+        }
+    }
+}
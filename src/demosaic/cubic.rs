@@ -23,34 +23,31 @@
 use std::cmp::min;
 use std::io::Read;
 
-#[cfg(feature = "rayon")]
-use std::slice;
-
-#[cfg(feature = "rayon")]
-use rayon::prelude::*;
-
-use ::{BayerDepth,BayerError,BayerResult,CFA,RasterMut};
-use bayer::{BayerRead8,BayerRead16};
-use border_mirror::*;
-use demosaic::check_depth;
+use ::{BayerDepth,BayerResult,Border,CFA,Demosaic,DemosaicOptions,RasterMut};
+use demosaic::{Engine,run_engine};
 
 const PADDING: usize = 3;
 
+pub(crate) const ENGINE: Engine = Engine {
+    alg: Demosaic::Cubic,
+    padding: PADDING,
+    border: Border::Mirror,
+    kernel_u8: debayer_row_u8,
+    kernel_u16: debayer_row_u16,
+    kernel_u32: debayer_row_u32,
+};
+
 pub fn run(r: &mut Read,
         depth: BayerDepth, cfa: CFA, dst: &mut RasterMut)
         -> BayerResult<()> {
-    if dst.w < 4 || dst.h < 4 {
-        return Err(BayerError::WrongResolution);
-    }
-    if !check_depth(depth, dst.depth) {
-        return Err(BayerError::WrongDepth);
-    }
+    run_with_options(r, depth, cfa, &DemosaicOptions::default(), dst)
+}
 
-    match depth {
-        BayerDepth::Depth8 => debayer_u8(r, cfa, dst),
-        BayerDepth::Depth16BE => debayer_u16(r, true, cfa, dst),
-        BayerDepth::Depth16LE => debayer_u16(r, false, cfa, dst),
-    }
+pub fn run_with_options(r: &mut Read,
+        depth: BayerDepth, cfa: CFA, options: &DemosaicOptions,
+        dst: &mut RasterMut)
+        -> BayerResult<()> {
+    run_engine(&ENGINE, r, depth, cfa, options, dst)
 }
 
 macro_rules! apply_kernel_row {
@@ -145,246 +142,61 @@ macro_rules! apply_kernel_g {
     }}
 }
 
-/*--------------------------------------------------------------*/
-/* Rayon                                                        */
 /*--------------------------------------------------------------*/
 
-#[cfg(feature = "rayon")]
 #[allow(unused_parens)]
-fn debayer_u8(r: &mut Read, cfa: CFA, dst: &mut RasterMut)
-        -> BayerResult<()> {
-    let (w, h) = (dst.w, dst.h);
-    let mut data = vec![0u8; (2 * PADDING + w) * (2 * PADDING + h)];
-
-    // Read all data.
-    {
-        let stride = 2 * PADDING + w;
-        let rdr = BorderMirror8::new(w, PADDING);
-        for mut row in data.chunks_mut(stride).skip(PADDING).take(h) {
-            rdr.read_line(r, &mut row)?;
-        }
-
-        {
-            let (top, src) = data.split_at_mut(stride * PADDING);
-            top[(stride * 0)..(stride * 1)].copy_from_slice(&src[(stride * 3)..(stride * 4)]);
-            top[(stride * 1)..(stride * 2)].copy_from_slice(&src[(stride * 2)..(stride * 3)]);
-            top[(stride * 2)..(stride * 3)].copy_from_slice(&src[(stride * 1)..(stride * 2)]);
-        }
-
-        {
-            let (src, bottom) = data.split_at_mut(stride * (h + PADDING));
-            let yy = PADDING + h;
-            bottom[(stride * 0)..(stride * 1)].copy_from_slice(&src[(stride * (yy - 2))..(stride * (yy - 1))]);
-            bottom[(stride * 1)..(stride * 2)].copy_from_slice(&src[(stride * (yy - 3))..(stride * (yy - 2))]);
-            bottom[(stride * 2)..(stride * 3)].copy_from_slice(&src[(stride * (yy - 4))..(stride * (yy - 3))]);
-        }
-    }
-
-    dst.buf.par_chunks_mut(dst.stride).enumerate()
-            .for_each(|(y, mut row)| {
-        let stride = 2 * PADDING + w;
-        let prv3 = &data[(stride * (PADDING + y - 3)) .. (stride * (PADDING + y - 2))];
-        let prv2 = &data[(stride * (PADDING + y - 2)) .. (stride * (PADDING + y - 1))];
-        let prv1 = &data[(stride * (PADDING + y - 1)) .. (stride * (PADDING + y + 0))];
-        let curr = &data[(stride * (PADDING + y + 0)) .. (stride * (PADDING + y + 1))];
-        let nxt1 = &data[(stride * (PADDING + y + 1)) .. (stride * (PADDING + y + 2))];
-        let nxt2 = &data[(stride * (PADDING + y + 2)) .. (stride * (PADDING + y + 3))];
-        let nxt3 = &data[(stride * (PADDING + y + 3)) .. (stride * (PADDING + y + 4))];
-        let cfa_y = if y % 2 == 0 { cfa } else { cfa.next_y() };
-
-        apply_kernel_row!(u8; row, prv3, prv2, prv1, curr, nxt1, nxt2, nxt3, cfa_y, w);
-    });
-
-    Ok(())
+fn debayer_row_u8(rows: &[&[u8]], cfa: CFA, row: &mut [u8]) {
+    let w = row.len() / 3;
+    apply_kernel_row!(u8; row, rows[0], rows[1], rows[2], rows[3], rows[4], rows[5], rows[6], cfa, w);
 }
 
-#[cfg(feature = "rayon")]
 #[allow(unused_parens)]
-fn debayer_u16(r: &mut Read, be: bool, cfa: CFA, dst: &mut RasterMut)
-        -> BayerResult<()> {
-    let (w, h) = (dst.w, dst.h);
-    let mut data = vec![0u16; (2 * PADDING + w) * (2 * PADDING + h)];
-
-    // Read all data.
-    {
-        let stride = 2 * PADDING + w;
-        let rdr: Box<BayerRead16> = if be {
-            Box::new(BorderMirror16BE::new(w, PADDING))
-        } else {
-            Box::new(BorderMirror16LE::new(w, PADDING))
-        };
-
-        for mut row in data.chunks_mut(stride).skip(PADDING).take(h) {
-            rdr.read_line(r, &mut row)?;
-        }
-
-        {
-            let (top, src) = data.split_at_mut(stride * PADDING);
-            top[(stride * 0)..(stride * 1)].copy_from_slice(&src[(stride * 3)..(stride * 4)]);
-            top[(stride * 1)..(stride * 2)].copy_from_slice(&src[(stride * 2)..(stride * 3)]);
-            top[(stride * 2)..(stride * 3)].copy_from_slice(&src[(stride * 1)..(stride * 2)]);
-        }
-
-        {
-            let (src, bottom) = data.split_at_mut(stride * (h + PADDING));
-            let yy = PADDING + h;
-            bottom[(stride * 0)..(stride * 1)].copy_from_slice(&src[(stride * (yy - 2))..(stride * (yy - 1))]);
-            bottom[(stride * 1)..(stride * 2)].copy_from_slice(&src[(stride * (yy - 3))..(stride * (yy - 2))]);
-            bottom[(stride * 2)..(stride * 3)].copy_from_slice(&src[(stride * (yy - 4))..(stride * (yy - 3))]);
-        }
-    }
-
-    dst.buf.par_chunks_mut(dst.stride).enumerate()
-            .for_each(|(y, mut row)| {
-        let stride = 2 * PADDING + w;
-        let prv3 = &data[(stride * (PADDING + y - 3)) .. (stride * (PADDING + y - 2))];
-        let prv2 = &data[(stride * (PADDING + y - 2)) .. (stride * (PADDING + y - 1))];
-        let prv1 = &data[(stride * (PADDING + y - 1)) .. (stride * (PADDING + y + 0))];
-        let curr = &data[(stride * (PADDING + y + 0)) .. (stride * (PADDING + y + 1))];
-        let nxt1 = &data[(stride * (PADDING + y + 1)) .. (stride * (PADDING + y + 2))];
-        let nxt2 = &data[(stride * (PADDING + y + 2)) .. (stride * (PADDING + y + 3))];
-        let nxt3 = &data[(stride * (PADDING + y + 3)) .. (stride * (PADDING + y + 4))];
-        let cfa_y = if y % 2 == 0 { cfa } else { cfa.next_y() };
-
-        let row16 = unsafe{ slice::from_raw_parts_mut(row.as_mut_ptr() as *mut u16, row.len() / 2) };
-        apply_kernel_row!(u16; row16, prv3, prv2, prv1, curr, nxt1, nxt2, nxt3, cfa_y, w);
-    });
-
-    Ok(())
+fn debayer_row_u16(rows: &[&[u16]], cfa: CFA, row: &mut [u16]) {
+    let w = row.len() / 3;
+    apply_kernel_row!(u16; row, rows[0], rows[1], rows[2], rows[3], rows[4], rows[5], rows[6], cfa, w);
 }
 
-/*--------------------------------------------------------------*/
-/* Naive                                                        */
-/*--------------------------------------------------------------*/
-
-#[cfg(not(feature = "rayon"))]
 #[allow(unused_parens)]
-fn debayer_u8(r: &mut Read, cfa: CFA, dst: &mut RasterMut)
-        -> BayerResult<()> {
-    let (w, h) = (dst.w, dst.h);
-    let mut prv3 = vec![0u8; 2 * PADDING + w];
-    let mut prv2 = vec![0u8; 2 * PADDING + w];
-    let mut prv1 = vec![0u8; 2 * PADDING + w];
-    let mut curr = vec![0u8; 2 * PADDING + w];
-    let mut nxt1 = vec![0u8; 2 * PADDING + w];
-    let mut nxt2 = vec![0u8; 2 * PADDING + w];
-    let mut nxt3 = vec![0u8; 2 * PADDING + w];
-    let mut cfa = cfa;
-
-    let rdr = BorderMirror8::new(w, PADDING);
-    rdr.read_line(r, &mut curr)?;
-    rdr.read_line(r, &mut nxt1)?;
-    rdr.read_line(r, &mut nxt2)?;
-    rdr.read_line(r, &mut nxt3)?;
-
-    prv1.copy_from_slice(&nxt1);
-    prv2.copy_from_slice(&nxt2);
-    prv3.copy_from_slice(&nxt3);
-
-    {   // y = 0.
-        let row = dst.borrow_row_u8_mut(0);
-        apply_kernel_row!(u8; row, nxt3, nxt2, nxt1, curr, nxt1, nxt2, nxt3, cfa, w);
-        cfa = cfa.next_y();
-    }
-
-    for y in 1..(h - 3) {
-        rotate!(prv3 <- prv2 <- prv1 <- curr <- nxt1 <- nxt2 <- nxt3);
-        rdr.read_line(r, &mut nxt3)?;
-
-        let row = dst.borrow_row_u8_mut(y);
-        apply_kernel_row!(u8; row, prv3, prv2, prv1, curr, nxt1, nxt2, nxt3, cfa, w);
-        cfa = cfa.next_y();
-    }
-
-    {   // y = h - 3.
-        let row = dst.borrow_row_u8_mut(h - 3);
-        apply_kernel_row!(u8; row, prv2, prv1, curr, nxt1, nxt2, nxt3, nxt2, cfa, w);
-        cfa = cfa.next_y();
-    }
-
-    {   // y = h - 2.
-        let row = dst.borrow_row_u8_mut(h - 2);
-        apply_kernel_row!(u8; row, prv1, curr, nxt1, nxt2, nxt3, nxt2, nxt1, cfa, w);
-        cfa = cfa.next_y();
-    }
-
-    {   // y = h - 1.
-        let row = dst.borrow_row_u8_mut(h - 1);
-        apply_kernel_row!(u8; row, curr, nxt1, nxt2, nxt3, nxt2, nxt1, curr, cfa, w);
-    }
-
-    Ok(())
+fn debayer_row_u32(rows: &[&[u32]], cfa: CFA, row: &mut [u32]) {
+    let w = row.len() / 3;
+    apply_kernel_row!(u32; row, rows[0], rows[1], rows[2], rows[3], rows[4], rows[5], rows[6], cfa, w);
 }
 
-#[cfg(not(feature = "rayon"))]
-#[allow(unused_parens)]
-fn debayer_u16(r: &mut Read, be: bool, cfa: CFA, dst: &mut RasterMut)
-        -> BayerResult<()> {
-    let (w, h) = (dst.w, dst.h);
-    let mut prv3 = vec![0u16; 2 * PADDING + w];
-    let mut prv2 = vec![0u16; 2 * PADDING + w];
-    let mut prv1 = vec![0u16; 2 * PADDING + w];
-    let mut curr = vec![0u16; 2 * PADDING + w];
-    let mut nxt1 = vec![0u16; 2 * PADDING + w];
-    let mut nxt2 = vec![0u16; 2 * PADDING + w];
-    let mut nxt3 = vec![0u16; 2 * PADDING + w];
-    let mut cfa = cfa;
-
-    let rdr: Box<BayerRead16> = if be {
-        Box::new(BorderMirror16BE::new(w, PADDING))
-    } else {
-        Box::new(BorderMirror16LE::new(w, PADDING))
-    };
-    rdr.read_line(r, &mut curr)?;
-    rdr.read_line(r, &mut nxt1)?;
-    rdr.read_line(r, &mut nxt2)?;
-    rdr.read_line(r, &mut nxt3)?;
-
-    prv1.copy_from_slice(&nxt1);
-    prv2.copy_from_slice(&nxt2);
-    prv3.copy_from_slice(&nxt3);
-
-    {   // y = 0.
-        let row = dst.borrow_row_u16_mut(0);
-        apply_kernel_row!(u16; row, nxt3, nxt2, nxt1, curr, nxt1, nxt2, nxt3, cfa, w);
-        cfa = cfa.next_y();
-    }
-
-    for y in 1..(h - 3) {
-        rotate!(prv3 <- prv2 <- prv1 <- curr <- nxt1 <- nxt2 <- nxt3);
-        rdr.read_line(r, &mut nxt3)?;
-
-        let row = dst.borrow_row_u16_mut(y);
-        apply_kernel_row!(u16; row, prv3, prv2, prv1, curr, nxt1, nxt2, nxt3, cfa, w);
-        cfa = cfa.next_y();
-    }
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use ::{BayerDepth,CFA,DemosaicOptions,RasterDepth,RasterMut};
+    use super::{run,run_with_options};
 
-    {   // y = h - 3.
-        let row = dst.borrow_row_u16_mut(h - 3);
-        apply_kernel_row!(u16; row, prv2, prv1, curr, nxt1, nxt2, nxt3, nxt2, cfa, w);
-        cfa = cfa.next_y();
-    }
+    #[test]
+    fn test_parallel() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+
+        const IMG_W: usize = 4;
+        const IMG_H: usize = 4;
+        let mut expected = [0u8; 3 * IMG_W * IMG_H];
+        let mut buf = [0u8; 3 * IMG_W * IMG_H];
 
-    {   // y = h - 2.
-        let row = dst.borrow_row_u16_mut(h - 2);
-        apply_kernel_row!(u16; row, prv1, curr, nxt1, nxt2, nxt3, nxt2, nxt1, cfa, w);
-        cfa = cfa.next_y();
-    }
+        let mut options = DemosaicOptions::default();
+        options.parallel = false;
+        let res = run_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::GRBG, &options,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut expected));
+        assert!(res.is_ok());
 
-    {   // y = h - 1.
-        let row = dst.borrow_row_u16_mut(h - 1);
-        apply_kernel_row!(u16; row, curr, nxt1, nxt2, nxt3, nxt2, nxt1, curr, cfa, w);
+        options.parallel = true;
+        let res = run_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::GRBG, &options,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+        assert_eq!(&buf[..], &expected[..]);
     }
 
-    Ok(())
-}
-
-#[cfg(test)]
-mod tests {
-    use std::io::Cursor;
-    use ::{CFA,RasterDepth,RasterMut};
-    use super::debayer_u8;
-
     #[test]
     fn test_even() {
         // R: set.seed(0); matrix(floor(runif(n=64, min=0, max=256)), nrow=8, byrow=TRUE)
@@ -412,7 +224,7 @@ mod tests {
         const IMG_H: usize = 8;
         let mut buf = [0u8; 3 * IMG_W * IMG_H];
 
-        let res = debayer_u8(&mut Cursor::new(&src[..]), CFA::RGGB,
+        let res = run(&mut Cursor::new(&src[..]), BayerDepth::Depth8, CFA::RGGB,
                 &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
         assert!(res.is_ok());
         assert_eq!(&buf[..], &expected[..]);
@@ -443,7 +255,7 @@ mod tests {
         const IMG_H: usize = 7;
         let mut buf = [0u8; 3 * IMG_W * IMG_H];
 
-        let res = debayer_u8(&mut Cursor::new(&src[..]), CFA::RGGB,
+        let res = run(&mut Cursor::new(&src[..]), BayerDepth::Depth8, CFA::RGGB,
                 &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
         assert!(res.is_ok());
         assert_eq!(&buf[..], &expected[..]);
@@ -473,7 +285,7 @@ mod tests {
         const IMG_H: usize = 7;
         let mut buf = [0u8; 3 * IMG_W * IMG_H];
 
-        let res = debayer_u8(&mut Cursor::new(&src[..]), CFA::RGGB,
+        let res = run(&mut Cursor::new(&src[..]), BayerDepth::Depth8, CFA::RGGB,
                 &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
         assert!(res.is_ok());
         assert_eq!(&buf[..], &expected[..]);
@@ -14,38 +14,37 @@
 
 use std::io::Read;
 
-#[cfg(feature = "rayon")]
-use std::slice;
-
-#[cfg(feature = "rayon")]
-use rayon::prelude::*;
-
-use ::{BayerDepth,BayerError,BayerResult,CFA,RasterMut};
-use bayer::{BayerRead8,BayerRead16};
-use border_replicate::*;
-use demosaic::check_depth;
+use ::{BayerDepth,BayerResult,Border,CFA,Demosaic,DemosaicOptions,RasterMut};
+use demosaic::{Engine,run_engine};
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+use demosaic::simd::linear_blocks;
 
 const PADDING: usize = 1;
 
+pub(crate) const ENGINE: Engine = Engine {
+    alg: Demosaic::Linear,
+    padding: PADDING,
+    border: Border::Replicate,
+    kernel_u8: debayer_row_u8,
+    kernel_u16: debayer_row_u16,
+    kernel_u32: debayer_row_u32,
+};
+
 pub fn run(r: &mut Read,
         depth: BayerDepth, cfa: CFA, dst: &mut RasterMut)
         -> BayerResult<()> {
-    if dst.w < 2 || dst.h < 2 {
-        return Err(BayerError::WrongResolution);
-    }
-    if !check_depth(depth, dst.depth) {
-        return Err(BayerError::WrongDepth);
-    }
+    run_with_options(r, depth, cfa, &DemosaicOptions::default(), dst)
+}
 
-    match depth {
-        BayerDepth::Depth8 => debayer_u8(r, cfa, dst),
-        BayerDepth::Depth16BE => debayer_u16(r, true, cfa, dst),
-        BayerDepth::Depth16LE => debayer_u16(r, false, cfa, dst),
-    }
+pub fn run_with_options(r: &mut Read,
+        depth: BayerDepth, cfa: CFA, options: &DemosaicOptions,
+        dst: &mut RasterMut)
+        -> BayerResult<()> {
+    run_engine(&ENGINE, r, depth, cfa, options, dst)
 }
 
 macro_rules! apply_kernel_row {
-    ($T:ty; $row:ident, $prev:expr, $curr:expr, $next:expr, $cfa:expr, $w:expr) => {{
+    ($T:ty; $blocks:ident; $row:ident, $prev:expr, $curr:expr, $next:expr, $cfa:expr, $w:expr) => {{
         let (mut i, cfa_c, cfa_g) =
             if $cfa == CFA::BGGR || $cfa == CFA::RGGB {
                 (0, $cfa, $cfa.next_x())
@@ -54,6 +53,8 @@ macro_rules! apply_kernel_row {
                 (1, $cfa.next_x(), $cfa)
             };
 
+        i = $blocks($prev, $curr, $next, cfa_c, i, $row);
+
         while i + 1 < $w {
             apply_kernel_c!($T; $row, $prev, $curr, $next, cfa_c, i);
             apply_kernel_g!($T; $row, $prev, $curr, $next, cfa_g, i + 1);
@@ -98,185 +99,67 @@ macro_rules! apply_kernel_g {
 }
 
 /*--------------------------------------------------------------*/
-/* Rayon                                                        */
-/*--------------------------------------------------------------*/
-
-#[cfg(feature = "rayon")]
-fn debayer_u8(r: &mut Read, cfa: CFA, dst: &mut RasterMut)
-        -> BayerResult<()> {
-    let (w, h) = (dst.w, dst.h);
-    let mut data = vec![0u8; (2 * PADDING + w) * (2 * PADDING + h)];
 
-    // Read all data.
-    {
-        let stride = 2 * PADDING + w;
-        let rdr = BorderReplicate8::new(w, PADDING);
-
-        for mut row in data.chunks_mut(stride).skip(PADDING).take(h) {
-            rdr.read_line(r, &mut row)?;
-        }
-
-        {
-            let (top, src) = data.split_at_mut(stride * PADDING);
-            top[(stride * 0)..(stride * 1)].copy_from_slice(
-                    &src[(stride * 1)..(stride * 2)]);
-        }
-
-        {
-            let (src, bottom) = data.split_at_mut(stride * (h + PADDING));
-            let yy = PADDING + h;
-            bottom[(stride * 0)..(stride * 1)].copy_from_slice(
-                    &src[(stride * (yy - 2))..(stride * (yy - 1))]);
-        }
-    }
-
-    dst.buf.par_chunks_mut(dst.stride).enumerate()
-            .for_each(|(y, mut row)| {
-        let stride = 2 * PADDING + w;
-        let prev = &data[(stride * (PADDING + y - 1)) .. (stride * (PADDING + y + 0))];
-        let curr = &data[(stride * (PADDING + y + 0)) .. (stride * (PADDING + y + 1))];
-        let next = &data[(stride * (PADDING + y + 1)) .. (stride * (PADDING + y + 2))];
-        let cfa_y = if y % 2 == 0 { cfa } else { cfa.next_y() };
-
-        apply_kernel_row!(u8; row, prev, curr, next, cfa_y, w);
-    });
-
-    Ok(())
+/// Interpolate the pixels from i on that fill whole vectors, returning
+/// the first pixel left to the scalar kernel.  Without SIMD, that is
+/// pixel i.
+fn scalar_blocks<T>(_prev: &[T], _curr: &[T], _next: &[T], _cfa_c: CFA, i: usize,
+        _row: &mut [T]) -> usize {
+    i
 }
 
-#[cfg(feature = "rayon")]
-fn debayer_u16(r: &mut Read, be: bool, cfa: CFA, dst: &mut RasterMut)
-        -> BayerResult<()> {
-    let (w, h) = (dst.w, dst.h);
-    let mut data = vec![0u16; (2 * PADDING + w) * (2 * PADDING + h)];
-
-    // Read all data.
-    {
-        let stride = 2 * PADDING + w;
-        let rdr: Box<BayerRead16> = if be {
-            Box::new(BorderReplicate16BE::new(w, PADDING))
-        } else {
-            Box::new(BorderReplicate16LE::new(w, PADDING))
-        };
-
-        for mut row in data.chunks_mut(stride).skip(PADDING).take(h) {
-            rdr.read_line(r, &mut row)?;
-        }
-
-        {
-            let (top, src) = data.split_at_mut(stride * PADDING);
-            top[(stride * 0)..(stride * 1)].copy_from_slice(
-                    &src[(stride * 1)..(stride * 2)]);
-        }
+#[cfg(not(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64"))))]
+use self::scalar_blocks as linear_blocks;
 
-        {
-            let (src, bottom) = data.split_at_mut(stride * (h + PADDING));
-            let yy = PADDING + h;
-            bottom[(stride * 0)..(stride * 1)].copy_from_slice(
-                    &src[(stride * (yy - 2))..(stride * (yy - 1))]);
-        }
-    }
-
-    dst.buf.par_chunks_mut(dst.stride).enumerate()
-            .for_each(|(y, mut row)| {
-        let stride = 2 * PADDING + w;
-        let prev = &data[(stride * (PADDING + y - 1)) .. (stride * (PADDING + y + 0))];
-        let curr = &data[(stride * (PADDING + y + 0)) .. (stride * (PADDING + y + 1))];
-        let next = &data[(stride * (PADDING + y + 1)) .. (stride * (PADDING + y + 2))];
-        let cfa_y = if y % 2 == 0 { cfa } else { cfa.next_y() };
-
-        let row16 = unsafe{ slice::from_raw_parts_mut(row.as_mut_ptr() as *mut u16, row.len() / 2) };
-        apply_kernel_row!(u16; row16, prev, curr, next, cfa_y, w);
-    });
-
-    Ok(())
+fn debayer_row_u8(rows: &[&[u8]], cfa: CFA, row: &mut [u8]) {
+    let w = row.len() / 3;
+    apply_kernel_row!(u8; linear_blocks; row, rows[0], rows[1], rows[2], cfa, w);
 }
 
-/*--------------------------------------------------------------*/
-/* Naive                                                        */
-/*--------------------------------------------------------------*/
-
-#[cfg(not(feature = "rayon"))]
-fn debayer_u8(r: &mut Read, cfa: CFA, dst: &mut RasterMut)
-        -> BayerResult<()> {
-    let (w, h) = (dst.w, dst.h);
-    let mut prev = vec![0u8; 2 * PADDING + w];
-    let mut curr = vec![0u8; 2 * PADDING + w];
-    let mut next = vec![0u8; 2 * PADDING + w];
-    let mut cfa = cfa;
-
-    let rdr = BorderReplicate8::new(w, PADDING);
-    rdr.read_line(r, &mut curr)?;
-    rdr.read_line(r, &mut next)?;
-
-    {   // y = 0.
-        let row = dst.borrow_row_u8_mut(0);
-        apply_kernel_row!(u8; row, next, curr, next, cfa, w);
-        cfa = cfa.next_y();
-    }
-
-    for y in 1..(h - 1) {
-        rotate!(prev <- curr <- next);
-        rdr.read_line(r, &mut next)?;
-
-        let row = dst.borrow_row_u8_mut(y);
-        apply_kernel_row!(u8; row, prev, curr, next, cfa, w);
-        cfa = cfa.next_y();
-    }
-
-    {   // y = h - 1.
-        let row = dst.borrow_row_u8_mut(h - 1);
-        apply_kernel_row!(u8; row, curr, next, curr, cfa, w);
-    }
-
-    Ok(())
+fn debayer_row_u16(rows: &[&[u16]], cfa: CFA, row: &mut [u16]) {
+    let w = row.len() / 3;
+    apply_kernel_row!(u16; linear_blocks; row, rows[0], rows[1], rows[2], cfa, w);
 }
 
-#[cfg(not(feature = "rayon"))]
-fn debayer_u16(r: &mut Read, be: bool, cfa: CFA, dst: &mut RasterMut)
-        -> BayerResult<()> {
-    let (w, h) = (dst.w, dst.h);
-    let mut prev = vec![0u16; 2 * PADDING + w];
-    let mut curr = vec![0u16; 2 * PADDING + w];
-    let mut next = vec![0u16; 2 * PADDING + w];
-    let mut cfa = cfa;
-
-    let rdr: Box<BayerRead16> = if be {
-        Box::new(BorderReplicate16BE::new(w, PADDING))
-    } else {
-        Box::new(BorderReplicate16LE::new(w, PADDING))
-    };
-    rdr.read_line(r, &mut curr)?;
-    rdr.read_line(r, &mut next)?;
-
-    {   // y = 0.
-        let row = dst.borrow_row_u16_mut(0);
-        apply_kernel_row!(u16; row, next, curr, next, cfa, w);
-        cfa = cfa.next_y();
-    }
-
-    for y in 1..(h - 1) {
-        rotate!(prev <- curr <- next);
-        rdr.read_line(r, &mut next)?;
-
-        let row = dst.borrow_row_u16_mut(y);
-        apply_kernel_row!(u16; row, prev, curr, next, cfa, w);
-        cfa = cfa.next_y();
-    }
-
-    {   // y = h - 1.
-        let row = dst.borrow_row_u16_mut(h - 1);
-        apply_kernel_row!(u16; row, curr, next, curr, cfa, w);
-    }
-
-    Ok(())
+fn debayer_row_u32(rows: &[&[u32]], cfa: CFA, row: &mut [u32]) {
+    let w = row.len() / 3;
+    apply_kernel_row!(u32; scalar_blocks; row, rows[0], rows[1], rows[2], cfa, w);
 }
 
 #[cfg(test)]
 mod tests {
     use std::io::Cursor;
-    use ::{CFA,RasterDepth,RasterMut};
-    use super::debayer_u8;
+    use ::{BayerDepth,CFA,DemosaicOptions,RasterDepth,RasterMut};
+    use super::{run,run_with_options};
+
+    #[test]
+    fn test_parallel() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+
+        const IMG_W: usize = 4;
+        const IMG_H: usize = 4;
+        let mut expected = [0u8; 3 * IMG_W * IMG_H];
+        let mut buf = [0u8; 3 * IMG_W * IMG_H];
+
+        let mut options = DemosaicOptions::default();
+        options.parallel = false;
+        let res = run_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::GRBG, &options,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut expected));
+        assert!(res.is_ok());
+
+        options.parallel = true;
+        let res = run_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::GRBG, &options,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+        assert_eq!(&buf[..], &expected[..]);
+    }
 
     #[test]
     fn test_even() {
@@ -297,7 +180,7 @@ mod tests {
         const IMG_H: usize = 4;
         let mut dst = [0u8; 3 * IMG_W * IMG_H];
 
-        let res = debayer_u8(&mut Cursor::new(&src[..]), CFA::RGGB,
+        let res = run(&mut Cursor::new(&src[..]), BayerDepth::Depth8, CFA::RGGB,
                 &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut dst[..]));
         assert!(res.is_ok());
         assert_eq!(&dst[..], &expected[..]);
@@ -320,7 +203,7 @@ mod tests {
         const IMG_H: usize = 3;
         let mut buf = [0u8; 3 * IMG_W * IMG_H];
 
-        let res = debayer_u8(&mut Cursor::new(&src[..]), CFA::RGGB,
+        let res = run(&mut Cursor::new(&src[..]), BayerDepth::Depth8, CFA::RGGB,
                 &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
         assert!(res.is_ok());
         assert_eq!(&buf[..], &expected[..]);
@@ -0,0 +1,233 @@
+//! Demosaicing using linear interpolation with median refinement.
+//!
+//! W. T. Freeman, "Median filter for reconstructing missing color
+//! samples", US patent 4,724,395, 1988.  The image is interpolated
+//! linearly, then the colour differences R - G and B - G are median
+//! filtered over a 3x3 neighbourhood.  The missing samples of each
+//! pixel are rebuilt from its own sample and the filtered
+//! differences, which removes most of the colour fringes along edges.
+//!
+//! ```text
+//!   at red:    G = R - median(R - G),  B = G + median(B - G)
+//!   at green:  R = G + median(R - G),  B = G + median(B - G)
+//!   at blue:   G = B - median(B - G),  R = G + median(R - G)
+//! ```
+//!
+//! Each row is refined from the linear interpolation of the row and
+//! the rows above and below it, so only the Bayer rows are kept.
+
+use std::cmp;
+use std::io::Read;
+
+use ::{BayerDepth,BayerResult,Border,CFA,Demosaic,DemosaicOptions,RasterMut};
+use demosaic::{Engine,Kernel,linear,run_engine};
+use raster::RasterSample;
+
+const PADDING: usize = 2;
+
+/// The number of columns refined at a time.  This is even, so that
+/// each block starts on the same column of the CFA pattern.
+const BLOCK: usize = 64;
+
+pub(crate) const ENGINE: Engine = Engine {
+    alg: Demosaic::LinearMedian,
+    padding: PADDING,
+    border: Border::Replicate,
+    kernel_u8: debayer_row_u8,
+    kernel_u16: debayer_row_u16,
+    kernel_u32: debayer_row_u32,
+};
+
+pub fn run(r: &mut Read,
+        depth: BayerDepth, cfa: CFA, dst: &mut RasterMut)
+        -> BayerResult<()> {
+    run_with_options(r, depth, cfa, &DemosaicOptions::default(), dst)
+}
+
+pub fn run_with_options(r: &mut Read,
+        depth: BayerDepth, cfa: CFA, options: &DemosaicOptions,
+        dst: &mut RasterMut)
+        -> BayerResult<()> {
+    run_engine(&ENGINE, r, depth, cfa, options, dst)
+}
+
+macro_rules! sort2 {
+    ($a:ident, $b:ident) => {{
+        let lo = cmp::min($a, $b);
+        $b = cmp::max($a, $b);
+        $a = lo;
+    }}
+}
+
+/// The median of the nine values, by a network of 19 exchanges.
+#[allow(unused_assignments)]
+fn median(v: [i32; 9]) -> i32 {
+    let [mut p0, mut p1, mut p2, mut p3, mut p4, mut p5, mut p6, mut p7, mut p8] = v;
+
+    sort2!(p1, p2); sort2!(p4, p5); sort2!(p7, p8);
+    sort2!(p0, p1); sort2!(p3, p4); sort2!(p6, p7);
+    sort2!(p1, p2); sort2!(p4, p5); sort2!(p7, p8);
+    sort2!(p0, p3); sort2!(p5, p8); sort2!(p4, p7);
+    sort2!(p3, p6); sort2!(p1, p4); sort2!(p2, p5);
+    sort2!(p4, p7); sort2!(p4, p2); sort2!(p6, p4);
+    sort2!(p4, p2);
+    p4
+}
+
+fn sample<T: RasterSample>(v: i32) -> T {
+    T::saturating_from(if v < 0 { 0 } else { v as u32 })
+}
+
+fn debayer_row<T: RasterSample>(rows: &[&[T]], cfa: CFA, row: &mut [T],
+        kernel: Kernel<T>) {
+    let w = row.len() / 3;
+    let own = &rows[PADDING][PADDING..];
+
+    // The linear interpolation of the rows above, on and below the
+    // output row, with one pixel of padding on either side, for each
+    // block of columns in turn.
+    let mut linear = [T::saturating_from(0); 3 * 3 * (BLOCK + 2)];
+
+    for x0 in (0..w).step_by(BLOCK) {
+        let n = cmp::min(BLOCK, w - x0);
+        let len = 3 * (n + 2);
+        for dy in 0..3 {
+            let cfa = if dy == 1 { cfa } else { cfa.next_y() };
+            let block = [
+                &rows[dy][x0..(x0 + n + 4)],
+                &rows[dy + 1][x0..(x0 + n + 4)],
+                &rows[dy + 2][x0..(x0 + n + 4)] ];
+            kernel(&block, cfa.next_x(), &mut linear[(dy * len)..((dy + 1) * len)]);
+        }
+
+        // The medians of the colour differences R - G and B - G.
+        let diff = |dy: usize, x: usize, c: usize| {
+            let px = &linear[(dy * len + 3 * x)..];
+            px[c].to_u32() as i32 - px[1].to_u32() as i32
+        };
+        let medians = |x: usize, c: usize| median([
+            diff(0, x, c), diff(0, x + 1, c), diff(0, x + 2, c),
+            diff(1, x, c), diff(1, x + 1, c), diff(1, x + 2, c),
+            diff(2, x, c), diff(2, x + 1, c), diff(2, x + 2, c) ]);
+
+        for (i, px) in row[(3 * x0)..(3 * (x0 + n))].chunks_mut(3).enumerate() {
+            let x = x0 + i;
+            let (rg, bg) = (medians(i, 0), medians(i, 2));
+            let c = cfa.at(x, 0).channel();
+            let g = match c {
+                0 => own[x].to_u32() as i32 - rg,
+                1 => own[x].to_u32() as i32,
+                _ => own[x].to_u32() as i32 - bg,
+            };
+
+            let g = sample::<T>(g).to_u32() as i32;
+            px[0] = sample(g + rg);
+            px[1] = sample(g);
+            px[2] = sample(g + bg);
+            px[c] = own[x];
+        }
+    }
+}
+
+/*--------------------------------------------------------------*/
+
+fn debayer_row_u8(rows: &[&[u8]], cfa: CFA, row: &mut [u8]) {
+    debayer_row(rows, cfa, row, linear::ENGINE.kernel_u8);
+}
+
+fn debayer_row_u16(rows: &[&[u16]], cfa: CFA, row: &mut [u16]) {
+    debayer_row(rows, cfa, row, linear::ENGINE.kernel_u16);
+}
+
+fn debayer_row_u32(rows: &[&[u32]], cfa: CFA, row: &mut [u32]) {
+    debayer_row(rows, cfa, row, linear::ENGINE.kernel_u32);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use ::{BayerDepth,BayerError,Border,CFA,DemosaicOptions,RasterDepth,RasterMut};
+    use super::{run,run_with_options};
+
+    #[test]
+    fn test_parallel() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+
+        const IMG_W: usize = 4;
+        const IMG_H: usize = 4;
+        let mut expected = [0u8; 3 * IMG_W * IMG_H];
+        let mut buf = [0u8; 3 * IMG_W * IMG_H];
+
+        let mut options = DemosaicOptions::default();
+        options.parallel = false;
+        let res = run_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::GRBG, &options,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut expected));
+        assert!(res.is_ok());
+
+        options.parallel = true;
+        let res = run_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::GRBG, &options,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+        assert_eq!(&buf[..], &expected[..]);
+    }
+
+    #[test]
+    fn test_mirror_small() {
+        let src = [0u8; 3 * 3];
+        let mut options = DemosaicOptions::default();
+        options.border = Some(Border::Mirror);
+
+        // Mirroring needs more pixels than the padding of 2.
+        for &(w, h) in [(2, 2), (3, 2), (2, 3)].iter() {
+            let mut dst = [0u8; 3 * 3 * 3];
+            let res = run_with_options(&mut Cursor::new(&src[..]), BayerDepth::Depth8, CFA::RGGB,
+                    &options, &mut RasterMut::new(w, h, RasterDepth::Depth8, &mut dst[..(3 * w * h)]));
+            match res {
+                Err(BayerError::WrongResolution { min_width: 3, min_height: 3, .. }) => (),
+                _ => panic!("{}x{}", w, h),
+            }
+        }
+
+        let mut dst = [0u8; 3 * 3 * 3];
+        let res = run_with_options(&mut Cursor::new(&src[..]), BayerDepth::Depth8, CFA::RGGB,
+                &options, &mut RasterMut::new(3, 3, RasterDepth::Depth8, &mut dst));
+        assert!(res.is_ok());
+
+        // The default border handles the smallest images.
+        let res = run(&mut Cursor::new(&src[..4]), BayerDepth::Depth8, CFA::RGGB,
+                &mut RasterMut::new(2, 2, RasterDepth::Depth8, &mut dst[..12]));
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn test_even() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+
+        let expected = [
+            229,203,105,   34, 67,  0,   95,167,146,   37,146,158,
+            252,232,184,   54, 99, 51,  160,229,213,   95,187,241,
+            169,161,113,  116,161,125,   15, 84,143,    0, 52,140,
+             53, 45, 59,   47,116,175,   29, 98,182,    2, 85,197 ];
+
+        const IMG_W: usize = 4;
+        const IMG_H: usize = 4;
+        let mut dst = [0u8; 3 * IMG_W * IMG_H];
+
+        let res = run(&mut Cursor::new(&src[..]), BayerDepth::Depth8, CFA::RGGB,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut dst[..]));
+        assert!(res.is_ok());
+        assert_eq!(&dst[..], &expected[..]);
+    }
+}
@@ -0,0 +1,266 @@
+//! Demosaicing using gradient-corrected linear interpolation.
+//!
+//! H. S. Malvar, L. He and R. Cutler, "High-quality linear
+//! interpolation for demosaicing of Bayer-patterned color images",
+//! ICASSP 2004.  Bilinear interpolation is corrected by the Laplacian
+//! of the channel sampled at each pixel, which preserves edges much
+//! better at little extra cost.
+//!
+//! ```text
+//!   green_kernel = (1 / 8) *
+//!       [  0  0 -1  0  0
+//!       ;  0  0  2  0  0
+//!       ; -1  2  4  2 -1
+//!       ;  0  0  2  0  0
+//!       ;  0  0 -1  0  0 ];
+//!
+//!   red/blue_kernel, at green, horizontal neighbours = (1 / 16) *
+//!       [  0  0  1  0  0
+//!       ;  0 -2  0 -2  0
+//!       ; -2  8 10  8 -2
+//!       ;  0 -2  0 -2  0
+//!       ;  0  0  1  0  0 ];
+//!
+//!   red/blue_kernel, at green, vertical neighbours = (1 / 16) *
+//!       [  0  0 -2  0  0
+//!       ;  0 -2  8 -2  0
+//!       ;  1  0 10  0  1
+//!       ;  0 -2  8 -2  0
+//!       ;  0  0 -2  0  0 ];
+//!
+//!   red/blue_kernel, at blue/red = (1 / 16) *
+//!       [  0  0 -3  0  0
+//!       ;  0  4  0  4  0
+//!       ; -3  0 12  0 -3
+//!       ;  0  4  0  4  0
+//!       ;  0  0 -3  0  0 ];
+//! ```
+
+use std::cmp::{max,min};
+use std::io::Read;
+
+use ::{BayerDepth,BayerResult,Border,CFA,Demosaic,DemosaicOptions,RasterMut};
+use demosaic::{Engine,run_engine};
+
+const PADDING: usize = 2;
+
+pub(crate) const ENGINE: Engine = Engine {
+    alg: Demosaic::MalvarHeCutler,
+    padding: PADDING,
+    border: Border::Replicate,
+    kernel_u8: debayer_row_u8,
+    kernel_u16: debayer_row_u16,
+    kernel_u32: debayer_row_u32,
+};
+
+pub fn run(r: &mut Read,
+        depth: BayerDepth, cfa: CFA, dst: &mut RasterMut)
+        -> BayerResult<()> {
+    run_with_options(r, depth, cfa, &DemosaicOptions::default(), dst)
+}
+
+pub fn run_with_options(r: &mut Read,
+        depth: BayerDepth, cfa: CFA, options: &DemosaicOptions,
+        dst: &mut RasterMut)
+        -> BayerResult<()> {
+    run_engine(&ENGINE, r, depth, cfa, options, dst)
+}
+
+macro_rules! apply_kernel_row {
+    ($T:ident; $row:ident, $prv2:expr, $prv1:expr, $curr:expr, $nxt1:expr, $nxt2:expr,
+            $cfa:expr, $w:expr) => {{
+        let (mut i, cfa_c, cfa_g) =
+            if $cfa == CFA::BGGR || $cfa == CFA::RGGB {
+                (0, $cfa, $cfa.next_x())
+            } else {
+                apply_kernel_g!($T; $row, $prv2, $prv1, $curr, $nxt1, $nxt2, $cfa, 0);
+                (1, $cfa.next_x(), $cfa)
+            };
+
+        while i + 1 < $w {
+            apply_kernel_c!($T; $row, $prv2, $prv1, $curr, $nxt1, $nxt2, cfa_c, i);
+            apply_kernel_g!($T; $row, $prv2, $prv1, $curr, $nxt1, $nxt2, cfa_g, i + 1);
+            i = i + 2;
+        }
+
+        if i < $w {
+            apply_kernel_c!($T; $row, $prv2, $prv1, $curr, $nxt1, $nxt2, cfa_c, i);
+        }
+    }}
+}
+
+macro_rules! clamp {
+    ($T:ident; $sum:expr, $div:expr) => {{
+        min(max($sum, 0) as u32 / $div, $T::max_value() as u32) as $T
+    }}
+}
+
+macro_rules! apply_kernel_c {
+    ($T:ident; $row:ident, $prv2:expr, $prv1:expr, $curr:expr, $nxt1:expr, $nxt2:expr,
+            $cfa:expr, $i:expr) => {{
+        // current = B/R, diagonal = R/B.
+        let (c, d) = if $cfa == CFA::BGGR { (2, 0) } else { (0, 2) };
+        let j = $i + PADDING;
+
+        let centre = $curr[j] as i32;
+        let axial
+            =   $prv1[j] as i32
+              + $curr[j - 1] as i32 + $curr[j + 1] as i32
+              + $nxt1[j] as i32;
+        let diagonal
+            =   $prv1[j - 1] as i32 + $prv1[j + 1] as i32
+              + $nxt1[j - 1] as i32 + $nxt1[j + 1] as i32;
+        let far
+            =   $prv2[j] as i32
+              + $curr[j - 2] as i32 + $curr[j + 2] as i32
+              + $nxt2[j] as i32;
+
+        $row[3 * $i + c] = $curr[j];
+        $row[3 * $i + 1]
+            = clamp!($T; 4 * centre + 2 * axial - far, 8);
+        $row[3 * $i + d]
+            = clamp!($T; 12 * centre + 4 * diagonal - 3 * far, 16);
+    }}
+}
+
+macro_rules! apply_kernel_g {
+    ($T:ident; $row:ident, $prv2:expr, $prv1:expr, $curr:expr, $nxt1:expr, $nxt2:expr,
+            $cfa:expr, $i:expr) => {{
+        // horizontal = B/R, vertical = R/G.
+        let (h, v) = if $cfa == CFA::GBRG { (2, 0) } else { (0, 2) };
+        let j = $i + PADDING;
+
+        let centre = $curr[j] as i32;
+        let horizontal = $curr[j - 1] as i32 + $curr[j + 1] as i32;
+        let vertical = $prv1[j] as i32 + $nxt1[j] as i32;
+        let diagonal
+            =   $prv1[j - 1] as i32 + $prv1[j + 1] as i32
+              + $nxt1[j - 1] as i32 + $nxt1[j + 1] as i32;
+        let far_h = $curr[j - 2] as i32 + $curr[j + 2] as i32;
+        let far_v = $prv2[j] as i32 + $nxt2[j] as i32;
+
+        $row[3 * $i + h]
+            = clamp!($T; 10 * centre + 8 * horizontal - 2 * diagonal - 2 * far_h + far_v, 16);
+        $row[3 * $i + 1] = $curr[j];
+        $row[3 * $i + v]
+            = clamp!($T; 10 * centre + 8 * vertical - 2 * diagonal - 2 * far_v + far_h, 16);
+    }}
+}
+
+/*--------------------------------------------------------------*/
+
+fn debayer_row_u8(rows: &[&[u8]], cfa: CFA, row: &mut [u8]) {
+    let w = row.len() / 3;
+    apply_kernel_row!(u8; row, rows[0], rows[1], rows[2], rows[3], rows[4], cfa, w);
+}
+
+fn debayer_row_u16(rows: &[&[u16]], cfa: CFA, row: &mut [u16]) {
+    let w = row.len() / 3;
+    apply_kernel_row!(u16; row, rows[0], rows[1], rows[2], rows[3], rows[4], cfa, w);
+}
+
+fn debayer_row_u32(rows: &[&[u32]], cfa: CFA, row: &mut [u32]) {
+    let w = row.len() / 3;
+    apply_kernel_row!(u32; row, rows[0], rows[1], rows[2], rows[3], rows[4], cfa, w);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use ::{BayerDepth,BayerError,Border,CFA,DemosaicOptions,RasterDepth,RasterMut};
+    use super::{run,run_with_options};
+
+    #[test]
+    fn test_parallel() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+
+        const IMG_W: usize = 4;
+        const IMG_H: usize = 4;
+        let mut expected = [0u8; 3 * IMG_W * IMG_H];
+        let mut buf = [0u8; 3 * IMG_W * IMG_H];
+
+        let mut options = DemosaicOptions::default();
+        options.parallel = false;
+        let res = run_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::GRBG, &options,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut expected));
+        assert!(res.is_ok());
+
+        options.parallel = true;
+        let res = run_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::GRBG, &options,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+        assert_eq!(&buf[..], &expected[..]);
+    }
+
+    #[test]
+    fn test_mirror_small() {
+        let src = [0u8; 3 * 3];
+        let mut options = DemosaicOptions::default();
+        options.border = Some(Border::Mirror);
+
+        // Mirroring needs more pixels than the padding of 2.
+        for &(w, h) in [(2, 2), (3, 2), (2, 3)].iter() {
+            let mut dst = [0u8; 3 * 3 * 3];
+            let res = run_with_options(&mut Cursor::new(&src[..]), BayerDepth::Depth8, CFA::RGGB,
+                    &options, &mut RasterMut::new(w, h, RasterDepth::Depth8, &mut dst[..(3 * w * h)]));
+            match res {
+                Err(BayerError::WrongResolution { min_width: 3, min_height: 3, .. }) => (),
+                _ => panic!("{}x{}", w, h),
+            }
+        }
+
+        let mut dst = [0u8; 3 * 3 * 3];
+        let res = run_with_options(&mut Cursor::new(&src[..]), BayerDepth::Depth8, CFA::RGGB,
+                &options, &mut RasterMut::new(3, 3, RasterDepth::Depth8, &mut dst));
+        assert!(res.is_ok());
+
+        // The default border handles the smallest images.
+        let res = run(&mut Cursor::new(&src[..4]), BayerDepth::Depth8, CFA::RGGB,
+                &mut RasterMut::new(2, 2, RasterDepth::Depth8, &mut dst[..12]));
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn test_even() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+
+        let expected = [
+            229,173, 87,   76, 67,  0,   95,161,135,   57,146,206,
+            255,232, 98,   68,133, 51,  132,229,198,   98,193,241,
+            169,161,130,  104,161,122,   15,105,122,    0, 52,158,
+             90, 45,122,  111,129,175,    0, 98,196,   10, 72,197 ];
+
+        const IMG_W: usize = 4;
+        const IMG_H: usize = 4;
+        let mut dst = [0u8; 3 * IMG_W * IMG_H];
+
+        let res = run(&mut Cursor::new(&src[..]), BayerDepth::Depth8, CFA::RGGB,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut dst[..]));
+        assert!(res.is_ok());
+        assert_eq!(&dst[..], &expected[..]);
+    }
+
+    #[test]
+    fn test_u16() {
+        // A flat image keeps its value, even at the top of the range.
+        let src = [0xFFu8; 2 * 4 * 4];
+        let mut buf = [0u8; 6 * 4 * 4];
+
+        let res = run(&mut Cursor::new(&src[..]), BayerDepth::Depth16BE, CFA::GBRG,
+                &mut RasterMut::new(4, 4, RasterDepth::Depth16, &mut buf));
+        assert!(res.is_ok());
+        assert!(buf.iter().all(|&v| v == 0xFF));
+    }
+}
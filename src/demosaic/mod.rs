@@ -1,6 +1,23 @@
 //! Collection of demosaicing algorithms.
 
-use ::{BayerDepth,RasterDepth};
+use std::cmp;
+use std::io::Read;
+use std::mem;
+use std::time::{Duration,Instant};
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+#[cfg(feature = "rayon")]
+use ::ThreadPool;
+
+use ::{BayerDepth,BayerError,BayerResult,Border,CFA,DemosaicOptions,FrameTiming,Narrowing,Overrun,ProcessingReport,RasterDepth,RasterLayout,RasterMut,Saturation,Transform,Truncation};
+use demosaic::calibration::Calibration;
+use bayer::{RawFormat,Raw8,Raw8Wide,Raw10,Raw12,Raw14,Dng12,Raw16BE,Raw16LE,Raw32,check_packing};
+use border_mirror::*;
+use border_none::*;
+use border_replicate::*;
+use errcode::OVERFLOW;
+use raster::RasterSample;
 
 /// The demosaicing algorithm to use to fill in the missing data.
 #[derive(Clone,Copy,Debug,Eq,PartialEq)]
@@ -8,44 +25,2161 @@ pub enum Demosaic {
     None,
     NearestNeighbour,
     Linear,
+    LinearMedian,
+    MalvarHeCutler,
     Cubic,
+    PPG,
 }
 
-macro_rules! rotate {
-    ($v0:ident <- $v1:ident) => {{
-        let rot = $v0;
-        $v0 = $v1;
-        $v1 = rot;
-    }};
-    ($v0:ident <- $v1:ident <- $v2:ident) => {{
-        let rot = $v0;
-        $v0 = $v1;
-        $v1 = $v2;
-        $v2 = rot;
-    }};
-    ($v0:ident <- $v1:ident <- $v2:ident <- $v3:ident <- $v4:ident <- $v5:ident <- $v6:ident) => {{
-        let rot = $v0;
-        $v0 = $v1;
-        $v1 = $v2;
-        $v2 = $v3;
-        $v3 = $v4;
-        $v4 = $v5;
-        $v5 = $v6;
-        $v6 = rot;
-    }};
+/// Description of a demosaicing algorithm.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub struct DemosaicInfo {
+    /// Human readable name of the algorithm.
+    pub name: &'static str,
+    /// The minimum image width supported by the algorithm.
+    pub min_width: usize,
+    /// The minimum image height supported by the algorithm.
+    pub min_height: usize,
+    /// Rough processing cost per pixel, relative to `Demosaic::None`.
+    pub relative_cost: u32,
+}
+
+const ALL_ALGORITHMS: [Demosaic; 7] = [
+    Demosaic::None,
+    Demosaic::NearestNeighbour,
+    Demosaic::Linear,
+    Demosaic::MalvarHeCutler,
+    Demosaic::Cubic,
+    Demosaic::LinearMedian,
+    Demosaic::PPG,
+];
+
+impl Demosaic {
+    /// All of the available demosaicing algorithms, from cheapest to
+    /// most expensive.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// for alg in bayer::Demosaic::all() {
+    ///     println!("{}", alg.info().name);
+    /// }
+    /// ```
+    pub fn all() -> &'static [Demosaic] {
+        &ALL_ALGORITHMS
+    }
+
+    /// The per-algorithm parts of the demosaicing process.
+    pub(crate) fn engine(self) -> Engine {
+        match self {
+            Demosaic::None => none::ENGINE,
+            Demosaic::NearestNeighbour => nearestneighbour::ENGINE,
+            Demosaic::Linear => linear::ENGINE,
+            Demosaic::LinearMedian => linearmedian::ENGINE,
+            Demosaic::MalvarHeCutler => malvarhecutler::ENGINE,
+            Demosaic::Cubic => cubic::ENGINE,
+            Demosaic::PPG => ppg::ENGINE,
+        }
+    }
+
+    /// The minimum (width, height) of the images supported by the
+    /// algorithm.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let (min_w, min_h) = bayer::Demosaic::Cubic.min_dimensions();
+    /// if 3 < min_w || 3 < min_h {
+    ///     // Pick another algorithm before allocating any buffers.
+    /// }
+    /// ```
+    pub fn min_dimensions(self) -> (usize, usize) {
+        let info = self.info();
+        (info.min_width, info.min_height)
+    }
+
+    /// The number of border pixels required on each side of the
+    /// Bayer rows passed to the per-row functions.
+    pub fn padding(self) -> usize {
+        self.engine().padding
+    }
+
+    /// Interpolate a single row of an 8-bit image.
+    ///
+    /// This is the core of the demosaicing process, for callers that
+    /// manage the I/O and scheduling themselves.  rows holds the
+    /// 2 * padding + 1 Bayer rows centred on the output row, each
+    /// padded with padding pixels on either side, e.g. by one of the
+    /// readers in the border module.  cfa is the pattern at the start
+    /// of the output row, and dst receives the RGB pixels.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number or the length of the rows do not match
+    /// the length of dst.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bayer::border::{BayerRead,BorderReplicate8};
+    ///
+    /// let src = [1u8,2,3,4, 5,6,7,8, 9,10,11,12];
+    /// let alg = bayer::Demosaic::Linear;
+    /// let p = alg.padding();
+    /// let rdr = BorderReplicate8::new(4, p);
+    ///
+    /// let mut padded = vec![vec![0u8; 4 + 2 * p]; 3];
+    /// for (row, line) in padded.iter_mut().zip(src.chunks(4)) {
+    ///     rdr.read_line(&mut &line[..], row).unwrap();
+    /// }
+    ///
+    /// // Interpolate the middle row, which starts with GBRG.
+    /// let rows: Vec<&[u8]> = padded.iter().map(|r| &r[..]).collect();
+    /// let mut dst = [0u8; 3 * 4];
+    /// alg.interpolate_row_u8(&rows, bayer::CFA::GBRG, &mut dst);
+    /// ```
+    pub fn interpolate_row_u8(self, rows: &[&[u8]], cfa: CFA, dst: &mut [u8]) {
+        if let Err(e) = self.try_interpolate_row_u8(rows, cfa, dst) {
+            panic!("{}", e);
+        }
+    }
+
+    /// Interpolate a single row of a 16-bit image.
+    ///
+    /// See `interpolate_row_u8`.
+    pub fn interpolate_row_u16(self, rows: &[&[u16]], cfa: CFA, dst: &mut [u16]) {
+        if let Err(e) = self.try_interpolate_row_u16(rows, cfa, dst) {
+            panic!("{}", e);
+        }
+    }
+
+    /// Interpolate a single row of an 8-bit image, returning
+    /// BayerError::InvalidArgument instead of panicking if the number
+    /// or the length of the rows do not match the length of dst.
+    ///
+    /// See `interpolate_row_u8`.
+    pub fn try_interpolate_row_u8(self, rows: &[&[u8]], cfa: CFA, dst: &mut [u8])
+            -> BayerResult<()> {
+        let engine = self.engine();
+        check_rows(&engine, rows, dst.len())?;
+        (engine.kernel_u8)(rows, cfa, dst);
+        Ok(())
+    }
+
+    /// Interpolate a single row of a 16-bit image, returning
+    /// BayerError::InvalidArgument instead of panicking.
+    ///
+    /// See `interpolate_row_u8`.
+    pub fn try_interpolate_row_u16(self, rows: &[&[u16]], cfa: CFA, dst: &mut [u16])
+            -> BayerResult<()> {
+        let engine = self.engine();
+        check_rows(&engine, rows, dst.len())?;
+        (engine.kernel_u16)(rows, cfa, dst);
+        Ok(())
+    }
+
+    /// Describe the demosaicing algorithm.
+    pub fn info(self) -> DemosaicInfo {
+        match self {
+            Demosaic::None => DemosaicInfo {
+                name: "none",
+                min_width: 2, min_height: 2, relative_cost: 1,
+            },
+            Demosaic::NearestNeighbour => DemosaicInfo {
+                name: "nearest neighbour",
+                min_width: 2, min_height: 2, relative_cost: 2,
+            },
+            Demosaic::Linear => DemosaicInfo {
+                name: "linear",
+                min_width: 2, min_height: 2, relative_cost: 4,
+            },
+            Demosaic::LinearMedian => DemosaicInfo {
+                name: "linear with median",
+                min_width: 2, min_height: 2, relative_cost: 30,
+            },
+            Demosaic::MalvarHeCutler => DemosaicInfo {
+                name: "malvar-he-cutler",
+                min_width: 2, min_height: 2, relative_cost: 6,
+            },
+            Demosaic::Cubic => DemosaicInfo {
+                name: "cubic",
+                min_width: 4, min_height: 4, relative_cost: 12,
+            },
+            Demosaic::PPG => DemosaicInfo {
+                name: "ppg",
+                min_width: 5, min_height: 5, relative_cost: 40,
+            },
+        }
+    }
 }
 
 pub mod cubic;
 pub mod linear;
+pub mod linearmedian;
+pub mod malvarhecutler;
 pub mod nearestneighbour;
 pub mod none;
+pub mod ppg;
+pub(crate) mod calibration;
+mod saturation;
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+mod simd;
+pub(crate) mod streaming;
+pub(crate) mod strips;
+mod tiles;
+
+/// The largest number of rows used by any kernel, i.e. 2 * PADDING + 1.
+const MAX_KERNEL_ROWS: usize = 9;
+
+/// The number of rows processed between progress reports and checks
+/// for cancellation.
+const ROWS_PER_BATCH: usize = 64;
+
+/// The number of pixels interpolated at a time into the stack before
+/// they are narrowed into an 8-bit raster.
+const NARROW_PIXELS: usize = 64;
+
+/// The 4x4 ordered dither matrix.
+const DITHER: [[u8; 4]; 4] = [
+    [ 0, 8, 2,10],
+    [12, 4,14, 6],
+    [ 3,11, 1, 9],
+    [15, 7,13, 5] ];
+
+/// Function interpolating a row, given the padded Bayer rows
+/// surrounding it, the CFA pattern of the row, and the output row.
+type Kernel<T> = fn(&[&[T]], CFA, &mut [T]);
+
+/// The per-algorithm parts of the demosaicing process.
+///
+/// These are plain values and function pointers, so an engine is
+/// cheap to copy and can be shared freely between threads.
+#[derive(Clone,Copy,Debug)]
+pub(crate) struct Engine {
+    alg: Demosaic,
+    padding: usize,
+    border: Border,
+    kernel_u8: Kernel<u8>,
+    kernel_u16: Kernel<u16>,
+    /// The kernel for rows of samples offset by a pedestal.
+    kernel_u32: Kernel<u32>,
+}
+
+impl Engine {
+    /// The algorithm implemented by the engine.
+    pub(crate) fn algorithm(&self) -> Demosaic {
+        self.alg
+    }
+}
+
+/// The most expensive algorithm, no more expensive than alg, that
+/// supports a w x h image.  If none do, returns alg.
+fn fallback_for(alg: Demosaic, w: usize, h: usize) -> Demosaic {
+    let cost = alg.info().relative_cost;
+    let mut best = alg;
+    let mut best_cost = 0;
+
+    for &a in ALL_ALGORITHMS.iter() {
+        let info = a.info();
+        let (min_w, min_h) = a.min_dimensions();
+        if w >= min_w && h >= min_h
+                && info.relative_cost <= cost && info.relative_cost > best_cost {
+            best = a;
+            best_cost = info.relative_cost;
+        }
+    }
+
+    best
+}
+
+/// Check the rows passed to the per-row functions.
+fn check_rows<T>(engine: &Engine, rows: &[&[T]], dst_len: usize)
+        -> BayerResult<()> {
+    let w = dst_len / 3;
+    let ok = dst_len == 3 * w
+        && rows.len() == 2 * engine.padding + 1
+        && rows.iter().all(|row| row.len() == w + 2 * engine.padding);
+
+    if ok {
+        Ok(())
+    } else {
+        Err(BayerError::InvalidArgument { reason: "rows do not match the output row" })
+    }
+}
+
+/// The engine to finish a frame with once it has exceeded its
+/// deadline.
+fn degraded_engine(engine: &Engine) -> Engine {
+    let cheap = Demosaic::NearestNeighbour;
+    if engine.alg.info().relative_cost > cheap.info().relative_cost {
+        cheap.engine()
+    } else {
+        *engine
+    }
+}
+
+/// The engine to use for a w x h image, after applying the fallback
+/// and fill options.
+fn resolve_engine(engine: &Engine, options: &DemosaicOptions, w: usize, h: usize)
+        -> Engine {
+    let mut engine = *engine;
+    if options.fallback {
+        engine = fallback_for(engine.alg, w, h).engine();
+    }
+    if engine.alg == Demosaic::None {
+        engine = none::engine_with_fill(options.fill);
+    }
+
+    engine
+}
+
+/// Run the demosaicing engine on the Bayer image.
+pub(crate) fn run_engine<R: Read + ?Sized>(engine: &Engine, r: &mut R,
+        depth: BayerDepth, cfa: CFA, options: &DemosaicOptions,
+        dst: &mut RasterMut)
+        -> BayerResult<()> {
+    run_engine_with(engine, &mut Scratch::default(), r, depth, cfa, options, dst)
+}
+
+/// Run the demosaicing engine on the Bayer image, using the scratch
+/// buffers.
+pub(crate) fn run_engine_with<R: Read + ?Sized>(engine: &Engine,
+        scratch: &mut Scratch, r: &mut R,
+        depth: BayerDepth, cfa: CFA, options: &DemosaicOptions,
+        dst: &mut RasterMut)
+        -> BayerResult<()> {
+    #[cfg(feature = "log")]
+    let start = ::std::time::Instant::now();
+
+    // The dimensions of the image, before the output transform.
+    let (w, h) = match options.transform {
+        Some(t) => t.dimensions(dst.w, dst.h),
+        None => (dst.w, dst.h),
+    };
+
+    let requested = engine.alg;
+    let res = Frame::new(engine, depth, cfa, options, w, h)
+        .and_then(|frame| frame.for_raster(dst.depth))
+        .and_then(|frame| {
+            let res = debayer(r, &frame, scratch, dst);
+            report(&frame, requested, dst.depth, &res);
+            res
+        });
+
+    #[cfg(feature = "log")]
+    debug!("{} {}x{} {:?} {:?}: {:?} in {:?}",
+            resolve_engine(engine, options, w, h).alg.info().name, w, h, depth, cfa,
+            res.as_ref().map_err(|e| e.code()), start.elapsed());
+
+    res
+}
+
+/// Record how the frame was processed, if the raster was written.
+fn report(frame: &Frame, requested: Demosaic, raster: RasterDepth, res: &BayerResult<()>) {
+    let options = frame.options;
+    if let Some(ref report) = options.report {
+        let (missing_rows, degraded_from_row) = match *res {
+            Ok(()) => (0, None),
+            Err(BayerError::Truncated { missing_rows }) => (missing_rows, None),
+            Err(BayerError::DeadlineExceeded { row }) if options.overrun == Overrun::Degrade =>
+                (0, Some(row)),
+            Err(_) => return,
+        };
+
+        let alg = frame.engine.alg;
+        let source_bits = match frame.depth {
+            BayerDepth::Depth8 => 8,
+            depth => match depth.packed() {
+                Some(format) => cmp::min(options.source_bits as u32, format.bits()) as u8,
+                None => options.source_bits,
+            },
+        };
+        let format = RowFormat::rgb(frame, raster);
+
+        report.record(ProcessingReport {
+            width: frame.w, height: frame.h, depth: frame.depth, cfa: frame.cfa, requested,
+            algorithm: alg,
+            border: frame.border,
+            fill: if alg == Demosaic::None { Some(options.fill) } else { None },
+            transform: options.transform,
+            missing_rows,
+            degraded_from_row,
+            source_bits,
+            scaling: frame.calibration.scaling(),
+            levels: options.levels,
+            white_balance: options.white_balance,
+            dark_frame: options.dark_frame.is_some(),
+            saturation: options.saturation,
+            intermediate: options.intermediate,
+            narrowing: format.narrow.map(|narrow| (narrow.shift, narrow.narrowing)),
+            float_divisor: format.divisor.map(|_| options.float_divisor.unwrap_or(frame.max)),
+        });
+    }
+}
+
+/// The configuration of the engine for one w x h Bayer image.
+#[derive(Clone)]
+pub(crate) struct Frame<'a> {
+    depth: BayerDepth,
+    cfa: CFA,
+    border: Border,
+    options: &'a DemosaicOptions,
+    /// The engine, after applying the fallback and fill options.
+    engine: Engine,
+    calibration: Calibration,
+    /// The largest sample of the rows, before any pedestal.
+    max: u32,
+    w: usize,
+    h: usize,
+}
+
+impl<'a> Frame<'a> {
+    /// Configure the engine for a w x h image, checking that the
+    /// image is supported.
+    pub(crate) fn new(engine: &Engine, depth: BayerDepth, cfa: CFA,
+            options: &'a DemosaicOptions, w: usize, h: usize)
+            -> BayerResult<Self> {
+        let engine = resolve_engine(engine, options, w, h);
+        let border = options.border.unwrap_or(engine.border);
+        check_resolution(&engine, border, w, h)?;
+        check_packing(depth, w)?;
+        let calibration = calibration_for(&engine,
+                Calibration::new(options, depth, Some(cfa), w, h)?);
+        let max = if depth == BayerDepth::Depth8 { 0xFF } else { 0xFFFF };
+
+        Ok(Frame { depth, cfa, border, options, engine, calibration, max, w, h })
+    }
+
+    /// Whether the rows are offset by a pedestal, and so are held in
+    /// 32-bit samples.
+    fn offset(&self) -> bool {
+        self.calibration.pedestal() > 0
+    }
+
+    /// The kernels interpolating rows of samples of type T.
+    fn kernels<T: ScratchSample>(&self) -> Kernels<T> {
+        let cheap = degraded_engine(&self.engine);
+
+        // The degraded engine never needs more padding than the
+        // engine it replaces, see test_degraded_padding.
+        debug_assert!(cheap.padding <= self.engine.padding);
+        Kernels {
+            kernel: T::kernel(&self.engine),
+            padding: self.engine.padding,
+            cheap: T::kernel(&cheap),
+            cheap_padding: cheap.padding,
+            cfa: self.cfa,
+            saturation: self.calibration.saturation(),
+            pedestal: self.calibration.pedestal(),
+        }
+    }
+
+    /// The reader of the raw rows.
+    fn reader<F: RawFormat>(&self) -> BayerResult<Reader<F>> {
+        Reader::new(self.border, self.w, self.engine.padding)
+    }
+
+    /// The frame for demosaicing into a raster of the given depth.
+    /// 8-bit images are widened for 16-bit rasters.
+    pub(crate) fn for_raster(&self, raster: RasterDepth) -> BayerResult<Self> {
+        check_conversion(self.depth, raster, self.options)?;
+        if self.depth != BayerDepth::Depth8 || raster != RasterDepth::Depth16 {
+            return Ok(self.clone());
+        }
+
+        Ok(Frame {
+            calibration: calibration_for(&self.engine,
+                    Calibration::widened(self.options, Some(self.cfa), self.w, self.h)?),
+            max: 0xFFFF,
+            ..self.clone()
+        })
+    }
+
+    /// Whether the samples of an 8-bit image are widened to 16 bits.
+    fn widen(&self) -> bool {
+        self.depth == BayerDepth::Depth8 && self.max > 0xFF
+    }
+
+    /// The depth of the rasters that hold the samples of the rows
+    /// without conversion.
+    pub(crate) fn sample_depth(&self) -> RasterDepth {
+        if self.max > 0xFF { RasterDepth::Depth16 } else { RasterDepth::Depth8 }
+    }
+}
+
+/// The calibration for the engine.  Without interpolation, clamping
+/// the calibrated samples to zero loses nothing, so no pedestal is
+/// needed.
+fn calibration_for(engine: &Engine, calibration: Calibration) -> Calibration {
+    if engine.alg == Demosaic::None {
+        calibration.unsigned()
+    } else {
+        calibration
+    }
+}
+
+/// Padded Bayer rows, stored in a ring buffer.
+///
+/// The rows above and below the image are synthesised on access,
+/// according to the border handling.
+struct BayerRows<T> {
+    data: Vec<T>,
+    stride: usize,
+    capacity: usize,
+    height: usize,
+    padding: usize,
+    border: Border,
+    truncation: Truncation,
+    calibration: Calibration,
+    count: usize,
+    missing: usize,
+}
+
+impl<T: RasterSample> BayerRows<T> {
+    fn new(frame: &Frame, truncation: Truncation, capacity: usize) -> BayerResult<Self> {
+        Self::with_buffer(Vec::new(), frame, truncation, capacity)
+    }
+
+    /// Create the rows of the frame in a recycled buffer, see
+    /// `into_buffer`.
+    fn with_buffer(mut data: Vec<T>, frame: &Frame, truncation: Truncation, capacity: usize)
+            -> BayerResult<Self> {
+        let padding = frame.engine.padding;
+
+        // Replicating a missing row needs the two rows before it.
+        let capacity = if capacity < 3 { 3 } else { capacity };
+        let stride = frame.w.checked_add(2 * padding).ok_or(OVERFLOW)?;
+
+        // The extra row at the end is the zero row for Border::None.
+        let len = capacity.checked_add(1)
+            .and_then(|n| n.checked_mul(stride))
+            .ok_or(OVERFLOW)?;
+        data.clear();
+        data.resize(len, T::default());
+
+        Ok(BayerRows {
+            data, stride, capacity,
+            height: frame.h,
+            padding,
+            border: frame.border,
+            truncation,
+            calibration: frame.calibration.clone(),
+            count: 0,
+            missing: 0,
+        })
+    }
+
+    /// Recover the buffer, so that it can be reused.
+    fn into_buffer(self) -> Vec<T> {
+        self.data
+    }
+
+    fn zero(&self) -> &[T] {
+        let start = self.stride * self.capacity;
+        &self.data[start..(start + self.stride)]
+    }
+
+    /// Read rows from the source until row y is available.
+    fn read_until<R, F>(&mut self, r: &mut R, rdr: &Reader<F>, y: usize)
+            -> BayerResult<()>
+            where R: Read + ?Sized, F: RawFormat<Sample = T> {
+        let last = if y < self.height { y } else { self.height - 1 };
+
+        while self.count <= last {
+            let start = self.stride * (self.count % self.capacity);
+
+            if self.missing == 0 {
+                let (calibration, y) = (&self.calibration, self.count);
+                let res = rdr.read_line_with(r, &mut self.data[start..(start + self.stride)],
+                        |raw| calibration.apply(y, raw));
+                if let Err(e) = res {
+                    if self.truncation == Truncation::Error {
+                        return Err(e);
+                    }
+                    self.missing = self.height - self.count;
+                }
+            }
+
+            if self.missing > 0 {
+                self.fill_missing(start);
+            }
+            self.count = self.count + 1;
+        }
+
+        Ok(())
+    }
+
+    /// Fill in the row at start, which could not be read.
+    fn fill_missing(&mut self, start: usize) {
+        let end = start + self.stride;
+
+        match self.truncation {
+            Truncation::Error => unreachable!(),
+            Truncation::Replicate => {
+                let src = match self.count {
+                    0 => None,
+                    1 => Some(0),
+                    n => Some(n - 2),
+                };
+
+                match src {
+                    Some(y) => {
+                        let src = self.stride * (y % self.capacity);
+                        self.data.copy_within(src..(src + self.stride), start);
+                    },
+                    None => {
+                        for e in self.data[start..end].iter_mut() {
+                            *e = T::default();
+                        }
+                    },
+                }
+            },
+            Truncation::Constant(v) => {
+                let v = T::saturating_from(v as u32);
+                let (x1, x2) = match self.border {
+                    Border::None => (start + self.padding, end - self.padding),
+                    _ => (start, end),
+                };
+
+                for e in self.data[start..end].iter_mut() {
+                    *e = T::default();
+                }
+                for e in self.data[x1..x2].iter_mut() {
+                    *e = v;
+                }
+            },
+        }
+    }
+
+    /// Report the rows that were filled in, once the image is done.
+    fn finish(&self) -> BayerResult<()> {
+        if self.missing > 0 {
+            Err(BayerError::Truncated { missing_rows: self.missing })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Borrow the padded row y, which may lie beyond the image.
+    fn row(&self, y: isize) -> &[T] {
+        let h = self.height as isize;
+        let src = if y < 0 {
+            match self.border {
+                Border::None => return self.zero(),
+                Border::Replicate => (-y) % 2,
+                Border::Mirror => -y,
+            }
+        } else if y >= h {
+            match self.border {
+                Border::None => return self.zero(),
+                Border::Replicate => h - 2 + (y - h) % 2,
+                Border::Mirror => 2 * h - 2 - y,
+            }
+        } else {
+            y
+        };
+
+        let start = self.stride * (src as usize % self.capacity);
+        &self.data[start..(start + self.stride)]
+    }
+
+    /// Borrow the rows surrounding the w pixels of row y from column
+    /// x0, with only padding pixels of padding on each side, for a
+    /// kernel that needs less padding than the rows were read with,
+    /// or for a tile of the image.
+    fn narrow_window<'a>(&'a self, y: usize, padding: usize, x0: usize, w: usize,
+            rows: &mut [&'a [T]]) {
+        let start = self.padding - padding + x0;
+        let end = start + w + 2 * padding;
+        let y0 = y as isize - padding as isize;
+        for (i, row) in rows.iter_mut().enumerate() {
+            *row = &self.row(y0 + i as isize)[start..end];
+        }
+    }
+}
+
+/// Buffers for the padded Bayer rows, recycled between runs.
+#[derive(Debug,Default)]
+pub(crate) struct Scratch {
+    buf_u8: Vec<u8>,
+    buf_u16: Vec<u16>,
+    buf_u32: Vec<u32>,
+}
+
+impl Clone for Scratch {
+    /// The buffers are not copied; the clone starts empty.
+    fn clone(&self) -> Self {
+        Scratch::default()
+    }
+}
+
+/// Samples for which the engines have kernels, and for which a
+/// scratch buffer is kept.
+trait ScratchSample: RasterSample {
+    fn buffer(scratch: &mut Scratch) -> &mut Vec<Self>;
+    fn kernel(engine: &Engine) -> Kernel<Self>;
+}
+
+impl ScratchSample for u8 {
+    fn buffer(scratch: &mut Scratch) -> &mut Vec<u8> {
+        &mut scratch.buf_u8
+    }
+
+    fn kernel(engine: &Engine) -> Kernel<u8> {
+        engine.kernel_u8
+    }
+}
+
+impl ScratchSample for u16 {
+    fn buffer(scratch: &mut Scratch) -> &mut Vec<u16> {
+        &mut scratch.buf_u16
+    }
+
+    fn kernel(engine: &Engine) -> Kernel<u16> {
+        engine.kernel_u16
+    }
+}
+
+impl ScratchSample for u32 {
+    fn buffer(scratch: &mut Scratch) -> &mut Vec<u32> {
+        &mut scratch.buf_u32
+    }
+
+    fn kernel(engine: &Engine) -> Kernel<u32> {
+        engine.kernel_u32
+    }
+}
+
+/// Bayer reader with the border handling chosen at run time.
+///
+/// Unlike a boxed BayerRead, this is generic over the source, so
+/// reads from in-memory sources can be inlined.
+enum Reader<F> {
+    None(BorderNone<F>),
+    Replicate(BorderReplicate<F>),
+    Mirror(BorderMirror<F>),
+}
+
+impl<F: RawFormat> Reader<F> {
+    fn new(border: Border, w: usize, padding: usize) -> BayerResult<Self> {
+        Ok(match border {
+            Border::None => Reader::None(BorderNone::try_new(w, padding)?),
+            Border::Replicate => Reader::Replicate(BorderReplicate::try_new(w, padding)?),
+            Border::Mirror => Reader::Mirror(BorderMirror::try_new(w, padding)?),
+        })
+    }
+
+    /// Read a line, passing the raw samples to f before the border
+    /// is filled in.
+    fn read_line_with<R, G>(&self, r: &mut R, dst: &mut [F::Sample], f: G)
+            -> BayerResult<()>
+            where R: Read + ?Sized, G: FnOnce(&mut [F::Sample]) {
+        match *self {
+            Reader::None(ref rdr) => rdr.read_line_with(r, dst, f),
+            Reader::Replicate(ref rdr) => rdr.read_line_with(r, dst, f),
+            Reader::Mirror(ref rdr) => rdr.read_line_with(r, dst, f),
+        }
+    }
+}
+
+/// Run the row kernels over the whole image.
+fn debayer<R: Read + ?Sized>(r: &mut R, frame: &Frame, scratch: &mut Scratch,
+        dst: &mut RasterMut)
+        -> BayerResult<()> {
+    match frame.depth {
+        BayerDepth::Depth8 if frame.widen() =>
+            debayer_format::<R, Raw8Wide>(r, frame, scratch, dst),
+        BayerDepth::Depth8 => debayer_format::<R, Raw8>(r, frame, scratch, dst),
+        BayerDepth::Depth16BE => debayer_format::<R, Raw16BE>(r, frame, scratch, dst),
+        BayerDepth::Depth16LE => debayer_format::<R, Raw16LE>(r, frame, scratch, dst),
+        BayerDepth::Depth10Packed => debayer_format::<R, Raw10>(r, frame, scratch, dst),
+        BayerDepth::Depth12Packed => debayer_format::<R, Raw12>(r, frame, scratch, dst),
+        BayerDepth::Depth12PackedDng => debayer_format::<R, Dng12>(r, frame, scratch, dst),
+        BayerDepth::Depth14Packed => debayer_format::<R, Raw14>(r, frame, scratch, dst),
+    }
+}
+
+/// Run the row kernels over the image in raw format F.
+fn debayer_format<R, F>(r: &mut R, frame: &Frame, scratch: &mut Scratch,
+        dst: &mut RasterMut)
+        -> BayerResult<()>
+        where R: Read + ?Sized, F: RawFormat, F::Sample: ScratchSample {
+    if frame.offset() {
+        debayer_rows(r, &frame.reader::<Raw32<F>>()?, frame, scratch, dst)
+    } else {
+        debayer_rows(r, &frame.reader::<F>()?, frame, scratch, dst)
+    }
+}
+
+/// The kernel of the requested algorithm, and the cheaper kernel used
+/// once the deadline has passed.
+#[derive(Clone,Copy)]
+struct Kernels<T> {
+    kernel: Kernel<T>,
+    padding: usize,
+    cheap: Kernel<T>,
+    cheap_padding: usize,
+    /// The pattern at the top-left of the image.
+    cfa: CFA,
+    saturation: Saturation,
+    pedestal: u16,
+}
+
+impl<T: RasterSample> Kernels<T> {
+    /// Interpolate row y with either kernel.
+    fn run(&self, rows: &BayerRows<T>, y: usize, degraded: bool, dst: &mut [T]) {
+        self.run_cols(rows, y, 0, degraded, dst)
+    }
+
+    /// Interpolate the pixels of row y from column x0 with either
+    /// kernel.
+    fn run_cols(&self, rows: &BayerRows<T>, y: usize, x0: usize, degraded: bool,
+            dst: &mut [T]) {
+        let (kernel, padding) = if degraded {
+            (self.cheap, self.cheap_padding)
+        } else {
+            (self.kernel, self.padding)
+        };
+
+        let cfa = self.cfa.at(x0, y);
+        let mut window: [&[T]; MAX_KERNEL_ROWS] = [&[]; MAX_KERNEL_ROWS];
+        let n = 2 * padding + 1;
+        rows.narrow_window(y, padding, x0, dst.len() / 3, &mut window[0..n]);
+        kernel(&window[0..n], cfa, dst);
+        saturation::fix_row(self.saturation, self.pedestal, &window[0..n], cfa, dst);
+        calibration::remove_pedestal(self.pedestal, dst);
+    }
+
+    /// Interpolate the pixels of row y from column x0 into the bytes
+    /// of a raster row of the given format.
+    fn run_format(&self, rows: &BayerRows<T>, y: usize, x0: usize, degraded: bool,
+            format: RowFormat, dst: &mut [u8]) {
+        let w = dst.len() / format.bytes_per_pixel();
+        if format.buffered::<T>() {
+            // The raster row is too short to hold the samples.
+            let n_bytes = 3 * format.depth.bytes_per_sample();
+            let mut buf = [T::default(); 3 * NARROW_PIXELS];
+            let mut x = 0;
+            while x < w {
+                let n = cmp::min(w - x, NARROW_PIXELS);
+                self.run_cols(rows, y, x0 + x, degraded, &mut buf[0..(3 * n)]);
+                format.convert(x0 + x, y, &buf[0..(3 * n)],
+                        &mut dst[(n_bytes * x)..(n_bytes * (x + n))]);
+                x = x + n;
+            }
+            format.expand_samples(w, dst);
+            return;
+        }
+
+        let len = 3 * w * mem::size_of::<T>();
+        self.run_cols(rows, y, x0, degraded, T::from_bytes_mut(&mut dst[0..len]));
+        format.expand_samples(w, dst);
+    }
+}
+
+/// The narrowing of 16-bit samples into an 8-bit raster.
+#[derive(Clone,Copy)]
+struct Narrow {
+    shift: u8,
+    narrowing: Narrowing,
+}
+
+impl Narrow {
+    /// Narrow the RGB pixels of row y, starting from column x.
+    fn apply<T: RasterSample>(self, x: usize, y: usize, src: &[T], dst: &mut [u8]) {
+        for (i, (px, out)) in src.chunks(3).zip(dst.chunks_mut(3)).enumerate() {
+            // The offset added before shifting, in 32ths of the
+            // value of the lowest bit kept.
+            let k = match self.narrowing {
+                Narrowing::Truncate => 0,
+                Narrowing::Round => 16,
+                Narrowing::Dither => 2 * DITHER[y % 4][(x + i) % 4] as u32 + 1,
+            };
+            let offset = (k << self.shift) >> 5;
+            for (d, &s) in out.iter_mut().zip(px.iter()) {
+                *d = cmp::min((s.to_u32() + offset) >> self.shift, 0xFF) as u8;
+            }
+        }
+    }
+}
+
+/// The format of the raster rows into which the RGB rows are written.
+#[derive(Clone,Copy)]
+pub(crate) struct RowFormat {
+    layout: RasterLayout,
+    depth: RasterDepth,
+    /// The largest sample of the RGB rows.
+    max: u32,
+    /// The divisor of the samples of floating-point rasters.
+    divisor: Option<f32>,
+    /// The narrowing of 16-bit samples into 8-bit rasters.
+    narrow: Option<Narrow>,
+}
+
+impl RowFormat {
+    /// The format of the raster, for the RGB rows of the frame.
+    pub(crate) fn new(frame: &Frame, dst: &RasterMut) -> Self {
+        Self::with_max(frame.options, frame.max, dst.layout, dst.depth)
+    }
+
+    /// The format of unpadded RGB rows of the given depth, for the
+    /// RGB rows of the frame.
+    fn rgb(frame: &Frame, depth: RasterDepth) -> Self {
+        Self::with_max(frame.options, frame.max, RasterLayout::Rgb, depth)
+    }
+
+    /// The format of the raster, for the RGB rows of an image
+    /// calibrated by `Calibration::for_raster`.
+    pub(crate) fn for_image(options: &DemosaicOptions, depth: BayerDepth, dst: &RasterMut)
+            -> Self {
+        let widened = depth != BayerDepth::Depth8 || dst.depth == RasterDepth::Depth16;
+        let max = if widened { 0xFFFF } else { 0xFF };
+        Self::with_max(options, max, dst.layout, dst.depth)
+    }
+
+    /// The largest sample of the RGB rows.
+    pub(crate) fn max(&self) -> u32 {
+        self.max
+    }
+
+    fn with_max(options: &DemosaicOptions, max: u32, layout: RasterLayout, depth: RasterDepth)
+            -> Self {
+        let divisor = match depth {
+            RasterDepth::Depth32F => Some(options.float_divisor.unwrap_or(max) as f32),
+            _ => None,
+        };
+        let narrow = match depth {
+            RasterDepth::Depth8 if max > 0xFF =>
+                Some(Narrow { shift: options.narrow_shift, narrowing: options.narrowing }),
+            _ => None,
+        };
+
+        RowFormat { layout, depth, max, divisor, narrow }
+    }
+
+    fn bytes_per_pixel(&self) -> usize {
+        self.depth.bytes_per_sample() * self.layout.channels()
+    }
+
+    /// Whether the RGB pixels with samples of type T must be converted
+    /// from a separate buffer, because the raster row is too short to
+    /// hold them, or because floating-point rasters need not be
+    /// aligned for them.
+    fn buffered<T>(&self) -> bool {
+        mem::size_of::<T>() > self.depth.bytes_per_sample()
+                || self.depth == RasterDepth::Depth32F
+    }
+
+    /// The floating-point value of a sample.
+    fn float(&self, v: u32) -> f32 {
+        cmp::min(v, self.max) as f32 / self.divisor.unwrap_or(1.0)
+    }
+
+    /// Convert the RGB pixels of row y, starting from column x, into
+    /// samples of the raster.
+    fn convert<T: RasterSample>(&self, x: usize, y: usize, src: &[T], dst: &mut [u8]) {
+        match self.depth {
+            RasterDepth::Depth8 => match self.narrow {
+                Some(narrow) => narrow.apply(x, y, src, dst),
+                None => for (d, &s) in dst.iter_mut().zip(src.iter()) {
+                    *d = u8::saturating_from(s.to_u32());
+                },
+            },
+            RasterDepth::Depth16 => {
+                for (d, &s) in u16::from_bytes_mut(dst).iter_mut().zip(src.iter()) {
+                    *d = u16::saturating_from(s.to_u32());
+                }
+            },
+            RasterDepth::Depth32F => {
+                for (d, &s) in dst.chunks_mut(4).zip(src.iter()) {
+                    d.copy_from_slice(&self.float(s.to_u32()).to_ne_bytes());
+                }
+            },
+        }
+    }
+
+    /// Rearrange a row of w RGB pixels, held in the start of the row
+    /// as samples of the raster, into the layout.
+    fn expand_samples(&self, w: usize, row: &mut [u8]) {
+        match self.depth {
+            RasterDepth::Depth8 => self.layout.expand_row(w, row),
+            RasterDepth::Depth16 => self.layout.expand_row(w, u16::from_bytes_mut(row)),
+            RasterDepth::Depth32F => self.layout.expand_row_f32(w, row),
+        }
+    }
+
+    /// Write a row of RGB pixels into row y of the raster.
+    pub(crate) fn put_row<T: RasterSample>(&self, y: usize, row: &[T], dst: &mut RasterMut) {
+        let w = row.len() / 3;
+        let pixels = &mut dst.borrow_pixels_mut(y)[0..(self.bytes_per_pixel() * w)];
+        self.convert(0, y, row, pixels);
+        self.expand_samples(w, pixels);
+    }
+}
+
+fn debayer_rows<R, F, T>(r: &mut R, rdr: &Reader<F>, frame: &Frame,
+        scratch: &mut Scratch, dst: &mut RasterMut)
+        -> BayerResult<()>
+        where R: Read + ?Sized, F: RawFormat<Sample = T>, T: ScratchSample {
+    let options = frame.options;
+    let kernels = frame.kernels::<T>();
+    let padding = kernels.padding;
+    let tile_size = options.tile_size.filter(|_| options.transform.is_none());
+    let parallel = cfg!(feature = "rayon") && options.parallel && options.transform.is_none();
+    let capacity = match tile_size {
+        Some((_, tile_h)) => cmp::max(tile_h, 1) + 2 * padding,
+        None if parallel => ROWS_PER_BATCH + 2 * padding,
+        None => 2 * padding + 1,
+    };
+    let buf = mem::replace(T::buffer(scratch), Vec::new());
+    let mut rows = BayerRows::with_buffer(buf, frame, options.truncation, capacity)?;
+
+    if let Some(tile_size) = tile_size {
+        let res = tiles::debayer_tiles(r, rdr, &mut rows, frame, kernels, tile_size, dst);
+        *T::buffer(scratch) = rows.into_buffer();
+        return res;
+    }
+
+    #[cfg(feature = "rayon")]
+    let res = if parallel {
+        debayer_rows_par(r, rdr, &mut rows, frame, kernels, dst)
+    } else {
+        debayer_rows_seq(r, rdr, &mut rows, frame, kernels, dst)
+    };
+
+    #[cfg(not(feature = "rayon"))]
+    let res = debayer_rows_seq(r, rdr, &mut rows, frame, kernels, dst);
+
+    *T::buffer(scratch) = rows.into_buffer();
+    res
+}
+
+/// The time spent on each phase of a run, if timing was requested.
+struct Stopwatch {
+    enabled: bool,
+    read: Duration,
+    interpolate: Duration,
+}
+
+impl Stopwatch {
+    fn new(options: &DemosaicOptions) -> Self {
+        Stopwatch {
+            enabled: options.timing.is_some(),
+            read: Duration::default(),
+            interpolate: Duration::default(),
+        }
+    }
+
+    fn time<T, G: FnOnce() -> T>(enabled: bool, acc: &mut Duration, f: G) -> T {
+        if !enabled {
+            return f();
+        }
+
+        let start = Instant::now();
+        let res = f();
+        *acc = *acc + start.elapsed();
+        res
+    }
+
+    fn read<T, G: FnOnce() -> T>(&mut self, f: G) -> T {
+        Self::time(self.enabled, &mut self.read, f)
+    }
+
+    fn interpolate<T, G: FnOnce() -> T>(&mut self, f: G) -> T {
+        Self::time(self.enabled, &mut self.interpolate, f)
+    }
+
+    /// Record the timing of a frame of the given number of raw bytes.
+    fn finish(&self, options: &DemosaicOptions, bytes: usize) {
+        if let Some(ref timing) = options.timing {
+            timing.record(FrameTiming {
+                read: self.read, interpolate: self.interpolate, bytes: bytes as u64,
+            });
+        }
+    }
+}
+
+/// The time budget of a frame.
+struct Deadline {
+    end: Option<Instant>,
+    overrun: Overrun,
+    exceeded: Option<usize>,
+}
+
+impl Deadline {
+    fn new(options: &DemosaicOptions) -> Self {
+        let start = Instant::now();
+        Deadline {
+            end: options.deadline.and_then(|d| start.checked_add(d)),
+            overrun: options.overrun,
+            exceeded: None,
+        }
+    }
+
+    /// Check the budget before processing row y.  Returns whether the
+    /// rows from y on should use the cheaper kernel.
+    fn check(&mut self, y: usize) -> BayerResult<bool> {
+        if self.exceeded.is_some() {
+            return Ok(true);
+        }
+
+        match self.end {
+            Some(end) if Instant::now() >= end => {
+                if self.overrun == Overrun::Abort {
+                    return Err(BayerError::DeadlineExceeded { row: y });
+                }
+                self.exceeded = Some(y);
+                Ok(true)
+            },
+            _ => Ok(false),
+        }
+    }
+
+    /// Report the overrun, once the frame is done.
+    fn finish(&self) -> BayerResult<()> {
+        match self.exceeded {
+            Some(row) => Err(BayerError::DeadlineExceeded { row }),
+            None => Ok(()),
+        }
+    }
+}
+
+fn report_progress(options: &DemosaicOptions, rows: usize) {
+    if let Some(ref progress) = options.progress {
+        progress.report(rows);
+    }
+}
+
+fn check_cancel(options: &DemosaicOptions) -> BayerResult<()> {
+    match options.cancel {
+        Some(ref token) if token.is_cancelled() => Err(BayerError::Cancelled),
+        _ => Ok(()),
+    }
+}
+
+/*--------------------------------------------------------------*/
+/* Rayon                                                        */
+/*--------------------------------------------------------------*/
+
+/// Read the image one batch of rows at a time, and process the rows
+/// of each batch in parallel.  Only the Bayer rows of the current
+/// batch and its padding are held in memory.
+#[cfg(feature = "rayon")]
+fn debayer_rows_par<R, F, T>(r: &mut R, rdr: &Reader<F>,
+        rows: &mut BayerRows<T>, frame: &Frame, kernels: Kernels<T>,
+        dst: &mut RasterMut)
+        -> BayerResult<()>
+        where R: Read + ?Sized, F: RawFormat<Sample = T>, T: RasterSample {
+    let (w, h, options) = (frame.w, frame.h, frame.options);
+    let mut stopwatch = Stopwatch::new(options);
+    let mut deadline = Deadline::new(options);
+
+    let format = RowFormat::new(frame, dst);
+    let bytes_per_pixel = format.bytes_per_pixel();
+    let x0 = bytes_per_pixel * dst.x;
+    let x1 = bytes_per_pixel * (dst.x + w);
+    let start = dst.stride * dst.y;
+    let end = dst.stride * (dst.y + h);
+
+    let stride = dst.stride;
+    let batch_len = stride * ROWS_PER_BATCH;
+    for (i, batch) in dst.buf[start..end].chunks_mut(batch_len).enumerate() {
+        let y0 = ROWS_PER_BATCH * i;
+        if y0 > 0 {
+            report_progress(options, y0);
+        }
+        check_cancel(options)?;
+        let degraded = deadline.check(y0)?;
+
+        let batch_h = batch.len() / stride;
+        stopwatch.read(|| rows.read_until(r, rdr, y0 + batch_h - 1 + kernels.padding))?;
+
+        let rows = &*rows;
+        stopwatch.interpolate(|| ThreadPool::install(&options.thread_pool, || {
+            batch.par_chunks_mut(stride).enumerate()
+                    .for_each(|(dy, row)| {
+                kernels.run_format(rows, y0 + dy, 0, degraded, format, &mut row[x0..x1]);
+            });
+        }));
+    }
+
+    report_progress(options, h);
+    stopwatch.finish(options, w * h * mem::size_of::<T>());
+    rows.finish()?;
+    deadline.finish()
+}
+
+/*--------------------------------------------------------------*/
+/* Naive                                                        */
+/*--------------------------------------------------------------*/
+
+/// Process the rows in order, keeping only the rows required by the
+/// kernel in memory.
+fn debayer_rows_seq<R, F, T>(r: &mut R, rdr: &Reader<F>,
+        rows: &mut BayerRows<T>, frame: &Frame, kernels: Kernels<T>,
+        dst: &mut RasterMut)
+        -> BayerResult<()>
+        where R: Read + ?Sized, F: RawFormat<Sample = T>, T: RasterSample {
+    let (w, h, options) = (frame.w, frame.h, frame.options);
+    let mut out = match options.transform {
+        Some(_) => vec![T::default(); 3 * w],
+        None => Vec::new(),
+    };
+    let format = RowFormat::new(frame, dst);
+    let mut stopwatch = Stopwatch::new(options);
+    let mut deadline = Deadline::new(options);
+    let mut degraded = false;
+
+    for y in 0..h {
+        if y % ROWS_PER_BATCH == 0 {
+            if y > 0 {
+                report_progress(options, y);
+            }
+            check_cancel(options)?;
+            degraded = deadline.check(y)?;
+        }
+
+        stopwatch.read(|| rows.read_until(r, rdr, y + kernels.padding))?;
+
+        let rows = &*rows;
+        stopwatch.interpolate(|| match options.transform {
+            Some(t) => {
+                kernels.run(rows, y, degraded, &mut out);
+                put_row_transformed(t, w, h, y, &out, format, dst);
+            },
+            None => kernels.run_format(rows, y, 0, degraded, format,
+                    dst.borrow_pixels_mut(y)),
+        });
+    }
+
+    report_progress(options, h);
+    stopwatch.finish(options, w * h * mem::size_of::<T>());
+    rows.finish()?;
+    deadline.finish()
+}
+
+/// Write row y of a w x h RGB image into the transformed raster.
+fn put_row_transformed<T: RasterSample>(t: Transform, w: usize, h: usize, y: usize,
+        row: &[T], format: RowFormat, dst: &mut RasterMut) {
+    let n = format.bytes_per_pixel();
+    for (x, pixel) in row.chunks(3).enumerate() {
+        let (dx, dy) = t.destination(w, h, x, y);
+        let out = &mut dst.borrow_pixels_mut(dy)[(n * dx)..(n * dx + n)];
+        format.convert(x, y, pixel, out);
+        format.expand_samples(1, out);
+    }
+}
+
+/// Check if a w x h image is large enough for the algorithm and the
+/// border handling.
+fn check_resolution(engine: &Engine, border: Border, w: usize, h: usize) -> BayerResult<()> {
+    let (mut min_width, mut min_height) = engine.alg.min_dimensions();
+
+    // Mirroring about the edge pixels requires more pixels than the
+    // padding, so that the mirrored pixels lie inside the image.
+    if border == Border::Mirror {
+        min_width = cmp::max(min_width, engine.padding + 1);
+        min_height = cmp::max(min_height, engine.padding + 1);
+    }
+
+    if w < min_width || h < min_height {
+        return Err(BayerError::WrongResolution {
+            width: w, height: h, min_width, min_height,
+        });
+    }
+
+    // A 16-bit RGB row, which also covers the padded Bayer rows.
+    if w.checked_mul(RasterDepth::Depth16.bytes_per_pixel()).is_none() {
+        return Err(OVERFLOW);
+    }
+
+    Ok(())
+}
 
 /// Check if the image depth and the raster depth are compatible.
-fn check_depth(bayer: BayerDepth, raster: RasterDepth) -> bool {
-    match raster {
+pub(crate) fn check_depth(bayer: BayerDepth, raster: RasterDepth) -> BayerResult<()> {
+    let ok = match raster {
         RasterDepth::Depth8 =>
             bayer == BayerDepth::Depth8,
         RasterDepth::Depth16 =>
-            bayer == BayerDepth::Depth16BE || bayer == BayerDepth::Depth16LE,
+            bayer != BayerDepth::Depth8,
+        RasterDepth::Depth32F => false,
+    };
+
+    if ok {
+        Ok(())
+    } else {
+        Err(BayerError::WrongDepth { bayer, raster })
+    }
+}
+
+/// Check the options for converting the samples of the image into
+/// those of the raster.  Any image can be demosaiced into any raster;
+/// the samples are widened, narrowed, or converted to floating point
+/// as needed.
+pub(crate) fn check_conversion(bayer: BayerDepth, raster: RasterDepth, options: &DemosaicOptions)
+        -> BayerResult<()> {
+    if raster == RasterDepth::Depth32F && options.float_divisor == Some(0) {
+        return Err(BayerError::InvalidArgument { reason: "the float divisor must not be zero" });
+    }
+    if raster == RasterDepth::Depth8 && bayer != BayerDepth::Depth8 && options.narrow_shift > 8 {
+        return Err(BayerError::InvalidArgument { reason: "the narrowing shift must be at most 8" });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::sync::{Arc,Mutex};
+    use std::time::Duration;
+    use ::{BayerDepth,BayerError,Border,CancelToken,CFA,DemosaicOptions,Intermediate,Levels,Narrowing,Overrun,Progress,RasterDepth,RasterLayout,RasterMut,Report,Saturation,Scaling,Timing,Transform,Truncation,WhiteBalance};
+    use ::demosaic_with_options;
+    use border::{BayerRead,BorderMirror8};
+    use super::{Demosaic,ROWS_PER_BATCH,degraded_engine,fallback_for};
+
+    #[test]
+    fn test_errors() {
+        let src = [0u8; 3 * 3];
+        let mut buf = [0u8; 6 * 3 * 3];
+
+        let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::RGGB, Demosaic::Cubic,
+                &DemosaicOptions::default(),
+                &mut RasterMut::new(3, 3, RasterDepth::Depth8, &mut buf));
+        match res {
+            Err(BayerError::WrongResolution { width: 3, height: 3, min_width: 4, min_height: 4 }) => (),
+            _ => panic!(),
+        }
+
+        let mut options = DemosaicOptions::default();
+        options.narrow_shift = 9;
+        let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth16LE, CFA::RGGB, Demosaic::Linear, &options,
+                &mut RasterMut::new(3, 3, RasterDepth::Depth8, &mut buf));
+        match res {
+            Err(BayerError::InvalidArgument { .. }) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_widen() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+
+        let mut expected = [0u8; 3 * 4 * 4];
+        let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::RGGB, Demosaic::Linear, &DemosaicOptions::default(),
+                &mut RasterMut::new(4, 4, RasterDepth::Depth8, &mut expected));
+        assert!(res.is_ok());
+
+        let mut options = DemosaicOptions::default();
+        options.widen_shift = 0;
+        let mut buf = [0u8; 6 * 4 * 4];
+        let mut dst = RasterMut::new(4, 4, RasterDepth::Depth16, &mut buf);
+        let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::RGGB, Demosaic::Linear, &options, &mut dst);
+        assert!(res.is_ok());
+        for y in 0..4 {
+            let row: Vec<u16> = expected[(12 * y)..(12 * y + 12)].iter().map(|&v| v as u16).collect();
+            assert_eq!(dst.borrow_row_u16_mut(y), &row[..]);
+        }
+
+        let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::RGGB, Demosaic::None, &DemosaicOptions::default(),
+                &mut dst);
+        assert!(res.is_ok());
+        assert_eq!(&dst.borrow_row_u16_mut(0)[0..6], &[0xE500, 0, 0, 0, 0x4300, 0]);
+
+        let mut replicate = DemosaicOptions::default();
+        replicate.widen_replicate = true;
+        let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::RGGB, Demosaic::None, &replicate, &mut dst);
+        assert!(res.is_ok());
+        assert_eq!(&dst.borrow_row_u16_mut(0)[0..6], &[0xE5E5, 0, 0, 0, 0x4343, 0]);
+
+        options.widen_shift = 9;
+        let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::RGGB, Demosaic::Linear, &options, &mut dst);
+        match res {
+            Err(BayerError::InvalidArgument { .. }) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_narrow() {
+        // R: set.seed(0); matrix(floor(runif(n=30, min=0, max=256)), nrow=5, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,232, 51,
+            229,241,169,161, 15, 52,
+             45,175, 98,197,253,116,
+            183,120,208, 81, 33,160,
+            112,124, 74,210,245, 88 ];
+        let wide: Vec<u8> = src.iter()
+                .flat_map(|&v| ((v as u16) << 8 | (v as u16 * 7) & 0xFF).to_le_bytes().to_vec())
+                .collect();
+
+        let modes = [
+            (false, None, None),
+            (true, None, None),
+            (true, Some((4, 2)), None),
+            (false, None, Some(Transform::Rotate90)) ];
+        for &(parallel, tile_size, transform) in modes.iter() {
+            let mut options = DemosaicOptions::default();
+            options.parallel = parallel;
+            options.tile_size = tile_size;
+            options.transform = transform;
+            let (w, h) = match transform {
+                Some(t) => t.dimensions(6, 5),
+                None => (6, 5),
+            };
+
+            let mut buf = [0u8; 6 * 6 * 5];
+            let mut dst = RasterMut::new(w, h, RasterDepth::Depth16, &mut buf);
+            let res = demosaic_with_options(&mut Cursor::new(&wide[..]),
+                    BayerDepth::Depth16LE, CFA::GRBG, Demosaic::Cubic, &options, &mut dst);
+            assert!(res.is_ok());
+            let expected: Vec<u16> = (0..h).flat_map(|y| dst.borrow_row_u16_mut(y).to_vec()).collect();
+
+            let cases: [(u8, Narrowing, fn(u16) -> u8); 3] = [
+                (8, Narrowing::Truncate, |v| (v >> 8) as u8),
+                (8, Narrowing::Round, |v| ::std::cmp::min((v as u32 + 0x80) >> 8, 0xFF) as u8),
+                (4, Narrowing::Truncate, |v| ::std::cmp::min(v >> 4, 0xFF) as u8) ];
+            for &(shift, narrowing, f) in cases.iter() {
+                options.narrow_shift = shift;
+                options.narrowing = narrowing;
+                let mut buf = [0u8; 3 * 6 * 5];
+                let res = demosaic_with_options(&mut Cursor::new(&wide[..]),
+                        BayerDepth::Depth16LE, CFA::GRBG, Demosaic::Cubic, &options,
+                        &mut RasterMut::new(w, h, RasterDepth::Depth8, &mut buf));
+                assert!(res.is_ok());
+                let narrowed: Vec<u8> = expected.iter().map(|&v| f(v)).collect();
+                assert_eq!(&buf[..], &narrowed[..]);
+            }
+
+            // Dithering rounds each sample either down or up.
+            options.narrow_shift = 8;
+            options.narrowing = Narrowing::Dither;
+            let mut buf = [0u8; 3 * 6 * 5];
+            let res = demosaic_with_options(&mut Cursor::new(&wide[..]),
+                    BayerDepth::Depth16LE, CFA::GRBG, Demosaic::Cubic, &options,
+                    &mut RasterMut::new(w, h, RasterDepth::Depth8, &mut buf));
+            assert!(res.is_ok());
+            for (&v, &e) in buf.iter().zip(expected.iter()) {
+                let lo = (e >> 8) as u8;
+                assert!(v == lo || v == lo.saturating_add(1));
+            }
+        }
+    }
+
+    #[test]
+    fn test_packed() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+
+        let depths = [
+            (BayerDepth::Depth10Packed, ::PackedFormat::Raw10),
+            (BayerDepth::Depth12Packed, ::PackedFormat::Raw12),
+            (BayerDepth::Depth12PackedDng, ::PackedFormat::Dng12),
+            (BayerDepth::Depth14Packed, ::PackedFormat::Raw14) ];
+
+        for &(depth, format) in depths.iter() {
+            let bits = format.bits();
+            let samples: Vec<u16> = src.iter().map(|&v| v << (bits - 8)).collect();
+            let unpacked: Vec<u8> = samples.iter().flat_map(|&v| vec![v as u8, (v >> 8) as u8]).collect();
+            let packed = ::pack(&samples, format);
+
+            let mut options = DemosaicOptions::default();
+            options.source_bits = bits as u8;
+            let mut expected = [0u8; 6 * 4 * 4];
+            let res = demosaic_with_options(&mut Cursor::new(&unpacked[..]),
+                    BayerDepth::Depth16LE, CFA::RGGB, Demosaic::Cubic, &options,
+                    &mut RasterMut::new(4, 4, RasterDepth::Depth16, &mut expected));
+            assert!(res.is_ok());
+
+            // Packed samples are scaled without setting source_bits.
+            let mut buf = [0u8; 6 * 4 * 4];
+            let res = demosaic_with_options(&mut Cursor::new(&packed[..]),
+                    depth, CFA::RGGB, Demosaic::Cubic, &DemosaicOptions::default(),
+                    &mut RasterMut::new(4, 4, RasterDepth::Depth16, &mut buf));
+            assert!(res.is_ok());
+            assert_eq!(&buf[..], &expected[..]);
+
+            let res = demosaic_with_options(&mut Cursor::new(&packed[..]),
+                    depth, CFA::RGGB, Demosaic::None, &DemosaicOptions::default(),
+                    &mut RasterMut::new(3, 4, RasterDepth::Depth16, &mut buf));
+            match res {
+                Err(BayerError::InvalidArgument { .. }) => (),
+                _ => panic!(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_progress_cancel() {
+        const IMG_W: usize = 4;
+        const IMG_H: usize = 2 * ROWS_PER_BATCH + 1;
+        let src = [0u8; IMG_W * IMG_H];
+        let mut buf = [0u8; 3 * IMG_W * IMG_H];
+
+        for &parallel in [false, true].iter() {
+            let reports = Arc::new(Mutex::new(Vec::new()));
+            let token = CancelToken::new();
+            let mut options = DemosaicOptions::default();
+            options.parallel = parallel;
+            options.cancel = Some(token.clone());
+            options.progress = {
+                let reports = reports.clone();
+                Some(Progress::new(move |rows| reports.lock().unwrap().push(rows)))
+            };
+
+            let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                    BayerDepth::Depth8, CFA::RGGB, Demosaic::Linear, &options,
+                    &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+            assert!(res.is_ok());
+            assert_eq!(*reports.lock().unwrap(),
+                    vec![ROWS_PER_BATCH, 2 * ROWS_PER_BATCH, IMG_H]);
+
+            token.cancel();
+            let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                    BayerDepth::Depth8, CFA::RGGB, Demosaic::Linear, &options,
+                    &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+            match res {
+                Err(BayerError::Cancelled) => (),
+                _ => panic!(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_rgba() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+
+        for &transform in [None, Some(Transform::Rotate90)].iter() {
+            for &(parallel, tile_size) in [(false, None), (true, None), (true, Some((3, 3)))].iter() {
+                let mut options = DemosaicOptions::default();
+                options.parallel = parallel;
+                options.tile_size = tile_size;
+                options.transform = transform;
+
+                let mut expected = [0u8; 3 * 4 * 4];
+                let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                        BayerDepth::Depth8, CFA::RGGB, Demosaic::Cubic, &options,
+                        &mut RasterMut::new(4, 4, RasterDepth::Depth8, &mut expected));
+                assert!(res.is_ok());
+
+                // A 4x4 raster at (1, 0) in a 5x4 buffer.
+                let mut buf = [0u8; 4 * 5 * 4];
+                let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                        BayerDepth::Depth8, CFA::RGGB, Demosaic::Cubic, &options,
+                        &mut RasterMut::try_with_layout(1, 0, 4, 4, 4 * 5, RasterDepth::Depth8,
+                                RasterLayout::Rgba { alpha: 0xFFFF }, &mut buf).unwrap());
+                assert!(res.is_ok());
+
+                for (row, expected) in buf.chunks(4 * 5).zip(expected.chunks(3 * 4)) {
+                    assert_eq!(&row[0..4], &[0; 4]);
+                    for (px, expected) in row[4..].chunks(4).zip(expected.chunks(3)) {
+                        assert_eq!(&px[0..3], expected);
+                        assert_eq!(px[3], 0xFF);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_float() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+        let wide: Vec<u8> = src.iter().flat_map(|&v| ((v as u16) << 8).to_be_bytes().to_vec()).collect();
+
+        for &(parallel, tile_size) in [(false, None), (true, None), (true, Some((3, 3)))].iter() {
+            let mut options = DemosaicOptions::default();
+            options.parallel = parallel;
+            options.tile_size = tile_size;
+
+            let mut expected8 = [0u8; 3 * 4 * 4];
+            let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                    BayerDepth::Depth8, CFA::RGGB, Demosaic::Cubic, &options,
+                    &mut RasterMut::new(4, 4, RasterDepth::Depth8, &mut expected8));
+            assert!(res.is_ok());
+
+            let mut expected16 = [0u8; 6 * 4 * 4];
+            let res = demosaic_with_options(&mut Cursor::new(&wide[..]),
+                    BayerDepth::Depth16BE, CFA::RGGB, Demosaic::Cubic, &options,
+                    &mut RasterMut::new(4, 4, RasterDepth::Depth16, &mut expected16));
+            assert!(res.is_ok());
+
+            let expected8: Vec<f32> = expected8.iter().map(|&v| v as f32).collect();
+            let expected16: Vec<f32> = expected16.chunks(2)
+                    .map(|s| u16::from_ne_bytes([s[0], s[1]]) as f32).collect();
+            let inputs = [
+                (BayerDepth::Depth8, &src[..], &expected8, 255.0),
+                (BayerDepth::Depth16BE, &wide[..], &expected16, 65535.0) ];
+            for &(depth, input, expected, max) in inputs.iter() {
+                for &divisor in [None, Some(1)].iter() {
+                    options.float_divisor = divisor;
+
+                    let mut buf = [0u8; 4 * 4 * 4 * 4];
+                    let res = demosaic_with_options(&mut Cursor::new(input), depth,
+                            CFA::RGGB, Demosaic::Cubic, &options,
+                            &mut RasterMut::try_with_layout(0, 0, 4, 4, 4 * 4 * 4, RasterDepth::Depth32F,
+                                    RasterLayout::Rgba { alpha: 0xFFFF }, &mut buf).unwrap());
+                    assert!(res.is_ok());
+
+                    let divisor = divisor.map_or(max, |d| d as f32);
+                    for (px, expected) in buf.chunks(4 * 4).zip(expected.chunks(3)) {
+                        let px: Vec<f32> = px.chunks(4)
+                                .map(|s| f32::from_ne_bytes([s[0], s[1], s[2], s[3]])).collect();
+                        for c in 0..3 {
+                            assert_eq!(px[c], expected[c] / divisor);
+                        }
+                        assert_eq!(px[3], 1.0);
+                    }
+                }
+            }
+        }
+
+        let mut options = DemosaicOptions::default();
+        options.float_divisor = Some(0);
+        let mut buf = [0u8; 4 * 3 * 4 * 4];
+        match demosaic_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::RGGB, Demosaic::Cubic, &options,
+                &mut RasterMut::new(4, 4, RasterDepth::Depth32F, &mut buf)) {
+            Err(BayerError::InvalidArgument { .. }) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_float_misaligned() {
+        const IMG_W: usize = 4;
+        const IMG_H: usize = 4;
+        let src: Vec<u8> = (0..(IMG_W * IMG_H)).map(|i| (i * 37 % 256) as u8).collect();
+        let len = 4 * 3 * IMG_W * IMG_H;
+
+        for &parallel in [false, true].iter() {
+            for &intermediate in [Intermediate::Unsigned, Intermediate::Signed].iter() {
+                let mut options = DemosaicOptions::default();
+                options.parallel = parallel;
+                options.intermediate = intermediate;
+                options.dark_frame = Some(Arc::new(vec![5; IMG_W * IMG_H]));
+
+                let mut expected = vec![0u8; len];
+                let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                        BayerDepth::Depth8, CFA::RGGB, Demosaic::Cubic, &options,
+                        &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth32F, &mut expected));
+                assert!(res.is_ok());
+
+                // Offset the raster to one byte past a 4-byte boundary.
+                let mut buf = vec![0u8; len + 4];
+                let offset = (4 - buf.as_ptr() as usize % 4) % 4 + 1;
+                let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                        BayerDepth::Depth8, CFA::RGGB, Demosaic::Cubic, &options,
+                        &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth32F,
+                                &mut buf[offset..(offset + len)]));
+                assert!(res.is_ok());
+                assert_eq!(&buf[offset..(offset + len)], &expected[..]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parallel_batches() {
+        const IMG_W: usize = 6;
+        const IMG_H: usize = 2 * ROWS_PER_BATCH + 3;
+        let src: Vec<u8> = (0..(IMG_W * IMG_H)).map(|i| (i * 37 % 256) as u8).collect();
+
+        for &alg in [Demosaic::Linear, Demosaic::Cubic].iter() {
+            let mut expected = [0u8; 3 * IMG_W * IMG_H];
+            let mut buf = [0u8; 3 * IMG_W * IMG_H];
+
+            let mut options = DemosaicOptions::default();
+            options.border = Some(Border::Mirror);
+            options.parallel = false;
+            let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                    BayerDepth::Depth8, CFA::BGGR, alg, &options,
+                    &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut expected));
+            assert!(res.is_ok());
+
+            options.parallel = true;
+            let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                    BayerDepth::Depth8, CFA::BGGR, alg, &options,
+                    &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+            assert!(res.is_ok());
+            assert_eq!(&buf[..], &expected[..]);
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_thread_pool() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+        let mut expected = [0u8; 3 * 4 * 4];
+        let mut buf = [0u8; 3 * 4 * 4];
+
+        let mut options = DemosaicOptions::default();
+        options.parallel = false;
+        let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::RGGB, Demosaic::Cubic, &options,
+                &mut RasterMut::new(4, 4, RasterDepth::Depth8, &mut expected));
+        assert!(res.is_ok());
+
+        let pool = ::rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+        options.parallel = true;
+        options.thread_pool = Some(::ThreadPool::new(pool));
+        let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::RGGB, Demosaic::Cubic, &options,
+                &mut RasterMut::new(4, 4, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+        assert_eq!(&buf[..], &expected[..]);
+        assert_eq!(options.clone(), options);
+    }
+
+    #[test]
+    fn test_timing() {
+        let src = [0u8; 2 * 8 * 6];
+        let mut buf = [0u8; 6 * 8 * 6];
+        let timing = Timing::new();
+        let mut options = DemosaicOptions::default();
+        options.timing = Some(timing.clone());
+        assert!(timing.last().is_none());
+
+        for &parallel in [false, true].iter() {
+            options.parallel = parallel;
+            let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                    BayerDepth::Depth16LE, CFA::RGGB, Demosaic::Cubic, &options,
+                    &mut RasterMut::new(8, 6, RasterDepth::Depth16, &mut buf));
+            assert!(res.is_ok());
+            assert_eq!(timing.last().unwrap().bytes, 2 * 8 * 6);
+        }
+    }
+
+    #[test]
+    fn test_report() {
+        let src = [0u8; 3 * 3];
+        let mut buf = [0u8; 3 * 3 * 3];
+        let report = Report::new();
+        let mut options = DemosaicOptions::default();
+        options.fallback = true;
+        options.truncation = Truncation::Replicate;
+        options.report = Some(report.clone());
+
+        let res = demosaic_with_options(&mut Cursor::new(&src[..7]),
+                BayerDepth::Depth8, CFA::GRBG, Demosaic::Cubic, &options,
+                &mut RasterMut::new(3, 3, RasterDepth::Depth8, &mut buf));
+        match res {
+            Err(BayerError::Truncated { missing_rows: 1 }) => (),
+            _ => panic!(),
+        }
+
+        let r = report.last().unwrap();
+        assert_eq!((r.width, r.height, r.depth, r.cfa), (3, 3, BayerDepth::Depth8, CFA::GRBG));
+        assert_eq!((r.requested, r.algorithm, r.border), (Demosaic::Cubic, Demosaic::MalvarHeCutler, Border::Replicate));
+        assert_eq!((r.missing_rows, r.fill, r.degraded_from_row), (1, None, None));
+        assert_eq!((r.source_bits, r.scaling, r.levels, r.white_balance), (8, Scaling::Identity, None, None));
+        assert_eq!((r.dark_frame, r.saturation, r.intermediate), (false, Saturation::Interpolate, Intermediate::Unsigned));
+        assert_eq!((r.narrowing, r.float_divisor), (None, None));
+        assert_eq!(r.to_string(),
+                "size=3x3 depth=Depth8 cfa=GRBG algorithm=MalvarHeCutler border=Replicate requested=Cubic missing_rows=1");
+
+        // The calibration and the conversion to the raster depth.
+        let mut options = DemosaicOptions::default();
+        options.report = Some(report.clone());
+        options.white_balance = Some(WhiteBalance::new(2.0, 1.0, 1.5));
+        options.dark_frame = Some(Arc::new(vec![1; 3 * 3]));
+        options.saturation = Saturation::Clip(0xF000);
+        options.intermediate = Intermediate::Signed;
+        options.widen_replicate = true;
+        let mut buf16 = [0u8; 2 * 3 * 3 * 3];
+        let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::GRBG, Demosaic::Linear, &options,
+                &mut RasterMut::new(3, 3, RasterDepth::Depth16, &mut buf16));
+        assert!(res.is_ok());
+        let r = report.last().unwrap();
+        assert_eq!(r.scaling, Scaling::Widen { shift: 8, replicate: true });
+        assert_eq!((r.dark_frame, r.narrowing, r.float_divisor), (true, None, None));
+        assert_eq!(r.to_string(),
+                "size=3x3 depth=Depth8 cfa=GRBG algorithm=Linear border=Replicate \
+                widen_shift=8 widen_replicate=true white_balance=2,1,1.5 dark_frame=true \
+                saturation=Clip(61440) intermediate=Signed");
+
+        let src16 = [0u8; 2 * 3 * 3];
+        let mut options = DemosaicOptions::default();
+        options.report = Some(report.clone());
+        options.source_bits = 12;
+        options.narrowing = Narrowing::Dither;
+        let res = demosaic_with_options(&mut Cursor::new(&src16[..]),
+                BayerDepth::Depth16LE, CFA::GRBG, Demosaic::Linear, &options,
+                &mut RasterMut::new(3, 3, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+        let r = report.last().unwrap();
+        assert_eq!((r.source_bits, r.scaling), (12, Scaling::SourceBits));
+        assert_eq!((r.narrowing, r.float_divisor), (Some((8, Narrowing::Dither)), None));
+        assert_eq!(r.to_string(),
+                "size=3x3 depth=Depth16LE cfa=GRBG algorithm=Linear border=Replicate \
+                source_bits=12 narrow_shift=8 narrowing=Dither");
+
+        options.source_bits = 16;
+        options.narrowing = Narrowing::Round;
+        options.levels = Some(Levels { black: [64, 64, 64, 64], white: 4000 });
+        let mut buf32 = [0u8; 4 * 3 * 3 * 3];
+        let res = demosaic_with_options(&mut Cursor::new(&src16[..]),
+                BayerDepth::Depth16LE, CFA::GRBG, Demosaic::Linear, &options,
+                &mut RasterMut::new(3, 3, RasterDepth::Depth32F, &mut buf32));
+        assert!(res.is_ok());
+        let r = report.last().unwrap();
+        assert_eq!((r.scaling, r.levels), (Scaling::Identity, options.levels));
+        assert_eq!((r.narrowing, r.float_divisor), (None, Some(0xFFFF)));
+        assert_eq!(r.to_string(),
+                "size=3x3 depth=Depth16LE cfa=GRBG algorithm=Linear border=Replicate \
+                black=64,64,64,64 white=4000 float_divisor=65535");
+
+        // Frames that were not written are not reported.
+        options.fallback = false;
+        let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::GRBG, Demosaic::Cubic, &options,
+                &mut RasterMut::new(3, 3, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_err());
+        assert_eq!(report.last().unwrap(), r);
+    }
+
+    #[test]
+    fn test_deadline() {
+        // R: set.seed(0); matrix(floor(runif(n=30, min=0, max=256)), nrow=5, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,232, 51,
+            229,241,169,161, 15, 52,
+             45,175, 98,197,253,116,
+            183,120,208, 81, 33,160,
+            112,124, 74,210,245, 88 ];
+
+        const IMG_W: usize = 6;
+        const IMG_H: usize = 5;
+        let mut expected = [0u8; 3 * IMG_W * IMG_H];
+        let mut buf = [0u8; 3 * IMG_W * IMG_H];
+
+        let mut options = DemosaicOptions::default();
+        options.border = Some(Border::Mirror);
+        let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::GRBG, Demosaic::NearestNeighbour, &options,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut expected));
+        assert!(res.is_ok());
+
+        for &parallel in [false, true].iter() {
+            let mut options = DemosaicOptions::default();
+            options.parallel = parallel;
+            options.deadline = Some(Duration::from_secs(0));
+
+            let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                    BayerDepth::Depth8, CFA::GRBG, Demosaic::Cubic, &options,
+                    &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+            match res {
+                Err(BayerError::DeadlineExceeded { row: 0 }) => (),
+                _ => panic!(),
+            }
+
+            // Cubic interpolation mirrors the border by default.
+            options.overrun = Overrun::Degrade;
+            let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                    BayerDepth::Depth8, CFA::GRBG, Demosaic::Cubic, &options,
+                    &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+            match res {
+                Err(BayerError::DeadlineExceeded { row: 0 }) => (),
+                _ => panic!(),
+            }
+            assert_eq!(&buf[..], &expected[..]);
+
+            options.deadline = Some(Duration::from_secs(3600));
+            let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                    BayerDepth::Depth8, CFA::GRBG, Demosaic::Cubic, &options,
+                    &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+            assert!(res.is_ok());
+        }
+    }
+
+    #[test]
+    fn test_interpolate_row() {
+        // R: set.seed(0); matrix(floor(runif(n=30, min=0, max=256)), nrow=5, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,232, 51,
+            229,241,169,161, 15, 52,
+             45,175, 98,197,253,116,
+            183,120,208, 81, 33,160,
+            112,124, 74,210,245, 88 ];
+
+        const IMG_W: usize = 6;
+        const IMG_H: usize = 5;
+        let mut expected = [0u8; 3 * IMG_W * IMG_H];
+
+        for &alg in Demosaic::all() {
+            let mut options = DemosaicOptions::default();
+            options.border = Some(Border::Mirror);
+            let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                    BayerDepth::Depth8, CFA::BGGR, alg, &options,
+                    &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut expected));
+            assert!(res.is_ok());
+
+            // Build the padded rows around row 1 by hand.
+            let p = alg.padding();
+            let rdr = BorderMirror8::new(IMG_W, p);
+            let mut padded = Vec::new();
+            for y in 0..(2 * p + 1) {
+                let y = (y as isize + 1 - p as isize).abs() as usize;
+                let y = if y < IMG_H { y } else { 2 * IMG_H - 2 - y };
+                let mut row = vec![0u8; IMG_W + 2 * p];
+                let line = &src[(IMG_W * y)..(IMG_W * (y + 1))];
+                assert!(rdr.read_line(&mut Cursor::new(line), &mut row).is_ok());
+                padded.push(row);
+            }
+
+            let rows: Vec<&[u8]> = padded.iter().map(|r| &r[..]).collect();
+            let mut buf = [0u8; 3 * IMG_W];
+            alg.interpolate_row_u8(&rows, CFA::BGGR.next_y(), &mut buf);
+            assert_eq!(&buf[..], &expected[(3 * IMG_W)..(6 * IMG_W)]);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_interpolate_row_short() {
+        let row = [0u16; 4];
+        let mut buf = [0u16; 3 * 4];
+        Demosaic::Linear.interpolate_row_u16(&[&row, &row, &row], CFA::RGGB, &mut buf);
+    }
+
+    #[test]
+    fn test_try_interpolate_row() {
+        let row = [0u8; 4];
+        let mut buf = [0u8; 3 * 4];
+        let res = Demosaic::Linear.try_interpolate_row_u8(&[&row, &row, &row], CFA::RGGB, &mut buf);
+        match res {
+            Err(BayerError::InvalidArgument { .. }) => (),
+            _ => panic!(),
+        }
+
+        let res = Demosaic::Linear.try_interpolate_row_u8(&[&row, &row], CFA::RGGB, &mut buf[..6]);
+        assert!(res.is_err());
+
+        let res = Demosaic::Linear.try_interpolate_row_u8(&[&row, &row, &row], CFA::RGGB, &mut buf[..6]);
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn test_degraded_padding() {
+        for &alg in Demosaic::all().iter() {
+            let engine = alg.engine();
+            assert!(degraded_engine(&engine).padding <= engine.padding);
+        }
+    }
+
+    #[test]
+    fn test_fallback() {
+        let src = [0u8; 3 * 3];
+        let mut buf = [0u8; 3 * 3 * 3];
+        let mut options = DemosaicOptions::default();
+        options.fallback = true;
+
+        let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::RGGB, Demosaic::Cubic, &options,
+                &mut RasterMut::new(3, 3, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+
+        let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::RGGB, Demosaic::Cubic, &options,
+                &mut RasterMut::new(1, 3, RasterDepth::Depth8, &mut buf));
+        match res {
+            Err(BayerError::WrongResolution { min_width: 4, min_height: 4, .. }) => (),
+            _ => panic!(),
+        }
+
+        assert_eq!(fallback_for(Demosaic::Cubic, 3, 8), Demosaic::MalvarHeCutler);
+        assert_eq!(fallback_for(Demosaic::NearestNeighbour, 8, 8), Demosaic::NearestNeighbour);
+    }
+
+    #[test]
+    fn test_border_none() {
+        const IMG_W: usize = 4;
+        const IMG_H: usize = 4;
+        let src = [100u8; IMG_W * IMG_H];
+        let mut buf = [0u8; 3 * IMG_W * IMG_H];
+
+        let mut options = DemosaicOptions::default();
+        options.border = Some(Border::None);
+
+        let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::RGGB, Demosaic::Linear, &options,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+
+        // Top-left corner only has half of its green neighbours and a
+        // quarter of its blue neighbours.  The interior is unaffected.
+        assert_eq!(&buf[0..3], &[100, 50, 25]);
+        assert_eq!(&buf[(3 * IMG_W + 3)..(3 * IMG_W + 6)], &[100, 100, 100]);
+    }
+
+    #[test]
+    fn test_border_choice() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+
+        const IMG_W: usize = 4;
+        const IMG_H: usize = 4;
+        let mut buf1 = [0u8; 3 * IMG_W * IMG_H];
+        let mut buf2 = [0u8; 3 * IMG_W * IMG_H];
+        let mut options = DemosaicOptions::default();
+
+        // Cubic interpolation mirrors by default.
+        options.border = Some(Border::Mirror);
+        let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::RGGB, Demosaic::Cubic, &options,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf1));
+        assert!(res.is_ok());
+
+        options.border = None;
+        let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::RGGB, Demosaic::Cubic, &options,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf2));
+        assert!(res.is_ok());
+        assert_eq!(&buf1[..], &buf2[..]);
+
+        options.border = Some(Border::Replicate);
+        let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::RGGB, Demosaic::Cubic, &options,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf2));
+        assert!(res.is_ok());
+        assert!(&buf1[..] != &buf2[..]);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175 ];
+
+        let replicated = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+            232, 51,229,241 ];
+
+        const IMG_W: usize = 4;
+        const IMG_H: usize = 4;
+        let mut expected = [0u8; 3 * IMG_W * IMG_H];
+        let mut buf = [0u8; 3 * IMG_W * IMG_H];
+        let mut options = DemosaicOptions::default();
+
+        let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::RGGB, Demosaic::Linear, &options,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+        match res {
+            Err(BayerError::Io(_)) => (),
+            _ => panic!(),
+        }
+
+        let res = demosaic_with_options(&mut Cursor::new(&replicated[..]),
+                BayerDepth::Depth8, CFA::RGGB, Demosaic::Linear, &options,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut expected));
+        assert!(res.is_ok());
+
+        options.truncation = Truncation::Replicate;
+        for &parallel in [false, true].iter() {
+            options.parallel = parallel;
+            buf = [0u8; 3 * IMG_W * IMG_H];
+
+            let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                    BayerDepth::Depth8, CFA::RGGB, Demosaic::Linear, &options,
+                    &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+            match res {
+                Err(BayerError::Truncated { missing_rows: 1 }) => (),
+                _ => panic!(),
+            }
+            assert_eq!(&buf[..], &expected[..]);
+        }
+
+        options.truncation = Truncation::Constant(0x1234);
+        let res = demosaic_with_options(&mut Cursor::new(&src[..2]),
+                BayerDepth::Depth8, CFA::RGGB, Demosaic::None, &options,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+        match res {
+            Err(BayerError::Truncated { missing_rows: 4 }) => (),
+            _ => panic!(),
+        }
+        assert_eq!(&buf[3 * IMG_W..6 * IMG_W], &[0,0xFF,0, 0,0,0xFF, 0,0xFF,0, 0,0,0xFF]);
+    }
+
+    #[test]
+    fn test_raster_offset() {
+        const IMG_W: usize = 4;
+        const IMG_H: usize = 4;
+        let src = [100u8; IMG_W * IMG_H];
+
+        for &parallel in [false, true].iter() {
+            let mut buf = [0xFFu8; 3 * (IMG_W + 2) * (IMG_H + 2)];
+            let mut options = DemosaicOptions::default();
+            options.parallel = parallel;
+
+            let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                    BayerDepth::Depth8, CFA::RGGB, Demosaic::Linear, &options,
+                    &mut RasterMut::with_offset(1, 1, IMG_W, IMG_H, 3 * (IMG_W + 2),
+                            RasterDepth::Depth8, &mut buf));
+            assert!(res.is_ok());
+
+            for (i, row) in buf.chunks(3 * (IMG_W + 2)).enumerate() {
+                if i == 0 || i == IMG_H + 1 {
+                    assert!(row.iter().all(|&v| v == 0xFF));
+                } else {
+                    assert!(row[0..3].iter().all(|&v| v == 0xFF));
+                    assert!(row[3..(3 * IMG_W + 3)].iter().all(|&v| v == 100));
+                    assert!(row[(3 * IMG_W + 3)..].iter().all(|&v| v == 0xFF));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_all() {
+        let all = Demosaic::all();
+        assert_eq!(all.len(), 7);
+
+        for (i, alg) in all.iter().enumerate() {
+            assert!(all[(i + 1)..].iter().all(|a| a != alg));
+            assert!(!alg.info().name.is_empty());
+            assert_eq!(alg.min_dimensions(), (alg.info().min_width, alg.info().min_height));
+        }
+
+        for pair in all.windows(2) {
+            assert!(pair[0].info().relative_cost <= pair[1].info().relative_cost);
+        }
     }
 }
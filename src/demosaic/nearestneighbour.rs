@@ -2,28 +2,31 @@
 
 use std::io::Read;
 
-use ::{BayerDepth,BayerError,BayerResult,CFA,RasterMut};
-use bayer::{BayerRead8,BayerRead16};
-use border_replicate::*;
-use demosaic::check_depth;
+use ::{BayerDepth,BayerResult,Border,CFA,Demosaic,DemosaicOptions,RasterMut};
+use demosaic::{Engine,run_engine};
 
 const PADDING: usize = 1;
 
+pub(crate) const ENGINE: Engine = Engine {
+    alg: Demosaic::NearestNeighbour,
+    padding: PADDING,
+    border: Border::Replicate,
+    kernel_u8: debayer_row_u8,
+    kernel_u16: debayer_row_u16,
+    kernel_u32: debayer_row_u32,
+};
+
 pub fn run(r: &mut Read,
         depth: BayerDepth, cfa: CFA, dst: &mut RasterMut)
         -> BayerResult<()> {
-    if dst.w < 2 || dst.h < 2 {
-        return Err(BayerError::WrongResolution);
-    }
-    if !check_depth(depth, dst.depth) {
-        return Err(BayerError::WrongDepth);
-    }
+    run_with_options(r, depth, cfa, &DemosaicOptions::default(), dst)
+}
 
-    match depth {
-        BayerDepth::Depth8 => debayer_u8(r, cfa, dst),
-        BayerDepth::Depth16BE => debayer_u16(r, true, cfa, dst),
-        BayerDepth::Depth16LE => debayer_u16(r, false, cfa, dst),
-    }
+pub fn run_with_options(r: &mut Read,
+        depth: BayerDepth, cfa: CFA, options: &DemosaicOptions,
+        dst: &mut RasterMut)
+        -> BayerResult<()> {
+    run_engine(&ENGINE, r, depth, cfa, options, dst)
 }
 
 macro_rules! apply_kernel_row {
@@ -74,85 +77,26 @@ macro_rules! apply_kernel_g {
 
 /*--------------------------------------------------------------*/
 
-fn debayer_u8(r: &mut Read, cfa: CFA, dst: &mut RasterMut)
-        -> BayerResult<()> {
-    let (w, h) = (dst.w, dst.h);
-    let mut prev = vec![0u8; 2 * PADDING + w];
-    let mut curr = vec![0u8; 2 * PADDING + w];
-    let mut cfa = cfa;
-
-    let rdr = BorderReplicate8::new(w, PADDING);
-    rdr.read_line(r, &mut prev)?;
-    rdr.read_line(r, &mut curr)?;
-
-    {   // y = 0.
-        let row = dst.borrow_row_u8_mut(0);
-        apply_kernel_row!(row, curr, prev, cfa, w);
-        cfa = cfa.next_y();
-    }
-
-    {   // y = 1.
-        let row = dst.borrow_row_u8_mut(1);
-        apply_kernel_row!(row, prev, curr, cfa, w);
-        cfa = cfa.next_y();
-    }
-
-    for y in 2..h {
-        rotate!(prev <- curr);
-        rdr.read_line(r, &mut curr)?;
-
-        let row = dst.borrow_row_u8_mut(y);
-        apply_kernel_row!(row, prev, curr, cfa, w);
-        cfa = cfa.next_y();
-    }
-
-    Ok(())
+fn debayer_row_u8(rows: &[&[u8]], cfa: CFA, row: &mut [u8]) {
+    let w = row.len() / 3;
+    apply_kernel_row!(row, rows[0], rows[1], cfa, w);
 }
 
-fn debayer_u16(r: &mut Read, be: bool, cfa: CFA, dst: &mut RasterMut)
-        -> BayerResult<()> {
-    let (w, h) = (dst.w, dst.h);
-    let mut prev = vec![0u16; 2 * PADDING + w];
-    let mut curr = vec![0u16; 2 * PADDING + w];
-    let mut cfa = cfa;
-
-    let rdr: Box<BayerRead16> = if be {
-        Box::new(BorderReplicate16BE::new(w, PADDING))
-    } else {
-        Box::new(BorderReplicate16LE::new(w, PADDING))
-    };
-    rdr.read_line(r, &mut prev)?;
-    rdr.read_line(r, &mut curr)?;
-
-    {   // y = 0.
-        let row = dst.borrow_row_u16_mut(0);
-        apply_kernel_row!(row, curr, prev, cfa, w);
-        cfa = cfa.next_y();
-    }
-
-    {   // y = 1.
-        let row = dst.borrow_row_u16_mut(1);
-        apply_kernel_row!(row, prev, curr, cfa, w);
-        cfa = cfa.next_y();
-    }
-
-    for y in 2..h {
-        rotate!(prev <- curr);
-        rdr.read_line(r, &mut curr)?;
-
-        let row = dst.borrow_row_u16_mut(y);
-        apply_kernel_row!(row, prev, curr, cfa, w);
-        cfa = cfa.next_y();
-    }
+fn debayer_row_u16(rows: &[&[u16]], cfa: CFA, row: &mut [u16]) {
+    let w = row.len() / 3;
+    apply_kernel_row!(row, rows[0], rows[1], cfa, w);
+}
 
-    Ok(())
+fn debayer_row_u32(rows: &[&[u32]], cfa: CFA, row: &mut [u32]) {
+    let w = row.len() / 3;
+    apply_kernel_row!(row, rows[0], rows[1], cfa, w);
 }
 
 #[cfg(test)]
 mod tests {
     use std::io::Cursor;
-    use ::{CFA,RasterDepth,RasterMut};
-    use super::debayer_u8;
+    use ::{BayerDepth,CFA,RasterDepth,RasterMut};
+    use super::run;
 
     #[test]
     fn test_even() {
@@ -173,7 +117,7 @@ mod tests {
         const IMG_H: usize = 4;
         let mut buf = [0u8; 3 * IMG_W * IMG_H];
 
-        let res = debayer_u8(&mut Cursor::new(&src[..]), CFA::RGGB,
+        let res = run(&mut Cursor::new(&src[..]), BayerDepth::Depth8, CFA::RGGB,
                 &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
         assert!(res.is_ok());
         assert_eq!(&buf[..], &expected[..]);
@@ -196,7 +140,7 @@ mod tests {
         const IMG_H: usize = 3;
         let mut buf = [0u8; 3 * IMG_W * IMG_H];
 
-        let res = debayer_u8(&mut Cursor::new(&src[..]), CFA::RGGB,
+        let res = run(&mut Cursor::new(&src[..]), BayerDepth::Depth8, CFA::RGGB,
                 &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
         assert!(res.is_ok());
         assert_eq!(&buf[..], &expected[..]);
@@ -2,32 +2,60 @@
 
 use std::io::Read;
 
-use ::{BayerDepth,BayerError,BayerResult,CFA,RasterMut};
-use bayer::{BayerRead8,BayerRead16};
-use border_none::*;
-use demosaic::check_depth;
+use ::{BayerDepth,BayerResult,Border,CFA,Demosaic,DemosaicOptions,Fill,RasterMut};
+use demosaic::{Engine,run_engine};
+
+const PADDING: usize = 0;
+
+pub(crate) const ENGINE: Engine = Engine {
+    alg: Demosaic::None,
+    padding: PADDING,
+    border: Border::None,
+    kernel_u8: debayer_row_u8,
+    kernel_u16: debayer_row_u16,
+    kernel_u32: debayer_row_u32,
+};
+
+const ENGINE_MID_GRAY: Engine = Engine {
+    kernel_u8: debayer_row_mid_gray_u8,
+    kernel_u16: debayer_row_mid_gray_u16,
+    kernel_u32: debayer_row_mid_gray_u32,
+    ..ENGINE
+};
+
+const ENGINE_REPLICATE: Engine = Engine {
+    kernel_u8: debayer_row_replicate_u8,
+    kernel_u16: debayer_row_replicate_u16,
+    kernel_u32: debayer_row_replicate_u32,
+    ..ENGINE
+};
+
+/// The engine filling the missing channels with the given value.
+pub(crate) fn engine_with_fill(fill: Fill) -> Engine {
+    match fill {
+        Fill::Zero => ENGINE,
+        Fill::MidGray => ENGINE_MID_GRAY,
+        Fill::Replicate => ENGINE_REPLICATE,
+    }
+}
 
 pub fn run(r: &mut Read,
         depth: BayerDepth, cfa: CFA, dst: &mut RasterMut)
         -> BayerResult<()> {
-    if dst.w < 2 || dst.h < 2 {
-        return Err(BayerError::WrongResolution);
-    }
-    if !check_depth(depth, dst.depth) {
-        return Err(BayerError::WrongDepth);
-    }
+    run_with_options(r, depth, cfa, &DemosaicOptions::default(), dst)
+}
 
-    match depth {
-        BayerDepth::Depth8 => debayer_u8(r, cfa, dst),
-        BayerDepth::Depth16BE => debayer_u16(r, true, cfa, dst),
-        BayerDepth::Depth16LE => debayer_u16(r, false, cfa, dst),
-    }
+pub fn run_with_options(r: &mut Read,
+        depth: BayerDepth, cfa: CFA, options: &DemosaicOptions,
+        dst: &mut RasterMut)
+        -> BayerResult<()> {
+    run_engine(&ENGINE, r, depth, cfa, options, dst)
 }
 
 macro_rules! apply_kernel_row {
-    ($row:ident, $curr:expr, $cfa:expr, $w:expr) => {{
+    ($row:ident, $curr:expr, $cfa:expr, $w:expr, $fill:expr) => {{
         for e in $row.iter_mut() {
-            *e = 0;
+            *e = $fill;
         }
 
         let (mut i, cfa_c) =
@@ -50,6 +78,16 @@ macro_rules! apply_kernel_row {
     }}
 }
 
+macro_rules! apply_kernel_replicate_row {
+    ($row:ident, $curr:expr, $w:expr) => {{
+        for i in 0..$w {
+            $row[3 * i + 0] = $curr[i];
+            $row[3 * i + 1] = $curr[i];
+            $row[3 * i + 2] = $curr[i];
+        }
+    }}
+}
+
 macro_rules! apply_kernel_c {
     ($row:ident, $curr:expr, $cfa:expr, $i:expr) => {{
         if $cfa == CFA::BGGR {
@@ -68,51 +106,58 @@ macro_rules! apply_kernel_g {
 
 /*--------------------------------------------------------------*/
 
-fn debayer_u8(r: &mut Read, cfa: CFA, dst: &mut RasterMut)
-        -> BayerResult<()> {
-    let (w, h) = (dst.w, dst.h);
-    let mut curr = vec![0u8; w];
-    let mut cfa = cfa;
+fn debayer_row_u8(rows: &[&[u8]], cfa: CFA, row: &mut [u8]) {
+    let w = row.len() / 3;
+    apply_kernel_row!(row, rows[0], cfa, w, 0);
+}
 
-    let rdr = BorderNone8::new();
+fn debayer_row_u16(rows: &[&[u16]], cfa: CFA, row: &mut [u16]) {
+    let w = row.len() / 3;
+    apply_kernel_row!(row, rows[0], cfa, w, 0);
+}
 
-    for y in 0..h {
-        let row = dst.borrow_row_u8_mut(y);
-        rdr.read_line(r, &mut curr)?;
-        apply_kernel_row!(row, curr, cfa, w);
-        cfa = cfa.next_y();
-    }
+fn debayer_row_u32(rows: &[&[u32]], cfa: CFA, row: &mut [u32]) {
+    let w = row.len() / 3;
+    apply_kernel_row!(row, rows[0], cfa, w, 0);
+}
 
-    Ok(())
+fn debayer_row_mid_gray_u8(rows: &[&[u8]], cfa: CFA, row: &mut [u8]) {
+    let w = row.len() / 3;
+    apply_kernel_row!(row, rows[0], cfa, w, 0x80);
 }
 
-fn debayer_u16(r: &mut Read, be: bool, cfa: CFA, dst: &mut RasterMut)
-        -> BayerResult<()> {
-    let (w, h) = (dst.w, dst.h);
-    let mut curr = vec![0u16; w];
-    let mut cfa = cfa;
-
-    let rdr: Box<BayerRead16> = if be {
-        Box::new(BorderNone16BE::new())
-    } else {
-        Box::new(BorderNone16LE::new())
-    };
-
-    for y in 0..h {
-        let row = dst.borrow_row_u16_mut(y);
-        rdr.read_line(r, &mut curr)?;
-        apply_kernel_row!(row, curr, cfa, w);
-        cfa = cfa.next_y();
-    }
+fn debayer_row_mid_gray_u16(rows: &[&[u16]], cfa: CFA, row: &mut [u16]) {
+    let w = row.len() / 3;
+    apply_kernel_row!(row, rows[0], cfa, w, 0x8000);
+}
 
-    Ok(())
+// The 32-bit rows hold 16-bit samples offset by a pedestal, which
+// is never needed without interpolation, see `Frame::new`.
+fn debayer_row_mid_gray_u32(rows: &[&[u32]], cfa: CFA, row: &mut [u32]) {
+    let w = row.len() / 3;
+    apply_kernel_row!(row, rows[0], cfa, w, 0x8000);
+}
+
+fn debayer_row_replicate_u8(rows: &[&[u8]], _cfa: CFA, row: &mut [u8]) {
+    let w = row.len() / 3;
+    apply_kernel_replicate_row!(row, rows[0], w);
+}
+
+fn debayer_row_replicate_u16(rows: &[&[u16]], _cfa: CFA, row: &mut [u16]) {
+    let w = row.len() / 3;
+    apply_kernel_replicate_row!(row, rows[0], w);
+}
+
+fn debayer_row_replicate_u32(rows: &[&[u32]], _cfa: CFA, row: &mut [u32]) {
+    let w = row.len() / 3;
+    apply_kernel_replicate_row!(row, rows[0], w);
 }
 
 #[cfg(test)]
 mod tests {
     use std::io::Cursor;
-    use ::{CFA,RasterDepth,RasterMut};
-    use super::debayer_u8;
+    use ::{BayerDepth,CFA,DemosaicOptions,Fill,RasterDepth,RasterMut};
+    use super::{run,run_with_options};
 
     #[test]
     fn test_even() {
@@ -133,7 +178,7 @@ mod tests {
         const IMG_H: usize = 4;
         let mut buf = [0u8; 3 * IMG_W * IMG_H];
 
-        let res = debayer_u8(&mut Cursor::new(&src[..]), CFA::RGGB,
+        let res = run(&mut Cursor::new(&src[..]), BayerDepth::Depth8, CFA::RGGB,
                 &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
         assert!(res.is_ok());
         assert_eq!(&buf[..], &expected[..]);
@@ -156,9 +201,41 @@ mod tests {
         const IMG_H: usize = 3;
         let mut buf = [0u8; 3 * IMG_W * IMG_H];
 
-        let res = debayer_u8(&mut Cursor::new(&src[..]), CFA::RGGB,
+        let res = run(&mut Cursor::new(&src[..]), BayerDepth::Depth8, CFA::RGGB,
                 &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
         assert!(res.is_ok());
         assert_eq!(&buf[..], &expected[..]);
     }
+
+    #[test]
+    fn test_fill() {
+        let src = [
+            229, 67,
+            232, 51 ];
+
+        let expected_mid_gray = [
+            229,128,128,  128, 67,128,
+            128,232,128,  128,128, 51 ];
+
+        let expected_replicate = [
+            229,229,229,   67, 67, 67,
+            232,232,232,   51, 51, 51 ];
+
+        const IMG_W: usize = 2;
+        const IMG_H: usize = 2;
+        let mut buf = [0u8; 3 * IMG_W * IMG_H];
+        let mut options = DemosaicOptions::default();
+
+        options.fill = Fill::MidGray;
+        let res = run_with_options(&mut Cursor::new(&src[..]), BayerDepth::Depth8, CFA::RGGB,
+                &options, &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+        assert_eq!(&buf[..], &expected_mid_gray[..]);
+
+        options.fill = Fill::Replicate;
+        let res = run_with_options(&mut Cursor::new(&src[..]), BayerDepth::Depth8, CFA::RGGB,
+                &options, &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+        assert_eq!(&buf[..], &expected_replicate[..]);
+    }
 }
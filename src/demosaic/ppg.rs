@@ -0,0 +1,275 @@
+//! Demosaicing using patterned pixel grouping (PPG).
+//!
+//! C.-K. Lin, "Pixel Grouping for Color Filter Array Demosaicing",
+//! 2003, as implemented by dcraw.  The algorithm runs in three steps:
+//!
+//! 1. Green is interpolated at the red and blue samples, along
+//!    whichever of the horizontal and vertical directions has the
+//!    smaller gradient, and limited to the range of the two green
+//!    samples in that direction.
+//!
+//! 2. Red and blue are interpolated at the green samples from the
+//!    colour differences of the neighbouring samples.
+//!
+//! 3. Blue is interpolated at the red samples, and red at the blue
+//!    samples, from the colour differences along whichever diagonal
+//!    has the smaller gradient.
+//!
+//! Each row is interpolated straight from the Bayer rows: the greens
+//! of step 1 are computed for the row and the rows above and below
+//! it, so the algorithm streams like the others at the cost of a
+//! wider kernel.
+
+use std::io::Read;
+
+use ::{BayerDepth,BayerResult,Border,CFA,Demosaic,DemosaicOptions,RasterMut};
+use demosaic::{Engine,run_engine};
+use raster::RasterSample;
+
+const PADDING: usize = 4;
+
+pub(crate) const ENGINE: Engine = Engine {
+    alg: Demosaic::PPG,
+    padding: PADDING,
+    border: Border::Mirror,
+    kernel_u8: debayer_row::<u8>,
+    kernel_u16: debayer_row::<u16>,
+    kernel_u32: debayer_row::<u32>,
+};
+
+pub fn run(r: &mut Read,
+        depth: BayerDepth, cfa: CFA, dst: &mut RasterMut)
+        -> BayerResult<()> {
+    run_with_options(r, depth, cfa, &DemosaicOptions::default(), dst)
+}
+
+pub fn run_with_options(r: &mut Read,
+        depth: BayerDepth, cfa: CFA, options: &DemosaicOptions,
+        dst: &mut RasterMut)
+        -> BayerResult<()> {
+    run_engine(&ENGINE, r, depth, cfa, options, dst)
+}
+
+/// The neighbourhood of the output pixel x in the padded Bayer rows.
+struct Neighbourhood<'a, T: 'a> {
+    rows: &'a [&'a [T]],
+    cfa: CFA,
+    x: usize,
+}
+
+impl<'a, T: RasterSample> Neighbourhood<'a, T> {
+    /// The raw sample at offset (dx, dy).
+    fn raw(&self, dx: isize, dy: isize) -> i32 {
+        let row = self.rows[(PADDING as isize + dy) as usize];
+        row[((self.x + PADDING) as isize + dx) as usize].to_u32() as i32
+    }
+
+    /// The channel of the sample at offset (dx, dy).
+    fn channel(&self, dx: isize, dy: isize) -> usize {
+        // Offset by PADDING, which is even, to keep the parity.
+        let x = (self.x + PADDING) as isize + dx;
+        let y = PADDING as isize + dy;
+        self.cfa.at(x as usize, y as usize).channel()
+    }
+
+    /// The green at offset (dx, dy), interpolated by step 1 if the
+    /// sample is red or blue.
+    fn green(&self, dx: isize, dy: isize) -> i32 {
+        let c = self.raw(dx, dy);
+        if self.channel(dx, dy) == 1 {
+            return c;
+        }
+
+        let mut guess = [0; 2];
+        let mut diff = [0; 2];
+        let mut limits = [(0, 0); 2];
+        for (i, &(ax, ay)) in [(1, 0), (0, 1)].iter().enumerate() {
+            let at = |k: isize| self.raw(dx + k * ax, dy + k * ay);
+            let (g_prev, g_next) = (at(-1), at(1));
+
+            guess[i] = (g_prev + c + g_next) * 2 - at(-2) - at(2);
+            diff[i] = ((at(-2) - c).abs() + (at(2) - c).abs() + (g_prev - g_next).abs()) * 3
+                + ((at(3) - g_next).abs() + (at(-3) - g_prev).abs()) * 2;
+            limits[i] = (g_prev, g_next);
+        }
+
+        let i = if diff[0] > diff[1] { 1 } else { 0 };
+        let (lo, hi) = if limits[i].0 < limits[i].1 { limits[i] } else { (limits[i].1, limits[i].0) };
+        clamp(guess[i] >> 2, lo, hi)
+    }
+
+}
+
+/// The greens of the rows above, on and below the output row, each
+/// with one pixel of padding.
+struct Greens {
+    rows: [Vec<i32>; 3],
+}
+
+impl Greens {
+    fn new<T: RasterSample>(rows: &[&[T]], cfa: CFA, w: usize) -> Self {
+        let row = |dy: isize| -> Vec<i32> {
+            (0..(w + 2)).map(|x| {
+                // Pixel x - 1, which is offset by 2 to keep the parity.
+                let n = Neighbourhood { rows, cfa, x: x + 1 };
+                n.green(-2, dy)
+            }).collect()
+        };
+
+        Greens { rows: [row(-1), row(0), row(1)] }
+    }
+
+    /// The green at offset (dx, dy) from pixel x.
+    fn at(&self, x: usize, dx: isize, dy: isize) -> i32 {
+        self.rows[(1 + dy) as usize][(x as isize + 1 + dx) as usize]
+    }
+}
+
+/// Interpolate the channel of the neighbours at offsets -d and d of
+/// pixel x from their colour differences with green g, returning
+/// twice the estimate and the gradient along d.
+fn colour_difference<T: RasterSample>(n: &Neighbourhood<T>, greens: &Greens,
+        d: (isize, isize), g: i32) -> (i32, i32) {
+    let (prev, next) = (n.raw(-d.0, -d.1), n.raw(d.0, d.1));
+    let (g_prev, g_next) = (greens.at(n.x, -d.0, -d.1), greens.at(n.x, d.0, d.1));
+
+    let guess = prev + next + 2 * g - g_prev - g_next;
+    let diff = (prev - next).abs() + (g_prev - g).abs() + (g_next - g).abs();
+    (guess, diff)
+}
+
+fn clamp(v: i32, lo: i32, hi: i32) -> i32 {
+    if v < lo { lo } else if v > hi { hi } else { v }
+}
+
+fn sample<T: RasterSample>(v: i32) -> T {
+    T::saturating_from(if v < 0 { 0 } else { v as u32 })
+}
+
+/*--------------------------------------------------------------*/
+
+fn debayer_row<T: RasterSample>(rows: &[&[T]], cfa: CFA, row: &mut [T]) {
+    let greens = Greens::new(rows, cfa, row.len() / 3);
+
+    for (x, px) in row.chunks_mut(3).enumerate() {
+        let n = Neighbourhood { rows, cfa, x };
+        let c = n.channel(0, 0);
+
+        if c == 1 {
+            // Step 2: the horizontal neighbours, then the vertical.
+            let g = n.raw(0, 0);
+            let h = n.channel(1, 0);
+            for &(d, ch) in [((1, 0), h), ((0, 1), 2 - h)].iter() {
+                let (guess, _) = colour_difference(&n, &greens, d, g);
+                px[ch] = sample(guess >> 1);
+            }
+            px[1] = sample(g);
+        } else {
+            // Steps 1 and 3.
+            let g = greens.at(x, 0, 0);
+            let (guess0, diff0) = colour_difference(&n, &greens, (1, 1), g);
+            let (guess1, diff1) = colour_difference(&n, &greens, (-1, 1), g);
+            let v = if diff0 < diff1 {
+                guess0 >> 1
+            } else if diff0 > diff1 {
+                guess1 >> 1
+            } else {
+                (guess0 + guess1) >> 2
+            };
+
+            px[c] = sample(n.raw(0, 0));
+            px[1] = sample(g);
+            px[2 - c] = sample(v);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use ::{BayerDepth,CFA,DemosaicOptions,RasterDepth,RasterMut};
+    use super::{run,run_with_options};
+
+    #[test]
+    fn test_parallel() {
+        // R: set.seed(0); matrix(floor(runif(n=64, min=0, max=256)), nrow=8, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,232, 51,229,241,
+            169,161, 15, 52, 45,175, 98,197,
+            127,183,253, 97,199,239, 54,166,
+             32, 68, 98,  3, 97,222, 87,123,
+            153,126, 47,211,171,203, 27,185,
+            105,210,165,200,141,135,202,  5,
+            122,187,177,122,220,112, 62, 18,
+             25, 80,132,169,104,233, 75,117 ];
+
+        const IMG_W: usize = 8;
+        const IMG_H: usize = 8;
+        let mut expected = [0u8; 3 * IMG_W * IMG_H];
+        let mut buf = [0u8; 3 * IMG_W * IMG_H];
+
+        let mut options = DemosaicOptions::default();
+        options.parallel = false;
+        let res = run_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::GRBG, &options,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut expected));
+        assert!(res.is_ok());
+
+        options.parallel = true;
+        let res = run_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::GRBG, &options,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+        assert_eq!(&buf[..], &expected[..]);
+    }
+
+    #[test]
+    fn test_even() {
+        // R: set.seed(0); matrix(floor(runif(n=64, min=0, max=256)), nrow=8, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,232, 51,229,241,
+            169,161, 15, 52, 45,175, 98,197,
+            127,183,253, 97,199,239, 54,166,
+             32, 68, 98,  3, 97,222, 87,123,
+            153,126, 47,211,171,203, 27,185,
+            105,210,165,200,141,135,202,  5,
+            122,187,177,122,220,112, 62, 18,
+             25, 80,132,169,104,233, 75,117 ];
+
+        let expected = [
+			229, 67, 80,  162, 67, 80,   95, 67, 33,  253,146, 65,  232, 45, 44,  210, 51,130,  229, 98,187,  255,241,255,
+			255,169,182,  255,148,161,   64, 15,  0,  255,133, 52,  203, 45, 44,  226, 96,175,  147, 98,187,  147, 98,197,
+			127, 68, 68,  247,183,183,  253,183,136,  197, 97,  9,  199, 69, 61,  255,239,255,   54, 87,199,  133,166,233,
+			 97, 32, 19,   71, 81, 68,   93, 98, 44,  168, 97,  3,  198, 97,112,  116, 97,222,    0, 87,167,    0, 87,123,
+			153, 82,112,  122,126,156,   47,126,137,  207,211,181,  171, 99,179,  160,203,239,   27,185,177,   27,185,104,
+			108,105,178,  182,137,210,  135,165,219,  211,165,200,  220,141,132,  151,187,135,  141,202, 77,  141,202,  5,
+			122,187,235,  164,187,235,  177,157,217,  175,122,163,  220,134,216,  173,112,150,   62, 25, 20,   55, 18,  0,
+			  0, 25, 49,   33, 56, 80,  152,132,167,  175,122,169,  190,104,192,  165,104,233,  112, 75,160,  112, 75,117 ];
+
+        const IMG_W: usize = 8;
+        const IMG_H: usize = 8;
+        let mut buf = [0u8; 3 * IMG_W * IMG_H];
+
+        let res = run(&mut Cursor::new(&src[..]), BayerDepth::Depth8, CFA::RGGB,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+        assert_eq!(&buf[..], &expected[..]);
+    }
+
+    #[test]
+    fn test_edge() {
+        // A vertical edge is interpolated along, not across.
+        let src: Vec<u16> = (0..(8 * 8)).map(|i| if i % 8 < 4 { 1000 } else { 60000 }).collect();
+        let bytes: Vec<u8> = src.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect();
+        let mut buf = [0u8; 6 * 8 * 8];
+
+        let mut dst = RasterMut::new(8, 8, RasterDepth::Depth16, &mut buf);
+        let res = run(&mut Cursor::new(&bytes[..]), BayerDepth::Depth16LE, CFA::BGGR, &mut dst);
+        assert!(res.is_ok());
+        for y in 0..8 {
+            let row = dst.borrow_row_u16_mut(y);
+            assert!(row[..12].iter().all(|&v| v == 1000));
+            assert!(row[12..].iter().all(|&v| v == 60000));
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Handling of saturated samples.
+//!
+//! Clipping happens as the Bayer rows are read.  The sentinel and
+//! exclusion strategies fix up each interpolated row afterwards, by
+//! looking for saturated samples under the kernel of each pixel.
+
+use ::{CFA,Saturation};
+use raster::RasterSample;
+
+/// Clamp a padded Bayer row to the white level, offset by the
+/// pedestal, if clipping.
+pub(crate) fn clip_row<T: RasterSample>(saturation: Saturation, pedestal: u16, row: &mut [T]) {
+    if let Saturation::Clip(white) = saturation {
+        let white = white as u32 + pedestal as u32;
+        let max = T::saturating_from(white);
+        for e in row.iter_mut() {
+            if e.to_u32() > white {
+                *e = max;
+            }
+        }
+    }
+}
+
+/// Fix up the RGB row interpolated from the padded Bayer rows, for
+/// the sentinel and exclusion strategies.  The white level is offset
+/// by the pedestal.
+pub(crate) fn fix_row<T: RasterSample>(saturation: Saturation, pedestal: u16,
+        rows: &[&[T]], cfa: CFA, dst: &mut [T]) {
+    let white = match saturation {
+        Saturation::Sentinel(white) | Saturation::Exclude(white) =>
+            white as u32 + pedestal as u32,
+        Saturation::Interpolate | Saturation::Clip(_) => return,
+    };
+
+    // Without padding, no pixel is interpolated from its neighbours.
+    let padding = rows.len() / 2;
+    if padding == 0 {
+        return;
+    }
+
+    let n = 2 * padding + 1;
+    let saturated = |j: usize| rows.iter().any(|row| row[j].to_u32() >= white);
+
+    // The number of saturated columns under the kernel of pixel x.
+    let mut count = (0..(n - 1)).filter(|&j| saturated(j)).count();
+    for (x, px) in dst.chunks_mut(3).enumerate() {
+        if saturated(x + n - 1) {
+            count = count + 1;
+        }
+
+        if count > 0 {
+            match saturation {
+                Saturation::Sentinel(_) => {
+                    for e in px.iter_mut() {
+                        *e = T::saturating_from(white);
+                    }
+                },
+                _ => exclude(rows, padding, cfa, x, white, px),
+            }
+        }
+
+        if saturated(x) {
+            count = count - 1;
+        }
+    }
+}
+
+/// Interpolate pixel x from the unsaturated samples of its 3x3
+/// neighbourhood, keeping its own sample.
+fn exclude<T: RasterSample>(rows: &[&[T]], padding: usize, cfa: CFA,
+        x: usize, white: u32, px: &mut [T]) {
+    let mut sum = [0u32; 3];
+    let mut count = [0u32; 3];
+    let mut own = 0;
+
+    for dy in 0..3 {
+        let row = rows[padding - 1 + dy];
+        for dx in 0..3 {
+            // Offset by 2 rather than -1, which has the same parity.
+            let c = cfa.at(x + dx + 1, dy + 1).channel();
+            if dx == 1 && dy == 1 {
+                own = c;
+                continue;
+            }
+
+            let v = row[x + padding - 1 + dx].to_u32();
+            if v < white {
+                sum[c] = sum[c] + v;
+                count[c] = count[c] + 1;
+            }
+        }
+    }
+
+    for c in 0..3 {
+        if c != own {
+            px[c] = if count[c] > 0 {
+                T::saturating_from(sum[c] / count[c])
+            } else {
+                T::saturating_from(white)
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{BayerDepth,CFA,Demosaic,DemosaicOptions,RasterDepth,RasterMut,Saturation,demosaic_from};
+
+    // A flat gray image with one saturated green sample.
+    fn run(alg: Demosaic, saturation: Saturation) -> [u8; 3 * 6 * 6] {
+        let mut src = [40u8; 6 * 6];
+        src[6 * 2 + 3] = 255;
+
+        let mut options = DemosaicOptions::default();
+        options.saturation = saturation;
+        let mut buf = [0u8; 3 * 6 * 6];
+        let res = demosaic_from(&mut &src[..], BayerDepth::Depth8, CFA::RGGB, alg,
+                &options, &mut RasterMut::new(6, 6, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+        buf
+    }
+
+    fn pixel(buf: &[u8], x: usize, y: usize) -> &[u8] {
+        &buf[(3 * (6 * y + x))..(3 * (6 * y + x) + 3)]
+    }
+
+    #[test]
+    fn test_interpolate() {
+        // The saturated sample leaks into the green of its neighbours.
+        let buf = run(Demosaic::Linear, Saturation::Interpolate);
+        assert_eq!(pixel(&buf, 2, 2), &[40, 93, 40]);
+        assert_eq!(pixel(&buf, 3, 2), &[40, 255, 40]);
+    }
+
+    #[test]
+    fn test_clip() {
+        let buf = run(Demosaic::Linear, Saturation::Clip(100));
+        assert_eq!(pixel(&buf, 2, 2), &[40, 55, 40]);
+        assert_eq!(pixel(&buf, 3, 2), &[40, 100, 40]);
+    }
+
+    #[test]
+    fn test_sentinel() {
+        let buf = run(Demosaic::Linear, Saturation::Sentinel(250));
+        for y in 0..6 {
+            for x in 0..6 {
+                let near = x >= 2 && x <= 4 && y >= 1 && y <= 3;
+                let expected = if near { [250; 3] } else { [40; 3] };
+                assert_eq!(pixel(&buf, x, y), &expected, "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_exclude() {
+        for &alg in [Demosaic::NearestNeighbour, Demosaic::Linear, Demosaic::Cubic].iter() {
+            let buf = run(alg, Saturation::Exclude(250));
+            for y in 0..6 {
+                for x in 0..6 {
+                    let expected = if (x, y) == (3, 2) { [40, 255, 40] } else { [40; 3] };
+                    assert_eq!(pixel(&buf, x, y), &expected, "{:?} ({}, {})", alg, x, y);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,226 @@
+//! SIMD implementations of the interpolation kernels.
+//!
+//! The kernels compute eight (SSE2) or sixteen (AVX2) pixels at a
+//! time in 16-bit lanes, then interleave the channels into the RGB
+//! row.  The instruction set is detected at run time; the pixels that
+//! do not fill a whole vector are left to the scalar kernels.
+//!
+//! The averages are computed without widening, using
+//!
+//! ```text
+//!   (a + b) / 2         = a/2 + b/2 + (a & b & 1)
+//!   (a + b + c + d) / 4 = a/4 + b/4 + c/4 + d/4
+//!                       + ((a & 3) + (b & 3) + (c & 3) + (d & 3)) / 4
+//! ```
+//!
+//! which give exactly the same results as the scalar kernels.
+
+#[cfg(target_arch = "x86")]
+use std::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+use ::CFA;
+
+/// Samples that can be loaded into 16-bit lanes.
+pub(crate) trait SimdSample: Copy {
+    /// Load 8 samples.
+    unsafe fn load_sse2(p: *const Self) -> __m128i;
+
+    /// Load 16 samples.  Requires AVX2.
+    unsafe fn load_avx2(p: *const Self) -> __m256i;
+
+    fn from_u16(v: u16) -> Self;
+}
+
+impl SimdSample for u8 {
+    #[inline(always)]
+    unsafe fn load_sse2(p: *const u8) -> __m128i {
+        _mm_unpacklo_epi8(_mm_loadl_epi64(p as *const __m128i), _mm_setzero_si128())
+    }
+
+    #[inline(always)]
+    unsafe fn load_avx2(p: *const u8) -> __m256i {
+        _mm256_cvtepu8_epi16(_mm_loadu_si128(p as *const __m128i))
+    }
+
+    #[inline(always)]
+    fn from_u16(v: u16) -> u8 {
+        v as u8
+    }
+}
+
+impl SimdSample for u16 {
+    #[inline(always)]
+    unsafe fn load_sse2(p: *const u16) -> __m128i {
+        _mm_loadu_si128(p as *const __m128i)
+    }
+
+    #[inline(always)]
+    unsafe fn load_avx2(p: *const u16) -> __m256i {
+        _mm256_loadu_si256(p as *const __m256i)
+    }
+
+    #[inline(always)]
+    fn from_u16(v: u16) -> u16 {
+        v
+    }
+}
+
+macro_rules! linear_blocks {
+    ($name:ident, $feature:tt, $lanes:expr, $load:ident, $store:ident,
+            $add:ident, $srli:ident, $and:ident, $andnot:ident, $or:ident,
+            $set1_16:ident, $set1_32:ident) => {
+        #[target_feature(enable = $feature)]
+        unsafe fn $name<T: SimdSample>(prev: &[T], curr: &[T], next: &[T],
+                cfa_c: CFA, mut i: usize, row: &mut [T]) -> usize {
+            let w = row.len() / 3;
+            let (c, d) = if cfa_c == CFA::BGGR { (2, 0) } else { (0, 2) };
+            // The rows always hold the padding pixels on either side;
+            // this guards the unchecked loads below against misuse
+            // within the crate, and cannot fail for rows of BayerRows.
+            assert!(prev.len() >= w + 2 && curr.len() >= w + 2 && next.len() >= w + 2);
+
+            // The even lanes hold the red/blue pixels.
+            let even = $set1_32(0xFFFF);
+            let one = $set1_16(1);
+            let three = $set1_16(3);
+
+            let avg2 = |a, b| $add($add($srli(a, 1), $srli(b, 1)), $and($and(a, b), one));
+            let avg4 = |a, b, c, d| {
+                let q = $add($add($srli(a, 2), $srli(b, 2)), $add($srli(c, 2), $srli(d, 2)));
+                let r = $add($add($and(a, three), $and(b, three)), $add($and(c, three), $and(d, three)));
+                $add(q, $srli(r, 2))
+            };
+            let select = |a, b| $or($and(even, a), $andnot(even, b));
+
+            let mut plane_c = [0u16; $lanes];
+            let mut plane_g = [0u16; $lanes];
+            let mut plane_d = [0u16; $lanes];
+
+            while i + $lanes <= w {
+                // Pixel i is at index i + 1 of the padded rows.
+                let prev_l = T::$load(prev.as_ptr().add(i));
+                let prev_m = T::$load(prev.as_ptr().add(i + 1));
+                let prev_r = T::$load(prev.as_ptr().add(i + 2));
+                let curr_l = T::$load(curr.as_ptr().add(i));
+                let curr_m = T::$load(curr.as_ptr().add(i + 1));
+                let curr_r = T::$load(curr.as_ptr().add(i + 2));
+                let next_l = T::$load(next.as_ptr().add(i));
+                let next_m = T::$load(next.as_ptr().add(i + 1));
+                let next_r = T::$load(next.as_ptr().add(i + 2));
+
+                let cross = avg4(prev_m, curr_l, curr_r, next_m);
+                let diagonal = avg4(prev_l, prev_r, next_l, next_r);
+                let horizontal = avg2(curr_l, curr_r);
+                let vertical = avg2(prev_m, next_m);
+
+                $store(plane_c.as_mut_ptr() as *mut _, select(curr_m, horizontal));
+                $store(plane_g.as_mut_ptr() as *mut _, select(cross, curr_m));
+                $store(plane_d.as_mut_ptr() as *mut _, select(diagonal, vertical));
+
+                for k in 0..$lanes {
+                    let px = &mut row[3 * (i + k)..3 * (i + k + 1)];
+                    px[c] = T::from_u16(plane_c[k]);
+                    px[1] = T::from_u16(plane_g[k]);
+                    px[d] = T::from_u16(plane_d[k]);
+                }
+
+                i = i + $lanes;
+            }
+
+            i
+        }
+    }
+}
+
+linear_blocks!(linear_blocks_sse2, "sse2", 8, load_sse2, _mm_storeu_si128,
+        _mm_add_epi16, _mm_srli_epi16, _mm_and_si128, _mm_andnot_si128, _mm_or_si128,
+        _mm_set1_epi16, _mm_set1_epi32);
+
+linear_blocks!(linear_blocks_avx2, "avx2", 16, load_avx2, _mm256_storeu_si256,
+        _mm256_add_epi16, _mm256_srli_epi16, _mm256_and_si256, _mm256_andnot_si256, _mm256_or_si256,
+        _mm256_set1_epi16, _mm256_set1_epi32);
+
+/// Interpolate the pixels of a row with linear interpolation, starting
+/// from the red/blue pixel i of colour cfa_c, in whole vectors.
+/// Returns the first pixel that was not interpolated.
+pub(crate) fn linear_blocks<T: SimdSample>(prev: &[T], curr: &[T], next: &[T],
+        cfa_c: CFA, i: usize, row: &mut [T]) -> usize {
+    let mut i = i;
+    unsafe {
+        if is_x86_feature_detected!("avx2") {
+            i = linear_blocks_avx2(prev, curr, next, cfa_c, i, row);
+        }
+        if is_x86_feature_detected!("sse2") {
+            i = linear_blocks_sse2(prev, curr, next, cfa_c, i, row);
+        }
+    }
+    i
+}
+
+#[cfg(test)]
+mod tests {
+    use ::CFA;
+    use super::linear_blocks;
+
+    /// The linear interpolation kernel, one pixel at a time.
+    fn reference(prev: &[u16], curr: &[u16], next: &[u16], cfa_c: CFA, i: usize, row: &mut [u16]) {
+        let (c, d) = if cfa_c == CFA::BGGR { (2, 0) } else { (0, 2) };
+        for x in i..row.len() / 3 {
+            let j = x + 1;
+            let px = &mut row[3 * x..3 * (x + 1)];
+            if (x - i) % 2 == 0 {
+                px[c] = curr[j];
+                px[1] = ((prev[j] as u32 + curr[j - 1] as u32 + curr[j + 1] as u32 + next[j] as u32) / 4) as u16;
+                px[d] = ((prev[j - 1] as u32 + prev[j + 1] as u32 + next[j - 1] as u32 + next[j + 1] as u32) / 4) as u16;
+            } else {
+                px[c] = ((curr[j - 1] as u32 + curr[j + 1] as u32) / 2) as u16;
+                px[1] = curr[j];
+                px[d] = ((prev[j] as u32 + next[j] as u32) / 2) as u16;
+            }
+        }
+    }
+
+    #[test]
+    fn test_linear_blocks() {
+        const W: usize = 41;
+
+        // A simple LCG, biased towards the top of the range to catch
+        // overflow.
+        let mut seed = 12345u32;
+        let mut rows = vec![[0u16; W + 2]; 3];
+        for row in rows.iter_mut() {
+            for v in row.iter_mut() {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                *v = 0xFFFF - ((seed >> 16) as u16 & 0x0FFF);
+            }
+        }
+
+        for &cfa_c in [CFA::RGGB, CFA::BGGR].iter() {
+            for i in 0..2 {
+                let mut expected = [0u16; 3 * W];
+                let mut buf = [0u16; 3 * W];
+                reference(&rows[0], &rows[1], &rows[2], cfa_c, i, &mut expected);
+
+                let end = linear_blocks(&rows[0], &rows[1], &rows[2], cfa_c, i, &mut buf);
+                assert!(end > i && end <= W);
+                assert_eq!(&buf[3 * i..3 * end], &expected[3 * i..3 * end]);
+
+                let narrow: Vec<Vec<u8>> = rows.iter()
+                        .map(|row| row.iter().map(|&v| v as u8).collect()).collect();
+                let mut expected = [0u16; 3 * W];
+                let mut buf = [0u8; 3 * W];
+                let wide: Vec<Vec<u16>> = narrow.iter()
+                        .map(|row| row.iter().map(|&v| v as u16).collect()).collect();
+                reference(&wide[0], &wide[1], &wide[2], cfa_c, i, &mut expected);
+
+                let end = linear_blocks(&narrow[0], &narrow[1], &narrow[2], cfa_c, i, &mut buf);
+                assert!(end > i && end <= W);
+                for x in 3 * i..3 * end {
+                    assert_eq!(buf[x] as u16, expected[x]);
+                }
+            }
+        }
+    }
+}
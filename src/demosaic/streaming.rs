@@ -0,0 +1,433 @@
+//! Demosaicing raw data that arrives in arbitrary chunks.
+
+use ::{BayerDepth,BayerError,BayerResult,CFA,DemosaicOptions,Demosaicer,RasterDepth,RasterMut,Truncation};
+use bayer::{RawFormat,Raw8,Raw8Wide,Raw10,Raw12,Raw14,Dng12,Raw16BE,Raw16LE,Raw32};
+use demosaic::{BayerRows,Engine,Frame,Kernels,Reader,RowFormat,ScratchSample,Stopwatch};
+use errcode::OVERFLOW;
+
+/// The rows of one image, for samples of one raw format.
+struct Stream<F: RawFormat> {
+    rdr: Reader<F>,
+    rows: BayerRows<F::Sample>,
+    kernels: Kernels<F::Sample>,
+    format: RowFormat,
+    out: Vec<u8>,
+}
+
+/// The rows of one image, for samples of any raw format.
+trait Rows: Send {
+    /// Add a complete raw row, then emit the rows that are ready.
+    fn push_row(&mut self, row: &[u8], emitted: &mut usize, stopwatch: &mut Stopwatch,
+            f: &mut FnMut(usize, &[u8]))
+            -> BayerResult<()>;
+
+    /// The number of raw rows received.
+    fn count(&self) -> usize;
+
+    /// Discard the raw rows received.
+    fn reset(&mut self);
+}
+
+/// A demosaicer for a single image whose raw data arrives in chunks
+/// of any size, e.g. from a socket or a DMA buffer.
+///
+/// The chunks are assembled into rows, and each RGB row is emitted as
+/// soon as the rows it depends on have arrived, so processing starts
+/// before the whole frame has been captured.  The emitted rows
+/// have the layout of a raster row: 8-bit samples for 8-bit images,
+/// and 16-bit samples in host byte order for 16-bit images.
+///
+/// # Example
+///
+/// ```
+/// let width: usize = 320;
+/// let height: usize = 200;
+/// let img = vec![0; width * height];
+/// let mut buf = vec![0; 3 * width * height];
+///
+/// let demosaicer = bayer::Demosaicer::new().build();
+/// let mut stream = bayer::StreamingDemosaicer::new(&demosaicer, width, height).unwrap();
+///
+/// let mut dst = bayer::RasterMut::new(
+///         width, height, bayer::RasterDepth::Depth8,
+///         &mut buf);
+/// for chunk in img.chunks(1000) {
+///     stream.push_into(chunk, &mut dst).unwrap();
+/// }
+/// assert!(stream.is_complete());
+/// ```
+pub struct StreamingDemosaicer {
+    w: usize,
+    h: usize,
+    depth: BayerDepth,
+    cfa: CFA,
+    engine: Engine,
+    /// The depth of the rows emitted by push.
+    sample_depth: RasterDepth,
+    /// The depth of the rows emitted by inner.
+    raster_depth: RasterDepth,
+    row_len: usize,
+    /// The number of raw bytes in the image.
+    len: usize,
+    pending: Vec<u8>,
+    emitted: usize,
+    inner: Box<Rows>,
+    options: DemosaicOptions,
+    stopwatch: Stopwatch,
+}
+
+impl<F: RawFormat> Stream<F> where F::Sample: ScratchSample {
+    fn new(frame: &Frame, format: RowFormat) -> BayerResult<Self> {
+        let kernels = frame.kernels();
+        Ok(Stream {
+            rdr: frame.reader()?,
+            rows: BayerRows::new(frame, Truncation::Error, 2 * kernels.padding + 1)?,
+            kernels,
+            format,
+            out: vec![0u8; frame.w.checked_mul(format.bytes_per_pixel()).ok_or(OVERFLOW)?],
+        })
+    }
+}
+
+impl<F: RawFormat + Send> Rows for Stream<F> where F::Sample: ScratchSample {
+    fn push_row(&mut self, row: &[u8], emitted: &mut usize, stopwatch: &mut Stopwatch,
+            f: &mut FnMut(usize, &[u8]))
+            -> BayerResult<()> {
+        let (h, padding) = (self.rows.height, self.rows.padding);
+        let y = self.rows.count;
+        {
+            let (rows, rdr) = (&mut self.rows, &self.rdr);
+            stopwatch.read(|| rows.read_until(&mut &row[..], rdr, y))?;
+        }
+
+        while *emitted < h && (*emitted + padding < self.rows.count || self.rows.count == h) {
+            let y = *emitted;
+            let (kernels, rows, format, out) = (&self.kernels, &self.rows, self.format, &mut self.out);
+            stopwatch.interpolate(|| kernels.run_format(rows, y, 0, false, format, out));
+
+            f(y, &self.out);
+            *emitted = y + 1;
+        }
+
+        Ok(())
+    }
+
+    fn count(&self) -> usize {
+        self.rows.count
+    }
+
+    fn reset(&mut self) {
+        self.rows.count = 0;
+    }
+}
+
+/// The rows of the frame, emitted as RGB rows of samples of the
+/// given depth.
+fn rows(frame: &Frame, depth: RasterDepth) -> BayerResult<Box<Rows>> {
+    let frame = frame.for_raster(depth)?;
+    let format = RowFormat::rgb(&frame, depth);
+    match frame.depth {
+        BayerDepth::Depth8 if frame.widen() => stream::<Raw8Wide>(&frame, format),
+        BayerDepth::Depth8 => stream::<Raw8>(&frame, format),
+        BayerDepth::Depth16BE => stream::<Raw16BE>(&frame, format),
+        BayerDepth::Depth16LE => stream::<Raw16LE>(&frame, format),
+        BayerDepth::Depth10Packed => stream::<Raw10>(&frame, format),
+        BayerDepth::Depth12Packed => stream::<Raw12>(&frame, format),
+        BayerDepth::Depth12PackedDng => stream::<Dng12>(&frame, format),
+        BayerDepth::Depth14Packed => stream::<Raw14>(&frame, format),
+    }
+}
+
+/// The rows of the frame in raw format F, held in 32-bit samples if
+/// they are offset by a pedestal.
+fn stream<F>(frame: &Frame, format: RowFormat) -> BayerResult<Box<Rows>>
+        where F: RawFormat + Send + 'static, F::Sample: ScratchSample {
+    if frame.offset() {
+        Ok(Box::new(Stream::<Raw32<F>>::new(frame, format)?))
+    } else {
+        Ok(Box::new(Stream::<F>::new(frame, format)?))
+    }
+}
+
+impl StreamingDemosaicer {
+    /// Create a streaming demosaicer for a w x h image, using the
+    /// configuration of the demosaicer.
+    pub fn new(demosaicer: &Demosaicer, w: usize, h: usize)
+            -> BayerResult<Self> {
+        let options = demosaicer.options();
+        let (depth, cfa) = (demosaicer.depth(), demosaicer.cfa());
+        let engine = demosaicer.algorithm().engine();
+        let frame = Frame::new(&engine, depth, cfa, options, w, h)?;
+        let sample_depth = frame.sample_depth();
+        let inner = rows(&frame, sample_depth)?;
+        let row_len = depth.byte_len(w).ok_or(OVERFLOW)?;
+
+        Ok(StreamingDemosaicer {
+            w, h, depth, cfa, engine, sample_depth,
+            raster_depth: sample_depth,
+            row_len,
+            len: row_len.checked_mul(h).ok_or(OVERFLOW)?,
+            pending: Vec::new(),
+            emitted: 0,
+            inner,
+            options: options.clone(),
+            stopwatch: Stopwatch::new(options),
+        })
+    }
+
+    /// Add a chunk of raw data, calling f(y, row) for each RGB row
+    /// completed.
+    ///
+    /// Data beyond the end of the image is rejected with
+    /// BayerError::TrailingData.
+    pub fn push<G>(&mut self, data: &[u8], f: G) -> BayerResult<()>
+            where G: FnMut(usize, &[u8]) {
+        let depth = self.sample_depth;
+        self.emit_depth(depth)?;
+        self.push_rows(data, f)
+    }
+
+    /// Emit rows of samples of the given raster depth.  The depth
+    /// cannot change once data for the image has been received.
+    fn emit_depth(&mut self, depth: RasterDepth) -> BayerResult<()> {
+        if depth == self.raster_depth {
+            return Ok(());
+        }
+        if self.bytes_received() > 0 {
+            return Err(BayerError::InvalidArgument {
+                reason: "the raster depth cannot change within an image",
+            });
+        }
+
+        let frame = Frame::new(&self.engine, self.depth, self.cfa, &self.options, self.w, self.h)?;
+        self.inner = rows(&frame, depth)?;
+        self.raster_depth = depth;
+        Ok(())
+    }
+
+    fn push_rows<G>(&mut self, data: &[u8], mut f: G) -> BayerResult<()>
+            where G: FnMut(usize, &[u8]) {
+        let remaining = self.len - self.bytes_received();
+        if data.len() > remaining {
+            return Err(BayerError::TrailingData { extra: data.len() - remaining });
+        }
+
+        let mut data = data;
+        while !data.is_empty() {
+            let row;
+            if self.pending.is_empty() && data.len() >= self.row_len {
+                // Whole rows are demosaiced straight from the chunk.
+                let (head, tail) = data.split_at(self.row_len);
+                row = head;
+                data = tail;
+            } else {
+                let n = ::std::cmp::min(self.row_len - self.pending.len(), data.len());
+                self.pending.extend_from_slice(&data[0..n]);
+                data = &data[n..];
+                row = &self.pending[..];
+            }
+
+            if row.len() == self.row_len {
+                self.inner.push_row(row, &mut self.emitted, &mut self.stopwatch, &mut f)?;
+                self.pending.clear();
+
+                if self.is_complete() {
+                    self.stopwatch.finish(&self.options, self.len);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add a chunk of raw data, writing the completed RGB rows into
+    /// the raster.
+    ///
+    /// The samples are converted to the depth of the raster as by
+    /// `demosaic_with_options`.  Every chunk of an image must be
+    /// written into rasters of the same depth.
+    pub fn push_into(&mut self, data: &[u8], dst: &mut RasterMut)
+            -> BayerResult<()> {
+        if (dst.w, dst.h) != (self.w, self.h) {
+            return Err(BayerError::DimensionMismatch {
+                width: self.w, height: self.h, raster_width: dst.w, raster_height: dst.h,
+            });
+        }
+        self.emit_depth(dst.depth)?;
+
+        self.push_rows(data, |y, row| dst.put_rgb_row(y, row))
+    }
+
+    /// The number of raw bytes in each row.  Pushing whole rows, e.g.
+    /// scanlines as they are captured, avoids copying them.
+    pub fn row_len(&self) -> usize {
+        self.row_len
+    }
+
+    /// The number of RGB rows emitted so far.
+    pub fn rows_completed(&self) -> usize {
+        self.emitted
+    }
+
+    /// Whether every row of the image has been emitted.
+    pub fn is_complete(&self) -> bool {
+        self.emitted == self.h
+    }
+
+    /// Discard any data received, ready for the next image.
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.emitted = 0;
+        self.stopwatch = Stopwatch::new(&self.options);
+        self.inner.reset();
+    }
+
+    fn bytes_received(&self) -> usize {
+        self.row_len * self.inner.count() + self.pending.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{BayerDepth,BayerError,CFA,Demosaic,DemosaicOptions,Demosaicer,RasterDepth,RasterMut,Timing,run_demosaic};
+    use super::StreamingDemosaicer;
+
+    #[test]
+    fn test_chunks() {
+        // R: set.seed(0); matrix(floor(runif(n=30, min=0, max=256)), nrow=5, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,232, 51,
+            229,241,169,161, 15, 52,
+             45,175, 98,197,253,116,
+            183,120,208, 81, 33,160,
+            112,124, 74,210,245, 88 ];
+
+        const IMG_W: usize = 6;
+        const IMG_H: usize = 5;
+
+        for &alg in Demosaic::all().iter() {
+            let mut expected = [0u8; 3 * IMG_W * IMG_H];
+            let res = run_demosaic(&mut &src[..], BayerDepth::Depth8, CFA::GBRG, alg,
+                    &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut expected));
+            assert!(res.is_ok());
+
+            let demosaicer = Demosaicer::new().cfa(CFA::GBRG).algorithm(alg).build();
+            let mut stream = StreamingDemosaicer::new(&demosaicer, IMG_W, IMG_H).unwrap();
+            let mut buf = [0u8; 3 * IMG_W * IMG_H];
+
+            for _ in 0..2 {
+                stream.reset();
+                buf = [0u8; 3 * IMG_W * IMG_H];
+                let mut dst = RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf);
+                for chunk in src.chunks(4) {
+                    assert!(!stream.is_complete());
+                    assert!(stream.push_into(chunk, &mut dst).is_ok());
+                }
+                assert!(stream.is_complete());
+            }
+            assert_eq!(&buf[..], &expected[..]);
+
+            match stream.push(&[0], |_, _| ()) {
+                Err(BayerError::TrailingData { extra: 1 }) => (),
+                _ => panic!(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_rows_emitted() {
+        let src = [0u8; 2 * 4 * 4];
+        let timing = Timing::new();
+        let mut options = DemosaicOptions::default();
+        options.timing = Some(timing.clone());
+
+        let demosaicer = Demosaicer::new()
+                .depth(BayerDepth::Depth16LE)
+                .algorithm(Demosaic::Linear)
+                .options(options)
+                .build();
+        let mut stream = StreamingDemosaicer::new(&demosaicer, 4, 4).unwrap();
+        let mut rows = Vec::new();
+
+        // Linear interpolation needs the row below the output row.
+        assert!(stream.push(&src[0..8], |y, row| rows.push((y, row.len()))).is_ok());
+        assert_eq!(stream.rows_completed(), 0);
+        assert!(stream.push(&src[8..28], |y, row| rows.push((y, row.len()))).is_ok());
+        assert_eq!(stream.rows_completed(), 2);
+        assert!(timing.last().is_none());
+        assert!(stream.push(&src[28..], |y, row| rows.push((y, row.len()))).is_ok());
+        assert_eq!(rows, [(0, 24), (1, 24), (2, 24), (3, 24)]);
+        assert_eq!(timing.last().unwrap().bytes, 32);
+
+        let demosaicer = Demosaicer::new().algorithm(Demosaic::Cubic).build();
+        assert!(StreamingDemosaicer::new(&demosaicer, 3, 4).is_err());
+    }
+
+    #[test]
+    fn test_scanlines() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+
+        let mut expected = [0u8; 3 * 4 * 4];
+        let res = run_demosaic(&mut &src[..], BayerDepth::Depth8, CFA::RGGB, Demosaic::Linear,
+                &mut RasterMut::new(4, 4, RasterDepth::Depth8, &mut expected));
+        assert!(res.is_ok());
+
+        // Whole scanlines, a scanline and a half, then the remainder.
+        let demosaicer = Demosaicer::new().build();
+        let mut stream = StreamingDemosaicer::new(&demosaicer, 4, 4).unwrap();
+        assert_eq!(stream.row_len(), 4);
+
+        let mut buf = [0u8; 3 * 4 * 4];
+        {
+            let mut dst = RasterMut::new(4, 4, RasterDepth::Depth8, &mut buf);
+            assert!(stream.push_into(&src[0..4], &mut dst).is_ok());
+            assert!(stream.push_into(&src[4..10], &mut dst).is_ok());
+            assert_eq!(stream.rows_completed(), 1);
+            assert!(stream.push_into(&src[10..], &mut dst).is_ok());
+        }
+        assert!(stream.is_complete());
+        assert_eq!(&buf[..], &expected[..]);
+    }
+
+    #[test]
+    fn test_depth() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+
+        // The samples are converted as by run_demosaic.
+        let demosaicer = Demosaicer::new().algorithm(Demosaic::Cubic).build();
+        let mut stream = StreamingDemosaicer::new(&demosaicer, 4, 4).unwrap();
+        for &(depth, bytes) in [(RasterDepth::Depth16, 6), (RasterDepth::Depth32F, 12)].iter() {
+            let mut expected = vec![0u8; bytes * 4 * 4];
+            let res = run_demosaic(&mut &src[..], BayerDepth::Depth8, CFA::RGGB, Demosaic::Cubic,
+                    &mut RasterMut::new(4, 4, depth, &mut expected));
+            assert!(res.is_ok());
+
+            let mut buf = vec![0u8; bytes * 4 * 4];
+            stream.reset();
+            for chunk in src.chunks(6) {
+                assert!(stream.push_into(chunk, &mut RasterMut::new(4, 4, depth, &mut buf)).is_ok());
+            }
+            assert!(stream.is_complete());
+            assert_eq!(buf, expected);
+        }
+
+        // The depth cannot change within an image.
+        let mut buf = [0u8; 6 * 4 * 4];
+        stream.reset();
+        assert!(stream.push(&src[0..4], |_, _| ()).is_ok());
+        match stream.push_into(&src[4..], &mut RasterMut::new(4, 4, RasterDepth::Depth16, &mut buf)) {
+            Err(BayerError::InvalidArgument { .. }) => (),
+            _ => panic!(),
+        }
+    }
+}
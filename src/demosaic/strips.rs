@@ -0,0 +1,187 @@
+//! Demosaicing huge images in horizontal strips.
+//!
+//! Only the Bayer rows of the current strip and its padding, and the
+//! RGB rows of the current strip, are held in memory.  The RGB rows
+//! are passed to a sink as each strip is completed.
+
+use std::cmp;
+use std::io::Read;
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+#[cfg(feature = "rayon")]
+use ::ThreadPool;
+
+use ::{BayerDepth,BayerResult,DemosaicOptions,RasterDepth};
+use bayer::{RawFormat,Raw8,Raw8Wide,Raw10,Raw12,Raw14,Dng12,Raw16BE,Raw16LE,Raw32};
+use demosaic::{BayerRows,Deadline,Frame,Kernels,Reader,RowFormat,ScratchSample,Stopwatch,
+        check_cancel,report_progress};
+use errcode::OVERFLOW;
+use raster::RasterSample;
+
+/// Run the demosaicing engine on the Bayer image of the frame,
+/// strip_rows rows at a time, calling sink(y, row) for each RGB row
+/// of samples of the given depth.  The frame must be configured for
+/// the depth by `Frame::for_raster`.
+pub(crate) fn run_engine_strips<R, G>(r: &mut R, frame: &Frame, depth: RasterDepth,
+        strip_rows: usize, sink: &mut G)
+        -> BayerResult<()>
+        where R: Read + ?Sized, G: FnMut(usize, &[u8]) -> BayerResult<()> {
+    let strip_rows = cmp::max(strip_rows, 1);
+    let format = RowFormat::rgb(frame, depth);
+    match frame.depth {
+        BayerDepth::Depth8 if frame.widen() =>
+            strips_format::<R, Raw8Wide, G>(r, frame, format, strip_rows, sink),
+        BayerDepth::Depth8 => strips_format::<R, Raw8, G>(r, frame, format, strip_rows, sink),
+        BayerDepth::Depth16BE => strips_format::<R, Raw16BE, G>(r, frame, format, strip_rows, sink),
+        BayerDepth::Depth16LE => strips_format::<R, Raw16LE, G>(r, frame, format, strip_rows, sink),
+        BayerDepth::Depth10Packed => strips_format::<R, Raw10, G>(r, frame, format, strip_rows, sink),
+        BayerDepth::Depth12Packed => strips_format::<R, Raw12, G>(r, frame, format, strip_rows, sink),
+        BayerDepth::Depth12PackedDng => strips_format::<R, Dng12, G>(r, frame, format, strip_rows, sink),
+        BayerDepth::Depth14Packed => strips_format::<R, Raw14, G>(r, frame, format, strip_rows, sink),
+    }
+}
+
+fn strips_format<R, F, G>(r: &mut R, frame: &Frame, format: RowFormat, strip_rows: usize,
+        sink: &mut G)
+        -> BayerResult<()>
+        where R: Read + ?Sized, F: RawFormat, F::Sample: ScratchSample,
+              G: FnMut(usize, &[u8]) -> BayerResult<()> {
+    if frame.offset() {
+        debayer_strips(r, &frame.reader::<Raw32<F>>()?, frame, format, strip_rows, sink)
+    } else {
+        debayer_strips(r, &frame.reader::<F>()?, frame, format, strip_rows, sink)
+    }
+}
+
+fn debayer_strips<R, F, T, G>(r: &mut R, rdr: &Reader<F>, frame: &Frame,
+        format: RowFormat, strip_rows: usize, sink: &mut G)
+        -> BayerResult<()>
+        where R: Read + ?Sized, F: RawFormat<Sample = T>, T: ScratchSample,
+              G: FnMut(usize, &[u8]) -> BayerResult<()> {
+    let (w, h, options) = (frame.w, frame.h, frame.options);
+    let kernels = frame.kernels::<T>();
+    let p = kernels.padding;
+    let mut rows = BayerRows::new(frame, options.truncation, strip_rows + 2 * p)?;
+    let row_len = w.checked_mul(format.bytes_per_pixel()).ok_or(OVERFLOW)?;
+    let out_len = row_len.checked_mul(strip_rows).ok_or(OVERFLOW)?;
+    let mut out = vec![0u8; out_len];
+    let mut stopwatch = Stopwatch::new(options);
+    let mut deadline = Deadline::new(options);
+
+    let mut y0 = 0;
+    while y0 < h {
+        let y1 = cmp::min(y0 + strip_rows, h);
+        if y0 > 0 {
+            report_progress(options, y0);
+        }
+        check_cancel(options)?;
+        let degraded = deadline.check(y0)?;
+
+        stopwatch.read(|| rows.read_until(r, rdr, y1 - 1 + p))?;
+
+        let strip = &mut out[0..(row_len * (y1 - y0))];
+        stopwatch.interpolate(|| process_strip(&rows, y0, kernels, degraded, format, options, strip));
+
+        for (dy, row) in strip.chunks(row_len).enumerate() {
+            sink(y0 + dy, row)?;
+        }
+
+        y0 = y1;
+    }
+
+    report_progress(options, h);
+    stopwatch.finish(options, w * h * format.depth.bytes_per_sample());
+    rows.finish()?;
+    deadline.finish()
+}
+
+/// Interpolate the rows of the strip starting at row y0.
+#[cfg_attr(not(feature = "rayon"), allow(unused_variables))]
+fn process_strip<T: RasterSample>(rows: &BayerRows<T>, y0: usize, kernels: Kernels<T>,
+        degraded: bool, format: RowFormat, options: &DemosaicOptions, strip: &mut [u8]) {
+    let row_len = format.bytes_per_pixel() * (rows.stride - 2 * rows.padding);
+    let f = |(dy, row): (usize, &mut [u8])|
+        kernels.run_format(rows, y0 + dy, 0, degraded, format, row);
+
+    #[cfg(feature = "rayon")]
+    {
+        if options.parallel {
+            ThreadPool::install(&options.thread_pool,
+                    || strip.par_chunks_mut(row_len).enumerate().for_each(f));
+            return;
+        }
+    }
+
+    strip.chunks_mut(row_len).enumerate().for_each(f);
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{BayerDepth,BayerError,CFA,Demosaic,Demosaicer,RasterDepth,RasterMut,run_demosaic};
+
+    #[test]
+    fn test_strips() {
+        // R: set.seed(0); matrix(floor(runif(n=30, min=0, max=256)), nrow=5, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,232, 51,
+            229,241,169,161, 15, 52,
+             45,175, 98,197,253,116,
+            183,120,208, 81, 33,160,
+            112,124, 74,210,245, 88 ];
+
+        const IMG_W: usize = 6;
+        const IMG_H: usize = 5;
+
+        for &alg in Demosaic::all().iter() {
+            let mut expected = [0u8; 3 * IMG_W * IMG_H];
+            let res = run_demosaic(&mut &src[..], BayerDepth::Depth8, CFA::BGGR, alg,
+                    &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut expected));
+            assert!(res.is_ok());
+
+            let demosaicer = Demosaicer::new().cfa(CFA::BGGR).algorithm(alg).build();
+            for strip_rows in 1..(IMG_H + 2) {
+                let mut buf = [0u8; 3 * IMG_W * IMG_H];
+                let mut next = 0;
+                let res = demosaicer.run_strips(&mut &src[..], IMG_W, IMG_H, strip_rows, |y, row| {
+                    assert_eq!(y, next);
+                    next = y + 1;
+                    buf[(3 * IMG_W * y)..(3 * IMG_W * (y + 1))].copy_from_slice(row);
+                    Ok(())
+                });
+                assert!(res.is_ok());
+                assert_eq!(&buf[..], &expected[..]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sink_error() {
+        let src = [0u8; 2 * 4 * 4];
+        let demosaicer = Demosaicer::new().depth(BayerDepth::Depth16BE).build();
+        let mut count = 0;
+
+        let res = demosaicer.run_strips(&mut &src[..], 4, 4, 2, |_, row| {
+            assert_eq!(row.len(), 6 * 4);
+            count = count + 1;
+            if count == 3 { Err(BayerError::NoGood) } else { Ok(()) }
+        });
+        match res {
+            Err(BayerError::NoGood) => (),
+            _ => panic!(),
+        }
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn test_overflow() {
+        let src = [0u8; 4 * 4];
+        let demosaicer = Demosaicer::new().build();
+
+        let res = demosaicer.run_strips(&mut &src[..], ::std::usize::MAX, 4, 2, |_, _| Ok(()));
+        match res {
+            Err(BayerError::InvalidArgument { .. }) => (),
+            _ => panic!(),
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! Demosaicing in tiles.
+//!
+//! The image is read one band of tiles at a time, and only the Bayer
+//! rows of the current band and its padding are held in memory.  Each
+//! tile is interpolated row by row, so that its Bayer and RGB rows
+//! stay in the cache, and the tiles of a band are processed in
+//! parallel if requested.
+
+use std::cmp;
+use std::io::Read;
+use std::mem;
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+#[cfg(feature = "rayon")]
+use ::ThreadPool;
+
+use ::{BayerResult,DemosaicOptions,RasterMut};
+use bayer::RawFormat;
+use demosaic::{BayerRows,Deadline,Frame,Kernels,Reader,RowFormat,Stopwatch,check_cancel,report_progress};
+use raster::RasterSample;
+
+/// Read the image one band at a time, then process the tiles of each
+/// band.
+pub(super) fn debayer_tiles<R, F, T>(r: &mut R, rdr: &Reader<F>,
+        rows: &mut BayerRows<T>, frame: &Frame, kernels: Kernels<T>,
+        tile_size: (usize, usize), dst: &mut RasterMut)
+        -> BayerResult<()>
+        where R: Read + ?Sized, F: RawFormat<Sample = T>, T: RasterSample {
+    let (w, h, options) = (frame.w, frame.h, frame.options);
+    let tile_w = cmp::max(tile_size.0, 1);
+    let tile_h = cmp::max(tile_size.1, 1);
+    let num_tiles = (w + tile_w - 1) / tile_w;
+    let mut stopwatch = Stopwatch::new(options);
+    let mut deadline = Deadline::new(options);
+
+    let format = RowFormat::new(frame, dst);
+    let bytes_per_pixel = format.bytes_per_pixel();
+    let x0 = bytes_per_pixel * dst.x;
+    let x1 = bytes_per_pixel * (dst.x + w);
+    let start = dst.stride * dst.y;
+    let end = dst.stride * (dst.y + h);
+
+    let stride = dst.stride;
+    for (i, band) in dst.buf[start..end].chunks_mut(stride * tile_h).enumerate() {
+        let y0 = tile_h * i;
+        let band_h = band.len() / stride;
+        if y0 > 0 {
+            report_progress(options, y0);
+        }
+        check_cancel(options)?;
+        let degraded = deadline.check(y0)?;
+
+        stopwatch.read(|| rows.read_until(r, rdr, y0 + band_h - 1 + kernels.padding))?;
+
+        // The output rows of each tile.
+        let mut tiles: Vec<Vec<&mut [u8]>> = (0..num_tiles)
+            .map(|_| Vec::with_capacity(band_h))
+            .collect();
+        for row in band.chunks_mut(stride) {
+            for (tile, out) in tiles.iter_mut().zip(row[x0..x1].chunks_mut(bytes_per_pixel * tile_w)) {
+                tile.push(out);
+            }
+        }
+
+        let rows = &*rows;
+        let f = |(t, tile): (usize, &mut Vec<&mut [u8]>)| {
+            let tx = tile_w * t;
+            for (dy, out) in tile.iter_mut().enumerate() {
+                let y = y0 + dy;
+                kernels.run_format(rows, y, tx, degraded, format, out);
+            }
+        };
+        stopwatch.interpolate(|| process_tiles(options, &mut tiles, f));
+    }
+
+    report_progress(options, h);
+    stopwatch.finish(options, w * h * mem::size_of::<T>());
+    rows.finish()?;
+    deadline.finish()
+}
+
+/// Process the tiles of a band, in parallel if requested.
+#[cfg_attr(not(feature = "rayon"), allow(unused_variables))]
+fn process_tiles<G>(options: &DemosaicOptions, tiles: &mut [Vec<&mut [u8]>], f: G)
+        where G: Fn((usize, &mut Vec<&mut [u8]>)) + Sync + Send {
+    #[cfg(feature = "rayon")]
+    {
+        if options.parallel {
+            ThreadPool::install(&options.thread_pool,
+                    || tiles.par_iter_mut().enumerate().for_each(f));
+            return;
+        }
+    }
+
+    tiles.iter_mut().enumerate().for_each(f);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use ::{BayerDepth,CFA,Demosaic,DemosaicOptions,RasterDepth,RasterMut};
+    use ::demosaic_with_options;
+
+    #[test]
+    fn test_tiles() {
+        // R: set.seed(0); matrix(floor(runif(n=64, min=0, max=256)), nrow=8, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,232, 51,229,241,
+            169,161, 15, 52, 45,175, 98,197,
+            127,183,253, 97,199,239, 54,166,
+             32, 68, 98,  3, 97,222, 87,123,
+            153,126, 47,211,171,203, 27,185,
+            105,210,165,200,141,135,202,  5,
+            122,187,177,122,220,112, 62, 18,
+             25, 80,132,169,104,233, 75,117 ];
+
+        for &alg in Demosaic::all().iter() {
+            let mut options = DemosaicOptions::default();
+            options.parallel = false;
+
+            let mut expected = [0u8; 3 * 8 * 8];
+            let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                    BayerDepth::Depth8, CFA::GRBG, alg, &options,
+                    &mut RasterMut::new(8, 8, RasterDepth::Depth8, &mut expected));
+            if res.is_err() {
+                continue;
+            }
+
+            for &tile_size in [(1, 1), (3, 2), (4, 3), (8, 8), (16, 16)].iter() {
+                for &parallel in [false, true].iter() {
+                    options.parallel = parallel;
+                    options.tile_size = Some(tile_size);
+
+                    let mut buf = [0u8; 3 * 8 * 8];
+                    let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                            BayerDepth::Depth8, CFA::GRBG, alg, &options,
+                            &mut RasterMut::new(8, 8, RasterDepth::Depth8, &mut buf));
+                    assert!(res.is_ok());
+                    assert_eq!(&buf[..], &expected[..], "{:?} {:?}", alg, tile_size);
+                }
+            }
+        }
+    }
+}
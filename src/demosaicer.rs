@@ -0,0 +1,639 @@
+//! Reusable demosaicer objects.
+
+use std::cmp;
+use std::io::{Read,Seek};
+use std::sync::{Mutex,PoisonError,TryLockError};
+use std::thread;
+
+use ::{BayerDepth,BayerError,BayerResult,Border,CFA,Demosaic,DemosaicOptions,PlanarRasterMut,RasterMut,Region};
+use demosaic::{Engine,Frame,Scratch,run_engine,run_engine_with};
+use demosaic::strips::run_engine_strips;
+use planar::run_engine_planar;
+use pyramid::run_engine_pyramid;
+use resample::run_engine_resized;
+use raster::RasterSample;
+use roi::{run_engine_rect,run_engine_roi};
+use slice::run_engine_slice;
+
+/// Builder for a Demosaicer.
+///
+/// Defaults to 8-bit RGGB images and linear interpolation.
+#[derive(Clone,Debug)]
+pub struct DemosaicerBuilder {
+    depth: BayerDepth,
+    cfa: CFA,
+    alg: Demosaic,
+    options: DemosaicOptions,
+}
+
+/// A demosaicer configuration that can be run on many images.
+///
+/// The algorithm is resolved when the demosaicer is built.  A
+/// demosaicer is Send and Sync, so one configuration can be shared
+/// by several threads, each processing its own frames.
+#[derive(Clone,Debug)]
+pub struct Demosaicer {
+    depth: BayerDepth,
+    cfa: CFA,
+    alg: Demosaic,
+    engine: Engine,
+    options: DemosaicOptions,
+    scratch: Scratch,
+    /// The scratch buffers of the left and right workers of
+    /// `run_pair`.
+    pair_scratch: [WorkerScratch; 2],
+}
+
+/// Scratch buffers shared by the calls of a worker.
+#[derive(Debug,Default)]
+struct WorkerScratch(Mutex<Scratch>);
+
+impl Clone for WorkerScratch {
+    /// The buffers are not copied; the clone starts empty.
+    fn clone(&self) -> Self {
+        WorkerScratch::default()
+    }
+}
+
+impl WorkerScratch {
+    /// Call f with the buffers, or with empty buffers if another
+    /// thread is using them.
+    fn with<T, F>(&self, f: F) -> T
+            where F: FnOnce(&mut Scratch) -> T {
+        match self.0.try_lock() {
+            Ok(mut scratch) => f(&mut scratch),
+            Err(TryLockError::Poisoned(e)) => f(&mut e.into_inner()),
+            Err(TryLockError::WouldBlock) => f(&mut Scratch::default()),
+        }
+    }
+}
+
+impl DemosaicerBuilder {
+    /// Create a builder with the default configuration.
+    pub fn new() -> Self {
+        DemosaicerBuilder {
+            depth: BayerDepth::Depth8,
+            cfa: CFA::RGGB,
+            alg: Demosaic::Linear,
+            options: DemosaicOptions::default(),
+        }
+    }
+
+    /// Set the depth and endianness of the raw images.
+    pub fn depth(mut self, depth: BayerDepth) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    /// Set the CFA pattern of the raw images.
+    pub fn cfa(mut self, cfa: CFA) -> Self {
+        self.cfa = cfa;
+        self
+    }
+
+    /// Set the demosaicing algorithm.
+    pub fn algorithm(mut self, alg: Demosaic) -> Self {
+        self.alg = alg;
+        self
+    }
+
+    /// Set the border handling, overriding the algorithm's default.
+    pub fn border(mut self, border: Border) -> Self {
+        self.options.border = Some(border);
+        self
+    }
+
+    /// Set the advanced demosaicing options.
+    pub fn options(mut self, options: DemosaicOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Create the demosaicer.
+    pub fn build(self) -> Demosaicer {
+        Demosaicer {
+            depth: self.depth,
+            cfa: self.cfa,
+            alg: self.alg,
+            engine: self.alg.engine(),
+            options: self.options,
+            scratch: Scratch::default(),
+            pair_scratch: Default::default(),
+        }
+    }
+}
+
+impl Default for DemosaicerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Demosaicer {
+    /// Start building a new demosaicer.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    ///
+    /// let width: usize = 320;
+    /// let height: usize = 200;
+    /// let img = vec![0; 2 * width * height];
+    /// let mut buf = vec![0; 6 * width * height];
+    ///
+    /// let demosaicer = bayer::Demosaicer::new()
+    ///         .cfa(bayer::CFA::RGGB)
+    ///         .depth(bayer::BayerDepth::Depth16LE)
+    ///         .algorithm(bayer::Demosaic::Linear)
+    ///         .border(bayer::Border::Mirror)
+    ///         .build();
+    ///
+    /// let mut dst = bayer::RasterMut::new(
+    ///         width, height, bayer::RasterDepth::Depth16,
+    ///         &mut buf);
+    /// demosaicer.run(&mut Cursor::new(&img[..]), &mut dst).unwrap();
+    /// ```
+    pub fn new() -> DemosaicerBuilder {
+        DemosaicerBuilder::new()
+    }
+
+    /// The depth and endianness of the raw images.
+    pub fn depth(&self) -> BayerDepth {
+        self.depth
+    }
+
+    /// The CFA pattern of the raw images.
+    pub fn cfa(&self) -> CFA {
+        self.cfa
+    }
+
+    /// The demosaicing algorithm.
+    pub fn algorithm(&self) -> Demosaic {
+        self.alg
+    }
+
+    /// The advanced demosaicing options.
+    pub fn options(&self) -> &DemosaicOptions {
+        &self.options
+    }
+
+    /// Run the demosaicing algorithm on the Bayer image.
+    pub fn run(&self, r: &mut Read, dst: &mut RasterMut)
+            -> BayerResult<()> {
+        run_engine(&self.engine, r, self.depth, self.cfa, &self.options, dst)
+    }
+
+    /// Run the demosaicing algorithm on the Bayer image, reading from
+    /// a concrete source type.  See `demosaic_from`.
+    pub fn run_from<R: Read>(&self, r: &mut R, dst: &mut RasterMut)
+            -> BayerResult<()> {
+        run_engine(&self.engine, r, self.depth, self.cfa, &self.options, dst)
+    }
+
+    /// Run the demosaicing algorithm on the Bayer image, keeping the
+    /// buffers for the padded Bayer rows for the next image.
+    ///
+    /// Once the buffers have grown to fit the images, processing a
+    /// sequence of frames allocates no memory per frame.  A clone of
+    /// the demosaicer starts with empty buffers.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let width: usize = 32;
+    /// let height: usize = 20;
+    /// let video = vec![0; 4 * width * height];
+    /// let mut buf = vec![0; 3 * width * height];
+    ///
+    /// let mut demosaicer = bayer::Demosaicer::new().build();
+    /// for frame in video.chunks(width * height) {
+    ///     let mut dst = bayer::RasterMut::new(
+    ///             width, height, bayer::RasterDepth::Depth8,
+    ///             &mut buf);
+    ///     demosaicer.process(&mut &frame[..], &mut dst).unwrap();
+    /// }
+    /// ```
+    pub fn process<R: Read + ?Sized>(&mut self, r: &mut R, dst: &mut RasterMut)
+            -> BayerResult<()> {
+        run_engine_with(&self.engine, &mut self.scratch, r,
+                self.depth, self.cfa, &self.options, dst)
+    }
+
+    /// Run the demosaicing algorithm on a Bayer image held in a
+    /// slice.  See `demosaic_slice`.
+    pub fn run_slice(&self, src: &[u8], dst: &mut RasterMut)
+            -> BayerResult<()> {
+        run_engine_slice(&self.engine, src, self.depth, self.cfa, &self.options, dst)
+    }
+
+    /// Run the demosaicing algorithm on a 16-bit Bayer image held in a
+    /// slice of samples in host byte order, whatever the configured
+    /// depth.  See `demosaic_slice_u16`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let img = [0u16; 4 * 4];
+    /// let mut buf = [0u8; 6 * 4 * 4];
+    ///
+    /// let demosaicer = bayer::Demosaicer::new()
+    ///         .algorithm(bayer::Demosaic::Cubic)
+    ///         .build();
+    /// demosaicer.run_slice_u16(&img,
+    ///         &mut bayer::RasterMut::new(4, 4, bayer::RasterDepth::Depth16, &mut buf))
+    ///         .unwrap();
+    /// ```
+    pub fn run_slice_u16(&self, src: &[u16], dst: &mut RasterMut)
+            -> BayerResult<()> {
+        run_engine_slice(&self.engine, u16::as_bytes(src), BayerDepth::native16(),
+                self.cfa, &self.options, dst)
+    }
+
+    /// Run the demosaicing algorithm on several in-memory Bayer
+    /// images concurrently, writing frames[i] into dsts[i].
+    ///
+    /// Each frame is processed by a single thread, which scales
+    /// better than parallelising within frames when there are many
+    /// small or medium frames.  The worker threads reuse their
+    /// scratch buffers from frame to frame.  Returns the result of
+    /// each frame.  If the number of frames and rasters differ, no
+    /// frames are processed and each result is
+    /// BayerError::InvalidArgument.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let width: usize = 32;
+    /// let height: usize = 20;
+    /// let video = vec![0; 4 * width * height];
+    /// let mut buf = vec![0; 4 * 3 * width * height];
+    ///
+    /// let frames: Vec<&[u8]> = video.chunks(width * height).collect();
+    /// let mut dsts: Vec<_> = buf.chunks_mut(3 * width * height)
+    ///     .map(|b| bayer::RasterMut::new(width, height, bayer::RasterDepth::Depth8, b))
+    ///     .collect();
+    ///
+    /// let demosaicer = bayer::Demosaicer::new().build();
+    /// for res in demosaicer.run_frames(&frames, &mut dsts) {
+    ///     res.unwrap();
+    /// }
+    /// ```
+    pub fn run_frames(&self, frames: &[&[u8]], dsts: &mut [RasterMut])
+            -> Vec<BayerResult<()>> {
+        if frames.len() != dsts.len() {
+            return frames.iter()
+                .map(|_| Err(BayerError::InvalidArgument { reason: "number of frames and rasters differ" }))
+                .collect();
+        }
+
+        self.run_jobs(frames.iter().map(|frame| &frame[..]).zip(dsts.iter_mut()), frames.len())
+    }
+
+    /// Run the demosaicing algorithm on the synchronised frames of
+    /// several sensors concurrently, reading sources[i] into dsts[i].
+    ///
+    /// This suits multi-camera arrays, where every sensor shares the
+    /// configuration.  See `run_frames`.
+    pub fn run_sensors<R: Read + Send>(&self, sources: &mut [R], dsts: &mut [RasterMut])
+            -> Vec<BayerResult<()>> {
+        if sources.len() != dsts.len() {
+            return sources.iter()
+                .map(|_| Err(BayerError::InvalidArgument { reason: "number of sources and rasters differ" }))
+                .collect();
+        }
+
+        let n = sources.len();
+        self.run_jobs(sources.iter_mut().zip(dsts.iter_mut()), n)
+    }
+
+    /// Run the demosaicing algorithm on the left and right frames of
+    /// a stereo pair concurrently.
+    ///
+    /// The right frame is processed on a second thread.  The rows of
+    /// each frame are also interpolated in parallel if requested by
+    /// the options.  Each side reuses its scratch buffers from pair to
+    /// pair.  If both frames fail, the error of the left frame is
+    /// returned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let width: usize = 32;
+    /// let height: usize = 20;
+    /// let left = vec![0; width * height];
+    /// let right = vec![0; width * height];
+    /// let mut buf_left = vec![0; 3 * width * height];
+    /// let mut buf_right = vec![0; 3 * width * height];
+    ///
+    /// let demosaicer = bayer::Demosaicer::new().build();
+    /// demosaicer.run_pair(&mut &left[..], &mut &right[..],
+    ///         &mut bayer::RasterMut::new(width, height, bayer::RasterDepth::Depth8, &mut buf_left),
+    ///         &mut bayer::RasterMut::new(width, height, bayer::RasterDepth::Depth8, &mut buf_right))
+    ///         .unwrap();
+    /// ```
+    pub fn run_pair<R: Read + Send>(&self, left: &mut R, right: &mut R,
+            dst_left: &mut RasterMut, dst_right: &mut RasterMut)
+            -> BayerResult<()> {
+        let (scratch_left, scratch_right) = (&self.pair_scratch[0], &self.pair_scratch[1]);
+        thread::scope(|s| {
+            let right = s.spawn(move || scratch_right.with(|scratch| {
+                run_engine_with(&self.engine, scratch, right, self.depth, self.cfa, &self.options,
+                        dst_right)
+            }));
+
+            let res_left = scratch_left.with(|scratch| {
+                run_engine_with(&self.engine, scratch, left, self.depth, self.cfa, &self.options,
+                        dst_left)
+            });
+            let res_right = right.join().unwrap_or(Err(BayerError::NoGood));
+            res_left.and(res_right)
+        })
+    }
+
+    /// Run the n jobs on a pool of worker threads, each processing
+    /// whole frames and reusing its scratch buffers.
+    fn run_jobs<'a, 'b: 'a, S, I>(&self, jobs: I, n: usize) -> Vec<BayerResult<()>>
+            where S: Read, I: Iterator<Item = (S, &'a mut RasterMut<'b>)> + Send {
+        let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        let threads = cmp::min(threads, n);
+        let mut options = self.options.clone();
+        options.parallel = false;
+
+        let jobs = Mutex::new(jobs.enumerate());
+        let results = Mutex::new((0..n).map(|_| None).collect::<Vec<_>>());
+
+        thread::scope(|s| {
+            for _ in 0..threads {
+                s.spawn(|| {
+                    let mut scratch = Scratch::default();
+                    loop {
+                        let job = jobs.lock().unwrap_or_else(PoisonError::into_inner).next();
+                        let (i, (mut src, dst)) = match job {
+                            Some(job) => job,
+                            None => break,
+                        };
+
+                        let res = run_engine_with(&self.engine, &mut scratch, &mut src,
+                                self.depth, self.cfa, &options, dst);
+                        results.lock().unwrap_or_else(PoisonError::into_inner)[i] = Some(res);
+                    }
+                });
+            }
+        });
+
+        results.into_inner().unwrap_or_else(PoisonError::into_inner).into_iter()
+            .map(|res| res.unwrap_or(Err(BayerError::NoGood)))
+            .collect()
+    }
+
+    /// Run the demosaicing algorithm on a region of a Bayer image,
+    /// seeking past the data outside its rectangle.  See
+    /// `demosaic_rect`.
+    pub fn run_rect<R: Read + Seek>(&self, r: &mut R, region: Region, dst: &mut RasterMut)
+            -> BayerResult<()> {
+        run_engine_rect(&self.engine, r, self.depth, self.cfa, &self.options, region, dst)
+    }
+
+    /// Run the demosaicing algorithm on a region of a Bayer image,
+    /// reading past the data outside its rectangle.  See
+    /// `demosaic_roi`.
+    pub fn run_roi<R: Read>(&self, r: &mut R, region: Region, dst: &mut RasterMut)
+            -> BayerResult<()> {
+        run_engine_roi(&self.engine, r, self.depth, self.cfa, &self.options, region, dst)
+    }
+
+    /// Run the demosaicing algorithm on a Bayer image, producing the
+    /// full resolution image and a series of downscaled levels.  See
+    /// `demosaic_pyramid`.
+    pub fn run_pyramid<R: Read>(&self, r: &mut R, levels: &mut [RasterMut])
+            -> BayerResult<()> {
+        run_engine_pyramid(&self.engine, r, self.depth, self.cfa, &self.options, levels)
+    }
+
+    /// Run the demosaicing algorithm on a w x h Bayer image,
+    /// resampling the result to the dimensions of dst.  See
+    /// `demosaic_resized`.
+    pub fn run_resized<R: Read>(&self, r: &mut R, w: usize, h: usize,
+            dst: &mut RasterMut)
+            -> BayerResult<()> {
+        run_engine_resized(&self.engine, r, self.depth, self.cfa, &self.options,
+                w, h, dst)
+    }
+
+    /// Run the demosaicing algorithm on a w x h Bayer image in strips
+    /// of strip_rows rows, calling sink(y, row) for each RGB row.
+    ///
+    /// Memory use is bounded by the strip size rather than the image
+    /// size, so this suits images too large to hold in memory.  The
+    /// rows passed to the sink have the layout of a raster row.  The
+    /// rows of each strip are interpolated in parallel if requested
+    /// by the options.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Write;
+    ///
+    /// let width: usize = 320;
+    /// let height: usize = 200;
+    /// let img = vec![0; width * height];
+    /// let mut out = Vec::new();
+    ///
+    /// let demosaicer = bayer::Demosaicer::new().build();
+    /// demosaicer.run_strips(&mut &img[..], width, height, 64, |_y, row| {
+    ///     out.write_all(row)?;
+    ///     Ok(())
+    /// }).unwrap();
+    /// assert_eq!(out.len(), 3 * width * height);
+    /// ```
+    pub fn run_strips<R, G>(&self, r: &mut R, w: usize, h: usize,
+            strip_rows: usize, mut sink: G)
+            -> BayerResult<()>
+            where R: Read, G: FnMut(usize, &[u8]) -> BayerResult<()> {
+        let frame = Frame::new(&self.engine, self.depth, self.cfa, &self.options, w, h)?;
+        run_engine_strips(r, &frame, frame.sample_depth(), strip_rows, &mut sink)
+    }
+
+    /// Run the demosaicing algorithm on the Bayer image, writing into
+    /// separate colour planes.  See `demosaic_planar`.
+    pub fn run_planar<R: Read>(&self, r: &mut R, dst: &mut PlanarRasterMut)
+            -> BayerResult<()> {
+        run_engine_planar(&self.engine, r, self.depth, self.cfa, &self.options, dst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::sync::Arc;
+    use std::thread;
+    use ::{BayerDepth,CFA,Demosaic,RasterDepth,RasterMut,run_demosaic};
+    use super::Demosaicer;
+
+    #[test]
+    fn test_reuse() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+
+        const IMG_W: usize = 4;
+        const IMG_H: usize = 4;
+        let mut expected = [0u8; 3 * IMG_W * IMG_H];
+        let mut buf = [0u8; 3 * IMG_W * IMG_H];
+
+        let res = run_demosaic(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::GBRG, Demosaic::Cubic,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut expected));
+        assert!(res.is_ok());
+
+        let demosaicer = Demosaicer::new()
+                .cfa(CFA::GBRG)
+                .algorithm(Demosaic::Cubic)
+                .build();
+
+        for _ in 0..2 {
+            let res = demosaicer.run(&mut Cursor::new(&src[..]),
+                    &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+            assert!(res.is_ok());
+            assert_eq!(&buf[..], &expected[..]);
+        }
+
+        buf = [0u8; 3 * IMG_W * IMG_H];
+        let res = demosaicer.run_from(&mut &src[..],
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+        assert_eq!(&buf[..], &expected[..]);
+
+        let mut demosaicer = demosaicer;
+        for _ in 0..2 {
+            buf = [0u8; 3 * IMG_W * IMG_H];
+            let res = demosaicer.process(&mut &src[..],
+                    &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+            assert!(res.is_ok());
+            assert_eq!(&buf[..], &expected[..]);
+        }
+    }
+
+    #[test]
+    fn test_frames() {
+        const IMG_W: usize = 4;
+        const IMG_H: usize = 4;
+        let video: Vec<u8> = (0..(5 * IMG_W * IMG_H)).map(|i| (i / (IMG_W * IMG_H)) as u8).collect();
+        let mut buf = [0u8; 5 * 3 * IMG_W * IMG_H];
+
+        let mut frames: Vec<&[u8]> = video.chunks(IMG_W * IMG_H).collect();
+        frames[3] = &frames[3][0..10];
+        let mut dsts: Vec<_> = buf.chunks_mut(3 * IMG_W * IMG_H)
+            .map(|b| RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, b))
+            .collect();
+
+        let demosaicer = Demosaicer::new().algorithm(Demosaic::Cubic).build();
+        let res = demosaicer.run_frames(&frames, &mut dsts);
+        assert_eq!(res.len(), 5);
+        drop(dsts);
+
+        for (i, frame) in buf.chunks(3 * IMG_W * IMG_H).enumerate() {
+            if i == 3 {
+                assert!(res[i].is_err());
+            } else {
+                assert!(res[i].is_ok());
+                assert!(frame.iter().all(|&v| v == i as u8));
+            }
+        }
+    }
+
+    #[test]
+    fn test_frames_mismatch() {
+        let video = [0u8; 2 * 4 * 4];
+        let mut buf = [0u8; 3 * 4 * 4];
+        let frames: Vec<&[u8]> = video.chunks(4 * 4).collect();
+        let mut dsts = [RasterMut::new(4, 4, RasterDepth::Depth8, &mut buf)];
+
+        let res = Demosaicer::new().build().run_frames(&frames, &mut dsts);
+        assert_eq!(res.len(), 2);
+        assert!(res.iter().all(|res| res.is_err()));
+    }
+
+    #[test]
+    fn test_pair() {
+        const IMG_W: usize = 4;
+        const IMG_H: usize = 4;
+        let (left, right) = ([10u8; IMG_W * IMG_H], [20u8; IMG_W * IMG_H]);
+        let mut buf_left = [0u8; 3 * IMG_W * IMG_H];
+        let mut buf_right = [0u8; 3 * IMG_W * IMG_H];
+
+        let demosaicer = Demosaicer::new().algorithm(Demosaic::Cubic).build();
+        let res = demosaicer.run_pair(&mut &left[..], &mut &right[..],
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf_left),
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf_right));
+        assert!(res.is_ok());
+        assert!(buf_left.iter().all(|&v| v == 10));
+        assert!(buf_right.iter().all(|&v| v == 20));
+
+        let res = demosaicer.run_pair(&mut &left[..], &mut &right[..10],
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf_left),
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf_right));
+        assert!(res.is_err());
+
+        // The scratch buffers are reused, swapping the frames.
+        let res = demosaicer.run_pair(&mut &right[..], &mut &left[..],
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf_left),
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf_right));
+        assert!(res.is_ok());
+        assert!(buf_left.iter().all(|&v| v == 20));
+        assert!(buf_right.iter().all(|&v| v == 10));
+    }
+
+    #[test]
+    fn test_sensors() {
+        const IMG_W: usize = 4;
+        const IMG_H: usize = 4;
+        let video: Vec<u8> = (0..(3 * IMG_W * IMG_H)).map(|i| (i / (IMG_W * IMG_H)) as u8).collect();
+        let mut buf = [0u8; 3 * 3 * IMG_W * IMG_H];
+
+        let mut sources: Vec<Cursor<&[u8]>> = video.chunks(IMG_W * IMG_H).map(Cursor::new).collect();
+        let mut dsts: Vec<_> = buf.chunks_mut(3 * IMG_W * IMG_H)
+            .map(|b| RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, b))
+            .collect();
+
+        let res = Demosaicer::new().build().run_sensors(&mut sources, &mut dsts);
+        assert!(res.iter().all(|res| res.is_ok()));
+        drop(dsts);
+
+        for (i, frame) in buf.chunks(3 * IMG_W * IMG_H).enumerate() {
+            assert!(frame.iter().all(|&v| v == i as u8));
+        }
+
+        let mut dsts = [RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf)];
+        let res = Demosaicer::new().build().run_sensors(&mut sources, &mut dsts);
+        assert_eq!(res.len(), 3);
+        assert!(res.iter().all(|res| res.is_err()));
+    }
+
+    #[test]
+    fn test_threads() {
+        const IMG_W: usize = 4;
+        const IMG_H: usize = 4;
+        let demosaicer = Arc::new(Demosaicer::new().algorithm(Demosaic::Cubic).build());
+
+        let threads: Vec<_> = (0..4).map(|i| {
+            let demosaicer = demosaicer.clone();
+            thread::spawn(move || {
+                let src = [i as u8; IMG_W * IMG_H];
+                let mut buf = [0u8; 3 * IMG_W * IMG_H];
+                let res = demosaicer.run(&mut Cursor::new(&src[..]),
+                        &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+                assert!(res.is_ok());
+                assert!(buf.iter().all(|&v| v == i as u8));
+            })
+        }).collect();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+    }
+}
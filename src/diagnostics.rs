@@ -0,0 +1,155 @@
+//! Sensor diagnostics.
+
+use ::{BayerDepth,BayerResult,DefectMap};
+use bayer::{check_input_len,get_sample};
+
+/// Lines whose spread exceeds the median spread of the lines of the
+/// same parity by this factor are considered defective.
+const SPREAD_RATIO: u32 = 8;
+
+/// Robust statistics of one row or column.
+struct LineStats {
+    max: u32,
+    median: u32,
+    /// The median absolute deviation from the median.
+    spread: u32,
+}
+
+impl LineStats {
+    fn new(mut v: Vec<u32>) -> Self {
+        v.sort();
+        let max = v.last().cloned().unwrap_or(0);
+        let median = median(&v);
+
+        let mut dev: Vec<u32> = v.iter()
+            .map(|&s| if s > median { s - median } else { median - s })
+            .collect();
+
+        LineStats { max, median, spread: median_of(&mut dev) }
+    }
+}
+
+/// The median of sorted values.
+fn median(v: &[u32]) -> u32 {
+    if v.is_empty() { 0 } else { v[v.len() / 2] }
+}
+
+fn median_of(v: &mut [u32]) -> u32 {
+    v.sort();
+    median(v)
+}
+
+/// The indices of the lines that look defective.
+///
+/// Even and odd lines hold different colours, so each line is only
+/// compared with the lines of the same parity.  Medians are used
+/// throughout so that a defective row does not hide a defective
+/// column, and vice versa.
+fn defective_lines(lines: &[LineStats]) -> Vec<usize> {
+    let mut defective = Vec::new();
+
+    for parity in 0..2 {
+        let group: Vec<usize> = (parity..lines.len()).step_by(2).collect();
+        let level = median_of(&mut group.iter().map(|&i| lines[i].median).collect::<Vec<_>>());
+        let spread = median_of(&mut group.iter().map(|&i| lines[i].spread).collect::<Vec<_>>());
+
+        for &i in group.iter() {
+            let s = &lines[i];
+            let all_zero = s.max == 0 && level > 0;
+            let constant = s.spread == 0 && spread > 0;
+            let extreme = spread > 0 && s.spread > SPREAD_RATIO * spread;
+
+            if all_zero || constant || extreme {
+                defective.push(i);
+            }
+        }
+    }
+
+    defective.sort();
+    defective
+}
+
+/// Find the rows and columns of a w x h Bayer image whose statistics
+/// indicate stuck or defective readout.
+///
+/// A line is flagged if it is all zero while its neighbours are not,
+/// if it is mostly constant while its neighbours vary, or if its
+/// spread is extreme compared with its neighbours.  The image should be a
+/// normally exposed frame, since a flat field gives these tests
+/// nothing to compare.  The returned map can be used to correct the
+/// lines, see `DefectMap::correct`.
+///
+/// # Example
+///
+/// ```
+/// let mut src = [0u8; 64];
+/// for (i, v) in src.iter_mut().enumerate() {
+///     *v = (i * 37 % 101) as u8;
+/// }
+/// for y in 0..8 {
+///     src[8 * y + 3] = 0;
+/// }
+///
+/// let map = bayer::find_defective_lines(&src, bayer::BayerDepth::Depth8, 8, 8).unwrap();
+/// assert_eq!(map.columns(), [3]);
+/// ```
+pub fn find_defective_lines(src: &[u8], depth: BayerDepth, w: usize, h: usize)
+        -> BayerResult<DefectMap> {
+    check_input_len(src.len(), depth, w, h)?;
+
+    let rows: Vec<LineStats> = (0..h)
+        .map(|y| LineStats::new((0..w).map(|x| get_sample(src, depth, w * y + x)).collect()))
+        .collect();
+    let columns: Vec<LineStats> = (0..w)
+        .map(|x| LineStats::new((0..h).map(|y| get_sample(src, depth, w * y + x)).collect()))
+        .collect();
+
+    let mut map = DefectMap::new();
+    for y in defective_lines(&rows) {
+        map.add_row(y);
+    }
+    for x in defective_lines(&columns) {
+        map.add_column(x);
+    }
+
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use ::BayerDepth;
+    use super::find_defective_lines;
+
+    #[test]
+    fn test_stuck_lines() {
+        const IMG_W: usize = 10;
+        const IMG_H: usize = 8;
+        let mut src = [0u8; 2 * IMG_W * IMG_H];
+
+        // A noisy 16-bit image around 1000, with a stuck row, a hot
+        // column, and a noisy column.
+        for y in 0..IMG_H {
+            for x in 0..IMG_W {
+                let mut v = 1000 + (x * 7 + y * 13) % 17;
+                if y == 5 { v = 4095; }
+                if x == 2 { v = 0; }
+                if x == 7 { v = if y % 2 == 0 { 0 } else { 4095 }; }
+
+                let i = 2 * (IMG_W * y + x);
+                src[i] = (v >> 8) as u8;
+                src[i + 1] = v as u8;
+            }
+        }
+
+        let map = find_defective_lines(&src, BayerDepth::Depth16BE, IMG_W, IMG_H).unwrap();
+        assert_eq!(map.rows(), [5]);
+        assert_eq!(map.columns(), [2, 7]);
+    }
+
+    #[test]
+    fn test_flat_field() {
+        let src = [100u8; 64];
+        let map = find_defective_lines(&src, BayerDepth::Depth8, 8, 8).unwrap();
+        assert!(map.rows().is_empty() && map.columns().is_empty());
+    }
+}
@@ -0,0 +1,173 @@
+//! Demosaicing fixed-size images without heap allocation.
+//!
+//! The image dimensions are compile-time parameters and the caller
+//! provides both buffers, so the interpolation only touches the
+//! stack and the given arrays.  This suits small sensor frames whose
+//! size is fixed by the hardware, e.g. 320x240 thermal or NIR Bayer
+//! sensors, where allocating per frame is undesirable.
+//!
+//! The crate still depends on the standard library, e.g. for the I/O
+//! errors of `BayerError`, so this is not available to `no_std`
+//! targets.
+//!
+//! The output is identical to `Demosaic::Linear` with
+//! `Border::Replicate`.
+
+use ::{BayerError,BayerResult,CFA,Demosaic};
+
+/// Samples that can be interpolated in place.
+trait FixedSample: Copy {
+    fn to_u32(self) -> u32;
+    fn from_u32(v: u32) -> Self;
+}
+
+impl FixedSample for u8 {
+    fn to_u32(self) -> u32 { self as u32 }
+    fn from_u32(v: u32) -> Self { v as u8 }
+}
+
+impl FixedSample for u16 {
+    fn to_u32(self) -> u32 { self as u32 }
+    fn from_u32(v: u32) -> Self { v as u16 }
+}
+
+/// Map a coordinate beyond the edges of the image onto the 2x2
+/// block on the edge, as done by the replicating border reader.
+fn replicate(i: isize, n: usize) -> usize {
+    if i < 0 {
+        (i & 1) as usize
+    } else if i as usize >= n {
+        n - 2 + (i as usize - n) % 2
+    } else {
+        i as usize
+    }
+}
+
+fn demosaic_fixed<T: FixedSample, const W: usize, const H: usize>(
+        src: &[[T; W]; H], cfa: CFA, dst: &mut [[[T; 3]; W]; H])
+        -> BayerResult<()> {
+    let (min_width, min_height) = Demosaic::Linear.min_dimensions();
+    if W < min_width || H < min_height {
+        return Err(BayerError::WrongResolution {
+            width: W, height: H, min_width, min_height,
+        });
+    }
+
+    let at = |x: usize, y: usize, dx: isize, dy: isize| {
+        let x = replicate(x as isize + dx, W);
+        let y = replicate(y as isize + dy, H);
+        src[y][x].to_u32()
+    };
+
+    let mut cfa_y = cfa;
+    for y in 0..H {
+        let mut cfa_x = cfa_y;
+        for x in 0..W {
+            let px = &mut dst[y][x];
+            let cross = (at(x, y, 0, -1) + at(x, y, -1, 0) + at(x, y, 1, 0) + at(x, y, 0, 1)) / 4;
+            let diagonal = (at(x, y, -1, -1) + at(x, y, 1, -1) + at(x, y, -1, 1) + at(x, y, 1, 1)) / 4;
+            let horizontal = (at(x, y, -1, 0) + at(x, y, 1, 0)) / 2;
+            let vertical = (at(x, y, 0, -1) + at(x, y, 0, 1)) / 2;
+
+            match cfa_x {
+                CFA::BGGR | CFA::RGGB => {
+                    let (c, d) = if cfa_x == CFA::BGGR { (2, 0) } else { (0, 2) };
+                    px[c] = src[y][x];
+                    px[1] = T::from_u32(cross);
+                    px[d] = T::from_u32(diagonal);
+                },
+                CFA::GBRG | CFA::GRBG => {
+                    let (h, v) = if cfa_x == CFA::GBRG { (2, 0) } else { (0, 2) };
+                    px[h] = T::from_u32(horizontal);
+                    px[1] = src[y][x];
+                    px[v] = T::from_u32(vertical);
+                },
+            }
+
+            cfa_x = cfa_x.next_x();
+        }
+
+        cfa_y = cfa_y.next_y();
+    }
+
+    Ok(())
+}
+
+/// Demosaic a fixed-size 8-bit Bayer image into a fixed-size RGB
+/// image, using linear interpolation, without allocating.
+///
+/// # Example
+///
+/// ```
+/// const W: usize = 320;
+/// const H: usize = 240;
+///
+/// static SRC: [[u8; W]; H] = [[0; W]; H];
+/// let mut dst = Box::new([[[0u8; 3]; W]; H]);
+///
+/// bayer::demosaic_fixed_u8(&SRC, bayer::CFA::RGGB, &mut dst).unwrap();
+/// ```
+pub fn demosaic_fixed_u8<const W: usize, const H: usize>(
+        src: &[[u8; W]; H], cfa: CFA, dst: &mut [[[u8; 3]; W]; H])
+        -> BayerResult<()> {
+    demosaic_fixed(src, cfa, dst)
+}
+
+/// Demosaic a fixed-size 16-bit Bayer image into a fixed-size RGB
+/// image, using linear interpolation, without allocating.
+pub fn demosaic_fixed_u16<const W: usize, const H: usize>(
+        src: &[[u16; W]; H], cfa: CFA, dst: &mut [[[u16; 3]; W]; H])
+        -> BayerResult<()> {
+    demosaic_fixed(src, cfa, dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{BayerDepth,BayerError,CFA,Demosaic,RasterDepth,RasterMut,run_demosaic};
+    use super::{demosaic_fixed_u8,demosaic_fixed_u16};
+
+    #[test]
+    fn test_linear() {
+        // R: set.seed(0); matrix(floor(runif(n=30, min=0, max=256)), nrow=5, byrow=TRUE)
+        let src = [
+            [229, 67, 95,146,232, 51],
+            [229,241,169,161, 15, 52],
+            [ 45,175, 98,197,253,116],
+            [183,120,208, 81, 33,160],
+            [112,124, 74,210,245, 88] ];
+
+        let raw: Vec<u8> = src.iter().flat_map(|r| r.iter().cloned()).collect();
+        for &cfa in [CFA::BGGR, CFA::GBRG, CFA::GRBG, CFA::RGGB].iter() {
+            let mut expected = [0u8; 3 * 6 * 5];
+            let res = run_demosaic(&mut &raw[..], BayerDepth::Depth8, cfa, Demosaic::Linear,
+                    &mut RasterMut::new(6, 5, RasterDepth::Depth8, &mut expected));
+            assert!(res.is_ok());
+
+            let mut dst = [[[0u8; 3]; 6]; 5];
+            let res = demosaic_fixed_u8(&src, cfa, &mut dst);
+            assert!(res.is_ok());
+
+            let buf: Vec<u8> = dst.iter().flat_map(|r| r.iter().flat_map(|p| p.iter().cloned())).collect();
+            assert_eq!(&buf[..], &expected[..]);
+        }
+    }
+
+    #[test]
+    fn test_u16() {
+        let src = [
+            [0x0100,0x0200],
+            [0x0300,0x0400] ];
+
+        let mut dst = [[[0u16; 3]; 2]; 2];
+        let res = demosaic_fixed_u16(&src, CFA::RGGB, &mut dst);
+        assert!(res.is_ok());
+        assert_eq!(dst[0][0], [0x0100, 0x0280, 0x0400]);
+        assert_eq!(dst[1][1], [0x0100, 0x0280, 0x0400]);
+
+        let res = demosaic_fixed_u16(&[[0u16; 1]; 2], CFA::RGGB, &mut [[[0u16; 3]; 1]; 2]);
+        match res {
+            Err(BayerError::WrongResolution { width: 1, height: 2, .. }) => (),
+            _ => panic!(),
+        }
+    }
+}
@@ -2,30 +2,111 @@
 
 use std::io;
 
+use ::{BayerDepth,RasterDepth,Rect};
+
 pub type BayerResult<T> = Result<T, BayerError>;
 
-quick_error! {
+/// The error for image dimensions that overflow the address space.
+pub(crate) const OVERFLOW: BayerError =
+    BayerError::InvalidArgument { reason: "image dimensions overflow" };
+
+/// The error for floating-point rasters passed to functions that only
+/// support integer samples.
+pub(crate) const FLOAT_RASTER: BayerError =
+    BayerError::InvalidArgument { reason: "floating-point rasters are not supported" };
 
-#[derive(Debug)]
+/// Errors returned by the demosaicing routines.
+///
+/// New variants may be added in future releases, so matches on this
+/// enum must include a wildcard arm.  Each variant has a stable
+/// numeric code, see `BayerError::code`.
+#[derive(Debug,Error)]
+#[non_exhaustive]
 pub enum BayerError {
     // Generic failure.  Please try to make something more meaningful.
-    NoGood {
-        description("No good")
-    }
+    #[error("No good")]
+    NoGood,
 
-    WrongResolution {
-        description("Wrong resolution")
-    }
-    WrongDepth {
-        description("Wrong depth")
-    }
+    #[error("Wrong resolution: {width}x{height} is smaller than the minimum {min_width}x{min_height}")]
+    WrongResolution { width: usize, height: usize, min_width: usize, min_height: usize },
+
+    #[error("Wrong depth: cannot write {bayer:?} Bayer data to a {raster:?} raster")]
+    WrongDepth { bayer: BayerDepth, raster: RasterDepth },
+
+    #[error("Dimension mismatch: {width}x{height} image, {raster_width}x{raster_height} raster")]
+    DimensionMismatch { width: usize, height: usize, raster_width: usize, raster_height: usize },
+
+    #[error("Input too short: expected {expected} bytes, got {got}")]
+    InputTooShort { expected: usize, got: usize },
+
+    #[error("Trailing data: {extra} bytes after the image")]
+    TrailingData { extra: usize },
+
+    #[error("Output too short: expected {expected} bytes, got {got}")]
+    OutputTooShort { expected: usize, got: usize },
+
+    #[error("Out of bounds: {}x{} rectangle at ({}, {}) exceeds the {width}x{height} image",
+            rect.w, rect.h, rect.x, rect.y)]
+    OutOfBounds { rect: Rect, width: usize, height: usize },
 
-    Io(err: io::Error) {
-        from()
-        description(err.description())
-        display("IO error: {}", err)
-        cause(err)
+    #[error("Invalid defect map on line {line}")]
+    InvalidDefectMap { line: usize },
+
+    #[error("Cancelled")]
+    Cancelled,
+
+    #[error("Truncated: {missing_rows} rows were missing and have been filled")]
+    Truncated { missing_rows: usize },
+
+    #[error("Deadline exceeded at row {row}")]
+    DeadlineExceeded { row: usize },
+
+    #[error("Invalid argument: {reason}")]
+    InvalidArgument { reason: &'static str },
+
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+}
+
+impl BayerError {
+    /// The stable numeric code of the error.
+    ///
+    /// These are the values returned by the C API, see libbayer.h.
+    /// Zero is reserved for success.
+    pub fn code(&self) -> u32 {
+        match *self {
+            BayerError::NoGood => 1,
+            BayerError::WrongResolution { .. } => 2,
+            BayerError::WrongDepth { .. } => 3,
+            BayerError::InputTooShort { .. } => 4,
+            BayerError::TrailingData { .. } => 5,
+            BayerError::DimensionMismatch { .. } => 6,
+            BayerError::Io(_) => 7,
+            BayerError::Cancelled => 8,
+            BayerError::OutputTooShort { .. } => 9,
+            BayerError::OutOfBounds { .. } => 10,
+            BayerError::InvalidDefectMap { .. } => 11,
+            BayerError::Truncated { .. } => 12,
+            BayerError::DeadlineExceeded { .. } => 13,
+            BayerError::InvalidArgument { .. } => 14,
+        }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::io;
+    use super::BayerError;
+
+    #[test]
+    fn test_source() {
+        let e = BayerError::from(io::Error::new(io::ErrorKind::Other, "oops"));
+        assert_eq!(e.code(), 7);
+        assert_eq!(e.source().unwrap().to_string(), "oops");
+
+        let e = BayerError::TrailingData { extra: 3 };
+        assert_eq!(e.code(), 5);
+        assert!(e.source().is_none());
+    }
 }
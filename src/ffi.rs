@@ -1,35 +1,39 @@
 //! Foreign function interface.
 
+#[cfg(feature = "ffi-allocator")]
+use std::alloc::GlobalAlloc;
+use std::alloc::Layout;
+use std::cmp;
 use std::io::{Cursor,Read};
 use std::mem;
 use std::ptr;
 use std::slice;
-use libc::{c_uchar,c_uint,size_t};
+use std::sync::atomic::{AtomicPtr,Ordering};
+use libc::{c_uchar,c_uint,c_void,size_t};
 
-use ::{BayerDepth,BayerError,BayerResult,CFA,RasterDepth,RasterMut};
+use ::{BayerDepth,BayerError,BayerResult,CFA,RasterDepth,RasterMut,check_input_len};
 use demosaic;
+use errcode::OVERFLOW;
 
 /// Dummy opaque structure, equivalent to RasterMut<'a>.
 pub struct CRasterMut;
 
-// Print with "file:line - " prefix, for more informative error messages.
-macro_rules! printerrorln {
+// Log with "file:line - " prefix, for more informative error messages.
+macro_rules! logerrorln {
     ($e:expr) => {{
-        println!("{}:{} - {}", file!(), line!(), $e);
-    }};
-    ($fmt:expr, $arg:tt) => {{
-        print!("{}:{} - ", file!(), line!());
-        println!($fmt, $arg);
+        bayer_warn!("{}:{} - {}", file!(), line!(), $e);
     }};
 }
 
 unsafe fn transmute_raster_mut<'a>(dst: *mut CRasterMut)
         -> &'a mut RasterMut<'a> {
-    let ptr: *mut RasterMut = mem::transmute(dst);
-    &mut *ptr
+    let ptr = dst as *mut RasterBox;
+    mem::transmute(&mut (*ptr).raster)
 }
 
-fn run_demosaic<F>(file: &'static str, line: u32,
+/// Demosaic the image at src into dst, which must be valid if they
+/// are not null.
+unsafe fn run_demosaic<F>(file: &'static str, line: u32,
         run: F,
         src: *const c_uchar, src_len: size_t,
         depth: c_uint, be: c_uint, cfa: c_uint,
@@ -37,16 +41,20 @@ fn run_demosaic<F>(file: &'static str, line: u32,
         -> c_uint
         where F: FnOnce(&mut Read, BayerDepth, CFA, &mut RasterMut) -> BayerResult<()> {
     if src.is_null() || dst.is_null() {
-        println!("{} {} - bad input parameters", file, line);
+        bayer_warn!("{}:{} - bad input parameters", file, line);
         return 1;
     }
 
     let depth = match (depth, be) {
         (8, _) => BayerDepth::Depth8,
+        (10, _) => BayerDepth::Depth10Packed,
+        (12, 0) => BayerDepth::Depth12Packed,
+        (12, _) => BayerDepth::Depth12PackedDng,
+        (14, _) => BayerDepth::Depth14Packed,
         (16, 0) => BayerDepth::Depth16LE,
         (16, _) => BayerDepth::Depth16BE,
         _ => {
-            println!("{} {} - invalid depth", file, line);
+            bayer_warn!("{}:{} - invalid depth", file, line);
             return 2;
         }
     };
@@ -57,19 +65,23 @@ fn run_demosaic<F>(file: &'static str, line: u32,
         2 => CFA::GRBG,
         3 => CFA::RGGB,
         _ => {
-            println!("{} {} - invalid cfa", file, line);
+            bayer_warn!("{}:{} - invalid cfa", file, line);
             return 1;
         }
     };
 
-    let src_slice = unsafe{ slice::from_raw_parts(src, src_len) };
-    let dst_raster = unsafe{ transmute_raster_mut(dst) };
+    let src_slice = slice::from_raw_parts(src, src_len);
+    let dst_raster = transmute_raster_mut(dst);
+
+    let res = check_input_len(src_len, depth, dst_raster.w, dst_raster.h)
+        .and_then(|_| run(&mut Cursor::new(&src_slice[..]), depth, cfa, dst_raster));
 
-    match run(&mut Cursor::new(&src_slice[..]), depth, cfa, dst_raster) {
+    match res {
         Ok(_) => 0,
-        Err(BayerError::WrongResolution) => 2,
-        Err(BayerError::WrongDepth) => 3,
-        Err(_) => 1,
+        Err(e) => {
+            bayer_warn!("{}:{} - {}", file, line, e);
+            e.code()
+        }
     }
 }
 
@@ -78,8 +90,13 @@ fn run_demosaic<F>(file: &'static str, line: u32,
 /*--------------------------------------------------------------*/
 
 /// Demosaicing without any interpolation.
+///
+/// # Safety
+///
+/// src must be null or point to src_len readable bytes, and dst must
+/// be null or a raster from this library that has not been freed.
 #[no_mangle]
-pub extern "C" fn bayerrs_demosaic_none(
+pub unsafe extern "C" fn bayerrs_demosaic_none(
         src: *const c_uchar, src_len: size_t,
         depth: c_uint, be: c_uint, cfa: c_uint,
         dst: *mut CRasterMut)
@@ -90,8 +107,13 @@ pub extern "C" fn bayerrs_demosaic_none(
 }
 
 /// Demosaicing using nearest neighbour interpolation.
+///
+/// # Safety
+///
+/// src must be null or point to src_len readable bytes, and dst must
+/// be null or a raster from this library that has not been freed.
 #[no_mangle]
-pub extern "C" fn bayerrs_demosaic_nearest_neighbour(
+pub unsafe extern "C" fn bayerrs_demosaic_nearest_neighbour(
         src: *const c_uchar, src_len: size_t,
         depth: c_uint, be: c_uint, cfa: c_uint,
         dst: *mut CRasterMut)
@@ -102,8 +124,13 @@ pub extern "C" fn bayerrs_demosaic_nearest_neighbour(
 }
 
 /// Demosaicing using linear interpolation.
+///
+/// # Safety
+///
+/// src must be null or point to src_len readable bytes, and dst must
+/// be null or a raster from this library that has not been freed.
 #[no_mangle]
-pub extern "C" fn bayerrs_demosaic_linear(
+pub unsafe extern "C" fn bayerrs_demosaic_linear(
         src: *const c_uchar, src_len: size_t,
         depth: c_uint, be: c_uint, cfa: c_uint,
         dst: *mut CRasterMut)
@@ -113,9 +140,48 @@ pub extern "C" fn bayerrs_demosaic_linear(
             src, src_len, depth, be, cfa, dst)
 }
 
+/// Demosaicing using linear interpolation with median refinement.
+///
+/// # Safety
+///
+/// src must be null or point to src_len readable bytes, and dst must
+/// be null or a raster from this library that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn bayerrs_demosaic_linear_median(
+        src: *const c_uchar, src_len: size_t,
+        depth: c_uint, be: c_uint, cfa: c_uint,
+        dst: *mut CRasterMut)
+        -> c_uint {
+    run_demosaic(file!(), line!(),
+            demosaic::linearmedian::run,
+            src, src_len, depth, be, cfa, dst)
+}
+
+/// Demosaicing using gradient-corrected linear interpolation.
+///
+/// # Safety
+///
+/// src must be null or point to src_len readable bytes, and dst must
+/// be null or a raster from this library that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn bayerrs_demosaic_malvar_he_cutler(
+        src: *const c_uchar, src_len: size_t,
+        depth: c_uint, be: c_uint, cfa: c_uint,
+        dst: *mut CRasterMut)
+        -> c_uint {
+    run_demosaic(file!(), line!(),
+            demosaic::malvarhecutler::run,
+            src, src_len, depth, be, cfa, dst)
+}
+
 /// Demosaicing using cubic interpolation.
+///
+/// # Safety
+///
+/// src must be null or point to src_len readable bytes, and dst must
+/// be null or a raster from this library that has not been freed.
 #[no_mangle]
-pub extern "C" fn bayerrs_demosaic_cubic(
+pub unsafe extern "C" fn bayerrs_demosaic_cubic(
         src: *const c_uchar, src_len: size_t,
         depth: c_uint, be: c_uint, cfa: c_uint,
         dst: *mut CRasterMut)
@@ -125,44 +191,379 @@ pub extern "C" fn bayerrs_demosaic_cubic(
             src, src_len, depth, be, cfa, dst)
 }
 
+/// Demosaicing using patterned pixel grouping.
+///
+/// # Safety
+///
+/// src must be null or point to src_len readable bytes, and dst must
+/// be null or a raster from this library that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn bayerrs_demosaic_ppg(
+        src: *const c_uchar, src_len: size_t,
+        depth: c_uint, be: c_uint, cfa: c_uint,
+        dst: *mut CRasterMut)
+        -> c_uint {
+    run_demosaic(file!(), line!(),
+            demosaic::ppg::run,
+            src, src_len, depth, be, cfa, dst)
+}
+
+/*--------------------------------------------------------------*/
+/* Allocator                                                    */
+/*--------------------------------------------------------------*/
+
+/// Allocation callback, with the signature of malloc.
+pub type MallocFn = unsafe extern "C" fn(size: size_t) -> *mut c_void;
+
+/// Deallocation callback, with the signature of free.
+pub type FreeFn = unsafe extern "C" fn(ptr: *mut c_void);
+
+/// The registered callbacks, or null for malloc and free.  They
+/// point into a registration that is never freed, so the allocations
+/// only load a pointer.
+static HOOKS: AtomicPtr<(MallocFn, FreeFn)> = AtomicPtr::new(ptr::null_mut());
+
+/// A pair of callbacks that has been registered.
+struct Registration {
+    hooks: (MallocFn, FreeFn),
+    next: *mut Registration,
+}
+
+/// The list of registrations, which is never freed.  A registration
+/// is reused when the same callbacks are registered again, so there
+/// is one for each distinct pair.
+static REGISTRATIONS: AtomicPtr<Registration> = AtomicPtr::new(ptr::null_mut());
+
+/// Find or add the registration of the callbacks.
+fn register(malloc: MallocFn, free: FreeFn) -> *mut (MallocFn, FreeFn) {
+    let mut head = REGISTRATIONS.load(Ordering::Acquire);
+    let mut r = head;
+    while !r.is_null() {
+        let reg = unsafe{ &mut *r };
+        if reg.hooks.0 as usize == malloc as usize && reg.hooks.1 as usize == free as usize {
+            return &mut reg.hooks;
+        }
+        r = reg.next;
+    }
+
+    let reg = Box::into_raw(Box::new(Registration { hooks: (malloc, free), next: head }));
+    loop {
+        match REGISTRATIONS.compare_exchange(head, reg, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => return unsafe{ &mut (*reg).hooks },
+            Err(h) => {
+                head = h;
+                unsafe{ (*reg).next = h };
+            },
+        }
+    }
+}
+
+fn hooks() -> (MallocFn, FreeFn) {
+    let hooks = HOOKS.load(Ordering::Acquire);
+    if hooks.is_null() {
+        (libc::malloc, libc::free)
+    } else {
+        unsafe{ *hooks }
+    }
+}
+
+/// The size of the header before each block, holding the free
+/// function and the start of the underlying allocation.
+const HEADER: usize = 2 * mem::size_of::<usize>();
+
+/// Allocate memory with the registered callbacks.
+///
+/// The free function is recorded with the block, so that it is
+/// returned to the allocator it came from, even if the callbacks are
+/// replaced in the meantime.
+unsafe fn hook_alloc(layout: Layout) -> *mut u8 {
+    let (malloc, free) = hooks();
+    let align = cmp::max(layout.align(), mem::align_of::<usize>());
+    let total = match layout.size().checked_add(HEADER + align) {
+        Some(total) => total,
+        None => return ptr::null_mut(),
+    };
+
+    let raw = malloc(total) as *mut u8;
+    if raw.is_null() {
+        return ptr::null_mut();
+    }
+
+    let offset = (HEADER + align - 1) - (raw as usize + HEADER + align - 1) % align;
+    let block = raw.add(offset);
+    let header = block as *mut usize;
+    *header.offset(-1) = raw as usize;
+    *header.offset(-2) = free as usize;
+    block
+}
+
+/// Free memory allocated by hook_alloc.
+unsafe fn hook_dealloc(block: *mut u8) {
+    let header = block as *mut usize;
+    let raw = *header.offset(-1) as *mut c_void;
+    let free: FreeFn = mem::transmute(*header.offset(-2));
+    free(raw);
+}
+
+/// Global allocator that routes the allocations of the library
+/// through the registered callbacks.  It is installed by the
+/// `ffi-allocator` feature.
+#[cfg(feature = "ffi-allocator")]
+struct HookAllocator;
+
+#[cfg(feature = "ffi-allocator")]
+unsafe impl GlobalAlloc for HookAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        hook_alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+        hook_dealloc(ptr)
+    }
+}
+
+#[cfg(feature = "ffi-allocator")]
+#[global_allocator]
+static ALLOCATOR: HookAllocator = HookAllocator;
+
+/// Register the malloc and free callbacks used for the memory
+/// allocated on behalf of the caller, e.g. the raster wrappers.  With
+/// the `ffi-allocator` feature, they are used for every allocation
+/// made by the library, including the scratch buffers.
+///
+/// Pass two nulls to restore malloc and free.  The callbacks should
+/// be registered before any other call, but memory is always
+/// returned to the callback that allocated it.  A copy of each
+/// distinct pair of callbacks is kept until the process exits, since
+/// allocations in flight may still be reading the previous ones.
+///
+/// # Safety
+///
+/// The callbacks must behave like malloc and free: malloc must return
+/// null or at least size bytes aligned for any type, and free must
+/// release any block returned by malloc.  Both must be safe to call
+/// from any thread, and must stay valid while memory allocated
+/// through them is in use.
+#[no_mangle]
+pub unsafe extern "C" fn bayerrs_set_allocator(malloc: Option<MallocFn>, free: Option<FreeFn>)
+        -> c_uint {
+    let hooks = match (malloc, free) {
+        (Some(malloc), Some(free)) => register(malloc, free),
+        (None, None) => ptr::null_mut(),
+        _ => {
+            let e = BayerError::InvalidArgument { reason: "malloc and free must both be given" };
+            logerrorln!(e);
+            return e.code();
+        }
+    };
+
+    HOOKS.store(hooks, Ordering::Release);
+    0
+}
+
 /*--------------------------------------------------------------*/
 /* Raster                                                       */
 /*--------------------------------------------------------------*/
 
-/// Allocate a new raster.
+/// The raster behind a CRasterMut, and the buffer allocated for it
+/// if it owns one.
+struct RasterBox {
+    raster: RasterMut<'static>,
+    owned: *mut u8,
+}
+
+/// Move the raster into memory allocated with the registered
+/// callbacks.
+fn box_raster(raster: RasterMut<'static>, owned: *mut u8) -> *mut CRasterMut {
+    let rptr = unsafe{ hook_alloc(Layout::new::<RasterBox>()) } as *mut RasterBox;
+    if rptr.is_null() {
+        logerrorln!("out of memory");
+        if !owned.is_null() {
+            unsafe{ hook_dealloc(owned) };
+        }
+        return ptr::null_mut();
+    }
+
+    unsafe{ ptr::write(rptr, RasterBox { raster, owned }) };
+    rptr as *mut CRasterMut
+}
+
+fn parse_raster_depth(depth: c_uint) -> Option<RasterDepth> {
+    match depth {
+        8 => Some(RasterDepth::Depth8),
+        16 => Some(RasterDepth::Depth16),
+        _ => None,
+    }
+}
+
+/// Allocate a new raster.  A 16-bit raster needs a buffer aligned to
+/// 2 bytes.
+///
+/// # Safety
+///
+/// buf must be null or point to buf_len writable bytes, which outlive
+/// the raster and are not accessed elsewhere while it is in use.
 #[no_mangle]
-pub extern "C" fn bayerrs_raster_mut_alloc(
+pub unsafe extern "C" fn bayerrs_raster_mut_alloc(
         x: size_t, y: size_t, w: size_t, h: size_t, stride: size_t, depth: c_uint,
         buf: *mut c_uchar, buf_len: size_t)
         -> *mut CRasterMut {
     if buf.is_null() {
-        printerrorln!("bad input parameters");
+        logerrorln!("bad input parameters");
         return ptr::null_mut();
     }
 
-    let depth = match depth {
-        8 => RasterDepth::Depth8,
-        16 => RasterDepth::Depth16,
-        _ => {
-            printerrorln!("bad input parameters");
+    let depth = match parse_raster_depth(depth) {
+        Some(depth) => depth,
+        None => {
+            logerrorln!("bad input parameters");
             return ptr::null_mut();
         }
     };
 
-    let buf_slice = unsafe{ slice::from_raw_parts_mut(buf, buf_len) };
-    let raster = RasterMut::with_offset(x, y, w, h, stride, depth, buf_slice);
-    let rptr = Box::into_raw(Box::new(raster));
-    let cptr: *mut CRasterMut = unsafe{ mem::transmute(rptr) };
-    cptr
+    let buf_slice = slice::from_raw_parts_mut(buf, buf_len);
+    match RasterMut::try_with_offset(x, y, w, h, stride, depth, buf_slice) {
+        Ok(raster) => box_raster(raster, ptr::null_mut()),
+        Err(e) => {
+            logerrorln!(e);
+            ptr::null_mut()
+        }
+    }
 }
 
-/// Free a previously allocated raster.
+/// Allocate a new w x h raster with its own zeroed buffer, which is
+/// freed with the raster.  See bayerrs_raster_mut_buffer.
 #[no_mangle]
-pub extern "C" fn bayerrs_raster_mut_free(raster: *mut CRasterMut) {
+pub extern "C" fn bayerrs_raster_mut_alloc_owned(
+        w: size_t, h: size_t, depth: c_uint)
+        -> *mut CRasterMut {
+    let depth = match parse_raster_depth(depth) {
+        Some(depth) => depth,
+        None => {
+            logerrorln!("bad input parameters");
+            return ptr::null_mut();
+        }
+    };
+
+    let len = match w.checked_mul(depth.bytes_per_pixel()).and_then(|n| n.checked_mul(h)) {
+        Some(len) => len,
+        None => {
+            logerrorln!(OVERFLOW);
+            return ptr::null_mut();
+        }
+    };
+
+    let layout = match Layout::from_size_align(len, mem::align_of::<u16>()) {
+        Ok(layout) => layout,
+        Err(_) => {
+            logerrorln!(OVERFLOW);
+            return ptr::null_mut();
+        }
+    };
+
+    let buf = unsafe{ hook_alloc(layout) };
+    if buf.is_null() {
+        logerrorln!("out of memory");
+        return ptr::null_mut();
+    }
+
+    let buf_slice = unsafe {
+        ptr::write_bytes(buf, 0, len);
+        slice::from_raw_parts_mut(buf, len)
+    };
+    match RasterMut::try_new(w, h, depth, buf_slice) {
+        Ok(raster) => box_raster(raster, buf),
+        Err(e) => {
+            logerrorln!(e);
+            unsafe{ hook_dealloc(buf) };
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Get the buffer of a raster, storing its length in len unless len
+/// is null.  Returns null if the raster is null.
+///
+/// # Safety
+///
+/// raster must be null or a raster from this library that has not
+/// been freed, and len must be null or point to a writable size_t.
+#[no_mangle]
+pub unsafe extern "C" fn bayerrs_raster_mut_buffer(raster: *mut CRasterMut, len: *mut size_t)
+        -> *mut c_uchar {
+    if raster.is_null() {
+        return ptr::null_mut();
+    }
+
+    let raster = transmute_raster_mut(raster);
+    if !len.is_null() {
+        *len = raster.buf.len();
+    }
+    raster.buf.as_mut_ptr()
+}
+
+/// Free a previously allocated raster, and its buffer if it owns
+/// one.
+///
+/// # Safety
+///
+/// raster must be null or a raster from this library that has not
+/// been freed.
+#[no_mangle]
+pub unsafe extern "C" fn bayerrs_raster_mut_free(raster: *mut CRasterMut) {
     if raster.is_null() {
         return;
     }
 
-    let rptr: *mut RasterMut = unsafe{ mem::transmute(raster) };
-    let _raster = unsafe{ Box::from_raw(rptr) };
+    let rptr = raster as *mut RasterBox;
+    let owned = (*rptr).owned;
+    ptr::drop_in_place(rptr);
+    hook_dealloc(rptr as *mut u8);
+    if !owned.is_null() {
+        hook_dealloc(owned);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+    use super::*;
+
+    #[test]
+    fn test_owned() {
+        let src = [0x80u8; 4 * 4];
+        let raster = bayerrs_raster_mut_alloc_owned(4, 4, 8);
+        assert!(raster != ptr::null_mut());
+
+        unsafe {
+            let mut len = 0;
+            let buf = bayerrs_raster_mut_buffer(raster, &mut len);
+            assert!(buf != ptr::null_mut());
+            assert_eq!(len, 3 * 4 * 4);
+
+            let res = bayerrs_demosaic_linear(src.as_ptr(), src.len(), 8, 0, 3, raster);
+            assert_eq!(res, 0);
+            let buf = ::std::slice::from_raw_parts(buf, len);
+            assert!(buf.iter().all(|&v| v == 0x80));
+            bayerrs_raster_mut_free(raster);
+            assert_eq!(bayerrs_raster_mut_buffer(ptr::null_mut(), ptr::null_mut()), ptr::null_mut());
+        }
+
+        assert_eq!(bayerrs_raster_mut_alloc_owned(4, 4, 12), ptr::null_mut());
+        assert_eq!(bayerrs_raster_mut_alloc_owned(usize::max_value(), 4, 16), ptr::null_mut());
+    }
+
+    #[test]
+    fn test_alignment() {
+        for &align in [1, 8, 16, 64, 4096].iter() {
+            let layout = Layout::from_size_align(100, align).unwrap();
+            unsafe {
+                let block = hook_alloc(layout);
+                assert!(!block.is_null());
+                assert_eq!(block as usize % align, 0);
+                ptr::write_bytes(block, 0xFF, 100);
+                hook_dealloc(block);
+            }
+        }
+    }
 }
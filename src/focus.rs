@@ -0,0 +1,90 @@
+//! Focus metrics computed on Bayer images.
+
+use ::{BayerDepth,BayerResult,CFA};
+use bayer::{check_input_len,get_sample};
+
+/// Measure the sharpness of a w x h Bayer image, as the variance of
+/// the Laplacian of the green samples.
+///
+/// The diagonal neighbours of a green sample are also green, so the
+/// Laplacian is taken along the diagonals and no interpolation is
+/// needed.  Only the green samples at least one pixel from the edge
+/// are used.  The result is larger for sharper images, and is only
+/// meaningful when comparing frames of the same scene.
+///
+/// # Example
+///
+/// ```
+/// let flat = [100u8; 16];
+/// let focus = bayer::focus_measure(&flat, bayer::BayerDepth::Depth8, 4, 4,
+///         bayer::CFA::RGGB).unwrap();
+/// assert_eq!(focus, 0.0);
+/// ```
+pub fn focus_measure(src: &[u8], depth: BayerDepth, w: usize, h: usize,
+        cfa: CFA)
+        -> BayerResult<f64> {
+    check_input_len(src.len(), depth, w, h)?;
+
+    let mut n = 0u64;
+    let mut sum = 0f64;
+    let mut sum_sq = 0f64;
+
+    for y in 1..h.saturating_sub(1) {
+        for x in 1..w.saturating_sub(1) {
+            if cfa.at(x, y).channel() != 1 {
+                continue;
+            }
+
+            let i = w * y + x;
+            let diag = get_sample(src, depth, i - w - 1)
+                    + get_sample(src, depth, i - w + 1)
+                    + get_sample(src, depth, i + w - 1)
+                    + get_sample(src, depth, i + w + 1);
+            let lap = 4.0 * get_sample(src, depth, i) as f64 - diag as f64;
+
+            n = n + 1;
+            sum = sum + lap;
+            sum_sq = sum_sq + lap * lap;
+        }
+    }
+
+    if n == 0 {
+        return Ok(0.0);
+    }
+
+    let mean = sum / n as f64;
+    Ok((sum_sq / n as f64 - mean * mean).max(0.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{BayerDepth,CFA};
+    use super::focus_measure;
+
+    #[test]
+    fn test_focus() {
+        // A sharp checkerboard of green samples, and a blurred one.
+        let sharp = [
+            0,0,0,0,0,
+            0,0,0,8,0,
+            0,0,0,0,0,
+            0,8,0,0,0,
+            0,0,0,0,0 ];
+        let blurred = [
+            0,0,0,0,0,
+            0,2,0,4,0,
+            0,0,4,0,0,
+            0,4,0,2,0,
+            0,0,0,0,0 ];
+
+        let a = focus_measure(&sharp, BayerDepth::Depth8, 5, 5, CFA::GRBG).unwrap();
+        let b = focus_measure(&blurred, BayerDepth::Depth8, 5, 5, CFA::GRBG).unwrap();
+        assert!(a > b && b > 0.0);
+
+        let res = focus_measure(&sharp, BayerDepth::Depth8, 5, 5, CFA::RGGB);
+        assert_eq!(res.unwrap(), 0.0);
+
+        let res = focus_measure(&sharp, BayerDepth::Depth16LE, 5, 5, CFA::RGGB);
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,175 @@
+//! Reading raw video files.
+
+use std::io::{self,Read,Seek,SeekFrom};
+
+use ::{BayerError,BayerResult,Demosaicer,RasterMut};
+
+/// Reader that counts the bytes read.
+struct CountingReader<'a, R: 'a> {
+    r: &'a mut R,
+    count: u64,
+}
+
+impl<'a, R: Read> Read for CountingReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.r.read(buf)?;
+        self.count = self.count + n as u64;
+        Ok(n)
+    }
+}
+
+/// Reader for a stream of back-to-back Bayer frames of the same size,
+/// such as a raw capture dump.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+///
+/// let width: usize = 32;
+/// let height: usize = 20;
+/// let video = vec![0; 3 * width * height];
+/// let mut buf = vec![0; 3 * width * height];
+///
+/// let demosaicer = bayer::Demosaicer::new().build();
+/// let mut frames = bayer::FrameReader::new(Cursor::new(video), demosaicer, width, height);
+/// assert_eq!(frames.frame_count().unwrap(), 3);
+///
+/// let mut dst = bayer::RasterMut::new(
+///         width, height, bayer::RasterDepth::Depth8,
+///         &mut buf);
+/// while let Some(index) = frames.read_frame(&mut dst).unwrap() {
+///     println!("frame {}", index);
+/// }
+/// ```
+pub struct FrameReader<R> {
+    r: R,
+    demosaicer: Demosaicer,
+    w: usize,
+    h: usize,
+    index: usize,
+}
+
+impl<R> FrameReader<R> {
+    /// Create a reader for frames of w x h pixels, to be demosaiced
+    /// by the demosaicer.
+    pub fn new(r: R, demosaicer: Demosaicer, w: usize, h: usize) -> Self {
+        FrameReader { r, demosaicer, w, h, index: 0 }
+    }
+
+    /// The number of bytes in each frame.
+    pub fn frame_len(&self) -> u64 {
+        self.demosaicer.depth().byte_len(self.w * self.h).unwrap_or(usize::MAX) as u64
+    }
+
+    /// The index of the next frame to be read.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Recover the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.r
+    }
+}
+
+impl<R: Read> FrameReader<R> {
+    /// Demosaic the next frame into dst, returning its index, or
+    /// None at the end of the stream.
+    ///
+    /// An incomplete frame at the end of the stream is an error,
+    /// unless the demosaicer is configured to tolerate truncation.
+    pub fn read_frame(&mut self, dst: &mut RasterMut)
+            -> BayerResult<Option<usize>> {
+        // A raster of the wrong size would lose track of the frames.
+        let (w, h) = match self.demosaicer.options().transform {
+            Some(t) => t.dimensions(dst.w, dst.h),
+            None => (dst.w, dst.h),
+        };
+        if (w, h) != (self.w, self.h) {
+            return Err(BayerError::DimensionMismatch {
+                width: self.w, height: self.h, raster_width: dst.w, raster_height: dst.h,
+            });
+        }
+
+        let mut r = CountingReader { r: &mut self.r, count: 0 };
+        let res = self.demosaicer.run_from(&mut r, dst);
+
+        if r.count == 0 {
+            match res {
+                Err(BayerError::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof =>
+                    return Ok(None),
+                Err(BayerError::Truncated { .. }) =>
+                    return Ok(None),
+                _ => (),
+            }
+        }
+
+        let index = self.index;
+        self.index = self.index + 1;
+        res.map(|_| Some(index))
+    }
+}
+
+impl<R: Seek> FrameReader<R> {
+    /// The number of complete frames in the stream.
+    pub fn frame_count(&mut self) -> BayerResult<usize> {
+        let pos = self.r.seek(SeekFrom::Current(0))?;
+        let len = self.r.seek(SeekFrom::End(0))?;
+        self.r.seek(SeekFrom::Start(pos))?;
+        Ok((len / self.frame_len()) as usize)
+    }
+
+    /// Move to the frame with the given index.
+    pub fn seek_frame(&mut self, index: usize) -> BayerResult<()> {
+        self.r.seek(SeekFrom::Start(index as u64 * self.frame_len()))?;
+        self.index = index;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use ::{BayerError,Demosaic,Demosaicer,RasterDepth,RasterMut};
+    use super::FrameReader;
+
+    #[test]
+    fn test_frames() {
+        const IMG_W: usize = 4;
+        const IMG_H: usize = 4;
+        let mut video = Vec::new();
+        for i in 0..3 {
+            video.extend_from_slice(&[10 * i as u8; IMG_W * IMG_H]);
+        }
+        video.extend_from_slice(&[0; 5]);
+
+        let demosaicer = Demosaicer::new().algorithm(Demosaic::Linear).build();
+        let mut frames = FrameReader::new(Cursor::new(video), demosaicer, IMG_W, IMG_H);
+        let mut buf = [0u8; 3 * IMG_W * IMG_H];
+        assert_eq!(frames.frame_count().unwrap(), 3);
+
+        for i in 0..3 {
+            let res = frames.read_frame(&mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+            assert_eq!(res.unwrap(), Some(i));
+            assert!(buf.iter().all(|&v| v == 10 * i as u8));
+        }
+
+        // The trailing partial frame.
+        let res = frames.read_frame(&mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_err());
+        let res = frames.read_frame(&mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+        assert_eq!(res.unwrap(), None);
+
+        assert!(frames.seek_frame(1).is_ok());
+        let res = frames.read_frame(&mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+        assert_eq!(res.unwrap(), Some(1));
+        assert!(buf.iter().all(|&v| v == 10));
+
+        let res = frames.read_frame(&mut RasterMut::new(2, 2, RasterDepth::Depth16, &mut buf));
+        match res {
+            Err(BayerError::DimensionMismatch { width: 4, height: 4, .. }) => (),
+            _ => panic!(),
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! Green-only interpolation.
+//!
+//! Focusing, alignment and guiding only need the detail in the image,
+//! not its colour.  Interpolating just the green plane, which holds
+//! half of the samples, is much cheaper than a full demosaic.
+
+use ::{BayerDepth,BayerResult,CFA};
+use bayer::{check_input_len,check_output_len,get_sample,put_sample};
+
+/// Interpolate the green plane of a w x h Bayer image to full
+/// resolution, producing a single-channel image.
+///
+/// Green samples are copied, and the red and blue samples are
+/// replaced by the mean of their green neighbours within the image.
+/// The output samples have the same depth and byte order as the
+/// input.
+///
+/// # Example
+///
+/// ```
+/// let src = [
+///     10,20,
+///     40,50 ];
+/// let mut dst = [0u8; 4];
+///
+/// bayer::interpolate_green(&src, bayer::BayerDepth::Depth8, 2, 2,
+///         bayer::CFA::RGGB, &mut dst).unwrap();
+/// assert_eq!(dst, [30,20, 40,30]);
+/// ```
+pub fn interpolate_green(src: &[u8], depth: BayerDepth, w: usize, h: usize,
+        cfa: CFA, dst: &mut [u8])
+        -> BayerResult<()> {
+    check_input_len(src.len(), depth, w, h)?;
+    check_output_len(dst.len(), depth, w, h)?;
+
+    for y in 0..h {
+        for x in 0..w {
+            let i = w * y + x;
+            if cfa.at(x, y).channel() == 1 {
+                put_sample(dst, depth, i, get_sample(src, depth, i));
+                continue;
+            }
+
+            let mut sum = 0;
+            let mut n = 0;
+            if x > 0 { sum = sum + get_sample(src, depth, i - 1); n = n + 1; }
+            if x + 1 < w { sum = sum + get_sample(src, depth, i + 1); n = n + 1; }
+            if y > 0 { sum = sum + get_sample(src, depth, i - w); n = n + 1; }
+            if y + 1 < h { sum = sum + get_sample(src, depth, i + w); n = n + 1; }
+
+            let v = if n > 0 { (sum + n / 2) / n } else { get_sample(src, depth, i) };
+            put_sample(dst, depth, i, v);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{BayerDepth,CFA};
+    use super::interpolate_green;
+
+    #[test]
+    fn test_green_u8() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+
+        // GRBG: the greens are at (0, 0), (1, 1), ...
+        let expected = [
+            229,125, 95,168,
+            150, 51,101,241,
+            169,103, 15,151,
+            172,175,129,197 ];
+
+        let mut buf = [0u8; 16];
+        let res = interpolate_green(&src, BayerDepth::Depth8, 4, 4, CFA::GRBG, &mut buf);
+        assert!(res.is_ok());
+        assert_eq!(&buf[..], &expected[..]);
+    }
+
+    #[test]
+    fn test_green_u16() {
+        let src = [
+            0x01,0x00, 0x03,0x00,
+            0x05,0x00, 0x07,0x00 ];
+        let mut buf = [0u8; 8];
+
+        let res = interpolate_green(&src, BayerDepth::Depth16LE, 2, 2, CFA::BGGR, &mut buf);
+        assert!(res.is_ok());
+        assert_eq!(buf, [0x04,0x00, 0x03,0x00, 0x05,0x00, 0x04,0x00]);
+
+        let res = interpolate_green(&src[..6], BayerDepth::Depth16LE, 2, 2, CFA::BGGR, &mut buf);
+        assert!(res.is_err());
+    }
+}
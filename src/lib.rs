@@ -1,22 +1,102 @@
 //! This crate provides routines for demosaicing Bayer raw images.
+//!
+//! # Panics
+//!
+//! The public functions return a `BayerError` instead of panicking on
+//! invalid input, with these exceptions, which document their panics:
+//!
+//! * `RasterMut::new`, `RasterMut::with_offset`, `Raster::new` and
+//!   `Raster::with_offset`.  Use the `try_` versions instead.
+//! * `Demosaic::interpolate_row_u8` and `interpolate_row_u16`.  Use
+//!   the `try_` versions instead.
+//! * `BorderNone::new`, `BorderMirror::new`, `BorderReplicate::new`
+//!   and `PingPong::new`.  Use `try_new` instead.
+//! * The `borrow_row_u8`, `borrow_row_u16` and `borrow_row_f32`
+//!   accessors of `Raster` and `RasterMut`, and their `_mut`
+//!   versions, which require an RGB raster of the matching depth and
+//!   a row within the raster.
+//!
+//! As elsewhere in Rust, running out of memory aborts the process.
 
 extern crate byteorder;
 extern crate libc;
 
+#[cfg(feature = "image")]
+extern crate image;
+
+#[cfg(feature = "log")]
+#[macro_use]
+extern crate log;
+
+#[cfg(feature = "ndarray")]
+extern crate ndarray;
+
 #[cfg(feature = "rayon")]
 extern crate rayon;
 
 #[macro_use]
-extern crate quick_error;
+extern crate thiserror;
+
+// Diagnostics go to the log crate if the log feature is enabled, and
+// are discarded otherwise.
+#[cfg(feature = "log")]
+macro_rules! bayer_warn {
+    ($($arg:tt)*) => { warn!($($arg)*) };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! bayer_warn {
+    ($($arg:tt)*) => { if false { let _ = format_args!($($arg)*); } };
+}
 
 use std::io::Read;
 
+#[cfg(feature = "ndarray")]
+pub use array::{demosaic_array_u8_with_options,demosaic_array_u16_with_options,run_demosaic_array_u8,run_demosaic_array_u16};
 pub use bayer::BayerDepth;
+pub use bayer::check_input_len;
 pub use bayer::CFA;
-pub use demosaic::Demosaic;
+pub use binning::bin2x2;
+pub use compare::{DiffStats,compare_algorithms};
+pub use confidence::{confidence_map,demosaic_with_confidence};
+pub use convert::swap_endianness;
+pub use crop::{Rect,crop,reorder_cfa};
+pub use defects::DefectMap;
+pub use demosaic::{Demosaic,DemosaicInfo};
+pub use demosaic::streaming::StreamingDemosaicer;
+pub use demosaicer::{Demosaicer,DemosaicerBuilder};
+pub use diagnostics::find_defective_lines;
+pub use embedded::{demosaic_fixed_u8,demosaic_fixed_u16};
 pub use errcode::BayerError;
 pub use errcode::BayerResult;
-pub use raster::RasterDepth;
+pub use focus::focus_measure;
+pub use frames::FrameReader;
+pub use green::interpolate_green;
+pub use luminance::luminance;
+pub use mosaic::{MosaicOptions,Noise,mosaic,mosaic_with_options};
+#[cfg(feature = "ppm")]
+pub use output::write_ppm;
+#[cfg(feature = "image")]
+pub use output::write_png;
+pub use packed::{PackedFormat,pack,unpack};
+pub use pattern::{CfaPattern,Colour,demosaic_pattern};
+pub use pingpong::PingPong;
+pub use planar::{PlanarRasterMut,demosaic_planar};
+pub use planes::{merge_planes,split_planes};
+pub use preview::{ToneCurve,preview,tone_mapped_preview};
+pub use pyramid::demosaic_pyramid;
+pub use quadbayer::{bin_quad,remosaic_quad};
+pub use options::{Border,CancelToken,DemosaicOptions,Fill,FrameTiming,Intermediate,Levels,Narrowing,Overrun,ProcessingReport,Progress,Report,Saturation,Scaling,Timing,Truncation};
+#[cfg(feature = "rayon")]
+pub use options::ThreadPool;
+pub use raster::{RasterDepth,RasterLayout};
+pub use resample::demosaic_resized;
+pub use slice::{demosaic_slice,demosaic_slice_u16};
+pub use roi::{Region,demosaic_rect,demosaic_roi};
+pub use source::BayerSource;
+pub use stats::{ChannelStats,channel_stats};
+pub use transform::{Transform,transform};
+pub use whitebalance::{WhiteBalance,WhiteBalancePreset,apply_white_balance,white_balance_from_rect};
 
 /// Mutable raster structure.
 pub struct RasterMut<'a> {
@@ -26,18 +106,96 @@ pub struct RasterMut<'a> {
     h: usize,
     stride: usize,
     depth: RasterDepth,
+    layout: RasterLayout,
     buf: &'a mut [u8],
 }
 
+/// Immutable raster structure.
+pub struct Raster<'a> {
+    x: usize,
+    y: usize,
+    w: usize,
+    h: usize,
+    stride: usize,
+    depth: RasterDepth,
+    layout: RasterLayout,
+    buf: &'a [u8],
+}
+
 pub mod demosaic;
 pub mod ffi;
 
+/// Bayer line readers that pad the raw data with border pixels.
+///
+/// These are the readers used by the demosaicing algorithms, and can
+/// be reused by custom kernels.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use bayer::border::{BayerRead,BorderReplicate8};
+///
+/// let src = [1,2, 3,4, 5,6];
+/// let mut buf = [0u8; 2 + 6 + 2];
+///
+/// let rdr = BorderReplicate8::new(6, 2);
+/// rdr.read_line(&mut Cursor::new(&src[..]), &mut buf).unwrap();
+/// assert_eq!(buf, [1,2, 1,2,3,4,5,6, 5,6]);
+/// ```
+pub mod border {
+    pub use bayer::{BayerRead,RawFormat,Raw8,Raw8Wide,Raw10,Raw12,Raw14,Dng12,Raw16BE,Raw16LE};
+    pub use border_mirror::{BorderMirror,BorderMirror8,BorderMirror16BE,BorderMirror16LE};
+    pub use border_mirror::{BorderMirror10Packed,BorderMirror12Packed,BorderMirror12PackedDng,BorderMirror14Packed};
+    pub use border_none::{BorderNone,BorderNone8,BorderNone16BE,BorderNone16LE};
+    pub use border_none::{BorderNone10Packed,BorderNone12Packed,BorderNone12PackedDng,BorderNone14Packed};
+    pub use border_replicate::{BorderReplicate,BorderReplicate8,BorderReplicate16BE,BorderReplicate16LE};
+    pub use border_replicate::{BorderReplicate10Packed,BorderReplicate12Packed,BorderReplicate12PackedDng,BorderReplicate14Packed};
+}
+
+#[cfg(feature = "ndarray")]
+mod array;
 mod bayer;
+mod binning;
 mod border_mirror;
 mod border_none;
 mod border_replicate;
+mod clear;
+mod compare;
+mod complementary;
+mod confidence;
+mod convert;
+mod crop;
+mod defects;
+mod demosaicer;
+mod diagnostics;
+mod embedded;
 mod errcode;
+mod focus;
+mod frames;
+mod green;
+mod luminance;
+mod mosaic;
+mod options;
+#[cfg(any(feature = "ppm", feature = "image"))]
+mod output;
+mod packed;
+mod pattern;
+mod pingpong;
+mod planar;
+mod planes;
+mod preview;
+mod pyramid;
+mod quadbayer;
 mod raster;
+mod resample;
+mod roi;
+mod slice;
+mod source;
+mod stats;
+mod transform;
+mod whitebalance;
+mod xtrans;
 
 /// Run the demosaicing algorithm on the Bayer image.
 ///
@@ -64,10 +222,395 @@ pub fn run_demosaic(r: &mut Read,
         depth: BayerDepth, cfa: CFA, alg: Demosaic,
         dst: &mut RasterMut)
         -> BayerResult<()> {
-    match alg {
-        Demosaic::None => demosaic::none::run(r, depth, cfa, dst),
-        Demosaic::NearestNeighbour => demosaic::nearestneighbour::run(r, depth, cfa, dst),
-        Demosaic::Linear => demosaic::linear::run(r, depth, cfa, dst),
-        Demosaic::Cubic => demosaic::cubic::run(r, depth, cfa, dst),
+    demosaic_with_options(r, depth, cfa, alg, &DemosaicOptions::default(), dst)
+}
+
+/// Run the demosaicing algorithm on the Bayer image, with additional
+/// options.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+///
+/// let width: usize = 320;
+/// let height: usize = 200;
+/// let img = vec![0; width * height];
+/// let mut buf = vec![0; 3 * width * height];
+///
+/// let mut options = bayer::DemosaicOptions::default();
+/// options.parallel = false;
+///
+/// let mut dst = bayer::RasterMut::new(
+///         width, height, bayer::RasterDepth::Depth8,
+///         &mut buf);
+/// bayer::demosaic_with_options(&mut Cursor::new(&img[..]),
+///         bayer::BayerDepth::Depth8,
+///         bayer::CFA::RGGB,
+///         bayer::Demosaic::Linear,
+///         &options,
+///         &mut dst);
+/// ```
+pub fn demosaic_with_options(r: &mut Read,
+        depth: BayerDepth, cfa: CFA, alg: Demosaic,
+        options: &DemosaicOptions, dst: &mut RasterMut)
+        -> BayerResult<()> {
+    demosaic::run_engine(&alg.engine(), r, depth, cfa, options, dst)
+}
+
+/// Run the demosaicing algorithm on the Bayer image, reading from a
+/// concrete source type.
+///
+/// This is equivalent to `demosaic_with_options`, but the reads are
+/// not dispatched through a trait object, which is faster for
+/// in-memory sources such as slices and cursors.
+///
+/// # Example
+///
+/// ```
+/// let width: usize = 320;
+/// let height: usize = 200;
+/// let img = vec![0; width * height];
+/// let mut buf = vec![0; 3 * width * height];
+///
+/// let mut dst = bayer::RasterMut::new(
+///         width, height, bayer::RasterDepth::Depth8,
+///         &mut buf);
+/// bayer::demosaic_from(&mut &img[..],
+///         bayer::BayerDepth::Depth8,
+///         bayer::CFA::RGGB,
+///         bayer::Demosaic::Linear,
+///         &bayer::DemosaicOptions::default(),
+///         &mut dst).unwrap();
+/// ```
+pub fn demosaic_from<R: Read>(r: &mut R,
+        depth: BayerDepth, cfa: CFA, alg: Demosaic,
+        options: &DemosaicOptions, dst: &mut RasterMut)
+        -> BayerResult<()> {
+    demosaic::run_engine(&alg.engine(), r, depth, cfa, options, dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp;
+    use std::io::Cursor;
+    use ::{BayerDepth,BayerError,BayerResult,Border,CFA,CfaPattern,Demosaic,DemosaicOptions,Demosaicer,PackedFormat,PlanarRasterMut,RasterDepth,RasterLayout,RasterMut,Rect,Region,StreamingDemosaicer,ToneCurve,Transform,WhiteBalance};
+
+    // Degenerate image sizes, which the public entry points must
+    // reject or process without panicking.
+    const SIZES: [(usize, usize); 8] = [
+        (0, 0), (0, 3), (1, 1), (2, 1), (1, 2), (2, 2), (3, 3), (5, 4) ];
+
+    // Image sizes whose sample counts or byte lengths overflow.
+    const HUGE_SIZES: [(usize, usize); 4] = [
+        (usize::MAX, 1), (1, usize::MAX), (usize::MAX / 2 + 1, 2), (4, usize::MAX / 4 + 1) ];
+
+    // Assert that a result is one of the given errors.
+    macro_rules! assert_err {
+        ($res:expr, $($p:pat)|+) => {
+            match $res {
+                $(Err($p))|+ => (),
+                Ok(_) => panic!("expected an error"),
+                Err(e) => panic!("unexpected error: {}", e),
+            }
+        }
+    }
+
+    // Whether the algorithm supports a w x h image with the border.
+    fn large_enough(alg: Demosaic, border: Option<Border>, w: usize, h: usize) -> bool {
+        let (mut min_width, mut min_height) = alg.min_dimensions();
+        if border == Some(Border::Mirror) {
+            min_width = cmp::max(min_width, alg.padding() + 1);
+            min_height = cmp::max(min_height, alg.padding() + 1);
+        }
+        w >= min_width && h >= min_height
+    }
+
+    // Assert that the image was processed if it is large enough, and
+    // rejected for its resolution otherwise.
+    fn check_size<T>(res: BayerResult<T>, large_enough: bool) {
+        if large_enough {
+            res.unwrap();
+        } else {
+            assert_err!(res, BayerError::WrongResolution { .. });
+        }
+    }
+
+    #[test]
+    fn test_degenerate_raw() {
+        let depths = [BayerDepth::Depth8, BayerDepth::Depth16LE, BayerDepth::Depth10Packed];
+
+        for &(w, h) in SIZES.iter() {
+            for &depth in depths.iter() {
+                let src = vec![0x80u8; depth.byte_len(w * h).unwrap()];
+                let mut dst = vec![0u8; 2 * src.len()];
+                let (cfa, rect) = (CFA::GRBG, Rect::new(0, 0, w, h));
+                let packed = depth == BayerDepth::Depth10Packed;
+
+                ::bin2x2(&src, depth, w, h, &mut dst).unwrap();
+                ::find_defective_lines(&src, depth, w, h).unwrap();
+                ::focus_measure(&src, depth, w, h, cfa).unwrap();
+                ::interpolate_green(&src, depth, w, h, cfa, &mut dst).unwrap();
+                ::luminance(&src, depth, w, h, cfa, &mut dst).unwrap();
+                ::bin_quad(&src, depth, w, h, &mut dst).unwrap();
+                ::remosaic_quad(&src, depth, w, h, cfa, &mut dst).unwrap();
+                ::white_balance_from_rect(&src, depth, w, h, cfa, rect, 0).unwrap();
+                ::apply_white_balance(&mut dst[..src.len()], depth, w, h, cfa,
+                        WhiteBalance::new(2.0, 1.0, 2.0), 0).unwrap();
+
+                // Packed rows cannot be rearranged in place.
+                let rearranged = [
+                    ::crop(&src, depth, w, h, cfa, rect, true, &mut dst).map(|_| ()),
+                    ::reorder_cfa(&src, depth, w, h, cfa, CFA::BGGR, &mut dst).map(|_| ()),
+                    ::split_planes(&src, depth, w, h, cfa, &mut dst).map(|_| ()),
+                    ::merge_planes(&src, depth, w / 2, h / 2, cfa, &mut dst).map(|_| ()),
+                    ::transform(&src, depth, w, h, cfa, Transform::Rotate90, &mut dst).map(|_| ()) ];
+                for (i, res) in rearranged.iter().enumerate() {
+                    match (i, res) {
+                        (_, &Err(BayerError::InvalidArgument { .. })) if packed => (),
+                        // Shifting the pattern by a row needs a row.
+                        (1, &Err(BayerError::OutOfBounds { .. })) if h == 0 => (),
+                        // The planes hold only the even rows and columns.
+                        (3, &Err(BayerError::TrailingData { .. }))
+                                if w * h > 0 && (w % 2 == 1 || h % 2 == 1) => (),
+                        (_, &Ok(_)) if !packed => (),
+                        (_, res) => panic!("unexpected result for {}x{} {:?}: {:?}", w, h, depth, res),
+                    }
+                }
+
+                let res = ::channel_stats(&mut &src[..], depth, w, h, cfa, &[50.0]);
+                if packed && w * h > 0 {
+                    assert_err!(res, BayerError::InvalidArgument { .. });
+                } else {
+                    res.unwrap();
+                }
+            }
+        }
+
+        let src = [0x80u8; 16];
+        let mut dst = [0u8; 32];
+        for &(w, h) in HUGE_SIZES.iter() {
+            for &depth in depths.iter() {
+                let (cfa, rect) = (CFA::GRBG, Rect::new(0, 0, 2, 2));
+                let packed = depth == BayerDepth::Depth10Packed;
+
+                assert!(::check_input_len(src.len(), depth, w, h).is_err());
+                assert_err!(::bin2x2(&src, depth, w, h, &mut dst), BayerError::InputTooShort { .. });
+                assert_err!(::find_defective_lines(&src, depth, w, h), BayerError::InputTooShort { .. });
+                assert_err!(::focus_measure(&src, depth, w, h, cfa), BayerError::InputTooShort { .. });
+                assert_err!(::interpolate_green(&src, depth, w, h, cfa, &mut dst),
+                        BayerError::InputTooShort { .. });
+                assert_err!(::luminance(&src, depth, w, h, cfa, &mut dst), BayerError::InputTooShort { .. });
+                assert_err!(::bin_quad(&src, depth, w, h, &mut dst), BayerError::InputTooShort { .. });
+                assert_err!(::remosaic_quad(&src, depth, w, h, cfa, &mut dst),
+                        BayerError::InputTooShort { .. });
+                assert_err!(::white_balance_from_rect(&src, depth, w, h, cfa, rect, 0),
+                        BayerError::InputTooShort { .. });
+                assert_err!(::apply_white_balance(&mut dst[..], depth, w, h, cfa,
+                        WhiteBalance::new(2.0, 1.0, 2.0), 0), BayerError::InputTooShort { .. });
+
+                let rearranged = [
+                    ::crop(&src, depth, w, h, cfa, rect, true, &mut dst).map(|_| ()),
+                    ::reorder_cfa(&src, depth, w, h, cfa, CFA::BGGR, &mut dst).map(|_| ()),
+                    ::split_planes(&src, depth, w, h, cfa, &mut dst).map(|_| ()),
+                    ::merge_planes(&src, depth, w / 2, h / 2, cfa, &mut dst).map(|_| ()),
+                    ::transform(&src, depth, w, h, cfa, Transform::Rotate90, &mut dst).map(|_| ()) ];
+                for (i, res) in rearranged.iter().enumerate() {
+                    match (i, res) {
+                        (_, &Err(BayerError::InvalidArgument { .. })) if packed => (),
+                        // Planes of no rows or columns leave the input unused.
+                        (3, &Err(BayerError::TrailingData { .. })) if w / 2 == 0 || h / 2 == 0 => (),
+                        (_, &Err(BayerError::InputTooShort { .. })) if !packed => (),
+                        (_, res) => panic!("unexpected result for {}x{} {:?}: {:?}", w, h, depth, res),
+                    }
+                }
+
+                // The statistics are read from a stream, which ends early.
+                assert_err!(::channel_stats(&mut &src[..], depth, w, h, cfa, &[50.0]),
+                        BayerError::InvalidArgument { .. } | BayerError::Io(_));
+            }
+
+            for &format in [PackedFormat::Raw10, PackedFormat::Raw12, PackedFormat::Raw14,
+                    PackedFormat::Dng12].iter() {
+                assert!(::unpack(&src, format, w.saturating_mul(h)).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn test_degenerate_demosaic() {
+        let depths = [
+            (BayerDepth::Depth8, RasterDepth::Depth8),
+            (BayerDepth::Depth16LE, RasterDepth::Depth16),
+            (BayerDepth::Depth16LE, RasterDepth::Depth32F) ];
+        let layouts = [
+            RasterLayout::Rgb, RasterLayout::Bgr,
+            RasterLayout::Rgba { alpha: 0xFFFF }, RasterLayout::Bgra { alpha: 0xFFFF } ];
+        let borders = [None, Some(Border::None), Some(Border::Replicate), Some(Border::Mirror)];
+        let patterns = [
+            CfaPattern::Mono, CfaPattern::QuadBayer(CFA::BGGR), CfaPattern::Rccb(CFA::RGGB),
+            CfaPattern::Rccc(CFA::GRBG), CfaPattern::Rgbw(CFA::BGGR), CfaPattern::cygm(),
+            CfaPattern::rgbe(), CfaPattern::xtrans() ];
+
+        for &(w, h) in SIZES.iter() {
+            for &(depth, raster_depth) in depths.iter() {
+                let src = vec![0x80u8; depth.byte_len(w * h).unwrap()];
+                let rect = Rect::new(0, 0, w, h);
+                let float = raster_depth == RasterDepth::Depth32F;
+                // Rasters cannot be empty.
+                let (raster_w, raster_h) = (cmp::max(w, 1), cmp::max(h, 1));
+
+                for (layout, misaligned) in layouts.iter().flat_map(|&l| vec![(l, false), (l, true)]) {
+                    let stride = layout.channels() * raster_depth.bytes_per_sample() * raster_w;
+                    let mut store = vec![0u8; stride * raster_h + 1];
+                    // Start the raster at an odd address if misaligned.
+                    let start = (misaligned as usize + store.as_ptr() as usize % 2) % 2;
+                    let buf = &mut store[start..(start + stride * raster_h)];
+                    let mut dst = match RasterMut::try_with_layout(0, 0, raster_w, raster_h, stride,
+                            raster_depth, layout, buf) {
+                        // 16-bit rasters must be aligned.
+                        Err(BayerError::InvalidArgument { .. })
+                                if misaligned && raster_depth == RasterDepth::Depth16 => continue,
+                        res => res.unwrap(),
+                    };
+                    let mut confidence = vec![0u8; w * h];
+
+                    for &alg in Demosaic::all().iter() {
+                        for &border in borders.iter() {
+                            let mut options = DemosaicOptions::default();
+                            options.border = border;
+                            let ok = large_enough(alg, border, w, h);
+
+                            check_size(::demosaic_from(&mut &src[..], depth, CFA::RGGB, alg, &options,
+                                    &mut dst), ok);
+                            check_size(::demosaic_slice(&src, depth, CFA::RGGB, alg, &options, &mut dst), ok);
+                            check_size(::demosaic_resized(&mut &src[..], depth, CFA::RGGB, alg, &options,
+                                    w, h, &mut dst), ok);
+
+                            let region = Region::new(w, h, rect);
+                            let res = ::demosaic_rect(&mut Cursor::new(&src[..]), depth, CFA::RGGB, alg,
+                                    &options, region, &mut dst);
+                            if w == 0 {
+                                assert_err!(res, BayerError::DimensionMismatch { .. });
+                            } else {
+                                check_size(res, ok);
+                            }
+                            let res = ::demosaic_roi(&mut &src[..], depth, CFA::RGGB, alg, &options,
+                                    region, &mut dst);
+                            if w == 0 {
+                                assert_err!(res, BayerError::DimensionMismatch { .. });
+                            } else {
+                                check_size(res, ok);
+                            }
+
+                            let res = ::demosaic_with_confidence(&mut &src[..], depth, CFA::RGGB, alg,
+                                    &options, &mut dst, &mut confidence);
+                            if w * h == 0 {
+                                assert_err!(res, BayerError::OutputTooShort { .. });
+                            } else if ok && float {
+                                assert_err!(res, BayerError::InvalidArgument { .. });
+                            } else {
+                                check_size(res, ok);
+                            }
+
+                            // The comparison uses the default borders.
+                            let res = ::compare_algorithms(&src, depth, CFA::RGGB, alg, Demosaic::Linear,
+                                    &mut dst);
+                            if float {
+                                assert_err!(res, BayerError::InvalidArgument { .. });
+                            } else {
+                                check_size(res, large_enough(alg, None, w, h)
+                                        && large_enough(Demosaic::Linear, None, w, h));
+                            }
+
+                            for pattern in patterns.iter() {
+                                let res = ::demosaic_pattern(&mut &src[..], depth, pattern, alg,
+                                        &options, &mut dst);
+                                match *pattern {
+                                    CfaPattern::Mono => check_size(res, large_enough(Demosaic::None,
+                                            border, w, h)),
+                                    // The image is read as a raster of samples.
+                                    CfaPattern::QuadBayer(_) if w * h == 0 =>
+                                        assert_err!(res, BayerError::Io(_)),
+                                    CfaPattern::QuadBayer(_) => check_size(res, ok),
+                                    CfaPattern::XTrans(_) => check_size(res, false),
+                                    _ => match alg {
+                                        Demosaic::None | Demosaic::NearestNeighbour | Demosaic::Linear =>
+                                            check_size(res, w >= 2 && h >= 2),
+                                        _ => assert_err!(res, BayerError::InvalidArgument { .. }),
+                                    },
+                                }
+                            }
+
+                            let demosaicer = Demosaicer::new().depth(depth).algorithm(alg)
+                                    .options(options).build();
+                            check_size(demosaicer.run_from(&mut &src[..], &mut dst), ok);
+                            check_size(demosaicer.run_strips(&mut &src[..], w, h, 1, |_, _| Ok(())), ok);
+                            let res = StreamingDemosaicer::new(&demosaicer, w, h)
+                                    .and_then(|mut stream| stream.push_into(&src, &mut dst));
+                            check_size(res, ok);
+                        }
+                    }
+
+                    let ok = large_enough(Demosaic::Linear, None, w, h);
+                    check_size(::demosaic_pyramid(&mut &src[..], depth, CFA::RGGB, Demosaic::Linear,
+                            &DemosaicOptions::default(), &mut [dst]), ok);
+                    let mut dst = RasterMut::try_with_layout(0, 0, raster_w, raster_h, stride,
+                            raster_depth, layout, buf).unwrap();
+                    check_size(::preview(&mut &src[..], depth, CFA::RGGB, w, h, &mut dst), ok);
+
+                    let res = ::tone_mapped_preview(&mut &src[..], depth, CFA::RGGB, 8,
+                            ToneCurve::Srgb, &mut dst);
+                    if raster_depth == RasterDepth::Depth8 {
+                        check_size(res, ok);
+                    } else {
+                        assert_err!(res, BayerError::WrongDepth { .. });
+                    }
+
+                    let res = ::confidence_map(&dst.as_raster(), &mut confidence);
+                    if w * h == 0 {
+                        assert_err!(res, BayerError::OutputTooShort { .. });
+                    } else if float {
+                        assert_err!(res, BayerError::InvalidArgument { .. });
+                    } else {
+                        res.unwrap();
+                    }
+
+                    let res = ::mosaic(&dst.as_raster(), CFA::RGGB, depth, &mut Vec::new());
+                    if float {
+                        assert_err!(res, BayerError::WrongDepth { .. });
+                    } else {
+                        res.unwrap();
+                    }
+                }
+
+                let bytes = raster_depth.bytes_per_sample() * w * h;
+                let (mut r, mut g, mut b) = (vec![0u8; bytes], vec![0u8; bytes], vec![0u8; bytes]);
+                if let Ok(mut dst) = PlanarRasterMut::try_new(w, h, raster_depth, [&mut r, &mut g, &mut b]) {
+                    check_size(::demosaic_planar(&mut &src[..], depth, CFA::RGGB, Demosaic::Linear,
+                            &DemosaicOptions::default(), &mut dst), w >= 2 && h >= 2);
+                }
+            }
+        }
+
+        let src = [0x80u8; 16];
+        for &(w, h) in HUGE_SIZES.iter() {
+            for &(depth, _) in depths.iter() {
+                for &alg in Demosaic::all().iter() {
+                    let demosaicer = Demosaicer::new().depth(depth).algorithm(alg).build();
+                    let res = demosaicer.run_strips(&mut &src[..], w, h, 1, |_, _| Ok(()));
+                    if !large_enough(alg, None, w, h) {
+                        assert_err!(res, BayerError::WrongResolution { .. });
+                    } else if w.checked_mul(RasterDepth::Depth16.bytes_per_pixel()).is_none() {
+                        assert_err!(res, BayerError::InvalidArgument { reason: "image dimensions overflow" });
+                    } else {
+                        // The rows fit in memory, but the input ends early.
+                        assert_err!(res, BayerError::Io(_));
+                    }
+                    assert_err!(StreamingDemosaicer::new(&demosaicer, w, h),
+                            BayerError::WrongResolution { .. } | BayerError::InvalidArgument { .. });
+                }
+            }
+        }
     }
 }
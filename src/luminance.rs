@@ -0,0 +1,94 @@
+//! Luminance estimates from Bayer images.
+
+use ::{BayerDepth,BayerResult,CFA};
+use bayer::{check_input_len,check_output_len,get_sample,put_sample};
+use planes::plane_offsets;
+
+/// Weights of the R, G1, G2, B samples, out of 256.
+///
+/// These are the Rec. 601 luma coefficients, with the green weight
+/// split between the two green samples.
+const WEIGHTS: [u32; 4] = [77, 75, 75, 29];
+
+/// Estimate the luminance of a w x h Bayer image without
+/// demosaicing it, producing one sample for each 2x2 block.
+///
+/// The four samples of each block are weighted by their colour.  The
+/// output has the same depth and byte order as the source.  If w or
+/// h is odd, the last column or row is dropped.  Returns the
+/// dimensions of the luminance image, (w / 2, h / 2).
+///
+/// # Example
+///
+/// ```
+/// let src = [
+///     100,100,
+///     100,100 ];
+/// let mut dst = [0u8; 1];
+///
+/// let (w, h) = bayer::luminance(&src, bayer::BayerDepth::Depth8, 2, 2,
+///         bayer::CFA::RGGB, &mut dst).unwrap();
+/// assert_eq!((w, h), (1, 1));
+/// assert_eq!(dst, [100]);
+/// ```
+pub fn luminance(src: &[u8], depth: BayerDepth, w: usize, h: usize,
+        cfa: CFA, dst: &mut [u8])
+        -> BayerResult<(usize, usize)> {
+    check_input_len(src.len(), depth, w, h)?;
+
+    let (dst_w, dst_h) = (w / 2, h / 2);
+    check_output_len(dst.len(), depth, dst_w, dst_h)?;
+
+    let offsets = plane_offsets(cfa);
+
+    for y in 0..dst_h {
+        for x in 0..dst_w {
+            let mut sum = 128;
+            for (&(dx, dy), &k) in offsets.iter().zip(WEIGHTS.iter()) {
+                sum = sum + k * get_sample(src, depth, w * (2 * y + dy) + 2 * x + dx);
+            }
+
+            put_sample(dst, depth, dst_w * y + x, sum >> 8);
+        }
+    }
+
+    Ok((dst_w, dst_h))
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{BayerDepth,CFA};
+    use super::luminance;
+
+    #[test]
+    fn test_luminance() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+
+        // BGGR: (29 * b + 75 * g1 + 75 * g2 + 77 * r + 128) / 256
+        let expected = [
+            129,193,
+            132,105 ];
+
+        let mut dst = [0u8; 4];
+        let res = luminance(&src, BayerDepth::Depth8, 4, 4, CFA::BGGR, &mut dst);
+        assert_eq!(res.unwrap(), (2, 2));
+        assert_eq!(dst, expected);
+    }
+
+    #[test]
+    fn test_luminance_u16() {
+        let src = [
+            0xFF,0xFF, 0xFF,0xFF, 0x00,0x00,
+            0xFF,0xFF, 0xFF,0xFF, 0x00,0x00 ];
+        let mut dst = [0u8; 2];
+
+        let res = luminance(&src, BayerDepth::Depth16BE, 3, 2, CFA::GRBG, &mut dst);
+        assert_eq!(res.unwrap(), (1, 1));
+        assert_eq!(dst, [0xFF,0xFF]);
+    }
+}
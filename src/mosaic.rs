@@ -0,0 +1,315 @@
+//! Mosaicing, i.e. converting RGB images into Bayer images.
+
+use std::io::Write;
+use byteorder::{BigEndian,LittleEndian,WriteBytesExt};
+
+use ::{BayerDepth,BayerResult,CFA,Raster,pack};
+use bayer::check_packing;
+use demosaic::check_depth;
+
+/// Sensor noise added to a simulated Bayer image.
+///
+/// Shot noise is drawn from a Poisson distribution with the signal
+/// converted to electrons, then read noise is drawn from a Gaussian
+/// distribution.  The same seed always produces the same noise.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Noise {
+    /// Electrons per sample value, for the shot noise.  Zero disables
+    /// the shot noise.
+    pub gain: f64,
+
+    /// Standard deviation of the read noise, in sample values.
+    pub read: f64,
+
+    /// Seed of the random number generator.
+    pub seed: u64,
+}
+
+/// Options controlling the mosaicing process.
+///
+/// New options may be added in future releases.  Construct the
+/// options with `MosaicOptions::default()` and then override the
+/// fields of interest.
+#[derive(Clone,Debug,Default,PartialEq)]
+#[non_exhaustive]
+pub struct MosaicOptions {
+    /// The value added to every sample, as the black level of a
+    /// sensor.  Samples are clipped to the range of the depth.
+    pub black_level: u16,
+
+    /// Noise added to the samples before the black level.
+    pub noise: Option<Noise>,
+}
+
+/// Pseudorandom number generator for the noise, splitmix64.
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A uniform sample in (0, 1].
+    fn uniform(&mut self) -> f64 {
+        ((self.next_u64() >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A standard normal sample, by the Box-Muller transform.
+    fn gaussian(&mut self) -> f64 {
+        let (u1, u2) = (self.uniform(), self.uniform());
+        (-2.0 * u1.ln()).sqrt() * (2.0 * ::std::f64::consts::PI * u2).cos()
+    }
+
+    /// A Poisson sample with the given mean.  Large means use the
+    /// normal approximation.
+    fn poisson(&mut self, mean: f64) -> f64 {
+        if mean <= 0.0 {
+            0.0
+        } else if mean < 30.0 {
+            let limit = (-mean).exp();
+            let mut k = 0.0;
+            let mut p = self.uniform();
+            while p > limit {
+                k = k + 1.0;
+                p = p * self.uniform();
+            }
+            k
+        } else {
+            (mean + mean.sqrt() * self.gaussian()).round().max(0.0)
+        }
+    }
+}
+
+impl Noise {
+    fn apply(&self, rng: &mut Rng, v: f64) -> f64 {
+        let v = if self.gain > 0.0 {
+            rng.poisson(v * self.gain) / self.gain
+        } else {
+            v
+        };
+
+        if self.read > 0.0 {
+            v + self.read * rng.gaussian()
+        } else {
+            v
+        }
+    }
+}
+
+/// Convert an RGB raster into a Bayer image, writing the raw data to
+/// w in the given depth and endianness.
+///
+/// This is the inverse of `Demosaic::None`, and is useful for
+/// generating test data and simulating sensors.  Packed depths keep
+/// the most significant bits of the 16-bit raster samples.
+///
+/// # Example
+///
+/// ```
+/// let rgb = [
+///     10,11,12,  20,21,22,
+///     30,31,32,  40,41,42 ];
+/// let mut raw = Vec::new();
+///
+/// bayer::mosaic(&bayer::Raster::new(2, 2, bayer::RasterDepth::Depth8, &rgb),
+///         bayer::CFA::RGGB, bayer::BayerDepth::Depth8,
+///         &mut raw).unwrap();
+/// assert_eq!(raw, [10, 21, 31, 42]);
+/// ```
+pub fn mosaic(src: &Raster, cfa: CFA, depth: BayerDepth, w: &mut Write)
+        -> BayerResult<()> {
+    mosaic_with_options(src, cfa, depth, &MosaicOptions::default(), w)
+}
+
+/// Convert an RGB raster into a Bayer image, with additional options
+/// to simulate a sensor.
+///
+/// # Example
+///
+/// ```
+/// let rgb = [0u8; 3 * 4 * 4];
+/// let mut options = bayer::MosaicOptions::default();
+/// options.black_level = 64;
+/// options.noise = Some(bayer::Noise { gain: 1.0, read: 2.0, seed: 0 });
+///
+/// let mut raw = Vec::new();
+/// bayer::mosaic_with_options(
+///         &bayer::Raster::new(4, 4, bayer::RasterDepth::Depth8, &rgb),
+///         bayer::CFA::RGGB, bayer::BayerDepth::Depth8,
+///         &options, &mut raw).unwrap();
+/// ```
+pub fn mosaic_with_options(src: &Raster, cfa: CFA, depth: BayerDepth,
+        options: &MosaicOptions, w: &mut Write)
+        -> BayerResult<()> {
+    check_depth(depth, src.depth)?;
+    check_packing(depth, src.w)?;
+
+    let max = match depth {
+        BayerDepth::Depth8 => 255.0,
+        BayerDepth::Depth16BE | BayerDepth::Depth16LE => 65535.0,
+        BayerDepth::Depth10Packed => 1023.0,
+        BayerDepth::Depth12Packed | BayerDepth::Depth12PackedDng => 4095.0,
+        BayerDepth::Depth14Packed => 16383.0,
+    };
+    let black = options.black_level as f64;
+    let mut rng = Rng(options.noise.map_or(0, |n| n.seed));
+    let mut simulate = |v: u16| -> u16 {
+        match options.noise {
+            Some(ref noise) => (noise.apply(&mut rng, v as f64) + black).round().max(0.0).min(max) as u16,
+            None => (v as f64 + black).min(max) as u16,
+        }
+    };
+
+    let mut row = Vec::with_capacity(depth.bytes_per_sample() * src.w);
+    let mut rgb = Vec::with_capacity(3 * src.w);
+    let mut samples = Vec::with_capacity(src.w);
+    let mut cfa = cfa;
+
+    for y in 0..src.h {
+        let mut cfa_x = cfa;
+        row.clear();
+        src.read_rgb_row(y, &mut rgb);
+
+        match depth {
+            BayerDepth::Depth8 => {
+                for px in rgb.chunks(3) {
+                    row.push(simulate(px[cfa_x.channel()]) as u8);
+                    cfa_x = cfa_x.next_x();
+                }
+            },
+            BayerDepth::Depth16BE => {
+                for px in rgb.chunks(3) {
+                    row.write_u16::<BigEndian>(simulate(px[cfa_x.channel()]))?;
+                    cfa_x = cfa_x.next_x();
+                }
+            },
+            BayerDepth::Depth16LE => {
+                for px in rgb.chunks(3) {
+                    row.write_u16::<LittleEndian>(simulate(px[cfa_x.channel()]))?;
+                    cfa_x = cfa_x.next_x();
+                }
+            },
+            BayerDepth::Depth10Packed | BayerDepth::Depth12Packed | BayerDepth::Depth12PackedDng
+                | BayerDepth::Depth14Packed => {
+                // Every packed depth has a packed format.
+                let format = depth.packed().unwrap();
+                let shift = 16 - format.bits();
+                samples.clear();
+                for px in rgb.chunks(3) {
+                    samples.push(simulate(px[cfa_x.channel()] >> shift));
+                    cfa_x = cfa_x.next_x();
+                }
+                row.extend_from_slice(&pack(&samples, format));
+            },
+        }
+
+        w.write_all(&row)?;
+        cfa = cfa.next_y();
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use ::{BayerDepth,CFA,Demosaic,Raster,RasterDepth,RasterLayout,RasterMut,run_demosaic};
+    use super::{MosaicOptions,Noise,mosaic,mosaic_with_options};
+
+    #[test]
+    fn test_round_trip() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+
+        const IMG_W: usize = 4;
+        const IMG_H: usize = 4;
+        let mut buf = [0u8; 3 * IMG_W * IMG_H];
+
+        for &cfa in [CFA::BGGR, CFA::GBRG, CFA::GRBG, CFA::RGGB].iter() {
+            let mut dst = RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf);
+            let res = run_demosaic(&mut Cursor::new(&src[..]),
+                    BayerDepth::Depth8, cfa, Demosaic::Linear, &mut dst);
+            assert!(res.is_ok());
+
+            let mut raw = Vec::new();
+            let res = mosaic(&dst.as_raster(), cfa, BayerDepth::Depth8, &mut raw);
+            assert!(res.is_ok());
+            assert_eq!(&raw[..], &src[..]);
+        }
+
+        let mut buf = [0u8; 4 * IMG_W * IMG_H];
+        let mut dst = RasterMut::try_with_layout(0, 0, IMG_W, IMG_H, 4 * IMG_W, RasterDepth::Depth8,
+                RasterLayout::Bgra { alpha: 0xFFFF }, &mut buf).unwrap();
+        let res = run_demosaic(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::GRBG, Demosaic::Linear, &mut dst);
+        assert!(res.is_ok());
+
+        let mut raw = Vec::new();
+        let res = mosaic(&dst.as_raster(), CFA::GRBG, BayerDepth::Depth8, &mut raw);
+        assert!(res.is_ok());
+        assert_eq!(&raw[..], &src[..]);
+    }
+
+    #[test]
+    fn test_u16() {
+        let rgb: [u16; 6] = [
+            0x0102,0x0304,0x0506,  0x0708,0x090A,0x0B0C ];
+
+        let mut buf = [0u8; 12];
+        for (i, v) in rgb.iter().enumerate() {
+            buf[2 * i..2 * i + 2].copy_from_slice(&v.to_ne_bytes());
+        }
+        let src = Raster::new(2, 1, RasterDepth::Depth16, &buf);
+
+        let mut raw = Vec::new();
+        let res = mosaic(&src, CFA::GRBG, BayerDepth::Depth16BE, &mut raw);
+        assert!(res.is_ok());
+        assert_eq!(raw, [0x03,0x04, 0x07,0x08]);
+
+        let mut raw = Vec::new();
+        let res = mosaic(&src, CFA::GRBG, BayerDepth::Depth16LE, &mut raw);
+        assert!(res.is_ok());
+        assert_eq!(raw, [0x04,0x03, 0x08,0x07]);
+
+        let res = mosaic(&src, CFA::GRBG, BayerDepth::Depth8, &mut Vec::new());
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_sensor() {
+        let rgb = [100u8; 3 * 64 * 64];
+        let src = Raster::new(64, 64, RasterDepth::Depth8, &rgb);
+
+        let mut options = MosaicOptions::default();
+        options.black_level = 200;
+        let mut raw = Vec::new();
+        let res = mosaic_with_options(&src, CFA::RGGB, BayerDepth::Depth8, &options, &mut raw);
+        assert!(res.is_ok());
+        assert!(raw.iter().all(|&v| v == 255));
+
+        options.black_level = 16;
+        options.noise = Some(Noise { gain: 4.0, read: 1.5, seed: 1 });
+        let mut raw1 = Vec::new();
+        let mut raw2 = Vec::new();
+        let res = mosaic_with_options(&src, CFA::RGGB, BayerDepth::Depth8, &options, &mut raw1);
+        assert!(res.is_ok());
+        let res = mosaic_with_options(&src, CFA::RGGB, BayerDepth::Depth8, &options, &mut raw2);
+        assert!(res.is_ok());
+        assert_eq!(raw1, raw2);
+
+        // Variance of the shot noise is 100 / 4, plus 1.5^2 read noise.
+        let n = raw1.len() as f64;
+        let mean = raw1.iter().map(|&v| v as f64).sum::<f64>() / n;
+        let var = raw1.iter().map(|&v| (v as f64 - mean).powi(2)).sum::<f64>() / n;
+        assert!((mean - 116.0).abs() < 0.5);
+        assert!((var - 27.25).abs() < 3.0);
+    }
+}
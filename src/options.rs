@@ -0,0 +1,717 @@
+//! Advanced demosaicing options.
+
+use std::fmt;
+use std::sync::{Arc,Mutex,PoisonError};
+use std::sync::atomic::{AtomicBool,Ordering};
+use std::time::Duration;
+
+use ::{BayerDepth,CFA,Demosaic,Transform,WhiteBalance};
+
+/// How the pixels beyond the edges of the image are synthesised for
+/// the interpolation kernels.
+///
+/// For example, given a row of raw data:
+///
+/// ```text
+///   None:       0   0   0   0  | r0 g0 r1 g1 ... rm gm rn gn | 0   0   0   0
+///   Replicate:  r0' g0' r0' g0' | r0 g0 r1 g1 ... rm gm rn gn | rn' gn' rn' gn'
+///   Mirror:     r2' g1' r1' g0' | r0 g0 r1 g1 ... rm gm rn gn | rn' gm' rm' gl'
+/// ```
+///
+/// The same logic is applied to the rows above and below the image.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum Border {
+    /// Pixels beyond the image are zero.
+    None,
+    /// Replicate the 2x2 block on the edge of the image.
+    Replicate,
+    /// Mirror the image about the pixels on the edge.  The image must
+    /// be larger than the padding of the algorithm in each dimension,
+    /// see `Demosaic::padding`.
+    Mirror,
+}
+
+/// The value written into the missing channels by `Demosaic::None`.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum Fill {
+    /// Fill with zero, leaving each pixel a pure red, green, or blue.
+    Zero,
+    /// Fill with half of the maximum sample value.
+    MidGray,
+    /// Copy the raw sample into all channels, producing a grayscale
+    /// image of the mosaic.
+    Replicate,
+}
+
+/// What to do when the Bayer image ends early.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum Truncation {
+    /// Abort with the read error.
+    Error,
+    /// Fill each missing row with the last complete row of the same
+    /// colours.
+    Replicate,
+    /// Fill the missing rows with the given raw sample value.
+    Constant(u16),
+}
+
+/// What to do when a frame exceeds its deadline.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum Overrun {
+    /// Stop, leaving the remaining rows of the raster unwritten.
+    Abort,
+    /// Finish the remaining rows with nearest neighbour
+    /// interpolation, or the requested algorithm if it is no more
+    /// expensive.
+    Degrade,
+}
+
+/// The treatment of samples at or above the white level, i.e. the
+/// samples of photosites that were saturated.
+///
+/// A saturated sample no longer measures the light falling on it, so
+/// interpolating it like any other sample spreads the clipped value
+/// into the neighbouring pixels as coloured halos around highlights.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum Saturation {
+    /// Interpolate saturated samples like any other.
+    Interpolate,
+    /// Clamp the samples to the given white level before
+    /// interpolation.
+    Clip(u16),
+    /// Set every channel of the pixels whose interpolation uses a
+    /// sample at or above the given white level to the white level,
+    /// so that they can be recognised and repaired later.
+    Sentinel(u16),
+    /// Exclude the samples at or above the given white level from the
+    /// neighbour averages.  The pixels whose interpolation uses such a
+    /// sample are interpolated again from the unsaturated samples of
+    /// their 3x3 neighbourhood; a channel with no unsaturated samples
+    /// is set to the white level.
+    Exclude(u16),
+}
+
+/// How calibrated samples that fall below zero are held during
+/// interpolation.
+///
+/// Subtracting a dark frame leaves the dark pixels scattered around
+/// zero.  Clamping them before interpolation biases the mean upwards
+/// and narrows the noise distribution.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum Intermediate {
+    /// Clamp the calibrated samples to zero before interpolation.
+    Unsigned,
+    /// Keep the negative samples through interpolation, and clamp only
+    /// the output.  The samples are held in 32 bits, with an offset
+    /// equal to the largest value of the dark frame, so the highlights
+    /// keep their full range.
+    Signed,
+}
+
+/// The black and white levels of a sensor.
+///
+/// The black level of each photosite is subtracted from its raw
+/// sample, and the range between the black and white levels is
+/// stretched to the full range of the image depth, i.e. 255 for 8-bit
+/// images and 65535 for the others.  Samples at or above the white
+/// level become the largest sample value.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub struct Levels {
+    /// The black levels of the top-left 2x2 block of photosites, in
+    /// row-major order, i.e. in the order of the CFA name.  For RGGB,
+    /// the red, green, green, and blue black levels.
+    pub black: [u16; 4],
+    /// The white level, above every black level.
+    pub white: u16,
+}
+
+/// The rounding of 16-bit samples narrowed into 8-bit rasters.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum Narrowing {
+    /// Discard the bits shifted out.
+    Truncate,
+    /// Round to the nearest 8-bit value.
+    Round,
+    /// Add a 4x4 ordered dither pattern before discarding the bits
+    /// shifted out, which breaks up the banding of smooth gradients.
+    Dither,
+}
+
+/// The scaling of the raw samples, as recorded in a processing
+/// report.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum Scaling {
+    /// The samples were used as read.
+    Identity,
+    /// The samples were scaled up from their significant bits to the
+    /// full 16-bit range.
+    SourceBits,
+    /// The samples of an 8-bit image were widened to 16 bits.
+    Widen {
+        /// The left shift applied to the samples.
+        shift: u8,
+        /// Whether the low bits were filled with copies of the high
+        /// bits.
+        replicate: bool,
+    },
+}
+
+/// Options controlling the demosaicing process.
+///
+/// New options may be added in future releases.  Construct the
+/// options with `DemosaicOptions::default()` and then override the
+/// fields of interest.
+///
+/// # Example
+///
+/// ```
+/// let mut options = bayer::DemosaicOptions::default();
+/// options.border = Some(bayer::Border::Mirror);
+/// options.parallel = false;
+/// ```
+#[derive(Clone,Debug,Eq,PartialEq)]
+#[non_exhaustive]
+pub struct DemosaicOptions {
+    /// The border handling.  If None, each algorithm uses its own
+    /// default: replicate for nearest neighbour and linear
+    /// interpolation, and mirror for cubic interpolation.
+    pub border: Option<Border>,
+
+    /// Hint to process the rows in parallel.
+    ///
+    /// This is only effective when the crate is built with the rayon
+    /// feature.
+    pub parallel: bool,
+
+    /// The thread pool used when the rows are processed in parallel.
+    /// If None, rayon's global pool is used.
+    #[cfg(feature = "rayon")]
+    pub thread_pool: Option<ThreadPool>,
+
+    /// Process the image in tiles of this many columns and rows, for
+    /// better use of the cache on very large images.  The image is
+    /// read one band of tiles at a time, so only the Bayer rows of
+    /// the current band and its padding are held in memory, and the
+    /// tiles of each band are processed in parallel if requested.
+    /// This is ignored if there is an output transform.
+    pub tile_size: Option<(usize, usize)>,
+
+    /// If the image is too small for the algorithm, fall back to the
+    /// most expensive cheaper algorithm that supports it, e.g. from
+    /// cubic to linear interpolation, instead of returning
+    /// BayerError::WrongResolution.
+    pub fallback: bool,
+
+    /// The value of the missing channels when no interpolation is
+    /// performed.
+    pub fill: Fill,
+
+    /// Callback receiving the number of rows completed so far.
+    pub progress: Option<Progress>,
+
+    /// Token used to abort the demosaicing process.
+    pub cancel: Option<CancelToken>,
+
+    /// The handling of images that end early.  Unless this is
+    /// Truncation::Error, the missing rows are filled in, the whole
+    /// raster is written, and BayerError::Truncated reports the
+    /// number of missing rows.
+    pub truncation: Truncation,
+
+    /// Recorder for the timing of each frame.
+    pub timing: Option<Timing>,
+
+    /// Time budget for each frame, checked between batches of rows.
+    /// When it is exceeded, the overrun option decides whether the
+    /// frame is abandoned or finished more cheaply; either way,
+    /// BayerError::DeadlineExceeded reports the first row that was
+    /// not processed with the requested algorithm.
+    pub deadline: Option<Duration>,
+
+    /// The handling of frames that exceed the deadline.
+    pub overrun: Overrun,
+
+    /// Rotate or flip the output as it is written, instead of
+    /// transforming the raster afterwards.  The raster must have the
+    /// dimensions of the transformed image, see
+    /// `Transform::dimensions`.  The rows are then processed
+    /// sequentially.  This only applies to the functions that write
+    /// a whole image into a raster.
+    pub transform: Option<Transform>,
+
+    /// The number of significant bits in the samples of 16-bit
+    /// images, e.g. 12 for 12-bit data stored in 16-bit words.  The
+    /// samples are clamped to this many bits and scaled up to the full
+    /// 16-bit range as they are read, so that the output is not
+    /// unexpectedly dark.  This is ignored for 8-bit images.  Packed
+    /// samples never have more bits than their packing, so packed
+    /// images are always scaled.
+    pub source_bits: u8,
+
+    /// The divisor of the samples written to floating-point rasters.
+    /// If None, the largest sample value: 255 for 8-bit images and
+    /// 65535 for the others, including images scaled according to
+    /// source_bits, giving values in [0, 1].  Use 1 to write the
+    /// sample values unchanged.  Must not be zero.
+    pub float_divisor: Option<u32>,
+
+    /// The left shift applied to the samples of 8-bit images
+    /// demosaiced into 16-bit rasters, at most 8.  The default of 8
+    /// maps 255 to 0xFF00.
+    pub widen_shift: u8,
+
+    /// Fill the low bits of the widened samples with copies of their
+    /// high bits, so that with the default shift 255 maps to 0xFFFF.
+    pub widen_replicate: bool,
+
+    /// The right shift applied to the samples of 16-bit images
+    /// demosaiced into 8-bit rasters, at most 8.  The default of 8
+    /// keeps the high byte; with smaller shifts, the samples saturate
+    /// at 255.
+    pub narrow_shift: u8,
+
+    /// The rounding of the samples of 16-bit images demosaiced into
+    /// 8-bit rasters.
+    pub narrowing: Narrowing,
+
+    /// The treatment of saturated samples.  The white level applies
+    /// to the calibrated samples.
+    pub saturation: Saturation,
+
+    /// The black and white levels of the raw samples, applied as the
+    /// rows are read.  This replaces the scaling of source_bits, and
+    /// cannot be combined with a dark frame, which already includes
+    /// the black level.
+    pub levels: Option<Levels>,
+
+    /// White balance multipliers applied to the raw samples as they
+    /// are read, after the levels and the dark frame, so that edges
+    /// between neutral and coloured areas are interpolated without
+    /// zippering.  The balanced samples saturate at the largest
+    /// sample value, and the white level of the saturation option
+    /// applies to them.  Only Bayer images can be balanced before
+    /// interpolation.
+    pub white_balance: Option<WhiteBalance>,
+
+    /// A dark frame of w * h raw values in row-major order, subtracted
+    /// from the samples of each image as they are read.  The dark
+    /// frame is scaled along with the samples, see source_bits.
+    pub dark_frame: Option<Arc<Vec<u16>>>,
+
+    /// The handling of calibrated samples below zero.
+    pub intermediate: Intermediate,
+
+    /// Recorder for a description of how each frame was processed.
+    /// This only applies to the functions that write a whole image
+    /// into a raster.
+    pub report: Option<Report>,
+}
+
+impl Default for DemosaicOptions {
+    fn default() -> Self {
+        DemosaicOptions {
+            border: None,
+            parallel: cfg!(feature = "rayon"),
+            #[cfg(feature = "rayon")]
+            thread_pool: None,
+            tile_size: None,
+            fallback: false,
+            fill: Fill::Zero,
+            progress: None,
+            cancel: None,
+            truncation: Truncation::Error,
+            timing: None,
+            deadline: None,
+            overrun: Overrun::Abort,
+            transform: None,
+            source_bits: 16,
+            float_divisor: None,
+            widen_shift: 8,
+            widen_replicate: false,
+            narrow_shift: 8,
+            narrowing: Narrowing::Round,
+            saturation: Saturation::Interpolate,
+            levels: None,
+            white_balance: None,
+            dark_frame: None,
+            intermediate: Intermediate::Unsigned,
+            report: None,
+        }
+    }
+}
+
+/// Progress callback, called with the number of rows completed after
+/// each batch of rows.
+///
+/// # Example
+///
+/// ```
+/// let mut options = bayer::DemosaicOptions::default();
+/// options.progress = Some(bayer::Progress::new(|rows| {
+///     println!("{} rows done", rows);
+/// }));
+/// ```
+#[derive(Clone)]
+pub struct Progress(Arc<Fn(usize) + Send + Sync>);
+
+impl Progress {
+    /// Wrap a closure as a progress callback.
+    pub fn new<F>(f: F) -> Self
+            where F: Fn(usize) + Send + Sync + 'static {
+        Progress(Arc::new(f))
+    }
+
+    /// Report that rows have been completed.
+    pub fn report(&self, rows: usize) {
+        (self.0)(rows)
+    }
+}
+
+impl fmt::Debug for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Progress")
+    }
+}
+
+/// Two callbacks are equal if they are clones of each other.
+impl PartialEq for Progress {
+    fn eq(&self, other: &Progress) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Progress {}
+
+/// Cancellation token, checked between batches of rows.
+///
+/// Clones of a token share the same state, so a token can be handed
+/// to the demosaicing thread while another thread cancels it.  A
+/// cancelled run returns BayerError::Cancelled, leaving the raster
+/// partially written.
+///
+/// # Example
+///
+/// ```
+/// let token = bayer::CancelToken::new();
+///
+/// let mut options = bayer::DemosaicOptions::default();
+/// options.cancel = Some(token.clone());
+///
+/// token.cancel();
+/// assert!(options.cancel.unwrap().is_cancelled());
+/// ```
+#[derive(Clone,Debug,Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Create a token that has not been cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request that the demosaicing process stops.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Check whether cancellation has been requested.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Two tokens are equal if they are clones of each other.
+impl PartialEq for CancelToken {
+    fn eq(&self, other: &CancelToken) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for CancelToken {}
+
+/// Thread pool on which the rows are processed in parallel, instead
+/// of rayon's global pool.
+///
+/// Clones of a pool share the same threads.
+///
+/// # Example
+///
+/// ```
+/// # extern crate bayer;
+/// # extern crate rayon;
+/// # fn main() {
+/// let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+///
+/// let mut options = bayer::DemosaicOptions::default();
+/// options.thread_pool = Some(bayer::ThreadPool::new(pool));
+/// # }
+/// ```
+#[cfg(feature = "rayon")]
+#[derive(Clone)]
+pub struct ThreadPool(Arc<::rayon::ThreadPool>);
+
+#[cfg(feature = "rayon")]
+impl ThreadPool {
+    /// Wrap a rayon thread pool.
+    pub fn new(pool: ::rayon::ThreadPool) -> Self {
+        ThreadPool(Arc::new(pool))
+    }
+
+    /// Run f on the pool, or on the global pool if there is none.
+    pub(crate) fn install<G, T>(pool: &Option<ThreadPool>, f: G) -> T
+            where G: FnOnce() -> T + Send, T: Send {
+        match *pool {
+            Some(ref pool) => pool.0.install(f),
+            None => f(),
+        }
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl fmt::Debug for ThreadPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ThreadPool")
+    }
+}
+
+/// Two pools are equal if they are clones of each other.
+#[cfg(feature = "rayon")]
+impl PartialEq for ThreadPool {
+    fn eq(&self, other: &ThreadPool) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl Eq for ThreadPool {}
+
+/// The time spent on each phase of demosaicing one frame.
+#[derive(Clone,Copy,Debug,Default,Eq,PartialEq)]
+pub struct FrameTiming {
+    /// Time spent reading and unpacking the raw data.
+    pub read: Duration,
+    /// Time spent in the interpolation kernels.
+    pub interpolate: Duration,
+    /// The number of raw bytes in the frame.
+    pub bytes: u64,
+}
+
+impl FrameTiming {
+    /// The total time spent on the frame.
+    pub fn total(&self) -> Duration {
+        self.read + self.interpolate
+    }
+
+    /// The throughput of the raw data, in megabytes per second.
+    pub fn throughput(&self) -> f64 {
+        let secs = self.total().as_secs_f64();
+        if secs > 0.0 { self.bytes as f64 / secs / 1e6 } else { 0.0 }
+    }
+}
+
+/// Recorder for the timing of the most recent frame.
+///
+/// Clones of a recorder share the same state, so the recorder can be
+/// given to a demosaicer and inspected after each frame.
+///
+/// # Example
+///
+/// ```
+/// let timing = bayer::Timing::new();
+///
+/// let mut options = bayer::DemosaicOptions::default();
+/// options.timing = Some(timing.clone());
+///
+/// // ... demosaic a frame with the options ...
+///
+/// if let Some(t) = timing.last() {
+///     println!("read {:?}, interpolate {:?}, {:.1} MB/s",
+///             t.read, t.interpolate, t.throughput());
+/// }
+/// ```
+#[derive(Clone,Debug,Default)]
+pub struct Timing(Arc<Mutex<Option<FrameTiming>>>);
+
+impl Timing {
+    /// Create a recorder that has not seen any frames.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The timing of the most recent frame, if any.
+    pub fn last(&self) -> Option<FrameTiming> {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Record the timing of a frame.
+    pub fn record(&self, timing: FrameTiming) {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = Some(timing);
+    }
+}
+
+/// Two recorders are equal if they are clones of each other.
+impl PartialEq for Timing {
+    fn eq(&self, other: &Timing) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Timing {}
+
+/// A description of exactly how a frame was processed, so that it
+/// can be stored with the output and the result reproduced later.
+///
+/// New fields may be added in future releases as more processing
+/// stages are reported.
+#[derive(Clone,Debug,Eq,PartialEq)]
+#[non_exhaustive]
+pub struct ProcessingReport {
+    /// The width of the Bayer image.
+    pub width: usize,
+    /// The height of the Bayer image.
+    pub height: usize,
+    /// The depth and byte order of the Bayer image.
+    pub depth: BayerDepth,
+    /// The CFA pattern at the top left of the Bayer image.
+    pub cfa: CFA,
+    /// The algorithm that was requested.
+    pub requested: Demosaic,
+    /// The algorithm that was applied, after any fallback.
+    pub algorithm: Demosaic,
+    /// The border handling that was applied.
+    pub border: Border,
+    /// The value of the missing channels, if no interpolation was
+    /// performed.
+    pub fill: Option<Fill>,
+    /// The transform applied to the output.
+    pub transform: Option<Transform>,
+    /// The number of rows missing from the input, which were filled
+    /// in according to the truncation option.
+    pub missing_rows: usize,
+    /// The first row finished with a cheaper algorithm after the
+    /// deadline was exceeded.
+    pub degraded_from_row: Option<usize>,
+    /// The number of significant bits of the raw samples.
+    pub source_bits: u8,
+    /// The scaling of the raw samples.
+    pub scaling: Scaling,
+    /// The black and white levels applied to the raw samples.
+    pub levels: Option<Levels>,
+    /// The white balance multipliers applied to the raw samples.
+    pub white_balance: Option<WhiteBalance>,
+    /// Whether a dark frame was subtracted from the raw samples.
+    pub dark_frame: bool,
+    /// The treatment of saturated samples.
+    pub saturation: Saturation,
+    /// The handling of calibrated samples below zero.
+    pub intermediate: Intermediate,
+    /// The right shift and rounding of the samples narrowed into an
+    /// 8-bit raster, if any.
+    pub narrowing: Option<(u8, Narrowing)>,
+    /// The divisor of the samples written to a floating-point raster,
+    /// if any.
+    pub float_divisor: Option<u32>,
+}
+
+/// Writes the report as a single line of key=value pairs, suitable
+/// for a comment or metadata field.
+impl fmt::Display for ProcessingReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "size={}x{} depth={:?} cfa={:?} algorithm={:?} border={:?}",
+                self.width, self.height, self.depth, self.cfa, self.algorithm, self.border)?;
+        if self.requested != self.algorithm {
+            write!(f, " requested={:?}", self.requested)?;
+        }
+        if let Some(fill) = self.fill {
+            write!(f, " fill={:?}", fill)?;
+        }
+        if let Some(t) = self.transform {
+            write!(f, " transform={:?}", t)?;
+        }
+        if self.missing_rows > 0 {
+            write!(f, " missing_rows={}", self.missing_rows)?;
+        }
+        if let Some(row) = self.degraded_from_row {
+            write!(f, " degraded_from_row={}", row)?;
+        }
+        match self.scaling {
+            Scaling::Identity => (),
+            Scaling::SourceBits => write!(f, " source_bits={}", self.source_bits)?,
+            Scaling::Widen { shift, replicate } =>
+                write!(f, " widen_shift={} widen_replicate={}", shift, replicate)?,
+        }
+        if let Some(levels) = self.levels {
+            let b = levels.black;
+            write!(f, " black={},{},{},{} white={}", b[0], b[1], b[2], b[3], levels.white)?;
+        }
+        if let Some(wb) = self.white_balance {
+            write!(f, " white_balance={},{},{}", wb.r, wb.g, wb.b)?;
+        }
+        if self.dark_frame {
+            write!(f, " dark_frame=true")?;
+        }
+        if self.saturation != Saturation::Interpolate {
+            write!(f, " saturation={:?}", self.saturation)?;
+        }
+        if self.intermediate != Intermediate::Unsigned {
+            write!(f, " intermediate={:?}", self.intermediate)?;
+        }
+        if let Some((shift, narrowing)) = self.narrowing {
+            write!(f, " narrow_shift={} narrowing={:?}", shift, narrowing)?;
+        }
+        if let Some(divisor) = self.float_divisor {
+            write!(f, " float_divisor={}", divisor)?;
+        }
+        Ok(())
+    }
+}
+
+/// Recorder for the processing report of the most recent frame.
+///
+/// Clones of a recorder share the same state, so the recorder can be
+/// given to a demosaicer and inspected after each frame.  A report is
+/// recorded whenever the raster was written, including frames that
+/// were truncated or finished after the deadline.
+///
+/// # Example
+///
+/// ```
+/// let report = bayer::Report::new();
+///
+/// let mut options = bayer::DemosaicOptions::default();
+/// options.report = Some(report.clone());
+///
+/// // ... demosaic a frame with the options ...
+///
+/// if let Some(r) = report.last() {
+///     println!("{}", r);
+/// }
+/// ```
+#[derive(Clone,Debug,Default)]
+pub struct Report(Arc<Mutex<Option<ProcessingReport>>>);
+
+impl Report {
+    /// Create a recorder that has not seen any frames.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The report of the most recent frame, if any.
+    pub fn last(&self) -> Option<ProcessingReport> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    /// Record the report of a frame.
+    pub fn record(&self, report: ProcessingReport) {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = Some(report);
+    }
+}
+
+/// Two recorders are equal if they are clones of each other.
+impl PartialEq for Report {
+    fn eq(&self, other: &Report) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Report {}
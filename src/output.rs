@@ -0,0 +1,124 @@
+//! Writing rasters to image files.
+//!
+//! These helpers are for quickly looking at the demosaiced images.
+//! PPM output requires the ppm feature, and PNG output requires the
+//! image feature.
+
+use std::io::Write;
+
+use ::{BayerResult,Raster,RasterDepth};
+
+/// Write the raster as a binary PPM image, with 16-bit samples for
+/// 16-bit and floating-point rasters.
+///
+/// # Example
+///
+/// ```
+/// let buf = [255,0,0, 0,255,0];
+/// let mut ppm = Vec::new();
+///
+/// bayer::write_ppm(&bayer::Raster::new(2, 1, bayer::RasterDepth::Depth8, &buf),
+///         &mut ppm).unwrap();
+/// assert_eq!(&ppm[0..11], b"P6\n2 1\n255\n");
+/// ```
+#[cfg(feature = "ppm")]
+pub fn write_ppm(src: &Raster, w: &mut Write) -> BayerResult<()> {
+    let maxval = match src.depth {
+        RasterDepth::Depth8 => 255,
+        RasterDepth::Depth16 | RasterDepth::Depth32F => 65535,
+    };
+
+    write!(w, "P6\n{} {}\n{}\n", src.w, src.h, maxval)?;
+    w.write_all(&rows_bytes(src, true))?;
+    Ok(())
+}
+
+/// Write the raster as a PNG image, with 16-bit samples for 16-bit
+/// and floating-point rasters.
+#[cfg(feature = "image")]
+pub fn write_png(src: &Raster, w: &mut Write) -> BayerResult<()> {
+    use std::io;
+    use image::{ColorType,ImageEncoder};
+    use image::codecs::png::PngEncoder;
+
+    let color = match src.depth {
+        RasterDepth::Depth8 => ColorType::Rgb8,
+        RasterDepth::Depth16 | RasterDepth::Depth32F => ColorType::Rgb16,
+    };
+
+    // The encoder expects 16-bit samples in host byte order.
+    PngEncoder::new(w)
+        .write_image(&rows_bytes(src, false), src.w as u32, src.h as u32, color)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    Ok(())
+}
+
+/// The pixels of the raster without any stride padding, with 16-bit
+/// samples in big-endian or host byte order.  Floating-point samples
+/// are clamped to [0, 1] and scaled to 16 bits.
+fn rows_bytes(src: &Raster, big_endian: bool) -> Vec<u8> {
+    let mut v = Vec::new();
+    let mut row = Vec::with_capacity(3 * src.w);
+
+    for y in 0..src.h {
+        src.read_rgb_row(y, &mut row);
+        for &s in row.iter() {
+            match src.depth {
+                RasterDepth::Depth8 => v.push(s as u8),
+                RasterDepth::Depth16 | RasterDepth::Depth32F => {
+                    let bytes = if big_endian { s.to_be_bytes() } else { s.to_ne_bytes() };
+                    v.extend_from_slice(&bytes);
+                },
+            }
+        }
+    }
+
+    v
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{Raster,RasterDepth,RasterLayout,RasterMut};
+    use super::rows_bytes;
+
+    #[test]
+    fn test_rows_bytes() {
+        let buf = [
+            1,2,3, 4,5,6, 0,0,0,
+            7,8,9, 10,11,12, 0,0,0 ];
+
+        let src = Raster::with_offset(0, 0, 2, 2, 9, RasterDepth::Depth8, &buf);
+        assert_eq!(rows_bytes(&src, true), [1,2,3, 4,5,6, 7,8,9, 10,11,12]);
+
+        let mut buf = [
+            3,2,1,0xFF, 6,5,4,0xFF,
+            9,8,7,0xFF, 12,11,10,0xFF ];
+        let src = RasterMut::try_with_layout(0, 0, 2, 2, 8, RasterDepth::Depth8,
+                RasterLayout::Bgra { alpha: 0xFFFF }, &mut buf).unwrap();
+        assert_eq!(rows_bytes(&src.as_raster(), true), [1,2,3, 4,5,6, 7,8,9, 10,11,12]);
+    }
+
+    #[cfg(feature = "ppm")]
+    #[test]
+    fn test_ppm_u16() {
+        let mut buf = [0u8; 6];
+        for (i, v) in [0x0102u16, 0x0304, 0x0506].iter().enumerate() {
+            buf[2 * i..2 * i + 2].copy_from_slice(&v.to_ne_bytes());
+        }
+
+        let mut ppm = Vec::new();
+        let res = super::write_ppm(&Raster::new(1, 1, RasterDepth::Depth16, &buf), &mut ppm);
+        assert!(res.is_ok());
+        assert_eq!(&ppm[..], &b"P6\n1 1\n65535\n\x01\x02\x03\x04\x05\x06"[..]);
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_png() {
+        let buf = [0u8; 3 * 4 * 4];
+        let mut png = Vec::new();
+        let res = super::write_png(&Raster::new(4, 4, RasterDepth::Depth8, &buf), &mut png);
+        assert!(res.is_ok());
+        assert_eq!(&png[1..4], b"PNG");
+    }
+}
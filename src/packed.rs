@@ -0,0 +1,210 @@
+//! Packed raw formats.
+//!
+//! Many sensors transmit samples of fewer than 16 bits packed tightly
+//! into bytes.  These functions convert between the packed formats
+//! and plain 16-bit samples.
+
+use ::{BayerError,BayerResult};
+use errcode::OVERFLOW;
+
+/// A packed raw format.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum PackedFormat {
+    /// MIPI CSI-2 RAW10: 4 samples in 5 bytes.  The first 4 bytes hold
+    /// the high 8 bits of each sample, and the fifth byte holds the
+    /// low 2 bits of each sample, starting from the least significant
+    /// bits.
+    Raw10,
+    /// MIPI CSI-2 RAW12: 2 samples in 3 bytes.  The first 2 bytes hold
+    /// the high 8 bits of each sample, and the third byte holds the
+    /// low 4 bits of each sample, starting from the least significant
+    /// bits.
+    Raw12,
+    /// MIPI CSI-2 RAW14: 4 samples in 7 bytes.  The first 4 bytes hold
+    /// the high 8 bits of each sample, and the last 3 bytes hold the
+    /// low 6 bits of each sample, starting from the least significant
+    /// bits.
+    Raw14,
+    /// 12-bit packing used by DNG and many cameras: 2 samples in 3
+    /// bytes, as a big-endian bit stream.
+    Dng12,
+}
+
+impl PackedFormat {
+    /// The number of bits per sample.
+    pub fn bits(self) -> u32 {
+        match self {
+            PackedFormat::Raw10 => 10,
+            PackedFormat::Raw12 | PackedFormat::Dng12 => 12,
+            PackedFormat::Raw14 => 14,
+        }
+    }
+
+    /// The number of samples and bytes in each packed group.
+    pub(crate) fn group(self) -> (usize, usize) {
+        match self {
+            PackedFormat::Raw10 => (4, 5),
+            PackedFormat::Raw12 | PackedFormat::Dng12 => (2, 3),
+            PackedFormat::Raw14 => (4, 7),
+        }
+    }
+
+    /// The number of bytes used by count packed samples, or None if
+    /// it overflows.  Incomplete groups at the end are padded to whole
+    /// groups.
+    pub fn packed_len(self, count: usize) -> Option<usize> {
+        let (samples, bytes) = self.group();
+        (count / samples).checked_add(if count % samples == 0 { 0 } else { 1 })
+            .and_then(|groups| groups.checked_mul(bytes))
+    }
+
+    /// Unpack one group of samples.
+    pub(crate) fn unpack_group(self, src: &[u8], dst: &mut [u16]) {
+        match self {
+            PackedFormat::Raw10 => {
+                for i in 0..4 {
+                    dst[i] = (src[i] as u16) << 2 | (src[4] as u16 >> (2 * i)) & 0x3;
+                }
+            },
+            PackedFormat::Raw12 => {
+                dst[0] = (src[0] as u16) << 4 | (src[2] as u16) & 0xF;
+                dst[1] = (src[1] as u16) << 4 | (src[2] as u16) >> 4;
+            },
+            PackedFormat::Raw14 => {
+                let low = src[4] as u32 | (src[5] as u32) << 8 | (src[6] as u32) << 16;
+                for i in 0..4 {
+                    dst[i] = (src[i] as u16) << 6 | (low >> (6 * i)) as u16 & 0x3F;
+                }
+            },
+            PackedFormat::Dng12 => {
+                dst[0] = (src[0] as u16) << 4 | (src[1] as u16) >> 4;
+                dst[1] = ((src[1] as u16) & 0xF) << 8 | src[2] as u16;
+            },
+        }
+    }
+
+    /// Pack one group of samples.
+    pub(crate) fn pack_group(self, src: &[u16], dst: &mut [u8]) {
+        match self {
+            PackedFormat::Raw10 => {
+                dst[4] = 0;
+                for i in 0..4 {
+                    dst[i] = (src[i] >> 2) as u8;
+                    dst[4] = dst[4] | ((src[i] & 0x3) << (2 * i)) as u8;
+                }
+            },
+            PackedFormat::Raw12 => {
+                dst[0] = (src[0] >> 4) as u8;
+                dst[1] = (src[1] >> 4) as u8;
+                dst[2] = (src[0] & 0xF) as u8 | ((src[1] & 0xF) << 4) as u8;
+            },
+            PackedFormat::Raw14 => {
+                let mut low = 0u32;
+                for i in 0..4 {
+                    dst[i] = (src[i] >> 6) as u8;
+                    low = low | ((src[i] & 0x3F) as u32) << (6 * i);
+                }
+                dst[4] = low as u8;
+                dst[5] = (low >> 8) as u8;
+                dst[6] = (low >> 16) as u8;
+            },
+            PackedFormat::Dng12 => {
+                dst[0] = (src[0] >> 4) as u8;
+                dst[1] = ((src[0] & 0xF) << 4) as u8 | (src[1] >> 8) as u8;
+                dst[2] = src[1] as u8;
+            },
+        }
+    }
+}
+
+/// Unpack count samples from packed data.
+///
+/// # Example
+///
+/// ```
+/// let packed = [0x12, 0x34, 0x56, 0x78, 0b11_10_01_00];
+/// let samples = bayer::unpack(&packed, bayer::PackedFormat::Raw10, 4).unwrap();
+/// assert_eq!(samples, [0x048, 0x0D1, 0x15A, 0x1E3]);
+/// ```
+pub fn unpack(src: &[u8], format: PackedFormat, count: usize)
+        -> BayerResult<Vec<u16>> {
+    let expected = format.packed_len(count).ok_or(OVERFLOW)?;
+    if src.len() < expected {
+        return Err(BayerError::InputTooShort { expected, got: src.len() });
+    }
+
+    let (samples, bytes) = format.group();
+    let mut dst = vec![0; expected / bytes * samples];
+    for (s, d) in src[0..expected].chunks(bytes).zip(dst.chunks_mut(samples)) {
+        format.unpack_group(s, d);
+    }
+
+    dst.truncate(count);
+    Ok(dst)
+}
+
+/// Pack samples, padding the last group with zeros.  Only the low
+/// bits of each sample are used.
+///
+/// # Example
+///
+/// ```
+/// let packed = bayer::pack(&[0xABC, 0x123], bayer::PackedFormat::Dng12);
+/// assert_eq!(packed, [0xAB, 0xC1, 0x23]);
+/// ```
+pub fn pack(src: &[u16], format: PackedFormat) -> Vec<u8> {
+    let (samples, bytes) = format.group();
+    let mask = (1 << format.bits()) - 1;
+    // The packed samples take fewer bytes than the source, so the
+    // length cannot overflow.
+    let mut dst = vec![0; format.packed_len(src.len()).unwrap()];
+    let mut group = vec![0; samples];
+
+    for (s, d) in src.chunks(samples).zip(dst.chunks_mut(bytes)) {
+        for (i, g) in group.iter_mut().enumerate() {
+            *g = if i < s.len() { s[i] & mask } else { 0 };
+        }
+        format.pack_group(&group, d);
+    }
+
+    dst
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PackedFormat,pack,unpack};
+
+    #[test]
+    fn test_raw12() {
+        let packed = [0xAB, 0x12, 0x3C];
+        assert_eq!(unpack(&packed, PackedFormat::Raw12, 2).unwrap(), [0xABC, 0x123]);
+        assert_eq!(pack(&[0xABC, 0x123], PackedFormat::Raw12), packed);
+    }
+
+    #[test]
+    fn test_raw14() {
+        let packed = [0xAB, 0x12, 0x34, 0xFF, 0b01_000011, 0b1111_0000, 0b111111_10];
+        let samples = [0x2AC3, 0x0481, 0x0D2F, 0x3FFF];
+        assert_eq!(unpack(&packed, PackedFormat::Raw14, 4).unwrap(), samples);
+        assert_eq!(pack(&samples, PackedFormat::Raw14), packed);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let formats = [PackedFormat::Raw10, PackedFormat::Raw12, PackedFormat::Raw14,
+                PackedFormat::Dng12];
+
+        for &format in formats.iter() {
+            let mask = (1 << format.bits()) - 1;
+            let src: Vec<u16> = (0..7).map(|i| (i * 997) as u16 & mask).collect();
+
+            let packed = pack(&src, format);
+            assert_eq!(Some(packed.len()), format.packed_len(7));
+            assert_eq!(unpack(&packed, format, 7).unwrap(), src);
+            assert!(unpack(&packed[1..], format, 7).is_err());
+
+            assert_eq!(format.packed_len(usize::max_value()), None);
+            assert!(unpack(&packed, format, usize::max_value()).is_err());
+        }
+    }
+}
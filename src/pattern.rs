@@ -0,0 +1,444 @@
+//! Colour filter array patterns other than the 2x2 Bayer pattern.
+
+use std::io::Read;
+
+use ::{BayerDepth,BayerError,BayerResult,CFA,Demosaic,DemosaicOptions,Fill,RasterMut};
+use bayer::get_sample;
+use clear;
+use complementary;
+use demosaic::calibration::Calibration;
+use errcode::OVERFLOW;
+use quadbayer;
+use xtrans;
+
+/// The colour of a filter in a colour filter array.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum Colour {
+    Red,
+    Green,
+    Blue,
+    /// A clear or white filter, which passes all colours.
+    Clear,
+    /// A cyan filter, which passes green and blue.
+    Cyan,
+    /// A yellow filter, which passes red and green.
+    Yellow,
+    /// A magenta filter, which passes red and blue.
+    Magenta,
+    /// An emerald filter, a bluish green.
+    Emerald,
+}
+
+/// The layout of a colour filter array.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum CfaPattern {
+    /// A 2x2 Bayer pattern.
+    Bayer(CFA),
+    /// No colour filters, as on monochrome sensors.
+    Mono,
+    /// A quad-Bayer pattern of 2x2 groups of same-coloured pixels,
+    /// with the groups in the given Bayer pattern.
+    QuadBayer(CFA),
+    /// A Bayer pattern with the greens replaced by clear pixels.
+    Rccb(CFA),
+    /// A Bayer pattern with the greens and blues replaced by clear
+    /// pixels.
+    Rccc(CFA),
+    /// A Bayer pattern with the greens on the rows of blue replaced by
+    /// white pixels.  The white samples are read but discarded; the
+    /// output is reconstructed from the red, green and blue samples only.
+    Rgbw(CFA),
+    /// A 2x2 pattern of cyan, yellow, green and magenta pixels,
+    /// indexed by row then column.
+    Cygm([[Colour; 2]; 2]),
+    /// A 2x2 pattern of red, green, blue and emerald pixels, indexed
+    /// by row then column.
+    Rgbe([[Colour; 2]; 2]),
+    /// A 6x6 X-Trans pattern, indexed by row then column.  Images
+    /// must hold at least one whole tile.
+    XTrans([[Colour; 6]; 6]),
+}
+
+impl Colour {
+    /// The channel (0 = red, 1 = green, 2 = blue, 3 = other) of the
+    /// colour.
+    pub(crate) fn channel(self) -> usize {
+        match self {
+            Colour::Red => 0,
+            Colour::Green => 1,
+            Colour::Blue => 2,
+            _ => 3,
+        }
+    }
+
+    fn from_channel(c: usize) -> Self {
+        match c {
+            0 => Colour::Red,
+            1 => Colour::Green,
+            _ => Colour::Blue,
+        }
+    }
+}
+
+impl CfaPattern {
+    /// The X-Trans pattern of the Fujifilm X-Trans sensors, starting
+    /// at the top-left pixel of the sensor.
+    pub fn xtrans() -> Self {
+        const R: Colour = Colour::Red;
+        const G: Colour = Colour::Green;
+        const B: Colour = Colour::Blue;
+
+        CfaPattern::XTrans([
+            [G,G,R,G,G,B],
+            [G,G,B,G,G,R],
+            [B,R,G,R,B,G],
+            [G,G,B,G,G,R],
+            [G,G,R,G,G,B],
+            [R,B,G,B,R,G] ])
+    }
+
+    /// The CYGM pattern with CY on the even rows and GM on the odd
+    /// rows.
+    pub fn cygm() -> Self {
+        CfaPattern::Cygm([
+            [Colour::Cyan, Colour::Yellow],
+            [Colour::Green, Colour::Magenta] ])
+    }
+
+    /// The RGBE pattern with RG on the even rows and BE on the odd
+    /// rows.
+    pub fn rgbe() -> Self {
+        CfaPattern::Rgbe([
+            [Colour::Red, Colour::Green],
+            [Colour::Blue, Colour::Emerald] ])
+    }
+
+    /// The width and height of the repeating tile of the pattern.
+    pub fn period(&self) -> usize {
+        match *self {
+            CfaPattern::Mono => 1,
+            CfaPattern::Bayer(_)
+                | CfaPattern::Rccb(_) | CfaPattern::Rccc(_) | CfaPattern::Rgbw(_)
+                | CfaPattern::Cygm(_) | CfaPattern::Rgbe(_) => 2,
+            CfaPattern::QuadBayer(_) => 4,
+            CfaPattern::XTrans(_) => 6,
+        }
+    }
+
+    /// The colour of the filter at pixel (x, y).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bayer::{CFA,CfaPattern,Colour};
+    ///
+    /// assert_eq!(CfaPattern::Bayer(CFA::RGGB).colour_at(1, 1), Colour::Blue);
+    /// assert_eq!(CfaPattern::xtrans().colour_at(7, 2), Colour::Red);
+    /// ```
+    pub fn colour_at(&self, x: usize, y: usize) -> Colour {
+        match *self {
+            CfaPattern::Bayer(cfa) => Colour::from_channel(cfa.at(x, y).channel()),
+            CfaPattern::Mono => Colour::Clear,
+            CfaPattern::QuadBayer(cfa) => Colour::from_channel(cfa.at(x / 2, y / 2).channel()),
+            CfaPattern::Rccb(cfa) =>
+                match Colour::from_channel(cfa.at(x, y).channel()) {
+                    Colour::Green => Colour::Clear,
+                    c => c,
+                },
+            CfaPattern::Rccc(cfa) =>
+                match Colour::from_channel(cfa.at(x, y).channel()) {
+                    Colour::Red => Colour::Red,
+                    _ => Colour::Clear,
+                },
+            CfaPattern::Rgbw(cfa) =>
+                match Colour::from_channel(cfa.at(x, y).channel()) {
+                    Colour::Green if cfa.at(x + 1, y).channel() == 2 => Colour::Clear,
+                    c => c,
+                },
+            CfaPattern::Cygm(ref layout) | CfaPattern::Rgbe(ref layout) =>
+                layout[y % 2][x % 2],
+            CfaPattern::XTrans(ref layout) => layout[y % 6][x % 6],
+        }
+    }
+
+    /// The pattern of an image cropped from an image with this
+    /// pattern, with the top-left pixel at (x, y).
+    ///
+    /// Quad-Bayer groups cannot be split, so the offsets of quad-Bayer
+    /// patterns must be even.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bayer::{CFA,CfaPattern};
+    ///
+    /// let pattern = CfaPattern::xtrans();
+    /// let cropped = pattern.with_offset(7, 2).unwrap();
+    /// assert_eq!(cropped.colour_at(0, 0), pattern.colour_at(7, 2));
+    /// assert_eq!(CfaPattern::Rccb(CFA::RGGB).with_offset(1, 0).ok(),
+    ///         Some(CfaPattern::Rccb(CFA::GRBG)));
+    /// ```
+    pub fn with_offset(&self, x: usize, y: usize) -> BayerResult<Self> {
+        let pattern = match *self {
+            CfaPattern::Bayer(cfa) => CfaPattern::Bayer(cfa.with_offset(x, y)),
+            CfaPattern::Mono => CfaPattern::Mono,
+            CfaPattern::QuadBayer(cfa) => {
+                if x % 2 != 0 || y % 2 != 0 {
+                    return Err(BayerError::InvalidArgument {
+                        reason: "quad-Bayer offsets must be even",
+                    });
+                }
+                CfaPattern::QuadBayer(cfa.with_offset(x / 2, y / 2))
+            },
+            CfaPattern::Rccb(cfa) => CfaPattern::Rccb(cfa.with_offset(x, y)),
+            CfaPattern::Rccc(cfa) => CfaPattern::Rccc(cfa.with_offset(x, y)),
+            CfaPattern::Rgbw(cfa) => CfaPattern::Rgbw(cfa.with_offset(x, y)),
+            CfaPattern::Cygm(ref layout) => CfaPattern::Cygm(shift(layout, x, y)),
+            CfaPattern::Rgbe(ref layout) => CfaPattern::Rgbe(shift(layout, x, y)),
+            CfaPattern::XTrans(ref layout) => CfaPattern::XTrans(shift(layout, x, y)),
+        };
+
+        Ok(pattern)
+    }
+}
+
+/// The layout starting at pixel (x, y) of the given layout.
+fn shift<const N: usize>(layout: &[[Colour; N]; N], x: usize, y: usize) -> [[Colour; N]; N] {
+    let mut shifted = *layout;
+    for (v, row) in shifted.iter_mut().enumerate() {
+        for (u, e) in row.iter_mut().enumerate() {
+            *e = layout[(y + v) % N][(x + u) % N];
+        }
+    }
+    shifted
+}
+
+impl From<CFA> for CfaPattern {
+    fn from(cfa: CFA) -> Self {
+        CfaPattern::Bayer(cfa)
+    }
+}
+
+/// Run the demosaicing algorithm on an image with the given colour
+/// filter array pattern.
+///
+/// Bayer images are demosaiced by alg, as by `demosaic_from`.
+/// Monochrome images are not demosaiced: each sample is copied into
+/// all three channels, whatever alg.
+/// Quad-Bayer images are read into memory and remosaiced into Bayer
+/// images, see `remosaic_quad`, before they are demosaiced by alg.
+/// RCCB, RCCC and RGBW images are read into memory and demosaiced by
+/// alg, which must be `Demosaic::None`, `Demosaic::NearestNeighbour`
+/// or `Demosaic::Linear`.  The output channels are red, clear and
+/// blue for RCCB, red, clear and clear for RCCC, and red, green and
+/// blue for RGBW, whose white samples are not used.
+/// CYGM and RGBE images are demosaiced likewise, then converted to
+/// RGB assuming ideal complementary filters, C = G + B, Y = R + G and
+/// M = R + B, and averaging the emerald samples with the green.
+/// X-Trans images are always demosaiced by a single-pass form of
+/// Markesteijn's algorithm, and do not support options.transform.
+///
+/// # Example
+///
+/// ```
+/// let width: usize = 60;
+/// let height: usize = 40;
+/// let img = vec![0; width * height];
+/// let mut buf = vec![0; 3 * width * height];
+///
+/// let mut dst = bayer::RasterMut::new(
+///         width, height, bayer::RasterDepth::Depth8,
+///         &mut buf);
+/// bayer::demosaic_pattern(&mut &img[..],
+///         bayer::BayerDepth::Depth8,
+///         &bayer::CfaPattern::xtrans(),
+///         bayer::Demosaic::Linear,
+///         &bayer::DemosaicOptions::default(),
+///         &mut dst).unwrap();
+/// ```
+pub fn demosaic_pattern<R: Read>(r: &mut R,
+        depth: BayerDepth, pattern: &CfaPattern, alg: Demosaic,
+        options: &DemosaicOptions, dst: &mut RasterMut)
+        -> BayerResult<()> {
+    match *pattern {
+        CfaPattern::Bayer(cfa) =>
+            ::demosaic_from(r, depth, cfa, alg, options, dst),
+        CfaPattern::Mono => {
+            let mut options = options.clone();
+            options.fill = Fill::Replicate;
+            ::demosaic_from(r, depth, CFA::RGGB, Demosaic::None, &options, dst)
+        },
+        CfaPattern::QuadBayer(cfa) => {
+            let (w, h) = options.transform.map_or((dst.w, dst.h),
+                    |t| t.dimensions(dst.w, dst.h));
+            let bayer = quadbayer::read_remosaiced(r, depth, w, h, cfa)?;
+            ::demosaic_from(&mut &bayer[..], depth, cfa, alg, options, dst)
+        },
+        CfaPattern::Rccb(_) | CfaPattern::Rccc(_) | CfaPattern::Rgbw(_) =>
+            clear::demosaic_clear(r, depth, pattern, alg, options, dst),
+        CfaPattern::Cygm(_) | CfaPattern::Rgbe(_) =>
+            complementary::demosaic_complementary(r, depth, pattern, alg, options, dst),
+        CfaPattern::XTrans(ref layout) =>
+            xtrans::demosaic_xtrans(r, depth, layout, options, dst),
+    }
+}
+
+/// Read the samples of a w x h image and calibrate them.
+pub(crate) fn read_samples<R: Read>(r: &mut R, depth: BayerDepth,
+        calibration: &Calibration, w: usize, h: usize)
+        -> BayerResult<Vec<u32>> {
+    let len = w.checked_mul(h).ok_or(OVERFLOW)?;
+    let mut raw = vec![0u8; depth.byte_len(len).ok_or(OVERFLOW)?];
+    r.read_exact(&mut raw)?;
+
+    let mut samples: Vec<u32> = (0..len).map(|i| get_sample(&raw, depth, i) as u32).collect();
+    if w > 0 {
+        for (y, row) in samples.chunks_mut(w).enumerate() {
+            calibration.apply(y, row);
+        }
+    }
+
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{BayerDepth,CFA,Demosaic,DemosaicOptions,RasterDepth,RasterLayout,RasterMut,demosaic_from};
+    use super::{CfaPattern,Colour,demosaic_pattern};
+
+    #[test]
+    fn test_xtrans() {
+        // Each row and column of the tile holds red and blue, and
+        // greens make up 20 of the 36 samples.
+        let pattern = CfaPattern::xtrans();
+        let count = |c: Colour| (0..36).filter(|i| pattern.colour_at(i % 6, i / 6) == c).count();
+        assert_eq!((count(Colour::Red), count(Colour::Green), count(Colour::Blue)), (8, 20, 8));
+
+        for i in 0..6 {
+            let row: Vec<Colour> = (0..6).map(|x| pattern.colour_at(x, i)).collect();
+            let col: Vec<Colour> = (0..6).map(|y| pattern.colour_at(i, y)).collect();
+            assert!(row.contains(&Colour::Red) && row.contains(&Colour::Blue));
+            assert!(col.contains(&Colour::Red) && col.contains(&Colour::Blue));
+        }
+        assert_eq!(pattern.colour_at(6, 6), pattern.colour_at(0, 0));
+    }
+
+    #[test]
+    fn test_bayer() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+
+        let mut expected = [0u8; 3 * 4 * 4];
+        let mut buf = [0u8; 3 * 4 * 4];
+        let options = DemosaicOptions::default();
+
+        let res = demosaic_from(&mut &src[..], BayerDepth::Depth8, CFA::GRBG,
+                Demosaic::Linear, &options,
+                &mut RasterMut::new(4, 4, RasterDepth::Depth8, &mut expected));
+        assert!(res.is_ok());
+
+        let res = demosaic_pattern(&mut &src[..], BayerDepth::Depth8, &CFA::GRBG.into(),
+                Demosaic::Linear, &options,
+                &mut RasterMut::new(4, 4, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+        assert_eq!(&buf[..], &expected[..]);
+    }
+
+    #[test]
+    fn test_with_offset() {
+        let patterns = [
+            CfaPattern::Bayer(CFA::GBRG), CfaPattern::Mono, CfaPattern::QuadBayer(CFA::BGGR),
+            CfaPattern::Rccb(CFA::RGGB), CfaPattern::Rccc(CFA::GRBG), CfaPattern::Rgbw(CFA::BGGR),
+            CfaPattern::cygm(), CfaPattern::rgbe(), CfaPattern::xtrans() ];
+
+        for pattern in patterns.iter() {
+            for &(x0, y0) in [(0, 0), (2, 4), (6, 10)].iter().chain([(1, 0), (3, 5)].iter()) {
+                let cropped = match pattern.with_offset(x0, y0) {
+                    Ok(cropped) => cropped,
+                    Err(_) => {
+                        assert!(*pattern == CfaPattern::QuadBayer(CFA::BGGR) && x0 % 2 == 1);
+                        continue;
+                    },
+                };
+
+                for y in 0..12 {
+                    for x in 0..12 {
+                        assert_eq!(cropped.colour_at(x, y), pattern.colour_at(x0 + x, y0 + y));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_layouts() {
+        const IMG_W: usize = 12;
+        const IMG_H: usize = 12;
+        let src: Vec<u8> = (0..(IMG_W * IMG_H)).map(|i| (i * 37 % 256) as u8).collect();
+        let wide: Vec<u8> = src.iter().flat_map(|&v| [v, 0x5A].to_vec()).collect();
+
+        let patterns = [
+            CfaPattern::Bayer(CFA::GBRG), CfaPattern::Mono, CfaPattern::QuadBayer(CFA::BGGR),
+            CfaPattern::Rccb(CFA::RGGB), CfaPattern::Rccc(CFA::GRBG), CfaPattern::Rgbw(CFA::BGGR),
+            CfaPattern::cygm(), CfaPattern::rgbe(), CfaPattern::xtrans() ];
+        let layouts = [
+            (RasterLayout::Bgr, [2, 1, 0]),
+            (RasterLayout::Rgba { alpha: 0xFFFF }, [0, 1, 2]),
+            (RasterLayout::Bgra { alpha: 0xFFFF }, [2, 1, 0]) ];
+        let depths = [
+            (BayerDepth::Depth8, &src[..], RasterDepth::Depth8),
+            (BayerDepth::Depth16BE, &wide[..], RasterDepth::Depth16) ];
+
+        for pattern in patterns.iter() {
+            for &(depth, input, raster_depth) in depths.iter() {
+                let bytes = raster_depth.bytes_per_sample();
+                let mut expected = vec![0u8; 3 * bytes * IMG_W * IMG_H];
+                let res = demosaic_pattern(&mut &input[..], depth, pattern,
+                        Demosaic::Linear, &DemosaicOptions::default(),
+                        &mut RasterMut::new(IMG_W, IMG_H, raster_depth, &mut expected));
+                assert!(res.is_ok());
+
+                for &(layout, order) in layouts.iter() {
+                    let channels = layout.channels();
+                    let mut buf = vec![0u8; channels * bytes * IMG_W * IMG_H];
+                    let res = demosaic_pattern(&mut &input[..], depth, pattern,
+                            Demosaic::Linear, &DemosaicOptions::default(),
+                            &mut RasterMut::try_with_layout(0, 0, IMG_W, IMG_H,
+                                    channels * bytes * IMG_W, raster_depth, layout, &mut buf)
+                                    .unwrap());
+                    assert!(res.is_ok());
+
+                    let pixels = buf.chunks(channels * bytes).zip(expected.chunks(3 * bytes));
+                    for (px, expected) in pixels {
+                        for c in 0..3 {
+                            assert_eq!(&px[(bytes * order[c])..(bytes * (order[c] + 1))],
+                                    &expected[(bytes * c)..(bytes * (c + 1))]);
+                        }
+                        if channels == 4 {
+                            assert!(px[(3 * bytes)..].iter().all(|&e| e == 0xFF));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_mono() {
+        let src = [
+            0x12,0x34, 0x56,0x78,
+            0x9A,0xBC, 0xDE,0xF0 ];
+
+        let mut buf = [0u8; 6 * 2 * 2];
+        let mut dst = RasterMut::new(2, 2, RasterDepth::Depth16, &mut buf);
+        let res = demosaic_pattern(&mut &src[..], BayerDepth::Depth16BE, &CfaPattern::Mono,
+                Demosaic::Cubic, &DemosaicOptions::default(), &mut dst);
+        assert!(res.is_ok());
+        assert_eq!(dst.borrow_row_u16_mut(0), &[0x1234,0x1234,0x1234, 0x5678,0x5678,0x5678]);
+        assert_eq!(dst.borrow_row_u16_mut(1), &[0x9ABC,0x9ABC,0x9ABC, 0xDEF0,0xDEF0,0xDEF0]);
+    }
+}
@@ -0,0 +1,155 @@
+//! Double-buffered rasters for video pipelines.
+
+use ::{BayerResult,Raster,RasterDepth,RasterMut};
+use errcode::OVERFLOW;
+
+/// A pair of rasters used alternately across frames.
+///
+/// One raster, the back buffer, is filled with the next frame while
+/// the other, the front buffer, holds the last completed frame.  The
+/// buffers are allocated once, so processing a video allocates no
+/// memory per frame.
+///
+/// # Example
+///
+/// ```
+/// let width: usize = 32;
+/// let height: usize = 20;
+/// let video = vec![0; 3 * width * height];
+/// let demosaicer = bayer::Demosaicer::new().build();
+/// let mut buffers = bayer::PingPong::new(width, height, bayer::RasterDepth::Depth8);
+///
+/// for frame in video.chunks(width * height) {
+///     let rgb = buffers.fill(|dst| demosaicer.run_from(&mut &frame[..], dst)).unwrap();
+///     assert_eq!(rgb.borrow_row_u8(0).len(), 3 * width);
+/// }
+/// ```
+pub struct PingPong {
+    w: usize,
+    h: usize,
+    depth: RasterDepth,
+    bufs: [Vec<u8>; 2],
+    back: usize,
+    completed: bool,
+}
+
+impl PingPong {
+    /// Allocate two w x h rasters of the given depth.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the rasters overflow the address space.  See
+    /// `try_new`.
+    pub fn new(w: usize, h: usize, depth: RasterDepth) -> Self {
+        match Self::try_new(w, h, depth) {
+            Ok(buffers) => buffers,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Allocate two w x h rasters of the given depth, returning an
+    /// error instead of panicking if they overflow the address space.
+    pub fn try_new(w: usize, h: usize, depth: RasterDepth) -> BayerResult<Self> {
+        let len = w.checked_mul(h)
+            .and_then(|n| n.checked_mul(depth.bytes_per_pixel()))
+            .ok_or(OVERFLOW)?;
+
+        Ok(PingPong {
+            w, h, depth,
+            bufs: [vec![0; len], vec![0; len]],
+            back: 0,
+            completed: false,
+        })
+    }
+
+    /// Borrow the last completed frame, if any, and the raster to be
+    /// filled with the next frame.
+    ///
+    /// The buffers always hold w x h images, so the rasters here and
+    /// below cannot fail to be created.
+    pub fn buffers(&mut self) -> (Option<Raster<'_>>, RasterMut<'_>) {
+        let (w, h, depth, completed) = (self.w, self.h, self.depth, self.completed);
+        let (first, second) = self.bufs.split_at_mut(1);
+        let (front, back) = if self.back == 0 {
+            (&second[0], &mut first[0])
+        } else {
+            (&first[0], &mut second[0])
+        };
+
+        let front = if completed { Some(Raster::new(w, h, depth, front)) } else { None };
+        (front, RasterMut::new(w, h, depth, back))
+    }
+
+    /// Borrow the last completed frame, if any.
+    pub fn front(&self) -> Option<Raster<'_>> {
+        if self.completed {
+            Some(Raster::new(self.w, self.h, self.depth, &self.bufs[1 - self.back]))
+        } else {
+            None
+        }
+    }
+
+    /// Mark the back buffer as completed, making it the front buffer.
+    pub fn swap(&mut self) {
+        self.back = 1 - self.back;
+        self.completed = true;
+    }
+
+    /// Fill the back buffer with f and swap the buffers, returning
+    /// the newly completed frame.
+    ///
+    /// If f fails, the buffers are not swapped, so the front buffer
+    /// still holds the previous frame.
+    pub fn fill<F>(&mut self, f: F) -> BayerResult<Raster<'_>>
+            where F: FnOnce(&mut RasterMut) -> BayerResult<()> {
+        {
+            let (_, mut back) = self.buffers();
+            f(&mut back)?;
+        }
+
+        self.swap();
+        Ok(Raster::new(self.w, self.h, self.depth, &self.bufs[1 - self.back]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{BayerError,RasterDepth};
+    use super::PingPong;
+
+    #[test]
+    fn test_alternate() {
+        let mut buffers = PingPong::new(2, 2, RasterDepth::Depth16);
+        assert!(buffers.front().is_none());
+
+        for i in 1..4u8 {
+            {
+                let (front, back) = buffers.buffers();
+                if i > 1 {
+                    assert!(front.unwrap().buf.iter().all(|&v| v == i - 1));
+                }
+                for v in back.buf.iter_mut() {
+                    *v = i;
+                }
+            }
+            buffers.swap();
+            assert!(buffers.front().unwrap().buf.iter().all(|&v| v == i));
+        }
+
+        let res = buffers.fill(|_| Err(BayerError::NoGood));
+        assert!(res.is_err());
+        assert!(buffers.front().unwrap().buf.iter().all(|&v| v == 3));
+
+        let res = buffers.fill(|dst| { dst.borrow_row_u16_mut(0)[0] = 7; Ok(()) });
+        assert_eq!(res.unwrap().borrow_row_u16(0)[0], 7);
+    }
+
+    #[test]
+    fn test_try_new() {
+        assert!(PingPong::try_new(2, 2, RasterDepth::Depth8).is_ok());
+        match PingPong::try_new(::std::usize::MAX, 2, RasterDepth::Depth8) {
+            Err(BayerError::InvalidArgument { .. }) => (),
+            _ => panic!(),
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! Demosaicing into separate colour planes.
+
+use std::io::Read;
+
+use ::{BayerDepth,BayerError,BayerResult,CFA,Demosaic,DemosaicOptions,RasterDepth};
+use demosaic::{Engine,Frame};
+use demosaic::strips::run_engine_strips;
+use errcode::OVERFLOW;
+
+/// The number of rows interpolated between writes to the planes.
+const STRIP_ROWS: usize = 64;
+
+/// Mutable planar raster, with the red, green, and blue samples in
+/// separate planes.
+///
+/// Each plane holds w x h samples of the raster depth, with each row
+/// starting a stride number of bytes after the previous one.
+pub struct PlanarRasterMut<'a> {
+    w: usize,
+    h: usize,
+    depth: RasterDepth,
+    planes: [&'a mut [u8]; 3],
+    strides: [usize; 3],
+}
+
+impl<'a> PlanarRasterMut<'a> {
+    /// Allocate a new planar raster for the given red, green, and blue
+    /// plane slices, with rows packed without padding.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// const IMG_W: usize = 320;
+    /// const IMG_H: usize = 200;
+    /// let mut r = [0; IMG_W * IMG_H];
+    /// let mut g = [0; IMG_W * IMG_H];
+    /// let mut b = [0; IMG_W * IMG_H];
+    ///
+    /// bayer::PlanarRasterMut::try_new(
+    ///         IMG_W, IMG_H, bayer::RasterDepth::Depth8,
+    ///         [&mut r, &mut g, &mut b])
+    ///         .unwrap();
+    /// ```
+    pub fn try_new(w: usize, h: usize, depth: RasterDepth, planes: [&'a mut [u8]; 3])
+            -> BayerResult<Self> {
+        let stride = w.checked_mul(depth.bytes_per_sample()).ok_or(OVERFLOW)?;
+        Self::try_with_strides(w, h, depth, planes, [stride; 3])
+    }
+
+    /// Allocate a new planar raster for the given red, green, and blue
+    /// plane slices.  The strides are in number of bytes.
+    pub fn try_with_strides(w: usize, h: usize, depth: RasterDepth,
+            planes: [&'a mut [u8]; 3], strides: [usize; 3])
+            -> BayerResult<Self> {
+        if w == 0 || h == 0 {
+            return Err(BayerError::InvalidArgument { reason: "empty raster" });
+        }
+
+        let bps = depth.bytes_per_sample();
+        let row_len = w.checked_mul(bps).ok_or(OVERFLOW)?;
+        for (plane, &stride) in planes.iter().zip(strides.iter()) {
+            if row_len > stride || stride % bps != 0 {
+                return Err(BayerError::InvalidArgument { reason: "invalid raster stride" });
+            }
+
+            let end = stride.checked_mul(h).ok_or(OVERFLOW)?;
+            if end > plane.len() {
+                return Err(BayerError::OutputTooShort { expected: end, got: plane.len() });
+            }
+        }
+
+        Ok(PlanarRasterMut { w, h, depth, planes, strides })
+    }
+
+    /// Write a row of RGB pixels, in bytes, into row y of the planes.
+    fn put_rgb_row(&mut self, y: usize, row: &[u8]) {
+        let bps = self.depth.bytes_per_sample();
+        for (c, plane) in self.planes.iter_mut().enumerate() {
+            let start = self.strides[c] * y;
+            let dst = &mut plane[start..(start + bps * self.w)];
+            for (px, out) in row.chunks(3 * bps).zip(dst.chunks_mut(bps)) {
+                out.copy_from_slice(&px[(bps * c)..(bps * (c + 1))]);
+            }
+        }
+    }
+}
+
+/// Run the demosaicing engine, writing into the planes.
+pub(crate) fn run_engine_planar<R: Read + ?Sized>(engine: &Engine, r: &mut R,
+        depth: BayerDepth, cfa: CFA, options: &DemosaicOptions,
+        dst: &mut PlanarRasterMut)
+        -> BayerResult<()> {
+    let (w, h) = (dst.w, dst.h);
+    let frame = Frame::new(engine, depth, cfa, options, w, h)?.for_raster(dst.depth)?;
+    run_engine_strips(r, &frame, dst.depth, STRIP_ROWS, &mut |y, row| {
+        dst.put_rgb_row(y, row);
+        Ok(())
+    })
+}
+
+/// Run the demosaicing algorithm on the Bayer image, writing the red,
+/// green, and blue samples into separate planes.
+///
+/// The rows are split into the planes as they are interpolated,
+/// without a pass over the whole image.  The samples are converted
+/// to the depth of the raster as by `demosaic_with_options`.  The
+/// transform and report options do not apply.
+///
+/// # Example
+///
+/// ```
+/// let img = [0u8; 4 * 4];
+/// let mut r = [0u8; 4 * 4];
+/// let mut g = [0u8; 4 * 4];
+/// let mut b = [0u8; 4 * 4];
+///
+/// bayer::demosaic_planar(&mut &img[..],
+///         bayer::BayerDepth::Depth8,
+///         bayer::CFA::RGGB,
+///         bayer::Demosaic::Linear,
+///         &bayer::DemosaicOptions::default(),
+///         &mut bayer::PlanarRasterMut::try_new(4, 4, bayer::RasterDepth::Depth8,
+///                 [&mut r, &mut g, &mut b]).unwrap())
+///         .unwrap();
+/// ```
+pub fn demosaic_planar<R: Read + ?Sized>(r: &mut R,
+        depth: BayerDepth, cfa: CFA, alg: Demosaic,
+        options: &DemosaicOptions, dst: &mut PlanarRasterMut)
+        -> BayerResult<()> {
+    run_engine_planar(&alg.engine(), r, depth, cfa, options, dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{BayerDepth,BayerError,CFA,Demosaic,DemosaicOptions,RasterDepth,RasterMut,demosaic_with_options};
+    use super::{PlanarRasterMut,demosaic_planar};
+
+    #[test]
+    fn test_planar() {
+        // R: set.seed(0); matrix(floor(runif(n=30, min=0, max=256)), nrow=5, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,232, 51,
+            229,241,169,161, 15, 52,
+             45,175, 98,197,253,116,
+            183,120,208, 81, 33,160,
+            112,124, 74,210,245, 88 ];
+        let options = DemosaicOptions::default();
+
+        for &alg in Demosaic::all().iter() {
+            let mut expected = [0u8; 6 * 6 * 5];
+            let res = demosaic_with_options(&mut &src[..], BayerDepth::Depth8, CFA::GRBG, alg,
+                    &options, &mut RasterMut::new(6, 5, RasterDepth::Depth16, &mut expected));
+            if res.is_err() {
+                continue;
+            }
+
+            // The planes are 16 bits deep, with rows padded to 8 pixels.
+            let wide: Vec<u8> = src.iter().flat_map(|&v| ((v as u16) << 8).to_be_bytes().to_vec()).collect();
+            let mut r = [0u8; 2 * 8 * 5];
+            let mut g = [0u8; 2 * 8 * 5];
+            let mut b = [0u8; 2 * 8 * 5];
+            let res = demosaic_planar(&mut &wide[..], BayerDepth::Depth16BE, CFA::GRBG, alg,
+                    &options, &mut PlanarRasterMut::try_with_strides(6, 5, RasterDepth::Depth16,
+                            [&mut r, &mut g, &mut b], [16; 3]).unwrap());
+            assert!(res.is_ok());
+
+            for y in 0..5 {
+                for x in 0..6 {
+                    for (c, plane) in [&r, &g, &b].iter().enumerate() {
+                        let i = 6 * (6 * y + x) + 2 * c;
+                        let j = 16 * y + 2 * x;
+                        assert_eq!(&plane[j..(j + 2)], &expected[i..(i + 2)]);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_depth() {
+        // The samples are converted as by demosaic_with_options.
+        let src: Vec<u8> = (0..(4 * 4)).map(|i| (i * 16) as u8).collect();
+        let options = DemosaicOptions::default();
+        for &depth in [RasterDepth::Depth16, RasterDepth::Depth32F].iter() {
+            let bps = depth.bytes_per_sample();
+            let mut expected = vec![0u8; 3 * bps * 4 * 4];
+            let res = demosaic_with_options(&mut &src[..], BayerDepth::Depth8, CFA::RGGB,
+                    Demosaic::Linear, &options, &mut RasterMut::new(4, 4, depth, &mut expected));
+            assert!(res.is_ok());
+
+            let mut r = vec![0u8; bps * 4 * 4];
+            let mut g = vec![0u8; bps * 4 * 4];
+            let mut b = vec![0u8; bps * 4 * 4];
+            let res = demosaic_planar(&mut &src[..], BayerDepth::Depth8, CFA::RGGB,
+                    Demosaic::Linear, &options,
+                    &mut PlanarRasterMut::try_new(4, 4, depth, [&mut r, &mut g, &mut b]).unwrap());
+            assert!(res.is_ok());
+
+            for (i, px) in expected.chunks(3 * bps).enumerate() {
+                for (c, plane) in [&r, &g, &b].iter().enumerate() {
+                    assert_eq!(&plane[(bps * i)..(bps * (i + 1))], &px[(bps * c)..(bps * (c + 1))]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_short_plane() {
+        let mut r = [0u8; 4 * 4];
+        let mut g = [0u8; 4 * 4];
+        let mut b = [0u8; 4 * 3];
+        match PlanarRasterMut::try_new(4, 4, RasterDepth::Depth8, [&mut r, &mut g, &mut b]) {
+            Err(BayerError::OutputTooShort { expected: 16, got: 12 }) => (),
+            _ => panic!(),
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! Splitting Bayer images into colour planes, and merging them.
+//!
+//! The planes are stored one after another in the order R, G1, G2, B,
+//! where G1 is the green pixel on the same row as the red pixel, and
+//! G2 is the green pixel on the same row as the blue pixel.  Each
+//! plane holds (w / 2) x (h / 2) samples.
+
+use ::{BayerDepth,BayerResult,CFA};
+use bayer::{check_input_len,check_output_len,check_unpacked};
+
+/// The position of the R, G1, G2, B pixels within the 2x2 block.
+pub(crate) fn plane_offsets(cfa: CFA) -> [(usize, usize); 4] {
+    let mut offsets = [(0, 0); 4];
+
+    for &(dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)].iter() {
+        let plane = match cfa.at(dx, dy).channel() {
+            0 => 0,
+            2 => 3,
+            _ if cfa.at(0, dy).channel() == 0 || cfa.at(1, dy).channel() == 0 => 1,
+            _ => 2,
+        };
+        offsets[plane] = (dx, dy);
+    }
+
+    offsets
+}
+
+/// Call f(i, j) for each sample, where i and j are the byte offsets
+/// of the sample in the Bayer image and in the planes.
+fn copy_planes<F>(depth: BayerDepth, w: usize, h: usize, cfa: CFA, mut f: F)
+        where F: FnMut(usize, usize) {
+    let bps = depth.bytes_per_sample();
+    let (plane_w, plane_h) = (w / 2, h / 2);
+    let plane_len = plane_w * plane_h;
+
+    for (p, &(dx, dy)) in plane_offsets(cfa).iter().enumerate() {
+        for y in 0..plane_h {
+            for x in 0..plane_w {
+                let i = bps * (w * (2 * y + dy) + 2 * x + dx);
+                let j = bps * (plane_len * p + plane_w * y + x);
+                f(i, j);
+            }
+        }
+    }
+}
+
+/// Split a w x h Bayer image into its R, G1, G2, B planes.
+///
+/// If w or h is odd, the last column or row is dropped.  Returns the
+/// dimensions of each plane.
+///
+/// # Example
+///
+/// ```
+/// let src = [
+///     1,2,3,4,
+///     5,6,7,8 ];
+/// let mut dst = [0u8; 8];
+///
+/// let (w, h) = bayer::split_planes(&src, bayer::BayerDepth::Depth8, 4, 2,
+///         bayer::CFA::GRBG, &mut dst).unwrap();
+/// assert_eq!((w, h), (2, 1));
+/// assert_eq!(dst, [2,4, 1,3, 6,8, 5,7]);
+/// ```
+pub fn split_planes(src: &[u8], depth: BayerDepth, w: usize, h: usize,
+        cfa: CFA, dst: &mut [u8])
+        -> BayerResult<(usize, usize)> {
+    check_unpacked(depth)?;
+    check_input_len(src.len(), depth, w, h)?;
+    check_output_len(dst.len(), depth, 2 * (w / 2), 2 * (h / 2))?;
+
+    let bps = depth.bytes_per_sample();
+    copy_planes(depth, w, h, cfa, |i, j| {
+        dst[j..(j + bps)].copy_from_slice(&src[i..(i + bps)]);
+    });
+
+    Ok((w / 2, h / 2))
+}
+
+/// Merge R, G1, G2, B planes of plane_w x plane_h samples into a
+/// Bayer image of (2 * plane_w) x (2 * plane_h) pixels.
+///
+/// This is the inverse of `split_planes`.
+pub fn merge_planes(src: &[u8], depth: BayerDepth,
+        plane_w: usize, plane_h: usize, cfa: CFA, dst: &mut [u8])
+        -> BayerResult<()> {
+    let w = plane_w.checked_mul(2).unwrap_or(::std::usize::MAX);
+    let h = plane_h.checked_mul(2).unwrap_or(::std::usize::MAX);
+    check_unpacked(depth)?;
+    check_input_len(src.len(), depth, w, h)?;
+    check_output_len(dst.len(), depth, w, h)?;
+
+    let bps = depth.bytes_per_sample();
+    copy_planes(depth, w, h, cfa, |i, j| {
+        dst[i..(i + bps)].copy_from_slice(&src[j..(j + bps)]);
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{BayerDepth,CFA};
+    use super::{merge_planes,split_planes};
+
+    #[test]
+    fn test_split() {
+        // Label each pixel of a 4x4 image by its colour, for each CFA.
+        let cases = [
+            (CFA::BGGR, [ 4,3,  2,1 ]),
+            (CFA::GBRG, [ 3,4,  1,2 ]),
+            (CFA::GRBG, [ 2,1,  4,3 ]),
+            (CFA::RGGB, [ 1,2,  3,4 ]) ];
+
+        for &(cfa, block) in cases.iter() {
+            let mut src = [0u8; 4 * 3];
+            for y in 0..3 {
+                for x in 0..4 {
+                    src[4 * y + x] = block[2 * (y % 2) + x % 2];
+                }
+            }
+
+            let mut dst = [0u8; 4 * 2];
+            let res = split_planes(&src, BayerDepth::Depth8, 4, 3, cfa, &mut dst);
+            assert_eq!(res.unwrap(), (2, 1));
+            assert_eq!(dst, [1,1, 2,2, 3,3, 4,4]);
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let src: Vec<u8> = (0..(2 * 6 * 4)).map(|i| i as u8).collect();
+        let mut planes = vec![0u8; src.len()];
+        let mut dst = vec![0u8; src.len()];
+
+        let res = split_planes(&src, BayerDepth::Depth16BE, 6, 4, CFA::GBRG, &mut planes);
+        assert_eq!(res.unwrap(), (3, 2));
+
+        let res = merge_planes(&planes, BayerDepth::Depth16BE, 3, 2, CFA::GBRG, &mut dst);
+        assert!(res.is_ok());
+        assert_eq!(dst, src);
+    }
+}
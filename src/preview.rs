@@ -0,0 +1,363 @@
+//! Fast previews of Bayer images.
+
+use std::cmp;
+use std::io::{self,Read};
+
+use ::{BayerDepth,BayerError,BayerResult,CFA,Demosaic,DemosaicOptions,RasterDepth,RasterMut};
+use bayer::{check_packing,get_sample};
+use demosaic::{RowFormat,check_conversion};
+use demosaic::calibration::Calibration;
+use errcode::OVERFLOW;
+use planes::plane_offsets;
+
+/// The transfer curve that maps linear sensor values to display
+/// values.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum ToneCurve {
+    /// No curve; the values are only scaled down to 8 bits.
+    Linear,
+    /// A power law with the given gamma, e.g. 2.2.
+    Gamma(f64),
+    /// The sRGB transfer function.
+    Srgb,
+}
+
+impl ToneCurve {
+    /// Map a linear value in [0, 1] to a display value in [0, 1].
+    fn apply(self, x: f64) -> f64 {
+        match self {
+            ToneCurve::Linear => x,
+            ToneCurve::Gamma(gamma) => x.powf(1.0 / gamma),
+            ToneCurve::Srgb =>
+                if x <= 0.003_130_8 {
+                    12.92 * x
+                } else {
+                    1.055 * x.powf(1.0 / 2.4) - 0.055
+                },
+        }
+    }
+
+    /// The table mapping samples up to max to 8-bit display values.
+    fn lut(self, max: usize) -> Vec<u8> {
+        (0..=max).map(|v| {
+            let y = self.apply(v as f64 / max as f64);
+            (y.clamp(0.0, 1.0) * 255.0 + 0.5) as u8
+        }).collect()
+    }
+}
+
+/// Skip n bytes of the source.
+fn skip<R: Read + ?Sized>(r: &mut R, n: usize) -> BayerResult<()> {
+    let skipped = io::copy(&mut r.take(n as u64), &mut io::sink())?;
+    if skipped < n as u64 {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+
+    Ok(())
+}
+
+/// Produce a small RGB preview of a w x h Bayer image, without
+/// demosaicing it.
+///
+/// Each preview pixel is taken from a single 2x2 block: the red and
+/// blue samples, and the average of the two green samples.  Every
+/// Nth block is sampled, where N is the smallest step that fits the
+/// preview in the raster.  The other rows are skipped without being
+/// decoded, so this is much faster than a full demosaic.
+///
+/// The preview is written to the top left corner of dst, and its
+/// dimensions are returned.  The samples are converted to the depth
+/// of the raster as by `demosaic_with_options` with the default
+/// options.  The whole image is consumed from the reader.
+///
+/// # Example
+///
+/// ```
+/// let width: usize = 320;
+/// let height: usize = 200;
+/// let img = vec![0; width * height];
+/// let mut buf = vec![0; 3 * 64 * 64];
+///
+/// let (w, h) = bayer::preview(&mut &img[..],
+///         bayer::BayerDepth::Depth8, bayer::CFA::RGGB, width, height,
+///         &mut bayer::RasterMut::new(64, 64, bayer::RasterDepth::Depth8, &mut buf))
+///         .unwrap();
+/// assert_eq!((w, h), (53, 33));
+/// ```
+pub fn preview<R: Read + ?Sized>(r: &mut R,
+        depth: BayerDepth, cfa: CFA, w: usize, h: usize,
+        dst: &mut RasterMut)
+        -> BayerResult<(usize, usize)> {
+    if w < 2 || h < 2 {
+        return Err(BayerError::WrongResolution {
+            width: w, height: h, min_width: 2, min_height: 2,
+        });
+    }
+    let options = DemosaicOptions::default();
+    check_conversion(depth, dst.depth, &options)?;
+    check_packing(depth, w)?;
+
+    // The samples are converted to the depth of the raster as by
+    // `demosaic_with_options`, widening 8-bit samples for 16-bit
+    // rasters.
+    let widen = match (depth, dst.depth) {
+        (BayerDepth::Depth8, RasterDepth::Depth16) =>
+            Some(Calibration::widened(&options, None, w, h)?),
+        _ => None,
+    };
+    let format = RowFormat::for_image(&options, depth, dst);
+
+    let (blocks_w, blocks_h) = (w / 2, h / 2);
+    let step_x = (blocks_w + dst.w - 1) / cmp::max(dst.w, 1);
+    let step_y = (blocks_h + dst.h - 1) / cmp::max(dst.h, 1);
+    let step = cmp::max(cmp::max(step_x, step_y), 1);
+    let (preview_w, preview_h) = (blocks_w / step, blocks_h / step);
+
+    let row_len = depth.byte_len(w).ok_or(OVERFLOW)?;
+    let mut rows = vec![0u8; row_len.checked_mul(2).ok_or(OVERFLOW)?];
+    let offsets = plane_offsets(cfa);
+    let mut out = vec![0u32; 3 * preview_w];
+    let mut y = 0;
+
+    for py in 0..preview_h {
+        let sy = 2 * step * py;
+        skip(r, row_len.saturating_mul(sy - y))?;
+        r.read_exact(&mut rows)?;
+        y = sy + 2;
+
+        for (px, rgb) in out.chunks_mut(3).enumerate() {
+            let sx = 2 * step * px;
+            let mut s = [0; 4];
+            for (v, &(dx, dy)) in s.iter_mut().zip(offsets.iter()) {
+                *v = get_sample(&rows, depth, w * dy + sx + dx);
+            }
+
+            rgb.copy_from_slice(&[s[0], (s[1] + s[2] + 1) / 2, s[3]]);
+        }
+
+        if let Some(ref widen) = widen {
+            widen.apply(sy, &mut out);
+        }
+        format.put_row(py, &out, dst);
+    }
+
+    skip(r, row_len.saturating_mul(h - y))?;
+    Ok((preview_w, preview_h))
+}
+
+/// Demosaic a Bayer image into an 8-bit RGB raster ready for display,
+/// in one call.
+///
+/// The image is demosaiced with bilinear interpolation, 16-bit
+/// samples are scaled up from their significant bits (see
+/// `DemosaicOptions::source_bits`), and the tone curve is applied to
+/// every channel.  The raster must be 8 bits deep and the size of
+/// the image.
+///
+/// # Example
+///
+/// ```
+/// // A flat 12-bit image at half scale.
+/// let img = [0x00, 0x08].repeat(4 * 4);
+/// let mut buf = [0u8; 3 * 4 * 4];
+///
+/// bayer::tone_mapped_preview(&mut &img[..],
+///         bayer::BayerDepth::Depth16LE, bayer::CFA::RGGB, 12, bayer::ToneCurve::Gamma(2.2),
+///         &mut bayer::RasterMut::new(4, 4, bayer::RasterDepth::Depth8, &mut buf))
+///         .unwrap();
+/// assert!(buf.iter().all(|&v| v == 186));
+/// ```
+pub fn tone_mapped_preview<R: Read>(r: &mut R,
+        depth: BayerDepth, cfa: CFA, source_bits: u8, curve: ToneCurve,
+        dst: &mut RasterMut)
+        -> BayerResult<()> {
+    if dst.depth != RasterDepth::Depth8 {
+        return Err(BayerError::WrongDepth { bayer: depth, raster: dst.depth });
+    }
+
+    let mut options = DemosaicOptions::default();
+    options.source_bits = source_bits;
+    let (w, h) = (dst.w, dst.h);
+
+    match depth {
+        BayerDepth::Depth8 => {
+            ::demosaic_from(r, depth, cfa, Demosaic::Linear, &options, dst)?;
+
+            // The tone curve does not apply to the alpha channel.
+            let lut = curve.lut(0xFF);
+            let channels = dst.layout.channels();
+            for y in 0..h {
+                for px in dst.borrow_pixels_mut(y).chunks_mut(channels) {
+                    for e in px[0..3].iter_mut() {
+                        *e = lut[*e as usize];
+                    }
+                }
+            }
+        },
+        BayerDepth::Depth16BE | BayerDepth::Depth16LE | BayerDepth::Depth10Packed
+            | BayerDepth::Depth12Packed | BayerDepth::Depth12PackedDng
+            | BayerDepth::Depth14Packed => {
+            let len = w.checked_mul(h).and_then(|n| n.checked_mul(6)).ok_or(OVERFLOW)?;
+            let mut buf = vec![0u8; len];
+            let mut linear = RasterMut::new(w, h, RasterDepth::Depth16, &mut buf);
+            ::demosaic_from(r, depth, cfa, Demosaic::Linear, &options, &mut linear)?;
+
+            let lut = curve.lut(0xFFFF);
+            for y in 0..h {
+                let src = linear.borrow_row_u16_mut(y);
+                dst.fill_rgb_row(y, w, |row: &mut [u8]| {
+                    for (e, &v) in row.iter_mut().zip(src.iter()) {
+                        *e = lut[v as usize];
+                    }
+                });
+            }
+        },
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{BayerDepth,BayerError,CFA,RasterDepth,RasterLayout,RasterMut};
+    use super::{ToneCurve,preview,tone_mapped_preview};
+
+    #[test]
+    fn test_preview() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+
+        let expected = [
+             51,150,229,   241,188, 95,
+            175,103,169,   197, 75, 15 ];
+
+        let mut buf = [0u8; 3 * 3 * 3];
+        let res = preview(&mut &src[..], BayerDepth::Depth8, CFA::BGGR, 4, 4,
+                &mut RasterMut::new(3, 3, RasterDepth::Depth8, &mut buf));
+        assert_eq!(res.unwrap(), (2, 2));
+        assert_eq!(&buf[0..6], &expected[0..6]);
+        assert_eq!(&buf[9..15], &expected[6..12]);
+
+        let mut buf = [0u8; 3];
+        let mut r = &src[..];
+        let res = preview(&mut r, BayerDepth::Depth8, CFA::BGGR, 4, 4,
+                &mut RasterMut::new(1, 1, RasterDepth::Depth8, &mut buf));
+        assert_eq!(res.unwrap(), (1, 1));
+        assert_eq!(&buf[..], &expected[0..3]);
+        assert!(r.is_empty());
+    }
+
+    #[test]
+    fn test_preview_u16() {
+        let src = [
+            0x01,0x00, 0x02,0x00, 0xFF,0xFF,
+            0x03,0x00, 0x04,0x00, 0xFF,0xFF,
+            0xFF,0xFF, 0xFF,0xFF, 0xFF,0xFF ];
+        let mut buf = [0u8; 6];
+
+        let res = preview(&mut &src[..], BayerDepth::Depth16LE, CFA::GRBG, 3, 3,
+                &mut RasterMut::new(1, 1, RasterDepth::Depth16, &mut buf));
+        assert_eq!(res.unwrap(), (1, 1));
+
+        let mut dst = RasterMut::new(1, 1, RasterDepth::Depth16, &mut buf);
+        assert_eq!(dst.borrow_row_u16_mut(0), [2, 3, 3]);
+    }
+
+    #[test]
+    fn test_preview_depth() {
+        // 8-bit samples are widened for 16-bit rasters, and divided
+        // for floating-point rasters.
+        let src = [10u8, 20, 30, 40];
+        let mut buf = [0u8; 6];
+        let res = preview(&mut &src[..], BayerDepth::Depth8, CFA::RGGB, 2, 2,
+                &mut RasterMut::new(1, 1, RasterDepth::Depth16, &mut buf));
+        assert_eq!(res.unwrap(), (1, 1));
+
+        let mut dst = RasterMut::new(1, 1, RasterDepth::Depth16, &mut buf);
+        assert_eq!(dst.borrow_row_u16_mut(0), [10 << 8, 25 << 8, 40 << 8]);
+
+        let mut buf = [0u8; 12];
+        let res = preview(&mut &src[..], BayerDepth::Depth8, CFA::RGGB, 2, 2,
+                &mut RasterMut::new(1, 1, RasterDepth::Depth32F, &mut buf));
+        assert_eq!(res.unwrap(), (1, 1));
+        let rgb: Vec<f32> = buf.chunks(4).map(|v| f32::from_ne_bytes([v[0], v[1], v[2], v[3]])).collect();
+        assert_eq!(rgb, [10.0 / 255.0, 25.0 / 255.0, 40.0 / 255.0]);
+    }
+
+    #[test]
+    fn test_tone_mapped_preview() {
+        // Flat 10-bit images in big-endian 16-bit words.
+        let run = |v: u16, curve: ToneCurve| {
+            let src = v.to_be_bytes().repeat(4 * 2);
+            let mut buf = [0u8; 3 * 4 * 2];
+            let res = tone_mapped_preview(&mut &src[..], BayerDepth::Depth16BE, CFA::GBRG, 10,
+                    curve, &mut RasterMut::new(4, 2, RasterDepth::Depth8, &mut buf));
+            assert!(res.is_ok());
+            assert!(buf.iter().all(|&e| e == buf[0]));
+            buf[0]
+        };
+
+        assert_eq!(run(0, ToneCurve::Srgb), 0);
+        assert_eq!(run(1, ToneCurve::Srgb), 3);
+        assert_eq!(run(512, ToneCurve::Srgb), 188);
+        assert_eq!(run(1023, ToneCurve::Srgb), 255);
+        assert_eq!(run(1, ToneCurve::Linear), 0);
+        assert_eq!(run(512, ToneCurve::Linear), 128);
+
+        let src = [0u8; 2 * 4 * 2];
+        let mut buf = [0u8; 6 * 4 * 2];
+        let res = tone_mapped_preview(&mut &src[..], BayerDepth::Depth16BE, CFA::GBRG, 10,
+                ToneCurve::Srgb, &mut RasterMut::new(4, 2, RasterDepth::Depth16, &mut buf));
+        match res {
+            Err(BayerError::WrongDepth { .. }) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_layout() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+
+        let mut buf = [0u8; 4 * 2 * 2];
+        let res = preview(&mut &src[..], BayerDepth::Depth8, CFA::BGGR, 4, 4,
+                &mut RasterMut::try_with_layout(0, 0, 2, 2, 4 * 2, RasterDepth::Depth8,
+                        RasterLayout::Bgra { alpha: 0x80 }, &mut buf).unwrap());
+        assert_eq!(res.unwrap(), (2, 2));
+        assert_eq!(buf, [
+            229,150, 51,0x80,  95,188,241,0x80,
+            169,103,175,0x80,  15, 75,197,0x80 ]);
+
+        // The tone curve leaves the alpha channel alone.
+        for &depth in [BayerDepth::Depth8, BayerDepth::Depth16BE].iter() {
+            let src = vec![0u8; depth.byte_len(4 * 2).unwrap()];
+            let mut buf = [0u8; 4 * 4 * 2];
+            let res = tone_mapped_preview(&mut &src[..], depth, CFA::GBRG, 8, ToneCurve::Srgb,
+                    &mut RasterMut::try_with_layout(0, 0, 4, 2, 4 * 4, RasterDepth::Depth8,
+                            RasterLayout::Rgba { alpha: 0x80 }, &mut buf).unwrap());
+            assert!(res.is_ok());
+            assert!(buf.chunks(4).all(|px| px == [0, 0, 0, 0x80]));
+        }
+    }
+
+    #[test]
+    fn test_preview_short() {
+        let src = [0u8; 4 * 3];
+        let mut buf = [0u8; 3 * 2 * 2];
+
+        let res = preview(&mut &src[..], BayerDepth::Depth8, CFA::RGGB, 4, 4,
+                &mut RasterMut::new(2, 2, RasterDepth::Depth8, &mut buf));
+        match res {
+            Err(BayerError::Io(_)) => (),
+            _ => panic!(),
+        }
+    }
+}
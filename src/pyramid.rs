@@ -0,0 +1,264 @@
+//! Demosaicing into a multi-resolution pyramid.
+
+use std::io::Read;
+use std::mem;
+
+use ::{BayerDepth,BayerError,BayerResult,CFA,Demosaic,DemosaicOptions,RasterDepth,RasterMut};
+use demosaic::{Engine,Frame,RowFormat};
+use demosaic::strips::run_engine_strips;
+
+/// The number of rows interpolated at a time.
+const STRIP_ROWS: usize = 16;
+
+/// Average each 2x2 block of pixels in the rows a and b.
+fn downsample_rows(a: &[u32], b: &[u32], dst: &mut [u32]) {
+    for (x, d) in dst.chunks_mut(3).enumerate() {
+        for c in 0..3 {
+            let i = 6 * x + c;
+            d[c] = (a[i] + a[i + 3] + b[i] + b[i + 3] + 2) / 4;
+        }
+    }
+}
+
+/// Run the demosaicing engine on the Bayer image, writing the full
+/// resolution image into the first level, and halving the resolution
+/// at each subsequent level.
+pub(crate) fn run_engine_pyramid<R: Read + ?Sized>(engine: &Engine, r: &mut R,
+        depth: BayerDepth, cfa: CFA, options: &DemosaicOptions,
+        levels: &mut [RasterMut])
+        -> BayerResult<()> {
+    if levels.is_empty() {
+        return Err(BayerError::InvalidArgument { reason: "no pyramid levels" });
+    }
+
+    for i in 0..levels.len() {
+        if levels[i].depth != levels[0].depth {
+            return Err(BayerError::InvalidArgument {
+                reason: "the pyramid levels must have the same depth",
+            });
+        }
+        if i > 0 {
+            let (w, h) = (levels[i - 1].w / 2, levels[i - 1].h / 2);
+            if (levels[i].w, levels[i].h) != (w, h) {
+                return Err(BayerError::DimensionMismatch {
+                    width: w, height: h, raster_width: levels[i].w, raster_height: levels[i].h,
+                });
+            }
+        }
+    }
+
+    let (w, h) = (levels[0].w, levels[0].h);
+    let mut rows: Vec<Vec<u32>> = levels.iter().map(|l| vec![0; 3 * l.w]).collect();
+    let mut pending = rows.clone();
+
+    // The levels are averaged from the samples of the frame, then
+    // converted to the depth of the levels.
+    let frame = Frame::new(engine, depth, cfa, options, w, h)?.for_raster(levels[0].depth)?;
+    let formats: Vec<RowFormat> = levels.iter().map(|l| RowFormat::new(&frame, l)).collect();
+    let sample_depth = frame.sample_depth();
+    run_engine_strips(r, &frame, sample_depth, STRIP_ROWS, &mut |y, row| {
+        match sample_depth {
+            RasterDepth::Depth8 => {
+                for (d, &s) in rows[0].iter_mut().zip(row.iter()) {
+                    *d = s as u32;
+                }
+            },
+            _ => {
+                for (d, s) in rows[0].iter_mut().zip(row.chunks(2)) {
+                    *d = u16::from_ne_bytes([s[0], s[1]]) as u32;
+                }
+            },
+        }
+
+        // Each odd row completes a row of the next level.
+        let mut y = y;
+        for i in 0..levels.len() {
+            formats[i].put_row(y, &rows[i], &mut levels[i]);
+            if i + 1 == levels.len() {
+                break;
+            }
+            if y % 2 == 0 {
+                mem::swap(&mut rows[i], &mut pending[i]);
+                break;
+            }
+
+            let (upper, lower) = rows.split_at_mut(i + 1);
+            downsample_rows(&pending[i], &upper[i], &mut lower[0]);
+            y = y / 2;
+        }
+
+        Ok(())
+    })
+}
+
+/// Run the demosaicing algorithm on a Bayer image, producing the full
+/// resolution image and a series of downscaled levels in one pass.
+///
+/// levels[0] receives the whole image, and must have its dimensions.
+/// Each following level has half the width and height of the level
+/// before, rounded down, and each of its pixels is the average of a
+/// 2x2 block of that level.  All of the levels have the same depth.
+/// There must be at least one level.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+///
+/// let width: usize = 320;
+/// let height: usize = 200;
+/// let img = vec![0; width * height];
+///
+/// let mut bufs: Vec<Vec<u8>> = (0..4)
+///     .map(|i| vec![0; 3 * (width >> i) * (height >> i)])
+///     .collect();
+/// let mut levels: Vec<bayer::RasterMut> = bufs.iter_mut().enumerate()
+///     .map(|(i, buf)| bayer::RasterMut::new(width >> i, height >> i,
+///             bayer::RasterDepth::Depth8, buf))
+///     .collect();
+///
+/// bayer::demosaic_pyramid(&mut Cursor::new(&img[..]),
+///         bayer::BayerDepth::Depth8,
+///         bayer::CFA::RGGB,
+///         bayer::Demosaic::Linear,
+///         &bayer::DemosaicOptions::default(),
+///         &mut levels).unwrap();
+/// ```
+pub fn demosaic_pyramid<R: Read>(r: &mut R,
+        depth: BayerDepth, cfa: CFA, alg: Demosaic,
+        options: &DemosaicOptions, levels: &mut [RasterMut])
+        -> BayerResult<()> {
+    run_engine_pyramid(&alg.engine(), r, depth, cfa, options, levels)
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{BayerDepth,BayerError,CFA,Demosaic,DemosaicOptions,RasterDepth,RasterLayout,RasterMut,run_demosaic};
+    use super::demosaic_pyramid;
+
+    /// Average each 2x2 block of a w x h RGB image.
+    fn box_average(src: &[u8], w: usize, h: usize) -> Vec<u8> {
+        let (dst_w, dst_h) = (w / 2, h / 2);
+        let mut dst = vec![0u8; 3 * dst_w * dst_h];
+        for (i, d) in dst.iter_mut().enumerate() {
+            let (x, y, c) = ((i / 3) % dst_w, i / (3 * dst_w), i % 3);
+            let at = |dx, dy| src[3 * (w * (2 * y + dy) + 2 * x + dx) + c] as u32;
+            *d = ((at(0, 0) + at(1, 0) + at(0, 1) + at(1, 1) + 2) / 4) as u8;
+        }
+        dst
+    }
+
+    #[test]
+    fn test_pyramid() {
+        // R: set.seed(0); matrix(floor(runif(n=30, min=0, max=256)), nrow=5, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,232, 51,
+            229,241,169,161, 15, 52,
+             45,175, 98,197,253,116,
+            183,120,208, 81, 33,160,
+            112,124, 74,210,245, 88 ];
+
+        const IMG_W: usize = 6;
+        const IMG_H: usize = 5;
+        let mut expected = [0u8; 3 * IMG_W * IMG_H];
+        let res = run_demosaic(&mut &src[..], BayerDepth::Depth8, CFA::RGGB, Demosaic::Cubic,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut expected));
+        assert!(res.is_ok());
+        let expected1 = box_average(&expected, 6, 5);
+        let expected2 = box_average(&expected1, 3, 2);
+
+        let mut buf0 = [0u8; 3 * 6 * 5];
+        let mut buf1 = [0u8; 3 * 3 * 2];
+        let mut buf2 = [0u8; 3 * 1 * 1];
+        {
+            let mut levels = [
+                RasterMut::new(6, 5, RasterDepth::Depth8, &mut buf0),
+                RasterMut::new(3, 2, RasterDepth::Depth8, &mut buf1),
+                RasterMut::new(1, 1, RasterDepth::Depth8, &mut buf2) ];
+            let res = demosaic_pyramid(&mut &src[..], BayerDepth::Depth8, CFA::RGGB,
+                    Demosaic::Cubic, &DemosaicOptions::default(), &mut levels);
+            assert!(res.is_ok());
+        }
+
+        assert_eq!(&buf0[..], &expected[..]);
+        assert_eq!(&buf1[..], &expected1[..]);
+        assert_eq!(&buf2[..], &expected2[..]);
+
+        // The reduced levels are written in their own layouts.
+        let mut buf1 = [0u8; 4 * 3 * 2];
+        let mut buf2 = [0u8; 3 * 1 * 1];
+        {
+            let mut levels = [
+                RasterMut::new(6, 5, RasterDepth::Depth8, &mut buf0),
+                RasterMut::try_with_layout(0, 0, 3, 2, 4 * 3, RasterDepth::Depth8,
+                        RasterLayout::Rgba { alpha: 0xFFFF }, &mut buf1).unwrap(),
+                RasterMut::try_with_layout(0, 0, 1, 1, 3, RasterDepth::Depth8,
+                        RasterLayout::Bgr, &mut buf2).unwrap() ];
+            let res = demosaic_pyramid(&mut &src[..], BayerDepth::Depth8, CFA::RGGB,
+                    Demosaic::Cubic, &DemosaicOptions::default(), &mut levels);
+            assert!(res.is_ok());
+        }
+
+        for (px, expected) in buf1.chunks(4).zip(expected1.chunks(3)) {
+            assert_eq!(&px[0..3], expected);
+            assert_eq!(px[3], 0xFF);
+        }
+        assert_eq!(buf2, [expected2[2], expected2[1], expected2[0]]);
+    }
+
+    #[test]
+    fn test_depth() {
+        // The levels are converted as by run_demosaic.
+        let src: Vec<u8> = (0..(4 * 4)).map(|i| (i * 16) as u8).collect();
+        let mut expected = [0u8; 6 * 4 * 4];
+        let res = run_demosaic(&mut &src[..], BayerDepth::Depth8, CFA::RGGB, Demosaic::Linear,
+                &mut RasterMut::new(4, 4, RasterDepth::Depth16, &mut expected));
+        assert!(res.is_ok());
+
+        let mut buf0 = [0u8; 6 * 4 * 4];
+        let mut buf1 = [0u8; 6 * 2 * 2];
+        {
+            let mut levels = [
+                RasterMut::new(4, 4, RasterDepth::Depth16, &mut buf0),
+                RasterMut::new(2, 2, RasterDepth::Depth16, &mut buf1) ];
+            let res = demosaic_pyramid(&mut &src[..], BayerDepth::Depth8, CFA::RGGB,
+                    Demosaic::Linear, &DemosaicOptions::default(), &mut levels);
+            assert!(res.is_ok());
+        }
+        assert_eq!(&buf0[..], &expected[..]);
+
+        let mut levels = [
+            RasterMut::new(4, 4, RasterDepth::Depth16, &mut buf0),
+            RasterMut::new(2, 2, RasterDepth::Depth8, &mut buf1[0..12]) ];
+        let res = demosaic_pyramid(&mut &src[..], BayerDepth::Depth8, CFA::RGGB,
+                Demosaic::Linear, &DemosaicOptions::default(), &mut levels);
+        match res {
+            Err(BayerError::InvalidArgument { .. }) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_mismatch() {
+        let src = [0u8; 4 * 4];
+        let mut buf0 = [0u8; 3 * 4 * 4];
+        let mut buf1 = [0u8; 3 * 1 * 1];
+
+        let mut levels = [
+            RasterMut::new(4, 4, RasterDepth::Depth8, &mut buf0),
+            RasterMut::new(1, 1, RasterDepth::Depth8, &mut buf1) ];
+        let res = demosaic_pyramid(&mut &src[..], BayerDepth::Depth8, CFA::RGGB,
+                Demosaic::Linear, &DemosaicOptions::default(), &mut levels);
+        match res {
+            Err(BayerError::DimensionMismatch { width: 2, height: 2, raster_width: 1, raster_height: 1 }) => (),
+            _ => panic!(),
+        }
+
+        let res = demosaic_pyramid(&mut &src[..], BayerDepth::Depth8, CFA::RGGB,
+                Demosaic::Linear, &DemosaicOptions::default(), &mut []);
+        match res {
+            Err(BayerError::InvalidArgument { .. }) => (),
+            _ => panic!(),
+        }
+    }
+}
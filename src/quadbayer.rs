@@ -0,0 +1,192 @@
+//! Quad-Bayer images.
+//!
+//! Quad-Bayer (or Tetracell) sensors group 2x2 pixels of the same
+//! colour, so the image is a Bayer pattern of 2x2 blocks.  Such
+//! images are either binned into a normal Bayer image of half the
+//! resolution, or remosaiced into a normal Bayer image of the same
+//! resolution and then demosaiced.
+
+use std::cmp;
+use std::io::Read;
+
+use ::{BayerDepth,BayerResult,CFA};
+use bayer::{check_input_len,check_output_len,get_sample,put_sample};
+use errcode::OVERFLOW;
+
+/// Halve the resolution of a w x h quad-Bayer image by averaging each
+/// 2x2 group of same-coloured pixels.
+///
+/// The binned image is a Bayer image with the same depth, whose CFA
+/// pattern is the pattern of the groups.  Returns the dimensions of the binned image, (w / 2, h / 2).
+///
+/// # Example
+///
+/// ```
+/// let src = [
+///     10,12, 20,22,
+///     14,16, 24,26,
+///     30,32, 40,42,
+///     34,36, 44,46 ];
+/// let mut dst = [0u8; 4];
+///
+/// let (w, h) = bayer::bin_quad(&src, bayer::BayerDepth::Depth8, 4, 4,
+///         &mut dst).unwrap();
+/// assert_eq!((w, h), (2, 2));
+/// assert_eq!(dst, [13,23, 33,43]);
+/// ```
+pub fn bin_quad(src: &[u8], depth: BayerDepth, w: usize, h: usize,
+        dst: &mut [u8])
+        -> BayerResult<(usize, usize)> {
+    check_input_len(src.len(), depth, w, h)?;
+
+    let (dst_w, dst_h) = (w / 2, h / 2);
+    check_output_len(dst.len(), depth, dst_w, dst_h)?;
+
+    for y in 0..dst_h {
+        for x in 0..dst_w {
+            let i = w * (2 * y) + 2 * x;
+            let sum = get_sample(src, depth, i) + get_sample(src, depth, i + 1)
+                + get_sample(src, depth, i + w) + get_sample(src, depth, i + w + 1);
+
+            put_sample(dst, depth, dst_w * y + x, (sum + 2) / 4);
+        }
+    }
+
+    Ok((dst_w, dst_h))
+}
+
+/// Convert a w x h quad-Bayer image into a Bayer image of the same
+/// resolution, depth and CFA pattern.
+///
+/// cfa is the pattern of the groups.  Each pixel whose colour differs
+/// in the Bayer pattern is replaced by the mean of the pixels of the
+/// Bayer colour around it.
+///
+/// # Example
+///
+/// ```
+/// let src = [
+///     10,10, 20,20,
+///     10,10, 20,20,
+///     20,20, 30,30,
+///     20,20, 30,30 ];
+/// let mut dst = [0u8; 16];
+///
+/// bayer::remosaic_quad(&src, bayer::BayerDepth::Depth8, 4, 4,
+///         bayer::CFA::RGGB, &mut dst).unwrap();
+/// assert_eq!(&dst[..4], &[10,20, 10,20]);
+/// ```
+pub fn remosaic_quad(src: &[u8], depth: BayerDepth, w: usize, h: usize,
+        cfa: CFA, dst: &mut [u8])
+        -> BayerResult<()> {
+    check_input_len(src.len(), depth, w, h)?;
+    check_output_len(dst.len(), depth, w, h)?;
+
+    let quad = |x: usize, y: usize| cfa.at(x / 2, y / 2).channel();
+
+    for y in 0..h {
+        for x in 0..w {
+            let i = w * y + x;
+            let c = cfa.at(x, y).channel();
+            if quad(x, y) == c {
+                put_sample(dst, depth, i, get_sample(src, depth, i));
+                continue;
+            }
+
+            // The pixels of colour c within a radius of 1, or 2 at the
+            // edges of the image.
+            let mut v = get_sample(src, depth, i);
+            for radius in 1..3 {
+                let mut sum = 0;
+                let mut n = 0;
+                for sy in y.saturating_sub(radius)..cmp::min(y + radius + 1, h) {
+                    for sx in x.saturating_sub(radius)..cmp::min(x + radius + 1, w) {
+                        if quad(sx, sy) == c {
+                            sum = sum + get_sample(src, depth, w * sy + sx);
+                            n = n + 1;
+                        }
+                    }
+                }
+
+                if n > 0 {
+                    v = (sum + n / 2) / n;
+                    break;
+                }
+            }
+
+            put_sample(dst, depth, i, v);
+        }
+    }
+
+    Ok(())
+}
+
+/// Read a w x h quad-Bayer image and remosaic it.
+pub(crate) fn read_remosaiced<R: Read>(r: &mut R, depth: BayerDepth,
+        w: usize, h: usize, cfa: CFA)
+        -> BayerResult<Vec<u8>> {
+    let len = w.checked_mul(h)
+        .and_then(|len| depth.byte_len(len))
+        .ok_or(OVERFLOW)?;
+    let mut src = vec![0u8; len];
+    r.read_exact(&mut src)?;
+
+    let mut dst = vec![0u8; len];
+    remosaic_quad(&src, depth, w, h, cfa, &mut dst)?;
+    Ok(dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{BayerDepth,CFA,CfaPattern,Demosaic,DemosaicOptions,RasterDepth,RasterMut,demosaic_pattern};
+    use super::{bin_quad,remosaic_quad};
+
+    #[test]
+    fn test_bin_odd() {
+        // The last row and column of an odd image are dropped.
+        let src = [
+            0x01,0x00, 0x02,0x00, 0x03,0x00,
+            0x04,0x00, 0x05,0x00, 0x06,0x00,
+            0x07,0x00, 0x08,0x00, 0xFF,0xFF ];
+
+        let mut dst = [0u8; 2];
+        let res = bin_quad(&src, BayerDepth::Depth16LE, 3, 3, &mut dst);
+        assert_eq!(res.unwrap(), (1, 1));
+        assert_eq!(dst, [0x03,0x00]);
+    }
+
+    #[test]
+    fn test_remosaic() {
+        // GRBG groups of a red, green and blue image.
+        let src = [
+            50,50, 10,10, 50,50,
+            50,50, 10,10, 50,50,
+            90,90, 50,50, 90,90,
+            90,90, 50,50, 90,90 ];
+
+        let expected = [
+            50,10, 50,10, 50,10,
+            90,50, 90,50, 90,50,
+            50,10, 50,10, 50,10,
+            90,50, 90,50, 90,50 ];
+
+        let mut dst = [0u8; 24];
+        let res = remosaic_quad(&src, BayerDepth::Depth8, 6, 4, CFA::GRBG, &mut dst);
+        assert!(res.is_ok());
+        assert_eq!(&dst[..], &expected[..]);
+    }
+
+    #[test]
+    fn test_demosaic() {
+        // A flat image stays flat.
+        let src = [77u8; 8 * 6];
+        let mut buf = [0u8; 3 * 8 * 6];
+
+        let pattern = CfaPattern::QuadBayer(CFA::BGGR);
+        let res = demosaic_pattern(&mut &src[..], BayerDepth::Depth8, &pattern,
+                Demosaic::Cubic, &DemosaicOptions::default(),
+                &mut RasterMut::new(8, 6, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+        assert!(buf.iter().all(|&v| v == 77));
+    }
+}
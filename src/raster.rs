@@ -1,19 +1,160 @@
 //! Raster implementation.
 
+use std::mem;
 use std::slice;
 
-use ::RasterMut;
+use ::{BayerError,BayerResult,Raster,RasterMut};
+use errcode::OVERFLOW;
 
 /// Depth of a raster.
 #[derive(Clone,Copy,Debug,Eq,PartialEq)]
 pub enum RasterDepth {
     Depth8,
+    /// Samples in host byte order.  The buffer must be aligned to 2
+    /// bytes.
     Depth16,
+    /// Floating-point samples, normalised according to
+    /// DemosaicOptions::float_divisor.
+    Depth32F,
+}
+
+/// Layout of the pixels of a raster.
+///
+/// Only the demosaicing functions that write a whole image into a
+/// raster support layouts other than RGB.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum RasterLayout {
+    /// Red, green, blue.
+    Rgb,
+    /// Red, green, blue, and a constant alpha value, clamped to the
+    /// largest sample value of the raster.  For floating-point
+    /// rasters, the alpha value is divided by 65535.
+    Rgba { alpha: u16 },
+    /// Blue, green, red, e.g. for OpenCV.
+    Bgr,
+    /// Blue, green, red, and a constant alpha value, see Rgba.
+    Bgra { alpha: u16 },
+}
+
+impl RasterLayout {
+    /// The number of samples per pixel.
+    pub fn channels(self) -> usize {
+        match self {
+            RasterLayout::Rgb | RasterLayout::Bgr => 3,
+            RasterLayout::Rgba { .. } | RasterLayout::Bgra { .. } => 4,
+        }
+    }
+
+    /// The index within a pixel of channel c (0 = red, 1 = green,
+    /// 2 = blue).
+    pub(crate) fn index(self, c: usize) -> usize {
+        match self {
+            RasterLayout::Rgb | RasterLayout::Rgba { .. } => c,
+            RasterLayout::Bgr | RasterLayout::Bgra { .. } => 2 - c,
+        }
+    }
+
+    /// The alpha value of the layout, if any.
+    fn alpha(self) -> u16 {
+        match self {
+            RasterLayout::Rgba { alpha } | RasterLayout::Bgra { alpha } => alpha,
+            RasterLayout::Rgb | RasterLayout::Bgr => 0xFFFF,
+        }
+    }
+
+    /// Rearrange a row of w RGB pixels, held in the start of the row,
+    /// into this layout.
+    pub(crate) fn expand_row<T: RasterSample>(self, w: usize, row: &mut [T]) {
+        self.expand_row_with(w, row, T::saturating_from(self.alpha() as u32))
+    }
+
+    /// Rearrange a row of w floating-point RGB pixels, held in the
+    /// start of the row of bytes, into this layout.
+    pub(crate) fn expand_row_f32(self, w: usize, row: &mut [u8]) {
+        let alpha = self.alpha() as f32 / 65535.0;
+        self.expand_row_with(w, f32_samples_mut(row), alpha.to_ne_bytes())
+    }
+
+    fn expand_row_with<T: Copy>(self, w: usize, row: &mut [T], alpha: T) {
+        match self {
+            RasterLayout::Rgb => (),
+            RasterLayout::Bgr => {
+                for px in row[0..(3 * w)].chunks_mut(3) {
+                    px.swap(0, 2);
+                }
+            },
+            RasterLayout::Rgba { .. } | RasterLayout::Bgra { .. } => {
+                let bgr = match self { RasterLayout::Bgra { .. } => true, _ => false };
+
+                // Move the pixels from the end, so that each pixel is
+                // read before it is overwritten.
+                for x in (0..w).rev() {
+                    row.copy_within((3 * x)..(3 * x + 3), 4 * x);
+                    if bgr {
+                        row.swap(4 * x, 4 * x + 2);
+                    }
+                    row[4 * x + 3] = alpha;
+                }
+            },
+        }
+    }
+}
+
+/// Reinterpret a row of bytes as a row of floating-point samples, in
+/// bytes, without requiring alignment.
+pub(crate) fn f32_samples_mut(buf: &mut [u8]) -> &mut [[u8; 4]] {
+    let len = buf.len() / 4;
+    unsafe {
+        slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut [u8; 4], len)
+    }
+}
+
+/// Check that a raster of the given geometry fits in the buffer.
+/// The rows of 16-bit rasters must be aligned for their samples;
+/// floating-point samples are accessed without requiring alignment.
+fn check_geometry(x: usize, y: usize, w: usize, h: usize, stride: usize,
+        depth: RasterDepth, layout: RasterLayout, buf: &[u8])
+        -> BayerResult<()> {
+    let len = buf.len();
+    let bytes_per_pixel = depth.bytes_per_sample() * layout.channels();
+    let x1 = x.checked_add(w).ok_or(OVERFLOW)?;
+    let y1 = y.checked_add(h).ok_or(OVERFLOW)?;
+    let row_end = x1.checked_mul(bytes_per_pixel).ok_or(OVERFLOW)?;
+    let end = stride.checked_mul(y1).ok_or(OVERFLOW)?;
+
+    if w == 0 || h == 0 {
+        return Err(BayerError::InvalidArgument { reason: "empty raster" });
+    }
+    if row_end > stride || stride % bytes_per_pixel != 0 {
+        return Err(BayerError::InvalidArgument { reason: "invalid raster stride" });
+    }
+    if end > len {
+        return Err(BayerError::OutputTooShort { expected: end, got: len });
+    }
+    // The stride is a multiple of the pixel size, so every row is
+    // aligned if the buffer is.
+    if depth == RasterDepth::Depth16 && buf.as_ptr() as usize % mem::align_of::<u16>() != 0 {
+        return Err(BayerError::InvalidArgument { reason: "misaligned raster buffer" });
+    }
+
+    Ok(())
+}
+
+/// Panic with the error from an invalid raster geometry.
+fn expect_geometry(res: BayerResult<()>) {
+    if let Err(e) = res {
+        panic!("{}", e);
+    }
 }
 
 impl<'a> RasterMut<'a> {
     /// Allocate a new raster for the given destination buffer slice.
     ///
+    /// # Panics
+    ///
+    /// Panics if the buffer does not hold a w x h image.  See
+    /// `try_new`.
+    ///
     /// # Examples
     ///
     /// ```
@@ -32,9 +173,30 @@ impl<'a> RasterMut<'a> {
         Self::with_offset(0, 0, w, h, stride, depth, buf)
     }
 
+    /// Allocate a new raster for the given destination buffer slice,
+    /// returning an error instead of panicking if the buffer does not
+    /// hold a w x h image.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut buf = [0; 3 * 2 * 2];
+    /// assert!(bayer::RasterMut::try_new(2, 2, bayer::RasterDepth::Depth8, &mut buf).is_ok());
+    /// assert!(bayer::RasterMut::try_new(2, 3, bayer::RasterDepth::Depth8, &mut buf).is_err());
+    /// ```
+    pub fn try_new(w: usize, h: usize, depth: RasterDepth, buf: &'a mut [u8])
+            -> BayerResult<Self> {
+        let stride = w.checked_mul(depth.bytes_per_pixel()).ok_or(OVERFLOW)?;
+        Self::try_with_offset(0, 0, w, h, stride, depth, buf)
+    }
+
     /// Allocate a new raster for the given destination buffer slice.
     /// Stride is in number of bytes.
     ///
+    /// # Panics
+    ///
+    /// Panics if the geometry is invalid.  See `try_with_offset`.
+    ///
     /// # Examples
     ///
     /// ```
@@ -50,15 +212,92 @@ impl<'a> RasterMut<'a> {
             x: usize, y: usize, w: usize, h: usize, stride: usize,
             depth: RasterDepth, buf: &'a mut [u8])
             -> Self {
-        let x1 = x.checked_add(w).expect("overflow");
-        let y1 = y.checked_add(h).expect("overflow");
-        let bytes_per_pixel = depth.bytes_per_pixel();
-        assert!(x < x1 && x1.checked_mul(bytes_per_pixel).expect("overflow") <= stride && h > 0);
-        assert!(stride.checked_mul(y1).expect("overflow") <= buf.len());
-        assert_eq!(stride % bytes_per_pixel, 0);
+        expect_geometry(check_geometry(x, y, w, h, stride, depth, RasterLayout::Rgb, buf));
 
         RasterMut {
             x, y, w, h, stride, depth, buf,
+            layout: RasterLayout::Rgb,
+        }
+    }
+
+    /// Allocate a new raster for the given destination buffer slice,
+    /// returning an error instead of panicking if the geometry is
+    /// invalid.  Stride is in number of bytes.
+    pub fn try_with_offset(
+            x: usize, y: usize, w: usize, h: usize, stride: usize,
+            depth: RasterDepth, buf: &'a mut [u8])
+            -> BayerResult<Self> {
+        check_geometry(x, y, w, h, stride, depth, RasterLayout::Rgb, buf)?;
+
+        Ok(RasterMut {
+            x, y, w, h, stride, depth, buf,
+            layout: RasterLayout::Rgb,
+        })
+    }
+
+    /// Allocate a new raster with the given pixel layout for the given
+    /// destination buffer slice.  Stride is in number of bytes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// const IMG_W: usize = 320;
+    /// const IMG_H: usize = 200;
+    /// let mut buf = [0; 4 * IMG_W * IMG_H];
+    ///
+    /// bayer::RasterMut::try_with_layout(
+    ///         0, 0, IMG_W, IMG_H, 4 * IMG_W, bayer::RasterDepth::Depth8,
+    ///         bayer::RasterLayout::Rgba { alpha: 0xFFFF }, &mut buf)
+    ///         .unwrap();
+    /// ```
+    pub fn try_with_layout(
+            x: usize, y: usize, w: usize, h: usize, stride: usize,
+            depth: RasterDepth, layout: RasterLayout, buf: &'a mut [u8])
+            -> BayerResult<Self> {
+        check_geometry(x, y, w, h, stride, depth, layout, buf)?;
+
+        Ok(RasterMut {
+            x, y, w, h, stride, depth, layout, buf,
+        })
+    }
+
+    /// Borrow the samples of all the pixels of row y, in the layout of
+    /// the raster.
+    pub(crate) fn borrow_pixels_mut(&mut self, y: usize) -> &mut [u8] {
+        assert!(y < self.h);
+
+        let bytes_per_pixel = self.depth.bytes_per_sample() * self.layout.channels();
+        let start = self.stride * (self.y + y) + bytes_per_pixel * self.x;
+        let end = start + bytes_per_pixel * self.w;
+        &mut self.buf[start..end]
+    }
+
+    /// Write a row of RGB pixels, in bytes, into the start of row y,
+    /// in the layout of the raster.
+    pub(crate) fn put_rgb_row(&mut self, y: usize, row: &[u8]) {
+        let layout = self.layout;
+        let depth = self.depth;
+        let bytes_per_pixel = depth.bytes_per_sample() * layout.channels();
+        let w = row.len() / depth.bytes_per_pixel();
+        let pixels = &mut self.borrow_pixels_mut(y)[0..(bytes_per_pixel * w)];
+        pixels[0..row.len()].copy_from_slice(row);
+        match depth {
+            RasterDepth::Depth8 => layout.expand_row(w, pixels),
+            RasterDepth::Depth16 => layout.expand_row(w, u16::from_bytes_mut(pixels)),
+            RasterDepth::Depth32F => layout.expand_row_f32(w, pixels),
+        }
+    }
+
+    /// Write the first w pixels of row y, in the layout of the raster,
+    /// from a row of RGB samples filled in by f.
+    pub(crate) fn fill_rgb_row<T, G>(&mut self, y: usize, w: usize, f: G)
+            where T: RasterSample, G: FnOnce(&mut [T]) {
+        if self.layout == RasterLayout::Rgb {
+            f(&mut T::from_bytes_mut(self.borrow_pixels_mut(y))[0..(3 * w)]);
+        } else {
+            let mut row = vec![T::default(); 3 * w];
+            f(&mut row);
+            self.put_rgb_row(y, T::as_bytes(&row));
         }
     }
 
@@ -66,10 +305,11 @@ impl<'a> RasterMut<'a> {
     ///
     /// # Panics
     ///
-    /// Panics if the raster is not 8-bpp.
+    /// Panics if the raster is not 8-bpp RGB.
     pub fn borrow_row_u8_mut(&mut self, y: usize)
             -> &mut [u8] {
         assert!(self.depth == RasterDepth::Depth8);
+        assert!(self.layout == RasterLayout::Rgb);
         assert!(y < self.h);
 
         let bytes_per_pixel = 3;
@@ -82,10 +322,11 @@ impl<'a> RasterMut<'a> {
     ///
     /// # Panics
     ///
-    /// Panics if the raster is not 16-bpp.
+    /// Panics if the raster is not 16-bpp RGB.
     pub fn borrow_row_u16_mut(&mut self, y: usize)
             -> &mut [u16] {
         assert!(self.depth == RasterDepth::Depth16);
+        assert!(self.layout == RasterLayout::Rgb);
         assert!(y < self.h);
 
         let bytes_per_pixel = 6;
@@ -97,22 +338,302 @@ impl<'a> RasterMut<'a> {
             slice::from_raw_parts_mut(s.as_mut_ptr() as *mut u16, 3 * self.w)
         }
     }
+
+    /// Borrow a mutable f32 row slice.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the raster is not floating-point RGB, or if the row
+    /// is not aligned to 4 bytes.
+    pub fn borrow_row_f32_mut(&mut self, y: usize)
+            -> &mut [f32] {
+        assert!(self.depth == RasterDepth::Depth32F);
+        assert!(self.layout == RasterLayout::Rgb);
+        assert!(y < self.h);
+
+        let bytes_per_pixel = 12;
+        let start = self.stride * (self.y + y) + bytes_per_pixel * self.x;
+        let end = start + bytes_per_pixel * self.w;
+        let s = &mut self.buf[start..end];
+        assert!(s.as_ptr() as usize % mem::align_of::<f32>() == 0);
+
+        unsafe {
+            slice::from_raw_parts_mut(s.as_mut_ptr() as *mut f32, 3 * self.w)
+        }
+    }
+}
+
+impl<'a> Raster<'a> {
+    /// Create a read-only raster for the given source buffer slice.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffer does not hold a w x h image.  See
+    /// `try_new`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// const IMG_W: usize = 320;
+    /// const IMG_H: usize = 200;
+    /// let buf = [0; 3 * IMG_W * IMG_H];
+    ///
+    /// bayer::Raster::new(
+    ///         IMG_W, IMG_H, bayer::RasterDepth::Depth8,
+    ///         &buf);
+    /// ```
+    pub fn new(w: usize, h: usize, depth: RasterDepth, buf: &'a [u8])
+            -> Self {
+        let bytes_per_pixel = depth.bytes_per_pixel();
+        let stride = w.checked_mul(bytes_per_pixel).expect("overflow");
+        Self::with_offset(0, 0, w, h, stride, depth, buf)
+    }
+
+    /// Create a read-only raster for the given source buffer slice,
+    /// returning an error instead of panicking if the buffer does not
+    /// hold a w x h image.
+    pub fn try_new(w: usize, h: usize, depth: RasterDepth, buf: &'a [u8])
+            -> BayerResult<Self> {
+        let stride = w.checked_mul(depth.bytes_per_pixel()).ok_or(OVERFLOW)?;
+        Self::try_with_offset(0, 0, w, h, stride, depth, buf)
+    }
+
+    /// Create a read-only raster for the given source buffer slice.
+    /// Stride is in number of bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the geometry is invalid.  See `try_with_offset`.
+    pub fn with_offset(
+            x: usize, y: usize, w: usize, h: usize, stride: usize,
+            depth: RasterDepth, buf: &'a [u8])
+            -> Self {
+        expect_geometry(check_geometry(x, y, w, h, stride, depth, RasterLayout::Rgb, buf));
+
+        Raster {
+            x, y, w, h, stride, depth, buf,
+            layout: RasterLayout::Rgb,
+        }
+    }
+
+    /// Create a read-only raster for the given source buffer slice,
+    /// returning an error instead of panicking if the geometry is
+    /// invalid.  Stride is in number of bytes.
+    pub fn try_with_offset(
+            x: usize, y: usize, w: usize, h: usize, stride: usize,
+            depth: RasterDepth, buf: &'a [u8])
+            -> BayerResult<Self> {
+        check_geometry(x, y, w, h, stride, depth, RasterLayout::Rgb, buf)?;
+
+        Ok(Raster {
+            x, y, w, h, stride, depth, buf,
+            layout: RasterLayout::Rgb,
+        })
+    }
+
+    /// Borrow a u8 row slice.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the raster is not 8-bpp RGB.
+    pub fn borrow_row_u8(&self, y: usize) -> &[u8] {
+        assert!(self.depth == RasterDepth::Depth8);
+        assert!(self.layout == RasterLayout::Rgb);
+        assert!(y < self.h);
+
+        let bytes_per_pixel = 3;
+        let start = self.stride * (self.y + y) + bytes_per_pixel * self.x;
+        let end = start + bytes_per_pixel * self.w;
+        &self.buf[start..end]
+    }
+
+    /// Borrow a u16 row slice.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the raster is not 16-bpp RGB.
+    pub fn borrow_row_u16(&self, y: usize) -> &[u16] {
+        assert!(self.depth == RasterDepth::Depth16);
+        assert!(self.layout == RasterLayout::Rgb);
+        assert!(y < self.h);
+
+        let bytes_per_pixel = 6;
+        let start = self.stride * (self.y + y) + bytes_per_pixel * self.x;
+        let end = start + bytes_per_pixel * self.w;
+        let s = &self.buf[start..end];
+
+        unsafe {
+            slice::from_raw_parts(s.as_ptr() as *const u16, 3 * self.w)
+        }
+    }
+
+    /// Borrow a f32 row slice.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the raster is not floating-point RGB, or if the row
+    /// is not aligned to 4 bytes.
+    pub fn borrow_row_f32(&self, y: usize) -> &[f32] {
+        assert!(self.depth == RasterDepth::Depth32F);
+        assert!(self.layout == RasterLayout::Rgb);
+        assert!(y < self.h);
+
+        let bytes_per_pixel = 12;
+        let start = self.stride * (self.y + y) + bytes_per_pixel * self.x;
+        let end = start + bytes_per_pixel * self.w;
+        let s = &self.buf[start..end];
+        assert!(s.as_ptr() as usize % mem::align_of::<f32>() == 0);
+
+        unsafe {
+            slice::from_raw_parts(s.as_ptr() as *const f32, 3 * self.w)
+        }
+    }
+
+    /// Read the red, green and blue samples of row y into row, in any
+    /// layout.  Floating-point samples are clamped to [0, 1] and
+    /// scaled to 16 bits.
+    pub(crate) fn read_rgb_row(&self, y: usize, row: &mut Vec<u16>) {
+        assert!(y < self.h);
+
+        let layout = self.layout;
+        let bytes = self.depth.bytes_per_sample();
+        let bytes_per_pixel = bytes * layout.channels();
+        let start = self.stride * (self.y + y) + bytes_per_pixel * self.x;
+        let end = start + bytes_per_pixel * self.w;
+
+        row.clear();
+        for px in self.buf[start..end].chunks(bytes_per_pixel) {
+            for c in 0..3 {
+                let s = &px[(bytes * layout.index(c))..];
+                row.push(match self.depth {
+                    RasterDepth::Depth8 => s[0] as u16,
+                    RasterDepth::Depth16 => u16::from_ne_bytes([s[0], s[1]]),
+                    RasterDepth::Depth32F => {
+                        let f = f32::from_ne_bytes([s[0], s[1], s[2], s[3]]);
+                        (f.max(0.0).min(1.0) * 65535.0 + 0.5) as u16
+                    },
+                });
+            }
+        }
+    }
+}
+
+impl<'a> RasterMut<'a> {
+    /// Borrow the raster as a read-only raster.
+    pub fn as_raster(&self) -> Raster<'_> {
+        Raster {
+            x: self.x, y: self.y, w: self.w, h: self.h,
+            stride: self.stride, depth: self.depth, layout: self.layout,
+            buf: &*self.buf,
+        }
+    }
+}
+
+/// Sample types that may be stored in a raster, or in the rows of
+/// the demosaicing engine.
+pub trait RasterSample: Copy + Default + Send + Sync {
+    /// Reinterpret a row of bytes as a row of samples.
+    fn from_bytes_mut(buf: &mut [u8]) -> &mut [Self];
+
+    /// Convert a raw value, saturating at the largest sample value.
+    fn saturating_from(v: u32) -> Self;
+
+    /// Widen the sample to a raw value.
+    fn to_u32(self) -> u32;
+
+    /// Reinterpret a row of samples as a row of bytes.
+    fn as_bytes(buf: &[Self]) -> &[u8];
+}
+
+impl RasterSample for u8 {
+    fn from_bytes_mut(buf: &mut [u8]) -> &mut [u8] {
+        buf
+    }
+
+    fn saturating_from(v: u32) -> u8 {
+        if v > 0xFF { 0xFF } else { v as u8 }
+    }
+
+    fn to_u32(self) -> u32 {
+        self as u32
+    }
+
+    fn as_bytes(buf: &[u8]) -> &[u8] {
+        buf
+    }
+}
+
+impl RasterSample for u16 {
+    fn from_bytes_mut(buf: &mut [u8]) -> &mut [u16] {
+        let len = buf.len() / mem::size_of::<u16>();
+        unsafe {
+            slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u16, len)
+        }
+    }
+
+    fn saturating_from(v: u32) -> u16 {
+        if v > 0xFFFF { 0xFFFF } else { v as u16 }
+    }
+
+    fn to_u32(self) -> u32 {
+        self as u32
+    }
+
+    fn as_bytes(buf: &[u16]) -> &[u8] {
+        let len = buf.len() * mem::size_of::<u16>();
+        unsafe {
+            slice::from_raw_parts(buf.as_ptr() as *const u8, len)
+        }
+    }
+}
+
+/// The samples of the engine rows of images calibrated with a
+/// pedestal, which leaves no headroom in 8 or 16 bits.  There are no
+/// 32-bit integer rasters.
+impl RasterSample for u32 {
+    fn from_bytes_mut(buf: &mut [u8]) -> &mut [u32] {
+        let len = buf.len() / mem::size_of::<u32>();
+        unsafe {
+            slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u32, len)
+        }
+    }
+
+    fn saturating_from(v: u32) -> u32 {
+        v
+    }
+
+    fn to_u32(self) -> u32 {
+        self
+    }
+
+    fn as_bytes(buf: &[u32]) -> &[u8] {
+        let len = buf.len() * mem::size_of::<u32>();
+        unsafe {
+            slice::from_raw_parts(buf.as_ptr() as *const u8, len)
+        }
+    }
 }
 
 impl RasterDepth {
-    /// The number of bytes per pixel for a raster of the given depth.
-    fn bytes_per_pixel(self) -> usize {
+    /// The number of bytes per pixel of an RGB raster of the given depth.
+    pub fn bytes_per_pixel(self) -> usize {
+        3 * self.bytes_per_sample()
+    }
+
+    /// The number of bytes per sample for a raster of the given depth.
+    pub fn bytes_per_sample(self) -> usize {
         match self {
-            RasterDepth::Depth8 => 3,
-            RasterDepth::Depth16 => 6,
+            RasterDepth::Depth8 => 1,
+            RasterDepth::Depth16 => 2,
+            RasterDepth::Depth32F => 4,
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use ::RasterMut;
-    use super::RasterDepth;
+    use ::{Raster,RasterMut};
+    use super::{RasterDepth,RasterLayout};
 
     #[test]
     #[should_panic]
@@ -122,6 +643,60 @@ mod tests {
                 ::std::usize::MAX, ::std::usize::MAX, RasterDepth::Depth8, &mut buf);
     }
 
+    #[test]
+    fn test_try_new() {
+        let mut buf = [0; 3 * 4 * 2];
+        assert!(RasterMut::try_new(
+                ::std::usize::MAX, ::std::usize::MAX, RasterDepth::Depth8, &mut buf).is_err());
+        assert!(RasterMut::try_new(4, 0, RasterDepth::Depth8, &mut buf).is_err());
+        assert!(RasterMut::try_with_offset(
+                1, 0, 4, 2, 3 * 4, RasterDepth::Depth8, &mut buf).is_err());
+        assert!(RasterMut::try_with_offset(
+                0, 0, 2, 2, 5, RasterDepth::Depth8, &mut buf).is_err());
+        assert!(Raster::try_new(4, 3, RasterDepth::Depth8, &buf).is_err());
+        assert!(Raster::try_new(2, 2, RasterDepth::Depth16, &buf).is_ok());
+    }
+
+    #[test]
+    fn test_put_rgb_row() {
+        let mut buf = [0u8; 8 * 2];
+        {
+            let mut dst = RasterMut::try_with_layout(0, 0, 2, 1, 8 * 2, RasterDepth::Depth16,
+                    RasterLayout::Rgba { alpha: 0x1234 }, &mut buf).unwrap();
+            let row: Vec<u8> = [1u16, 2, 3, 4, 5, 6].iter()
+                .flat_map(|v| v.to_ne_bytes().to_vec())
+                .collect();
+            dst.put_rgb_row(0, &row);
+        }
+
+        let expected: Vec<u8> = [1u16, 2, 3, 0x1234, 4, 5, 6, 0x1234].iter()
+            .flat_map(|v| v.to_ne_bytes().to_vec())
+            .collect();
+        assert_eq!(&buf[..], &expected[..]);
+
+        let mut buf = [0u8; 3 * 2];
+        assert!(RasterMut::try_with_layout(0, 0, 2, 1, 3 * 2, RasterDepth::Depth8,
+                RasterLayout::Rgba { alpha: 0 }, &mut buf).is_err());
+    }
+
+    #[test]
+    fn test_expand_row() {
+        let rgb = [1u8,2,3, 4,5,6];
+        let layouts = [
+            (RasterLayout::Rgb, &[1,2,3, 4,5,6][..]),
+            (RasterLayout::Bgr, &[3,2,1, 6,5,4][..]),
+            (RasterLayout::Rgba { alpha: 0x1FF }, &[1,2,3,255, 4,5,6,255][..]),
+            (RasterLayout::Bgra { alpha: 7 }, &[3,2,1,7, 6,5,4,7][..]) ];
+
+        for &(layout, expected) in layouts.iter() {
+            let mut row = [0u8; 8];
+            let row = &mut row[0..(2 * layout.channels())];
+            row[0..6].copy_from_slice(&rgb);
+            layout.expand_row(2, row);
+            assert_eq!(&row[..], expected);
+        }
+    }
+
     #[test]
     fn test_borrow_row_u16_mut() {
         let expected = [
@@ -0,0 +1,216 @@
+//! Demosaicing and resampling in one pass.
+
+use std::io::Read;
+use std::mem;
+
+use ::{BayerDepth,BayerResult,CFA,Demosaic,DemosaicOptions,RasterDepth,RasterMut};
+use demosaic::{Engine,Frame,RowFormat};
+use demosaic::strips::run_engine_strips;
+use errcode::OVERFLOW;
+
+/// The number of rows interpolated at a time.
+const STRIP_ROWS: usize = 16;
+
+/// The two source samples nearest to each output sample, and the
+/// weight of the second one.
+fn sample_positions(src_len: usize, dst_len: usize) -> Vec<(usize, usize, f32)> {
+    let scale = src_len as f64 / dst_len as f64;
+    (0..dst_len).map(|i| {
+        let s = ((i as f64 + 0.5) * scale - 0.5).max(0.0);
+        // Empty images are rejected before any rows are produced.
+        let last = src_len.saturating_sub(1);
+        let s0 = (s as usize).min(last);
+        let s1 = (s0 + 1).min(last);
+        (s0, s1, (s - s0 as f64) as f32)
+    }).collect()
+}
+
+/// Decode a row of RGB samples of the given depth produced by the
+/// engine.
+fn decode_row(row: &[u8], depth: RasterDepth, dst: &mut [f32]) {
+    match depth {
+        RasterDepth::Depth8 => {
+            for (d, &s) in dst.iter_mut().zip(row.iter()) {
+                *d = s as f32;
+            }
+        },
+        _ => {
+            for (d, s) in dst.iter_mut().zip(row.chunks(2)) {
+                *d = u16::from_ne_bytes([s[0], s[1]]) as f32;
+            }
+        },
+    }
+}
+
+/// Run the demosaicing engine on a w x h Bayer image, resampling the
+/// interpolated rows to the dimensions of dst as they are produced.
+pub(crate) fn run_engine_resized<R: Read + ?Sized>(engine: &Engine, r: &mut R,
+        depth: BayerDepth, cfa: CFA, options: &DemosaicOptions,
+        w: usize, h: usize, dst: &mut RasterMut)
+        -> BayerResult<()> {
+    let xs = sample_positions(w, dst.w);
+    let ys = sample_positions(h, dst.h);
+    let row_len = w.checked_mul(3).ok_or(OVERFLOW)?;
+    let mut prev = vec![0.0f32; row_len];
+    let mut curr = vec![0.0f32; row_len];
+    let mut out = vec![0u32; 3 * dst.w];
+    let mut oy = 0;
+
+    // The rows are resampled from the samples of the frame, then
+    // converted to the depth of the raster.
+    let frame = Frame::new(engine, depth, cfa, options, w, h)?.for_raster(dst.depth)?;
+    let format = RowFormat::new(&frame, dst);
+    let sample_depth = frame.sample_depth();
+    run_engine_strips(r, &frame, sample_depth, STRIP_ROWS, &mut |y, row| {
+        mem::swap(&mut prev, &mut curr);
+        decode_row(row, sample_depth, &mut curr);
+
+        // Emit the output rows whose lower source row has arrived.
+        while oy < ys.len() && ys[oy].1 == y {
+            let (y0, _, fy) = ys[oy];
+            let upper = if y0 == y { &curr } else { &prev };
+
+            for (o, &(x0, x1, fx)) in out.chunks_mut(3).zip(xs.iter()) {
+                for c in 0..3 {
+                    let top = upper[3 * x0 + c] + (upper[3 * x1 + c] - upper[3 * x0 + c]) * fx;
+                    let bottom = curr[3 * x0 + c] + (curr[3 * x1 + c] - curr[3 * x0 + c]) * fx;
+                    o[c] = (top + (bottom - top) * fy + 0.5) as u32;
+                }
+            }
+
+            format.put_row(oy, &out, dst);
+            oy = oy + 1;
+        }
+
+        Ok(())
+    })
+}
+
+/// Run the demosaicing algorithm on a w x h Bayer image, resampling
+/// the result to the dimensions of dst.
+///
+/// The interpolated rows are resampled bilinearly as they are
+/// produced, so the full-size RGB image is never held in memory.
+/// This suits display surfaces of a different size to the sensor.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+///
+/// let width: usize = 320;
+/// let height: usize = 200;
+/// let img = vec![0; width * height];
+/// let mut buf = vec![0; 3 * 128 * 80];
+///
+/// let mut dst = bayer::RasterMut::new(
+///         128, 80, bayer::RasterDepth::Depth8,
+///         &mut buf);
+/// bayer::demosaic_resized(&mut Cursor::new(&img[..]),
+///         bayer::BayerDepth::Depth8,
+///         bayer::CFA::RGGB,
+///         bayer::Demosaic::Linear,
+///         &bayer::DemosaicOptions::default(),
+///         width, height, &mut dst).unwrap();
+/// ```
+pub fn demosaic_resized<R: Read>(r: &mut R,
+        depth: BayerDepth, cfa: CFA, alg: Demosaic,
+        options: &DemosaicOptions,
+        w: usize, h: usize, dst: &mut RasterMut)
+        -> BayerResult<()> {
+    run_engine_resized(&alg.engine(), r, depth, cfa, options, w, h, dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{BayerDepth,CFA,Demosaic,DemosaicOptions,Fill,RasterDepth,RasterLayout,RasterMut,run_demosaic};
+    use super::demosaic_resized;
+
+    // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+    const SRC: [u8; 16] = [
+        229, 67, 95,146,
+        232, 51,229,241,
+        169,161, 15, 52,
+         45,175, 98,197 ];
+
+    #[test]
+    fn test_same_size() {
+        const IMG_W: usize = 4;
+        const IMG_H: usize = 4;
+        let mut expected = [0u8; 3 * IMG_W * IMG_H];
+        let mut buf = [0u8; 3 * IMG_W * IMG_H];
+
+        let res = run_demosaic(&mut &SRC[..], BayerDepth::Depth8, CFA::RGGB, Demosaic::Cubic,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut expected));
+        assert!(res.is_ok());
+
+        let res = demosaic_resized(&mut &SRC[..], BayerDepth::Depth8, CFA::RGGB, Demosaic::Cubic,
+                &DemosaicOptions::default(), IMG_W, IMG_H,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+        assert_eq!(&buf[..], &expected[..]);
+    }
+
+    #[test]
+    fn test_half_size() {
+        const IMG_W: usize = 4;
+        const IMG_H: usize = 4;
+        let mut rgb = [0u8; 3 * IMG_W * IMG_H];
+        let mut buf = [0u8; 3 * 2 * 2];
+
+        let res = run_demosaic(&mut &SRC[..], BayerDepth::Depth8, CFA::GRBG, Demosaic::Linear,
+                &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut rgb));
+        assert!(res.is_ok());
+
+        // Each output pixel is the average of a 2x2 block.
+        let mut expected = [0u8; 3 * 2 * 2];
+        for (i, e) in expected.iter_mut().enumerate() {
+            let (x, y, c) = ((i / 3) % 2, i / 6, i % 3);
+            let at = |dx, dy| rgb[3 * (IMG_W * (2 * y + dy) + 2 * x + dx) + c] as u32;
+            *e = ((at(0, 0) + at(1, 0) + at(0, 1) + at(1, 1) + 2) / 4) as u8;
+        }
+
+        let res = demosaic_resized(&mut &SRC[..], BayerDepth::Depth8, CFA::GRBG, Demosaic::Linear,
+                &DemosaicOptions::default(), IMG_W, IMG_H,
+                &mut RasterMut::new(2, 2, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+        assert_eq!(buf, expected);
+
+        let mut buf = [0u8; 3 * 2 * 2];
+        let res = demosaic_resized(&mut &SRC[..], BayerDepth::Depth8, CFA::GRBG, Demosaic::Linear,
+                &DemosaicOptions::default(), IMG_W, IMG_H,
+                &mut RasterMut::try_with_layout(0, 0, 2, 2, 3 * 2, RasterDepth::Depth8,
+                        RasterLayout::Bgr, &mut buf).unwrap());
+        assert!(res.is_ok());
+        for (px, expected) in buf.chunks(3).zip(expected.chunks(3)) {
+            assert_eq!(px, [expected[2], expected[1], expected[0]]);
+        }
+    }
+
+    #[test]
+    fn test_upscale_u16() {
+        let src = [0x12,0x34, 0x12,0x34, 0x12,0x34, 0x12,0x34];
+        let mut buf = [0u8; 6 * 5 * 3];
+
+        let mut options = DemosaicOptions::default();
+        options.fill = Fill::Replicate;
+
+        // The samples are narrowed for 8-bit rasters.
+        let mut narrow = [0u8; 3 * 5 * 3];
+        let res = demosaic_resized(&mut &src[..], BayerDepth::Depth16BE, CFA::BGGR, Demosaic::None,
+                &options, 2, 2,
+                &mut RasterMut::new(5, 3, RasterDepth::Depth8, &mut narrow));
+        assert!(res.is_ok());
+        assert!(narrow.iter().all(|&v| v == 0x12));
+
+        let res = demosaic_resized(&mut &src[..], BayerDepth::Depth16BE, CFA::BGGR, Demosaic::None,
+                &options, 2, 2,
+                &mut RasterMut::new(5, 3, RasterDepth::Depth16, &mut buf));
+        assert!(res.is_ok());
+
+        let mut dst = RasterMut::new(5, 3, RasterDepth::Depth16, &mut buf);
+        for y in 0..3 {
+            assert!(dst.borrow_row_u16_mut(y).iter().all(|&v| v == 0x1234));
+        }
+    }
+}
@@ -0,0 +1,378 @@
+//! Demosaicing a rectangle of a Bayer image.
+
+use std::cmp;
+use std::io::{Read,Seek,SeekFrom};
+use std::sync::Arc;
+
+use ::{BayerDepth,BayerError,BayerResult,CFA,Demosaic,DemosaicOptions,Rect,RasterMut};
+use bayer::check_unpacked;
+use demosaic::{Engine,run_engine};
+
+/// A rectangle of a width x height image.
+#[derive(Clone,Copy,Debug,Default,Eq,PartialEq)]
+pub struct Region {
+    pub width: usize,
+    pub height: usize,
+    pub rect: Rect,
+}
+
+impl Region {
+    /// Create the region of rect within a width x height image.
+    pub fn new(width: usize, height: usize, rect: Rect) -> Self {
+        Region { width, height, rect }
+    }
+}
+
+/// Run the demosaicing engine on a region of a Bayer image, reading
+/// only the rows and columns required.
+pub(crate) fn run_engine_rect<R: Read + Seek + ?Sized>(engine: &Engine, r: &mut R,
+        depth: BayerDepth, cfa: CFA, options: &DemosaicOptions,
+        region: Region, dst: &mut RasterMut)
+        -> BayerResult<()> {
+    let w = region.width;
+    let bps = depth.bytes_per_sample();
+    run_engine_region(engine, depth, cfa, options, region, dst, |(x0, x1, y0, _), raw| {
+        let row_len = bps * (x1 - x0);
+        let base = r.seek(SeekFrom::Current(0))?;
+        for (i, row) in raw.chunks_mut(row_len).enumerate() {
+            let offset = bps * (w * (y0 + i) + x0);
+            r.seek(SeekFrom::Start(base + offset as u64))?;
+            r.read_exact(row)?;
+        }
+        r.seek(SeekFrom::Start(base + (bps * w * region.height) as u64))?;
+        Ok(())
+    })
+}
+
+/// Run the demosaicing engine on a region of a Bayer image, reading
+/// the whole image but keeping only the rows and columns required.
+pub(crate) fn run_engine_roi<R: Read + ?Sized>(engine: &Engine, r: &mut R,
+        depth: BayerDepth, cfa: CFA, options: &DemosaicOptions,
+        region: Region, dst: &mut RasterMut)
+        -> BayerResult<()> {
+    let bps = depth.bytes_per_sample();
+    run_engine_region(engine, depth, cfa, options, region, dst, |(x0, x1, y0, y1), raw| {
+        let row_len = bps * (x1 - x0);
+        let mut line = vec![0u8; bps * region.width];
+        for y in 0..region.height {
+            r.read_exact(&mut line)?;
+            if y0 <= y && y < y1 {
+                let i = row_len * (y - y0);
+                raw[i..(i + row_len)].copy_from_slice(&line[(bps * x0)..(bps * x1)]);
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Check the region, and extend its rectangle by the padding of the
+/// kernel, so that the pixels in the rectangle are interpolated
+/// exactly as they would be in the whole image.  Returns the columns
+/// x0..x1 and rows y0..y1 to read.
+fn extended_rect(engine: &Engine, region: Region, dst: &RasterMut)
+        -> BayerResult<(usize, usize, usize, usize)> {
+    let Region { width: w, height: h, rect } = region;
+    rect.check_bounds(w, h)?;
+    if (dst.w, dst.h) != (rect.w, rect.h) {
+        return Err(BayerError::DimensionMismatch {
+            width: rect.w, height: rect.h, raster_width: dst.w, raster_height: dst.h,
+        });
+    }
+
+    let alg = engine.algorithm();
+    let p = alg.padding();
+    let (min_w, min_h) = alg.min_dimensions();
+    let (x0, x1) = extend(rect.x, rect.x + rect.w, p, min_w, w);
+    let (y0, y1) = extend(rect.y, rect.y + rect.h, p, min_h, h);
+    Ok((x0, x1, y0, y1))
+}
+
+/// Read the raw samples of the extended rectangle of the region with
+/// read, demosaic them, then copy the rectangle into dst.
+fn run_engine_region<F>(engine: &Engine,
+        depth: BayerDepth, cfa: CFA, options: &DemosaicOptions,
+        region: Region, dst: &mut RasterMut, read: F)
+        -> BayerResult<()>
+        where F: FnOnce((usize, usize, usize, usize), &mut [u8]) -> BayerResult<()> {
+    check_unpacked(depth)?;
+    let extended = extended_rect(engine, region, dst)?;
+    let (x0, x1, y0, y1) = extended;
+    let (ew, eh) = (x1 - x0, y1 - y0);
+
+    let mut raw = vec![0u8; depth.bytes_per_sample() * ew * eh];
+    read(extended, &mut raw)?;
+
+    let bytes_per_pixel = dst.depth.bytes_per_pixel();
+    let mut rgb = vec![0u8; bytes_per_pixel * ew * eh];
+    let options = cropped_options(options, region, extended)?;
+    run_engine(engine, &mut &raw[..], depth, cfa.with_offset(x0, y0), &options,
+            &mut RasterMut::new(ew, eh, dst.depth, &mut rgb))?;
+
+    let rect = region.rect;
+    let len = bytes_per_pixel * rect.w;
+    for y in 0..rect.h {
+        let i = bytes_per_pixel * (ew * (rect.y - y0 + y) + rect.x - x0);
+        dst.put_rgb_row(y, &rgb[i..(i + len)]);
+    }
+
+    Ok(())
+}
+
+/// The options for the extended rectangle of the region.  The
+/// dark frame is cropped to the rectangle, and the black levels are
+/// rotated to start at its top-left photosite.  The rectangle is not
+/// transformed.
+fn cropped_options(options: &DemosaicOptions, region: Region,
+        (x0, x1, y0, y1): (usize, usize, usize, usize))
+        -> BayerResult<DemosaicOptions> {
+    let (w, h) = (region.width, region.height);
+    let mut options = options.clone();
+    options.transform = None;
+
+    if let Some(ref mut levels) = options.levels {
+        let black = levels.black;
+        for dy in 0..2 {
+            for dx in 0..2 {
+                levels.black[2 * dy + dx] = black[2 * ((y0 + dy) % 2) + (x0 + dx) % 2];
+            }
+        }
+    }
+
+    if let Some(dark) = options.dark_frame.take() {
+        if Some(dark.len()) != w.checked_mul(h) {
+            return Err(BayerError::InvalidArgument {
+                reason: "the dark frame does not match the image",
+            });
+        }
+        let mut cropped = Vec::with_capacity((x1 - x0) * (y1 - y0));
+        for y in y0..y1 {
+            cropped.extend_from_slice(&dark[(w * y + x0)..(w * y + x1)]);
+        }
+        options.dark_frame = Some(Arc::new(cropped));
+    }
+
+    Ok(options)
+}
+
+/// Extend the range start..end by padding on either side, and to at
+/// least min_len, within 0..len.
+fn extend(start: usize, end: usize, padding: usize, min_len: usize, len: usize)
+        -> (usize, usize) {
+    let mut start = start.saturating_sub(padding);
+    let mut end = cmp::min(end + padding, len);
+
+    while end - start < min_len && (start > 0 || end < len) {
+        if end < len {
+            end = end + 1;
+        } else {
+            start = start - 1;
+        }
+    }
+
+    (start, end)
+}
+
+/// Run the demosaicing algorithm on a region of a Bayer image,
+/// seeking past the data outside its rectangle.
+///
+/// The source must be positioned at the start of the image, and is
+/// left positioned at the end of the image.  dst must have the
+/// dimensions of the rectangle.  The result is identical to the
+/// corresponding part of the whole demosaiced image, but only the
+/// rows and columns near the rectangle are read.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+///
+/// let width: usize = 320;
+/// let height: usize = 200;
+/// let img = vec![0; width * height];
+/// let mut buf = vec![0; 3 * 16 * 16];
+///
+/// let mut dst = bayer::RasterMut::new(
+///         16, 16, bayer::RasterDepth::Depth8,
+///         &mut buf);
+/// bayer::demosaic_rect(&mut Cursor::new(&img[..]),
+///         bayer::BayerDepth::Depth8,
+///         bayer::CFA::RGGB,
+///         bayer::Demosaic::Linear,
+///         &bayer::DemosaicOptions::default(),
+///         bayer::Region::new(width, height, bayer::Rect::new(100, 50, 16, 16)),
+///         &mut dst).unwrap();
+/// ```
+pub fn demosaic_rect<R: Read + Seek>(r: &mut R,
+        depth: BayerDepth, cfa: CFA, alg: Demosaic,
+        options: &DemosaicOptions, region: Region, dst: &mut RasterMut)
+        -> BayerResult<()> {
+    run_engine_rect(&alg.engine(), r, depth, cfa, options, region, dst)
+}
+
+/// Run the demosaicing algorithm on a region of a Bayer image,
+/// reading past the data outside its rectangle.
+///
+/// This is like `demosaic_rect`, for sources that cannot seek.  The
+/// whole image is consumed, but only the rows and columns near the
+/// rectangle are kept, so no full-size buffer is allocated.
+///
+/// # Example
+///
+/// ```
+/// let width: usize = 320;
+/// let height: usize = 200;
+/// let img = vec![0; width * height];
+/// let mut buf = vec![0; 3 * 16 * 16];
+///
+/// let mut dst = bayer::RasterMut::new(
+///         16, 16, bayer::RasterDepth::Depth8,
+///         &mut buf);
+/// bayer::demosaic_roi(&mut &img[..],
+///         bayer::BayerDepth::Depth8,
+///         bayer::CFA::RGGB,
+///         bayer::Demosaic::Linear,
+///         &bayer::DemosaicOptions::default(),
+///         bayer::Region::new(width, height, bayer::Rect::new(100, 50, 16, 16)),
+///         &mut dst).unwrap();
+/// ```
+pub fn demosaic_roi<R: Read>(r: &mut R,
+        depth: BayerDepth, cfa: CFA, alg: Demosaic,
+        options: &DemosaicOptions, region: Region, dst: &mut RasterMut)
+        -> BayerResult<()> {
+    run_engine_roi(&alg.engine(), r, depth, cfa, options, region, dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor,Seek,SeekFrom};
+    use std::sync::Arc;
+    use ::{BayerDepth,CFA,Demosaic,DemosaicOptions,Levels,Rect,RasterDepth,RasterMut,
+        demosaic_with_options,run_demosaic};
+    use super::{Region,demosaic_rect,demosaic_roi};
+
+    #[test]
+    fn test_rect() {
+        // R: set.seed(0); matrix(floor(runif(n=30, min=0, max=256)), nrow=5, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,232, 51,
+            229,241,169,161, 15, 52,
+             45,175, 98,197,253,116,
+            183,120,208, 81, 33,160,
+            112,124, 74,210,245, 88 ];
+
+        const IMG_W: usize = 6;
+        const IMG_H: usize = 5;
+        let rects = [
+            Rect::new(0, 0, 6, 5), Rect::new(2, 1, 2, 3),
+            Rect::new(5, 4, 1, 1), Rect::new(0, 2, 3, 1) ];
+
+        for &alg in Demosaic::all().iter() {
+            let mut expected = [0u8; 3 * IMG_W * IMG_H];
+            let res = run_demosaic(&mut &src[..], BayerDepth::Depth8, CFA::GRBG, alg,
+                    &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut expected));
+            assert!(res.is_ok());
+
+            for &rect in rects.iter() {
+                let mut buf = vec![0u8; 3 * rect.w * rect.h];
+                let mut cursor = Cursor::new(&src[..]);
+                let res = demosaic_rect(&mut cursor, BayerDepth::Depth8, CFA::GRBG, alg,
+                        &DemosaicOptions::default(), Region::new(IMG_W, IMG_H, rect),
+                        &mut RasterMut::new(rect.w, rect.h, RasterDepth::Depth8, &mut buf));
+                assert!(res.is_ok());
+                assert_eq!(cursor.seek(SeekFrom::Current(0)).unwrap(), 30);
+
+                let mut roi = vec![0u8; 3 * rect.w * rect.h];
+                let mut rdr = &src[..];
+                let res = demosaic_roi(&mut rdr, BayerDepth::Depth8, CFA::GRBG, alg,
+                        &DemosaicOptions::default(), Region::new(IMG_W, IMG_H, rect),
+                        &mut RasterMut::new(rect.w, rect.h, RasterDepth::Depth8, &mut roi));
+                assert!(res.is_ok());
+                assert!(rdr.is_empty());
+                assert_eq!(roi, buf);
+
+                for y in 0..rect.h {
+                    let i = 3 * (IMG_W * (rect.y + y) + rect.x);
+                    assert_eq!(&buf[(3 * rect.w * y)..(3 * rect.w * (y + 1))],
+                            &expected[i..(i + 3 * rect.w)]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_rect_calibration() {
+        let src = [
+            229, 67, 95,146,232, 51,
+            229,241,169,161, 15, 52,
+             45,175, 98,197,253,116,
+            183,120,208, 81, 33,160,
+            112,124, 74,210,245, 88 ];
+
+        const IMG_W: usize = 6;
+        const IMG_H: usize = 5;
+        let rects = [
+            Rect::new(1, 1, 3, 3), Rect::new(3, 2, 2, 2),
+            Rect::new(5, 4, 1, 1), Rect::new(0, 0, 6, 5) ];
+
+        let mut dark = DemosaicOptions::default();
+        dark.dark_frame = Some(Arc::new((0..30).map(|i| 2 * i as u16).collect()));
+        let mut levels = DemosaicOptions::default();
+        levels.levels = Some(Levels { black: [10, 20, 30, 40], white: 250 });
+
+        for options in [dark, levels].iter() {
+            for &alg in Demosaic::all().iter() {
+                let mut expected = [0u8; 3 * IMG_W * IMG_H];
+                let res = demosaic_with_options(&mut &src[..], BayerDepth::Depth8, CFA::GRBG,
+                        alg, options,
+                        &mut RasterMut::new(IMG_W, IMG_H, RasterDepth::Depth8, &mut expected));
+                assert!(res.is_ok());
+
+                for &rect in rects.iter() {
+                    let mut buf = vec![0u8; 3 * rect.w * rect.h];
+                    let res = demosaic_rect(&mut Cursor::new(&src[..]), BayerDepth::Depth8,
+                            CFA::GRBG, alg, options, Region::new(IMG_W, IMG_H, rect),
+                            &mut RasterMut::new(rect.w, rect.h, RasterDepth::Depth8, &mut buf));
+                    assert!(res.is_ok());
+
+                    let mut roi = vec![0u8; 3 * rect.w * rect.h];
+                    let res = demosaic_roi(&mut &src[..], BayerDepth::Depth8, CFA::GRBG, alg,
+                            options, Region::new(IMG_W, IMG_H, rect),
+                            &mut RasterMut::new(rect.w, rect.h, RasterDepth::Depth8, &mut roi));
+                    assert!(res.is_ok());
+                    assert_eq!(roi, buf);
+
+                    for y in 0..rect.h {
+                        let i = 3 * (IMG_W * (rect.y + y) + rect.x);
+                        assert_eq!(&buf[(3 * rect.w * y)..(3 * rect.w * (y + 1))],
+                                &expected[i..(i + 3 * rect.w)]);
+                    }
+                }
+            }
+        }
+
+        // The dark frame must match the whole image.
+        let mut options = DemosaicOptions::default();
+        options.dark_frame = Some(Arc::new(vec![0; 4]));
+        let mut buf = [0u8; 3 * 2 * 2];
+        let res = demosaic_rect(&mut Cursor::new(&src[..]), BayerDepth::Depth8, CFA::GRBG,
+                Demosaic::Linear, &options, Region::new(IMG_W, IMG_H, Rect::new(0, 0, 2, 2)),
+                &mut RasterMut::new(2, 2, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_rect_errors() {
+        let src = [0u8; 16];
+        let mut buf = [0u8; 3 * 4];
+
+        let res = demosaic_rect(&mut Cursor::new(&src[..]), BayerDepth::Depth8, CFA::RGGB,
+                Demosaic::Linear, &DemosaicOptions::default(), Region::new(4, 4, Rect::new(3, 3, 2, 2)),
+                &mut RasterMut::new(2, 2, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_err());
+
+        let res = demosaic_rect(&mut Cursor::new(&src[..]), BayerDepth::Depth8, CFA::RGGB,
+                Demosaic::Linear, &DemosaicOptions::default(), Region::new(4, 4, Rect::new(0, 0, 1, 2)),
+                &mut RasterMut::new(2, 2, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_err());
+    }
+}
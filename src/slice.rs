@@ -0,0 +1,134 @@
+//! Demosaicing images that are already in memory.
+
+use ::{BayerDepth,BayerError,BayerResult,CFA,Demosaic,DemosaicOptions,RasterMut};
+use bayer::check_input_len;
+use demosaic::{Engine,run_engine};
+use raster::RasterSample;
+
+/// Run the demosaicing engine on a Bayer image held in a slice.
+pub(crate) fn run_engine_slice(engine: &Engine, src: &[u8],
+        depth: BayerDepth, cfa: CFA, options: &DemosaicOptions, dst: &mut RasterMut)
+        -> BayerResult<()> {
+    // The dimensions of the image, before the output transform.
+    let (w, h) = match options.transform {
+        Some(t) => t.dimensions(dst.w, dst.h),
+        None => (dst.w, dst.h),
+    };
+
+    if let Err(e @ BayerError::TrailingData { .. }) = check_input_len(src.len(), depth, w, h) {
+        return Err(e);
+    }
+
+    run_engine(engine, &mut &src[..], depth, cfa, options, dst)
+}
+
+/// Run the demosaicing algorithm on a Bayer image held in a slice,
+/// e.g. a memory-mapped file or a DMA buffer.
+///
+/// The rows are copied straight from the slice into the padded rows
+/// used by the interpolation kernels, without going through a
+/// reader.  The slice must not contain more than one image; a short
+/// slice is handled according to `DemosaicOptions::truncation`.
+///
+/// # Example
+///
+/// ```
+/// let width: usize = 320;
+/// let height: usize = 200;
+/// let img = vec![0; width * height];
+/// let mut buf = vec![0; 3 * width * height];
+///
+/// bayer::demosaic_slice(&img,
+///         bayer::BayerDepth::Depth8,
+///         bayer::CFA::RGGB,
+///         bayer::Demosaic::Linear,
+///         &bayer::DemosaicOptions::default(),
+///         &mut bayer::RasterMut::new(width, height, bayer::RasterDepth::Depth8, &mut buf))
+///         .unwrap();
+/// ```
+pub fn demosaic_slice(src: &[u8],
+        depth: BayerDepth, cfa: CFA, alg: Demosaic,
+        options: &DemosaicOptions, dst: &mut RasterMut)
+        -> BayerResult<()> {
+    run_engine_slice(&alg.engine(), src, depth, cfa, options, dst)
+}
+
+/// Run the demosaicing algorithm on a 16-bit Bayer image held in a
+/// slice of samples in host byte order.
+///
+/// The raster must be 16 bits deep.  See `demosaic_slice`.
+///
+/// # Example
+///
+/// ```
+/// let img = [0x0FFFu16; 4 * 4];
+/// let mut buf = [0u8; 6 * 4 * 4];
+///
+/// let mut options = bayer::DemosaicOptions::default();
+/// options.source_bits = 12;
+///
+/// bayer::demosaic_slice_u16(&img,
+///         bayer::CFA::RGGB,
+///         bayer::Demosaic::Linear,
+///         &options,
+///         &mut bayer::RasterMut::new(4, 4, bayer::RasterDepth::Depth16, &mut buf))
+///         .unwrap();
+/// assert!(buf.iter().all(|&v| v == 0xFF));
+/// ```
+pub fn demosaic_slice_u16(src: &[u16],
+        cfa: CFA, alg: Demosaic,
+        options: &DemosaicOptions, dst: &mut RasterMut)
+        -> BayerResult<()> {
+    run_engine_slice(&alg.engine(), u16::as_bytes(src), BayerDepth::native16(), cfa, options, dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{BayerDepth,BayerError,CFA,Demosaic,DemosaicOptions,RasterDepth,RasterMut,demosaic_from};
+    use super::{demosaic_slice,demosaic_slice_u16};
+
+    #[test]
+    fn test_slice() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+        let wide: Vec<u16> = src.iter().map(|&v| (v as u16) << 8).collect();
+        let options = DemosaicOptions::default();
+
+        for &alg in Demosaic::all().iter() {
+            let mut expected = [0u8; 6 * 4 * 4];
+            let res = demosaic_from(&mut &src[..], BayerDepth::Depth8, CFA::GBRG, alg, &options,
+                    &mut RasterMut::new(4, 4, RasterDepth::Depth16, &mut expected));
+            if res.is_err() {
+                continue;
+            }
+
+            let mut buf = [0u8; 6 * 4 * 4];
+            let res = demosaic_slice(&src, BayerDepth::Depth8, CFA::GBRG, alg, &options,
+                    &mut RasterMut::new(4, 4, RasterDepth::Depth16, &mut buf));
+            assert!(res.is_ok());
+            assert_eq!(&buf[..], &expected[..]);
+
+            let res = demosaic_slice_u16(&wide, CFA::GBRG, alg, &options,
+                    &mut RasterMut::new(4, 4, RasterDepth::Depth16, &mut buf));
+            assert!(res.is_ok());
+            assert_eq!(&buf[..], &expected[..]);
+        }
+    }
+
+    #[test]
+    fn test_trailing_data() {
+        let src = [0u8; 17];
+        let mut buf = [0u8; 3 * 4 * 4];
+        let res = demosaic_slice(&src, BayerDepth::Depth8, CFA::RGGB, Demosaic::Linear,
+                &DemosaicOptions::default(),
+                &mut RasterMut::new(4, 4, RasterDepth::Depth8, &mut buf));
+        match res {
+            Err(BayerError::TrailingData { extra: 1 }) => (),
+            _ => panic!(),
+        }
+    }
+}
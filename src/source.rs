@@ -0,0 +1,128 @@
+//! Raw data sources with padded rows.
+
+use std::io::{self,Read};
+
+use ::{BayerDepth,BayerError,BayerResult};
+use errcode::OVERFLOW;
+
+/// Reader for raw data whose rows are padded, e.g. to the 4 or 64
+/// byte alignment required by a camera driver.
+///
+/// The padding after each row is skipped, so the reader produces the
+/// raw data of the image without any padding and can be passed to
+/// any of the demosaicing functions.  The padding after the last row
+/// is optional.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Read;
+///
+/// // A 2x2 image with rows padded to 4 bytes.
+/// let src = [1,2,0,0, 3,4,0,0];
+/// let mut rdr = bayer::BayerSource::with_stride(&src[..],
+///         bayer::BayerDepth::Depth8, 2, 4).unwrap();
+///
+/// let mut buf = Vec::new();
+/// rdr.read_to_end(&mut buf).unwrap();
+/// assert_eq!(buf, [1,2, 3,4]);
+/// ```
+pub struct BayerSource<R> {
+    r: R,
+    row_len: usize,
+    padding: usize,
+    pos: usize,
+}
+
+impl<R: Read> BayerSource<R> {
+    /// Wrap a reader of a w pixel wide image of the given depth, where
+    /// each row starts stride bytes after the previous one.
+    pub fn with_stride(r: R, depth: BayerDepth, w: usize, stride: usize)
+            -> BayerResult<Self> {
+        let row_len = depth.byte_len(w).ok_or(OVERFLOW)?;
+        if stride < row_len {
+            return Err(BayerError::InvalidArgument {
+                reason: "the stride must be at least the length of a row",
+            });
+        }
+
+        Ok(BayerSource { r, row_len, padding: stride - row_len, pos: 0 })
+    }
+
+    /// Unwrap the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.r
+    }
+}
+
+impl<R: Read> Read for BayerSource<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.row_len == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+
+        // Skip the padding of the previous row only when the next row
+        // is read, so that the last row need not be padded.
+        if self.pos == self.row_len {
+            let skipped = io::copy(&mut (&mut self.r).take(self.padding as u64), &mut io::sink())?;
+            if skipped < self.padding as u64 {
+                return Ok(0);
+            }
+            self.pos = 0;
+        }
+
+        let n = ::std::cmp::min(buf.len(), self.row_len - self.pos);
+        let n = self.r.read(&mut buf[0..n])?;
+        self.pos = self.pos + n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use ::{BayerDepth,BayerError,CFA,Demosaic,RasterDepth,RasterMut,run_demosaic};
+    use super::BayerSource;
+
+    #[test]
+    fn test_stride() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+
+        let mut padded = Vec::new();
+        for row in src.chunks(4) {
+            padded.extend_from_slice(row);
+            padded.extend_from_slice(&[0xFF; 3]);
+        }
+
+        let mut expected = [0u8; 3 * 4 * 4];
+        let res = run_demosaic(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, CFA::RGGB, Demosaic::Linear,
+                &mut RasterMut::new(4, 4, RasterDepth::Depth8, &mut expected));
+        assert!(res.is_ok());
+
+        let mut buf = [0u8; 3 * 4 * 4];
+        let mut rdr = BayerSource::with_stride(Cursor::new(&padded[..]),
+                BayerDepth::Depth8, 4, 7).unwrap();
+        let res = run_demosaic(&mut rdr,
+                BayerDepth::Depth8, CFA::RGGB, Demosaic::Linear,
+                &mut RasterMut::new(4, 4, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+        assert_eq!(&buf[..], &expected[..]);
+
+        // The padding of the last row is never read.
+        assert_eq!(rdr.into_inner().position(), 7 * 3 + 4);
+    }
+
+    #[test]
+    fn test_short_stride() {
+        match BayerSource::with_stride(&[0u8; 16][..], BayerDepth::Depth16LE, 4, 6) {
+            Err(BayerError::InvalidArgument { .. }) => (),
+            _ => panic!(),
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! Statistics of the raw Bayer data.
+
+use std::io::Read;
+
+use ::{BayerDepth,BayerResult,CFA};
+use bayer::{check_packing,get_sample};
+use errcode::OVERFLOW;
+use planes::plane_offsets;
+
+/// Statistics of the samples of one CFA channel.
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct ChannelStats {
+    /// The number of samples.
+    pub count: u64,
+    pub min: u32,
+    pub max: u32,
+    pub mean: f64,
+    /// The population standard deviation.
+    pub stddev: f64,
+    /// The requested percentiles, in the order requested.
+    pub percentiles: Vec<u32>,
+}
+
+/// Accumulated statistics of one channel.
+struct Accumulator {
+    count: u64,
+    min: u32,
+    max: u32,
+    sum: u64,
+    sum_sq: u64,
+    histogram: Vec<u64>,
+}
+
+impl Accumulator {
+    fn new(histogram_len: usize) -> Self {
+        Accumulator {
+            count: 0, min: ::std::u32::MAX, max: 0, sum: 0, sum_sq: 0,
+            histogram: vec![0; histogram_len],
+        }
+    }
+
+    fn add(&mut self, v: u32) {
+        self.count = self.count + 1;
+        self.min = if v < self.min { v } else { self.min };
+        self.max = if v > self.max { v } else { self.max };
+        self.sum = self.sum + v as u64;
+        self.sum_sq = self.sum_sq + v as u64 * v as u64;
+
+        if !self.histogram.is_empty() {
+            self.histogram[v as usize] = self.histogram[v as usize] + 1;
+        }
+    }
+
+    /// The smallest value such that at least p percent of the samples
+    /// are less than or equal to it.
+    fn percentile(&self, p: f64) -> u32 {
+        let rank = (p / 100.0 * self.count as f64).ceil().max(1.0) as u64;
+        let mut total = 0;
+
+        for (v, &n) in self.histogram.iter().enumerate() {
+            total = total + n;
+            if total >= rank {
+                return v as u32;
+            }
+        }
+
+        self.max
+    }
+
+    fn finish(&self, percentiles: &[f64]) -> ChannelStats {
+        if self.count == 0 {
+            return ChannelStats {
+                percentiles: vec![0; percentiles.len()],
+                ..ChannelStats::default()
+            };
+        }
+
+        let n = self.count as f64;
+        let mean = self.sum as f64 / n;
+        let var = (self.sum_sq as f64 / n - mean * mean).max(0.0);
+
+        ChannelStats {
+            count: self.count,
+            min: self.min,
+            max: self.max,
+            mean,
+            stddev: var.sqrt(),
+            percentiles: percentiles.iter().map(|&p| self.percentile(p)).collect(),
+        }
+    }
+}
+
+/// Compute the statistics of each CFA channel of a w x h Bayer image
+/// in a single pass, returning the statistics of the R, G1, G2, B
+/// channels in that order.  See `split_planes` for the meaning of G1
+/// and G2.
+///
+/// percentiles lists the percentiles, from 0 to 100, to compute.
+/// Computing percentiles requires a histogram of 65536 bins per
+/// channel for 16-bit images.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+///
+/// let img = [
+///     10,20,10,20,
+///     30,40,30,40 ];
+///
+/// let stats = bayer::channel_stats(&mut Cursor::new(&img[..]),
+///         bayer::BayerDepth::Depth8, 4, 2, bayer::CFA::RGGB,
+///         &[50.0]).unwrap();
+/// assert_eq!(stats[0].mean, 10.0);
+/// assert_eq!(stats[3].percentiles, [40]);
+/// ```
+pub fn channel_stats(r: &mut Read, depth: BayerDepth, w: usize, h: usize,
+        cfa: CFA, percentiles: &[f64])
+        -> BayerResult<[ChannelStats; 4]> {
+    let histogram_len = match (percentiles.is_empty(), depth) {
+        (true, _) => 0,
+        (false, BayerDepth::Depth8) => 1 << 8,
+        (false, _) => 1 << 16,
+    };
+
+    let mut acc = [
+        Accumulator::new(histogram_len),
+        Accumulator::new(histogram_len),
+        Accumulator::new(histogram_len),
+        Accumulator::new(histogram_len) ];
+
+    // The channel of each pixel of the 2x2 block.
+    let mut channel = [[0; 2]; 2];
+    for (p, &(dx, dy)) in plane_offsets(cfa).iter().enumerate() {
+        channel[dy][dx] = p;
+    }
+
+    check_packing(depth, w)?;
+    // Allocations are limited to isize::MAX bytes.
+    let row_len = depth.byte_len(w).filter(|&n| n <= isize::max_value() as usize)
+        .ok_or(OVERFLOW)?;
+    let mut row = vec![0u8; row_len];
+    for y in 0..h {
+        r.read_exact(&mut row)?;
+        for x in 0..w {
+            acc[channel[y % 2][x % 2]].add(get_sample(&row, depth, x));
+        }
+    }
+
+    Ok([
+        acc[0].finish(percentiles),
+        acc[1].finish(percentiles),
+        acc[2].finish(percentiles),
+        acc[3].finish(percentiles) ])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use ::{BayerDepth,CFA};
+    use super::channel_stats;
+
+    #[test]
+    fn test_stats() {
+        // R: set.seed(0); matrix(floor(runif(n=16, min=0, max=256)), nrow=4, byrow=TRUE)
+        let src = [
+            229, 67, 95,146,
+            232, 51,229,241,
+            169,161, 15, 52,
+             45,175, 98,197 ];
+
+        let stats = channel_stats(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, 4, 4, CFA::BGGR, &[0.0, 50.0, 100.0]).unwrap();
+
+        // B: 229, 95, 169, 15.
+        assert_eq!(stats[3].count, 4);
+        assert_eq!((stats[3].min, stats[3].max), (15, 229));
+        assert_eq!(stats[3].mean, 127.0);
+        assert!((stats[3].stddev - 80.2122).abs() < 1e-4);
+        assert_eq!(stats[3].percentiles, [15, 95, 229]);
+
+        // R: 51, 241, 175, 197.
+        assert_eq!(stats[0].mean, 166.0);
+
+        // G1 is on the row of the red pixels: 232, 229, 45, 98.
+        assert_eq!(stats[1].mean, 151.0);
+        assert_eq!(stats[2].mean, 106.5);
+    }
+
+    #[test]
+    fn test_short() {
+        let src = [0u8; 7];
+        let res = channel_stats(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth16LE, 2, 2, CFA::RGGB, &[]);
+        assert!(res.is_err());
+    }
+}
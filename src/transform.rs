@@ -0,0 +1,215 @@
+//! Rotating and flipping Bayer images.
+//!
+//! The transforms move the raw samples without interpolation, and
+//! report the CFA pattern of the transformed image, so orientation can
+//! be corrected before demosaicing.
+
+use ::{BayerDepth,BayerResult,CFA};
+use bayer::{check_input_len,check_output_len,check_unpacked};
+
+/// A rotation or flip of an image.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum Transform {
+    /// Rotate 90 degrees clockwise.
+    Rotate90,
+    /// Rotate 180 degrees.
+    Rotate180,
+    /// Rotate 90 degrees anti-clockwise.
+    Rotate270,
+    /// Mirror left and right.
+    FlipHorizontal,
+    /// Mirror top and bottom.
+    FlipVertical,
+}
+
+impl Transform {
+    /// The dimensions of a w x h image after the transform.
+    pub fn dimensions(self, w: usize, h: usize) -> (usize, usize) {
+        match self {
+            Transform::Rotate90 | Transform::Rotate270 => (h, w),
+            _ => (w, h),
+        }
+    }
+
+    /// The source coordinates of pixel (x, y) of the transformed
+    /// image.  The arithmetic wraps, which preserves the parity of
+    /// coordinates beyond the image.
+    fn source(self, w: usize, h: usize, x: usize, y: usize) -> (usize, usize) {
+        match self {
+            Transform::Rotate90 => (y, h.wrapping_sub(1).wrapping_sub(x)),
+            Transform::Rotate180 => (w.wrapping_sub(1).wrapping_sub(x), h.wrapping_sub(1).wrapping_sub(y)),
+            Transform::Rotate270 => (w.wrapping_sub(1).wrapping_sub(y), x),
+            Transform::FlipHorizontal => (w.wrapping_sub(1).wrapping_sub(x), y),
+            Transform::FlipVertical => (x, h.wrapping_sub(1).wrapping_sub(y)),
+        }
+    }
+
+    /// The coordinates of pixel (x, y) of a w x h image after the
+    /// transform.
+    pub(crate) fn destination(self, w: usize, h: usize, x: usize, y: usize) -> (usize, usize) {
+        match self {
+            Transform::Rotate90 => (h - 1 - y, x),
+            Transform::Rotate180 => (w - 1 - x, h - 1 - y),
+            Transform::Rotate270 => (y, w - 1 - x),
+            Transform::FlipHorizontal => (w - 1 - x, y),
+            Transform::FlipVertical => (x, h - 1 - y),
+        }
+    }
+
+    /// The CFA pattern of a w x h image after the transform.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let t = bayer::Transform::Rotate180;
+    /// assert_eq!(t.cfa(bayer::CFA::RGGB, 4, 4), bayer::CFA::BGGR);
+    /// assert_eq!(t.cfa(bayer::CFA::RGGB, 5, 4), bayer::CFA::GBRG);
+    /// ```
+    pub fn cfa(self, cfa: CFA, w: usize, h: usize) -> CFA {
+        let (x0, y0) = self.source(w, h, 0, 0);
+        let (x1, y1) = self.source(w, h, 1, 0);
+        CFA::from_channels(cfa.at(x0, y0).channel(), cfa.at(x1, y1).channel())
+    }
+}
+
+/// Rotate or flip a w x h Bayer image, returning the CFA pattern of
+/// the transformed image.
+///
+/// dst must hold the transformed image, whose dimensions are given by
+/// `Transform::dimensions`.
+///
+/// # Example
+///
+/// ```
+/// let src = [
+///     1,2,3,
+///     4,5,6 ];
+/// let mut dst = [0u8; 6];
+///
+/// let cfa = bayer::transform(&src, bayer::BayerDepth::Depth8, 3, 2,
+///         bayer::CFA::RGGB, bayer::Transform::Rotate90, &mut dst).unwrap();
+/// assert_eq!(dst, [4,1, 5,2, 6,3]);
+/// assert_eq!(cfa, bayer::CFA::GRBG);
+/// ```
+pub fn transform(src: &[u8], depth: BayerDepth, w: usize, h: usize,
+        cfa: CFA, t: Transform, dst: &mut [u8])
+        -> BayerResult<CFA> {
+    check_unpacked(depth)?;
+    check_input_len(src.len(), depth, w, h)?;
+    check_output_len(dst.len(), depth, w, h)?;
+
+    let bps = depth.bytes_per_sample();
+    let (dst_w, dst_h) = t.dimensions(w, h);
+
+    for y in 0..dst_h {
+        for x in 0..dst_w {
+            let (sx, sy) = t.source(w, h, x, y);
+            let i = bps * (w * sy + sx);
+            let j = bps * (dst_w * y + x);
+            dst[j..(j + bps)].copy_from_slice(&src[i..(i + bps)]);
+        }
+    }
+
+    Ok(t.cfa(cfa, w, h))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use ::{BayerDepth,CFA,Demosaic,DemosaicOptions,RasterDepth,RasterMut};
+    use ::{demosaic_with_options,run_demosaic};
+    use super::{Transform,transform};
+
+    /// Check that demosaicing with no interpolation and then
+    /// transforming gives the same colours as transforming first.
+    fn check_transform(t: Transform, cfa: CFA, w: usize, h: usize) {
+        let src: Vec<u8> = (0..(w * h)).map(|i| i as u8).collect();
+        let mut rgb = vec![0u8; 3 * w * h];
+        let res = run_demosaic(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, cfa, Demosaic::None,
+                &mut RasterMut::new(w, h, RasterDepth::Depth8, &mut rgb));
+        assert!(res.is_ok());
+
+        let mut raw = vec![0u8; w * h];
+        let new_cfa = transform(&src, BayerDepth::Depth8, w, h, cfa, t, &mut raw).unwrap();
+
+        let (dst_w, dst_h) = t.dimensions(w, h);
+        let mut dst = vec![0u8; 3 * w * h];
+        let res = run_demosaic(&mut Cursor::new(&raw[..]),
+                BayerDepth::Depth8, new_cfa, Demosaic::None,
+                &mut RasterMut::new(dst_w, dst_h, RasterDepth::Depth8, &mut dst));
+        assert!(res.is_ok());
+
+        for y in 0..dst_h {
+            for x in 0..dst_w {
+                let (sx, sy) = t.source(w, h, x, y);
+                let i = 3 * (w * sy + sx);
+                let j = 3 * (dst_w * y + x);
+                assert_eq!(&dst[j..(j + 3)], &rgb[i..(i + 3)]);
+                assert_eq!(t.destination(w, h, sx, sy), (x, y));
+            }
+        }
+    }
+
+    /// Check that transforming the output as it is written gives the
+    /// same result as transforming the demosaiced image.
+    fn check_output_transform(t: Transform, cfa: CFA, w: usize, h: usize) {
+        let src: Vec<u8> = (0..(w * h)).map(|i| (i * 37) as u8).collect();
+        let mut rgb = vec![0u8; 3 * w * h];
+        let res = run_demosaic(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, cfa, Demosaic::Linear,
+                &mut RasterMut::new(w, h, RasterDepth::Depth8, &mut rgb));
+        assert!(res.is_ok());
+
+        let mut options = DemosaicOptions::default();
+        options.transform = Some(t);
+
+        let (dst_w, dst_h) = t.dimensions(w, h);
+        let mut dst = vec![0u8; 3 * w * h];
+        let res = demosaic_with_options(&mut Cursor::new(&src[..]),
+                BayerDepth::Depth8, cfa, Demosaic::Linear, &options,
+                &mut RasterMut::new(dst_w, dst_h, RasterDepth::Depth8, &mut dst));
+        assert!(res.is_ok());
+
+        for y in 0..dst_h {
+            for x in 0..dst_w {
+                let (sx, sy) = t.source(w, h, x, y);
+                let i = 3 * (w * sy + sx);
+                let j = 3 * (dst_w * y + x);
+                assert_eq!(&dst[j..(j + 3)], &rgb[i..(i + 3)]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_transforms() {
+        let ts = [Transform::Rotate90, Transform::Rotate180, Transform::Rotate270,
+                Transform::FlipHorizontal, Transform::FlipVertical];
+        let cfas = [CFA::BGGR, CFA::GBRG, CFA::GRBG, CFA::RGGB];
+
+        for &t in ts.iter() {
+            for &cfa in cfas.iter() {
+                check_transform(t, cfa, 4, 6);
+                check_transform(t, cfa, 5, 3);
+                check_output_transform(t, cfa, 4, 6);
+                check_output_transform(t, cfa, 5, 3);
+            }
+        }
+    }
+
+    #[test]
+    fn test_u16() {
+        let src = [
+            0x01,0x02, 0x03,0x04,
+            0x05,0x06, 0x07,0x08 ];
+        let mut dst = [0u8; 8];
+
+        let cfa = transform(&src, BayerDepth::Depth16BE, 2, 2,
+                CFA::RGGB, Transform::FlipHorizontal, &mut dst).unwrap();
+        assert_eq!(dst, [0x03,0x04, 0x01,0x02, 0x07,0x08, 0x05,0x06]);
+        assert_eq!(cfa, CFA::GRBG);
+
+        assert!(transform(&src, BayerDepth::Depth16BE, 2, 2,
+                CFA::RGGB, Transform::FlipHorizontal, &mut dst[0..6]).is_err());
+    }
+}
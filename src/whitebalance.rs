@@ -0,0 +1,284 @@
+//! White balance.
+
+use ::{BayerDepth,BayerResult,CFA,Rect};
+use bayer::{check_input_len,get_sample,put_sample};
+
+/// White balance multipliers for the red, green and blue channels.
+///
+/// Multipliers compare equal only if they are identical, bit for bit,
+/// so that the options holding them can be compared.
+#[derive(Clone,Copy,Debug)]
+pub struct WhiteBalance {
+    pub r: f64,
+    pub g: f64,
+    pub b: f64,
+}
+
+impl WhiteBalance {
+    /// Create white balance multipliers.
+    pub fn new(r: f64, g: f64, b: f64) -> Self {
+        WhiteBalance { r, g, b }
+    }
+
+    /// The multipliers as an array, indexed by channel.
+    pub fn gains(&self) -> [f64; 3] {
+        [self.r, self.g, self.b]
+    }
+}
+
+impl PartialEq for WhiteBalance {
+    fn eq(&self, other: &WhiteBalance) -> bool {
+        self.r.to_bits() == other.r.to_bits()
+            && self.g.to_bits() == other.g.to_bits()
+            && self.b.to_bits() == other.b.to_bits()
+    }
+}
+
+impl Eq for WhiteBalance {}
+
+impl From<WhiteBalancePreset> for WhiteBalance {
+    fn from(preset: WhiteBalancePreset) -> Self {
+        preset.white_balance()
+    }
+}
+
+impl Default for WhiteBalance {
+    /// Unity gains, which leave the image unchanged.
+    fn default() -> Self {
+        WhiteBalance::new(1.0, 1.0, 1.0)
+    }
+}
+
+/// Named white balance settings for common light sources.
+///
+/// The multipliers are typical of silicon sensors behind a standard
+/// Bayer filter, normalised so that green is 1.0.  They are a quick
+/// approximation, not a substitute for measuring a gray card with
+/// `white_balance_from_rect`.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum WhiteBalancePreset {
+    Daylight,
+    Cloudy,
+    Tungsten,
+    Flash,
+}
+
+const ALL_PRESETS: [WhiteBalancePreset; 4] = [
+    WhiteBalancePreset::Daylight,
+    WhiteBalancePreset::Cloudy,
+    WhiteBalancePreset::Tungsten,
+    WhiteBalancePreset::Flash,
+];
+
+impl WhiteBalancePreset {
+    /// All of the presets.
+    pub fn all() -> &'static [WhiteBalancePreset] {
+        &ALL_PRESETS
+    }
+
+    /// The lower-case name of the preset.
+    pub fn name(self) -> &'static str {
+        match self {
+            WhiteBalancePreset::Daylight => "daylight",
+            WhiteBalancePreset::Cloudy => "cloudy",
+            WhiteBalancePreset::Tungsten => "tungsten",
+            WhiteBalancePreset::Flash => "flash",
+        }
+    }
+
+    /// Look up a preset by name, ignoring case.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let preset = bayer::WhiteBalancePreset::from_name("Tungsten").unwrap();
+    /// let wb = bayer::WhiteBalance::from(preset);
+    /// assert!(wb.b > wb.r);
+    /// ```
+    pub fn from_name(name: &str) -> Option<WhiteBalancePreset> {
+        ALL_PRESETS.iter().cloned()
+            .find(|p| p.name().eq_ignore_ascii_case(name))
+    }
+
+    /// The white balance multipliers of the preset.
+    pub fn white_balance(self) -> WhiteBalance {
+        match self {
+            WhiteBalancePreset::Daylight => WhiteBalance::new(2.0, 1.0, 1.5),
+            WhiteBalancePreset::Cloudy => WhiteBalance::new(2.2, 1.0, 1.35),
+            WhiteBalancePreset::Tungsten => WhiteBalance::new(1.3, 1.0, 2.4),
+            WhiteBalancePreset::Flash => WhiteBalance::new(2.1, 1.0, 1.45),
+        }
+    }
+}
+
+/// Compute the white balance multipliers that make a rectangle of a
+/// w x h Bayer image neutral gray, e.g. the image of a gray card.
+///
+/// Samples greater than or equal to clip are excluded, since clipped
+/// samples no longer measure the colour of the light.  The
+/// multipliers are normalised so that green is 1.0.  A channel with
+/// no usable samples gets a multiplier of 1.0.
+///
+/// # Example
+///
+/// ```
+/// let src = [
+///     50,100,
+///     100,200 ];
+///
+/// let wb = bayer::white_balance_from_rect(&src, bayer::BayerDepth::Depth8, 2, 2,
+///         bayer::CFA::RGGB, bayer::Rect::new(0, 0, 2, 2), 255).unwrap();
+/// assert_eq!(wb, bayer::WhiteBalance::new(2.0, 1.0, 0.5));
+/// ```
+pub fn white_balance_from_rect(src: &[u8], depth: BayerDepth,
+        w: usize, h: usize, cfa: CFA, rect: Rect, clip: u32)
+        -> BayerResult<WhiteBalance> {
+    check_input_len(src.len(), depth, w, h)?;
+    rect.check_bounds(w, h)?;
+
+    let mut sum = [0u64; 3];
+    let mut count = [0u64; 3];
+
+    for y in rect.y..(rect.y + rect.h) {
+        for x in rect.x..(rect.x + rect.w) {
+            let v = get_sample(src, depth, w * y + x);
+            if v < clip {
+                let c = cfa.at(x, y).channel();
+                sum[c] = sum[c] + v as u64;
+                count[c] = count[c] + 1;
+            }
+        }
+    }
+
+    let mut mean = [0.0; 3];
+    for c in 0..3 {
+        if count[c] > 0 {
+            mean[c] = sum[c] as f64 / count[c] as f64;
+        }
+    }
+
+    let gain = |c: usize| {
+        if mean[c] > 0.0 && mean[1] > 0.0 { mean[1] / mean[c] } else { 1.0 }
+    };
+
+    Ok(WhiteBalance::new(gain(0), 1.0, gain(2)))
+}
+
+/// Apply white balance multipliers to a w x h Bayer image in place,
+/// for tools that expect balanced raw data.
+///
+/// Each sample is multiplied by the gain of its channel in the CFA
+/// pattern, rounded, and clamped to clip, which is usually the white
+/// level of the sensor.  Samples that were already at or above clip
+/// are set to clip, so that clipped highlights stay neutral.
+///
+/// # Example
+///
+/// ```
+/// let mut buf = [
+///     50,100,
+///     100,200 ];
+///
+/// bayer::apply_white_balance(&mut buf, bayer::BayerDepth::Depth8, 2, 2,
+///         bayer::CFA::RGGB, bayer::WhiteBalance::new(2.0, 1.0, 0.5), 255).unwrap();
+/// assert_eq!(buf, [100,100, 100,100]);
+/// ```
+pub fn apply_white_balance(buf: &mut [u8], depth: BayerDepth,
+        w: usize, h: usize, cfa: CFA, wb: WhiteBalance, clip: u32)
+        -> BayerResult<()> {
+    check_input_len(buf.len(), depth, w, h)?;
+
+    let gains = wb.gains();
+    for y in 0..h {
+        for x in 0..w {
+            let i = w * y + x;
+            let v = get_sample(buf, depth, i);
+            let balanced = if v >= clip {
+                clip
+            } else {
+                let gain = gains[cfa.at(x, y).channel()];
+                let v = (v as f64 * gain + 0.5).max(0.0);
+                if v >= clip as f64 { clip } else { v as u32 }
+            };
+            put_sample(buf, depth, i, balanced);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{BayerDepth,CFA,Rect};
+    use super::{WhiteBalance,WhiteBalancePreset,apply_white_balance,white_balance_from_rect};
+
+    #[test]
+    fn test_gray_card() {
+        // A gray card in the bottom right, with one clipped green sample.
+        let src = [
+            255,255,255,255,
+            255, 40, 80, 40,
+            255, 20,255, 20,
+            255, 40, 80, 40 ];
+
+        let wb = white_balance_from_rect(&src, BayerDepth::Depth8, 4, 4,
+                CFA::GBRG, Rect::new(1, 1, 3, 3), 255).unwrap();
+        assert_eq!(wb, WhiteBalance::new(0.5, 1.0, 2.0));
+
+        let res = white_balance_from_rect(&src, BayerDepth::Depth8, 4, 4,
+                CFA::GBRG, Rect::new(2, 2, 3, 3), 255);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_presets() {
+        for &preset in WhiteBalancePreset::all() {
+            assert_eq!(WhiteBalancePreset::from_name(preset.name()), Some(preset));
+            assert_eq!(WhiteBalance::from(preset).g, 1.0);
+        }
+
+        // Warmer light needs less red and more blue.
+        let daylight = WhiteBalance::from(WhiteBalancePreset::Daylight);
+        let cloudy = WhiteBalance::from(WhiteBalancePreset::Cloudy);
+        let tungsten = WhiteBalance::from(WhiteBalancePreset::Tungsten);
+        assert!(cloudy.r > daylight.r && cloudy.b < daylight.b);
+        assert!(tungsten.r < daylight.r && tungsten.b > daylight.b);
+
+        assert_eq!(WhiteBalancePreset::from_name("FLASH"), Some(WhiteBalancePreset::Flash));
+        assert_eq!(WhiteBalancePreset::from_name("sodium"), None);
+    }
+
+    #[test]
+    fn test_apply() {
+        // 12-bit samples in little-endian 16-bit words, one clipped.
+        let mut buf = [
+            0x00,0x01, 0x00,0x02, 0x00,0x08, 0x00,0x02,
+            0x00,0x02, 0x00,0x04, 0x00,0x02, 0xFF,0x0F ];
+
+        let res = apply_white_balance(&mut buf, BayerDepth::Depth16LE, 4, 2,
+                CFA::GBRG, WhiteBalance::new(0.5, 1.0, 2.0), 0xFFF);
+        assert!(res.is_ok());
+        assert_eq!(buf, [
+            0x00,0x01, 0x00,0x04, 0x00,0x08, 0x00,0x04,
+            0x00,0x01, 0x00,0x04, 0x00,0x01, 0xFF,0x0F ]);
+
+        // Gains that push samples past the clip level.
+        let res = apply_white_balance(&mut buf, BayerDepth::Depth16LE, 4, 2,
+                CFA::GBRG, WhiteBalance::new(8.0, 1.0, 8.0), 0xFFF);
+        assert!(res.is_ok());
+        assert_eq!(&buf[2..4], &[0xFF,0x0F]);
+        assert_eq!(&buf[8..10], &[0x00,0x08]);
+
+        let res = apply_white_balance(&mut buf[..15], BayerDepth::Depth16LE, 4, 2,
+                CFA::GBRG, WhiteBalance::default(), 0xFFF);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_all_clipped() {
+        let src = [0xFFu8; 8];
+        let wb = white_balance_from_rect(&src, BayerDepth::Depth16LE, 2, 2,
+                CFA::RGGB, Rect::new(0, 0, 2, 2), 4095).unwrap();
+        assert_eq!(wb, WhiteBalance::default());
+    }
+}
@@ -0,0 +1,449 @@
+//! Demosaicing of X-Trans images.
+//!
+//! A single-pass form of F. Markesteijn's algorithm, as implemented
+//! by dcraw.  The algorithm runs in three steps:
+//!
+//! 1. Green is interpolated at the red and blue samples in each of
+//!    four directions (horizontal, vertical and the two diagonals),
+//!    from the nearest green samples along the direction, and limited
+//!    to the range of the greens around the pixel.
+//!
+//! 2. For each direction, red and blue are interpolated from the
+//!    colour differences of the nearest red and blue samples.
+//!
+//! 3. The directions whose interpolations are the most homogeneous
+//!    around each pixel are averaged.  Homogeneity is measured in the
+//!    space L = R + 2G + B, a = R - G, b = B - G rather than CIELab.
+//!
+//! The image is read into memory, then interpolated in bands of rows,
+//! each extended by the rows that the three steps depend on.
+
+use std::cmp;
+use std::io::Read;
+
+use ::{BayerDepth,BayerError,BayerResult,DemosaicOptions,RasterMut};
+use demosaic::calibration::Calibration;
+use demosaic::{RowFormat,check_conversion};
+use pattern::{Colour,read_samples};
+
+/// The directions of interpolation.
+const DIRECTIONS: [(isize, isize); 4] = [(1, 0), (0, 1), (1, 1), (1, -1)];
+
+/// The number of output rows interpolated at a time.
+const BAND: usize = 64;
+
+/// The number of extra rows on either side of a band that the output
+/// rows depend on.
+const MARGIN: usize = 6;
+
+/// The calibrated samples of an X-Trans image.
+struct Mosaic<'a> {
+    w: usize,
+    h: usize,
+    layout: &'a [[Colour; 6]; 6],
+    samples: Vec<u32>,
+}
+
+impl<'a> Mosaic<'a> {
+    fn read<R: Read>(r: &mut R, depth: BayerDepth, layout: &'a [[Colour; 6]; 6],
+            calibration: &Calibration, w: usize, h: usize)
+            -> BayerResult<Self> {
+        let samples = read_samples(r, depth, calibration, w, h)?;
+        Ok(Mosaic { w, h, layout, samples })
+    }
+
+    fn channel(&self, x: usize, y: usize) -> usize {
+        self.layout[y % 6][x % 6].channel()
+    }
+
+    fn sample(&self, x: usize, y: usize) -> i32 {
+        self.samples[self.w * y + x] as i32
+    }
+}
+
+/// A plane of values for the rows y0..(y0 + h) of the image.
+struct Plane<T> {
+    w: usize,
+    h: usize,
+    y0: usize,
+    data: Vec<T>,
+}
+
+impl<T: Copy> Plane<T> {
+    fn new(w: usize, y0: usize, h: usize, v: T) -> Self {
+        Plane { w, h, y0, data: vec![v; w * h] }
+    }
+
+    /// The index of pixel (x, y) offset by (dx, dy), if it lies
+    /// within the plane.
+    fn index(&self, x: usize, y: usize, dx: isize, dy: isize) -> Option<usize> {
+        let x = x as isize + dx;
+        let y = y as isize - self.y0 as isize + dy;
+        if x < 0 || y < 0 || x >= self.w as isize || y >= self.h as isize {
+            None
+        } else {
+            Some(self.w * y as usize + x as usize)
+        }
+    }
+
+    fn at(&self, x: usize, y: usize) -> T {
+        self.data[self.w * (y - self.y0) + x]
+    }
+
+    fn set(&mut self, x: usize, y: usize, v: T) {
+        self.data[self.w * (y - self.y0) + x] = v;
+    }
+}
+
+/// Divide a by n, rounding to the nearest integer.
+fn div_round(a: i32, n: i32) -> i32 {
+    if a < 0 { -((-a + n / 2) / n) } else { (a + n / 2) / n }
+}
+
+/*--------------------------------------------------------------*/
+
+/// Step 1: the green at pixel (x, y) interpolated along direction d.
+fn green(m: &Mosaic, x: usize, y: usize, d: (isize, isize)) -> i32 {
+    if m.channel(x, y) == 1 {
+        return m.sample(x, y);
+    }
+
+    // The nearest green at most 3 pixels away on side s.
+    let nearest = |s: isize| -> Option<(i32, i32)> {
+        for k in 1..4 {
+            let (u, v) = (x as isize + s * k * d.0, y as isize + s * k * d.1);
+            if u < 0 || v < 0 || u >= m.w as isize || v >= m.h as isize {
+                return None;
+            }
+            if m.channel(u as usize, v as usize) == 1 {
+                return Some((k as i32, m.sample(u as usize, v as usize)));
+            }
+        }
+        None
+    };
+
+    // The range of the greens around the pixel.
+    let mut limits = None;
+    for v in y.saturating_sub(1)..cmp::min(y + 2, m.h) {
+        for u in x.saturating_sub(1)..cmp::min(x + 2, m.w) {
+            if m.channel(u, v) == 1 {
+                let g = m.sample(u, v);
+                limits = Some(limits.map_or((g, g), |(lo, hi)| (cmp::min(lo, g), cmp::max(hi, g))));
+            }
+        }
+    }
+
+    let g = match (nearest(-1), nearest(1)) {
+        (Some((k0, g0)), Some((k1, g1))) => div_round(g0 * k1 + g1 * k0, k0 + k1),
+        (Some((_, g)), None) | (None, Some((_, g))) => g,
+        (None, None) => limits.map_or(m.sample(x, y), |(lo, hi)| (lo + hi) / 2),
+    };
+
+    limits.map_or(g, |(lo, hi)| cmp::max(lo, cmp::min(g, hi)))
+}
+
+/// Step 2: the colour of pixel (x, y) from the greens of one
+/// direction.
+fn colour(m: &Mosaic, greens: &Plane<i32>, x: usize, y: usize) -> [i32; 3] {
+    let g = greens.at(x, y);
+    let mut rgb = [g; 3];
+
+    for &c in [0, 2].iter() {
+        if m.channel(x, y) == c {
+            rgb[c] = m.sample(x, y);
+            continue;
+        }
+
+        // The mean colour difference of the samples of channel c
+        // within a radius of 1, or 2 at the edges of the image.
+        for radius in 1..3 {
+            let mut sum = 0;
+            let mut n = 0;
+            for dy in -radius..(radius + 1) {
+                for dx in -radius..(radius + 1) {
+                    if let Some(i) = greens.index(x, y, dx, dy) {
+                        let (u, v) = (i % greens.w, greens.y0 + i / greens.w);
+                        if m.channel(u, v) == c {
+                            sum = sum + m.sample(u, v) - greens.data[i];
+                            n = n + 1;
+                        }
+                    }
+                }
+            }
+
+            if n > 0 {
+                rgb[c] = g + div_round(sum, n);
+                break;
+            }
+        }
+    }
+
+    rgb
+}
+
+/// The colour in the space used to measure homogeneity.
+fn lab(rgb: [i32; 3]) -> [i64; 3] {
+    let [r, g, b] = rgb;
+    [(r + 2 * g + b) as i64, (r - g) as i64, (b - g) as i64]
+}
+
+/// Step 3: the squared second derivative of the colour of pixel
+/// (x, y) along direction d.
+fn derivative(colours: &Plane<[i32; 3]>, x: usize, y: usize, d: (isize, isize)) -> i64 {
+    let at = |s: isize| {
+        let i = colours.index(x, y, s * d.0, s * d.1)
+            .unwrap_or(colours.w * (y - colours.y0) + x);
+        lab(colours.data[i])
+    };
+
+    let (prev, centre, next) = (at(-1), at(0), at(1));
+    (0..3).map(|k| {
+        let v = 2 * centre[k] - prev[k] - next[k];
+        v * v
+    }).sum()
+}
+
+/// Interpolate the rows y0..y1, writing them to dst.
+fn interpolate_band(m: &Mosaic, y0: usize, y1: usize, pedestal: u16, format: RowFormat,
+        dst: &mut RasterMut) {
+    let w = m.w;
+    let e0 = y0.saturating_sub(MARGIN);
+    let e1 = cmp::min(y1 + MARGIN, m.h);
+
+    let mut colours = Vec::with_capacity(DIRECTIONS.len());
+    let mut derivatives = Vec::with_capacity(DIRECTIONS.len());
+    for &d in DIRECTIONS.iter() {
+        let mut greens = Plane::new(w, e0, e1 - e0, 0);
+        for y in e0..e1 {
+            for x in 0..w {
+                greens.set(x, y, green(m, x, y, d));
+            }
+        }
+
+        let mut rgb = Plane::new(w, e0, e1 - e0, [0; 3]);
+        for y in e0..e1 {
+            for x in 0..w {
+                rgb.set(x, y, colour(m, &greens, x, y));
+            }
+        }
+
+        let mut drv = Plane::new(w, e0, e1 - e0, 0);
+        for y in e0..e1 {
+            for x in 0..w {
+                drv.set(x, y, derivative(&rgb, x, y, d));
+            }
+        }
+
+        colours.push(rgb);
+        derivatives.push(drv);
+    }
+
+    // The number of neighbours of each pixel whose derivative is
+    // within 8 times the smallest derivative of the pixel.
+    let mut homogeneity: Vec<Plane<u8>> = DIRECTIONS.iter()
+        .map(|_| Plane::new(w, e0, e1 - e0, 0)).collect();
+    for y in e0..e1 {
+        for x in 0..w {
+            let tr = 8 * derivatives.iter().map(|drv| drv.at(x, y)).min().unwrap_or(0);
+            for (homo, drv) in homogeneity.iter_mut().zip(derivatives.iter()) {
+                let mut n = 0;
+                for dy in -1..2 {
+                    for dx in -1..2 {
+                        if let Some(i) = drv.index(x, y, dx, dy) {
+                            if drv.data[i] <= tr {
+                                n = n + 1;
+                            }
+                        }
+                    }
+                }
+                homo.set(x, y, n);
+            }
+        }
+    }
+
+    let mut row = vec![0u32; 3 * w];
+    for y in y0..y1 {
+        for (x, px) in row.chunks_mut(3).enumerate() {
+            let mut hm = [0u32; 4];
+            for (h, homo) in hm.iter_mut().zip(homogeneity.iter()) {
+                for dy in -2..3 {
+                    for dx in -2..3 {
+                        if let Some(i) = homo.index(x, y, dx, dy) {
+                            *h = *h + homo.data[i] as u32;
+                        }
+                    }
+                }
+            }
+
+            // Average the directions that are nearly the most
+            // homogeneous.
+            let max = hm.iter().cloned().max().unwrap_or(0);
+            let max = max - (max >> 3);
+            let mut sum = [0; 3];
+            let mut n = 0;
+            for (&h, rgb) in hm.iter().zip(colours.iter()) {
+                if h >= max {
+                    let v = rgb.at(x, y);
+                    for c in 0..3 {
+                        sum[c] = sum[c] + v[c];
+                    }
+                    n = n + 1;
+                }
+            }
+
+            for c in 0..3 {
+                px[c] = cmp::max(0, div_round(sum[c], n) - pedestal as i32) as u32;
+            }
+        }
+        format.put_row(y, &row, dst);
+    }
+}
+
+/// Run the demosaicing algorithm on an X-Trans image with the given
+/// 6x6 layout, indexed by row then column.
+pub(crate) fn demosaic_xtrans<R: Read>(r: &mut R,
+        depth: BayerDepth, layout: &[[Colour; 6]; 6], options: &DemosaicOptions,
+        dst: &mut RasterMut)
+        -> BayerResult<()> {
+    check_conversion(depth, dst.depth, options)?;
+    if options.transform.is_some() {
+        return Err(BayerError::InvalidArgument {
+            reason: "X-Trans images cannot be transformed",
+        });
+    }
+
+    // Each pixel is interpolated from the colours around it, which
+    // needs at least one whole tile of the pattern.
+    let (w, h) = (dst.w, dst.h);
+    if w < 6 || h < 6 {
+        return Err(BayerError::WrongResolution {
+            width: w, height: h, min_width: 6, min_height: 6,
+        });
+    }
+
+    let calibration = Calibration::for_raster(options, depth, dst.depth, None, w, h)?;
+    let format = RowFormat::for_image(options, depth, dst);
+    let mosaic = Mosaic::read(r, depth, layout, &calibration, w, h)?;
+
+    for y0 in (0..h).step_by(BAND) {
+        let y1 = cmp::min(y0 + BAND, h);
+        interpolate_band(&mosaic, y0, y1, calibration.pedestal(), format, dst);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{BayerDepth,BayerError,CfaPattern,Colour,Demosaic,DemosaicOptions,RasterDepth,RasterMut,demosaic_pattern};
+    use demosaic::RowFormat;
+    use demosaic::calibration::Calibration;
+    use super::{Mosaic,interpolate_band};
+
+    fn run(src: &[u8], depth: BayerDepth, w: usize, h: usize, dst: &mut RasterMut)
+            -> Result<(), BayerError> {
+        assert_eq!((dst.w, dst.h), (w, h));
+        demosaic_pattern(&mut &src[..], depth, &CfaPattern::xtrans(),
+                Demosaic::Linear, &DemosaicOptions::default(), dst)
+    }
+
+    #[test]
+    fn test_flat() {
+        // A grey image stays grey, and a red image red.
+        let src = [100u8; 14 * 10];
+        let mut buf = [0u8; 3 * 14 * 10];
+        let res = run(&src, BayerDepth::Depth8, 14, 10,
+                &mut RasterMut::new(14, 10, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+        assert!(buf.iter().all(|&v| v == 100));
+
+        let pattern = CfaPattern::xtrans();
+        let src: Vec<u8> = (0..(14 * 10))
+            .map(|i| if pattern.colour_at(i % 14, i / 14) == Colour::Red { 200 } else { 0 })
+            .collect();
+        let res = run(&src, BayerDepth::Depth8, 14, 10,
+                &mut RasterMut::new(14, 10, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+        assert!(buf.chunks(3).all(|px| px == [200, 0, 0]));
+    }
+
+    #[test]
+    fn test_edge() {
+        // A vertical edge is interpolated along, not across.
+        let src: Vec<u16> = (0..(12 * 12)).map(|i| if i % 12 < 6 { 1000 } else { 60000 }).collect();
+        let bytes: Vec<u8> = src.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect();
+        let mut buf = [0u8; 6 * 12 * 12];
+
+        let mut dst = RasterMut::new(12, 12, RasterDepth::Depth16, &mut buf);
+        let res = run(&bytes, BayerDepth::Depth16LE, 12, 12, &mut dst);
+        assert!(res.is_ok());
+        for y in 0..12 {
+            let row = dst.borrow_row_u16_mut(y);
+            assert!(row[..18].iter().all(|&v| v == 1000));
+            assert!(row[18..].iter().all(|&v| v == 60000));
+        }
+    }
+
+    #[test]
+    fn test_bands() {
+        // The bands are stitched together seamlessly.
+        let (w, h) = (20, 150);
+        let src: Vec<u8> = (0..(w * h)).map(|i| ((i * 7919) % 251) as u8).collect();
+        let mut expected = vec![0u8; 3 * w * h];
+        let mut buf = vec![0u8; 3 * w * h];
+
+        let options = DemosaicOptions::default();
+        let calibration = Calibration::new(&options, BayerDepth::Depth8, None, w, h).unwrap();
+        let layout = match CfaPattern::xtrans() {
+            CfaPattern::XTrans(layout) => layout,
+            _ => panic!(),
+        };
+        let m = Mosaic::read(&mut &src[..], BayerDepth::Depth8, &layout, &calibration, w, h).unwrap();
+        let mut dst = RasterMut::new(w, h, RasterDepth::Depth8, &mut expected);
+        let format = RowFormat::for_image(&options, BayerDepth::Depth8, &dst);
+        interpolate_band(&m, 0, h, 0, format, &mut dst);
+
+        let res = run(&src, BayerDepth::Depth8, w, h,
+                &mut RasterMut::new(w, h, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+        assert!(buf == expected);
+    }
+
+    #[test]
+    fn test_depth() {
+        // 8-bit images are widened for 16-bit rasters, and divided for
+        // floating-point rasters.
+        let src = [100u8; 6 * 6];
+        let mut buf = [0u8; 6 * 6 * 6];
+        let mut dst = RasterMut::new(6, 6, RasterDepth::Depth16, &mut buf);
+        let res = run(&src, BayerDepth::Depth8, 6, 6, &mut dst);
+        assert!(res.is_ok());
+        for y in 0..6 {
+            assert!(dst.borrow_row_u16_mut(y).iter().all(|&v| v == 100 << 8));
+        }
+
+        let mut buf = [0u8; 12 * 6 * 6];
+        let res = run(&src, BayerDepth::Depth8, 6, 6,
+                &mut RasterMut::new(6, 6, RasterDepth::Depth32F, &mut buf));
+        assert!(res.is_ok());
+        assert!(buf.chunks(4).all(|v| f32::from_ne_bytes([v[0], v[1], v[2], v[3]]) == 100.0 / 255.0));
+    }
+
+    #[test]
+    fn test_small() {
+        let src = [0u8; 6 * 6];
+        let mut buf = [0u8; 3 * 6 * 6];
+        for &(w, h) in [(1, 1), (5, 6), (6, 5)].iter() {
+            let res = run(&src[..(w * h)], BayerDepth::Depth8, w, h,
+                    &mut RasterMut::new(w, h, RasterDepth::Depth8, &mut buf[..(3 * w * h)]));
+            match res {
+                Err(BayerError::WrongResolution { min_width: 6, min_height: 6, .. }) => (),
+                _ => panic!(),
+            }
+        }
+
+        let res = run(&src, BayerDepth::Depth8, 6, 6,
+                &mut RasterMut::new(6, 6, RasterDepth::Depth8, &mut buf));
+        assert!(res.is_ok());
+    }
+}
@@ -0,0 +1,43 @@
+//! The allocator callbacks of the C API are global, so the test that
+//! swaps them runs in its own binary, apart from the other tests.
+
+extern crate bayer;
+extern crate libc;
+
+use std::sync::atomic::{AtomicUsize,Ordering};
+use libc::{c_void,size_t};
+use bayer::ffi::*;
+
+static MALLOCS: AtomicUsize = AtomicUsize::new(0);
+static FREES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe extern "C" fn counting_malloc(size: size_t) -> *mut c_void {
+    MALLOCS.fetch_add(1, Ordering::SeqCst);
+    libc::malloc(size)
+}
+
+unsafe extern "C" fn counting_free(ptr: *mut c_void) {
+    FREES.fetch_add(1, Ordering::SeqCst);
+    libc::free(ptr)
+}
+
+#[test]
+fn test_allocator() {
+    let mut buf = [0u8; 3 * 2 * 2];
+    unsafe {
+        assert_eq!(bayerrs_set_allocator(Some(counting_malloc), None), 14);
+        assert_eq!(bayerrs_set_allocator(Some(counting_malloc), Some(counting_free)), 0);
+        // Registering the same callbacks again reuses the registration.
+        assert_eq!(bayerrs_set_allocator(Some(counting_malloc), Some(counting_free)), 0);
+
+        let (mallocs, frees) = (MALLOCS.load(Ordering::SeqCst), FREES.load(Ordering::SeqCst));
+        let raster = bayerrs_raster_mut_alloc(0, 0, 2, 2, 6, 8, buf.as_mut_ptr(), buf.len());
+        assert!(!raster.is_null());
+        assert!(MALLOCS.load(Ordering::SeqCst) > mallocs);
+
+        // The raster is returned to the callbacks that allocated it.
+        assert_eq!(bayerrs_set_allocator(None, None), 0);
+        bayerrs_raster_mut_free(raster);
+        assert!(FREES.load(Ordering::SeqCst) > frees);
+    }
+}